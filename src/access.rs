@@ -0,0 +1,144 @@
+use std::str::FromStr;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// Who is allowed to use the bot, set with `ACCESS_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessMode {
+    /// Anyone who finds the bot can use it.
+    Public,
+    /// Only the master and the users listed in `ALLOWED_USER_IDS`, allowed with `/admin allow` or invited with `/invite`.
+    InviteOnly,
+    /// Only the master can use the bot.
+    MasterOnly,
+}
+
+impl FromStr for AccessMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "public" => Ok(Self::Public),
+            "invite-only" | "invite_only" => Ok(Self::InviteOnly),
+            "master-only" | "master_only" => Ok(Self::MasterOnly),
+            _ => Err(Error::Config(format!("Unknown access mode {}, expected public, invite-only or master-only.", value))),
+        }
+    }
+}
+
+impl std::fmt::Display for AccessMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::InviteOnly => write!(f, "invite-only"),
+            Self::MasterOnly => write!(f, "master-only"),
+        }
+    }
+}
+
+/// A user the master allowed with `/admin allow`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccessGrant {
+    pub granted_at: i64,
+}
+
+/// A single-use code handed out with `/invite`, redeemed through the
+/// `t.me/<bot>?start=<code>` deep link.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Invite {
+    pub created_by: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+/// Checks if the Telegram user is the master of the bot.
+pub fn is_master(from_id: i64) -> bool {
+    super::config().tg_master_id == from_id
+}
+
+/// Checks if the Telegram user may use the bot under the configured access mode.
+pub fn is_allowed(db: &Database, from_id: i64) -> Result<bool, Error> {
+    if is_master(from_id) {
+        return Ok(true);
+    }
+
+    match super::config().access_mode {
+        AccessMode::Public => Ok(true),
+        AccessMode::MasterOnly => Ok(false),
+        AccessMode::InviteOnly => {
+            Ok(super::config().allowed_user_ids.contains(&from_id) || db.access.contains_key(from_id.to_be_bytes())?)
+        },
+    }
+}
+
+/// Allows the user to use the bot when running invite-only.
+pub fn allow(db: &Database, from_id: i64) -> Result<(), Error> {
+    db.access.insert(&from_id.to_be_bytes(), AccessGrant {
+        granted_at: Utc::now().timestamp(),
+    })?;
+
+    Ok(())
+}
+
+/// Revokes a grant made with `/admin allow`, returns `false` if there was none.
+pub fn revoke(db: &Database, from_id: i64) -> Result<bool, Error> {
+    Ok(db.access.remove(from_id.to_be_bytes())?.is_some())
+}
+
+/// Drops the invites that expired unused, returns how many were removed.
+pub fn purge_expired_invites(db: &Database) -> Result<usize, Error> {
+    let now = Utc::now().timestamp();
+
+    let expired = db.invites
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, invite)| invite.expires_at <= now)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &expired {
+        db.invites.remove(key)?;
+    }
+
+    Ok(expired.len())
+}
+
+/// Creates an invite code valid for `ttl`, dropping the expired ones.
+pub fn create_invite(db: &Database, created_by: i64, ttl: Duration) -> Result<String, Error> {
+    purge_expired_invites(db)?;
+
+    let now = Utc::now().timestamp();
+    let code = Uuid::new_v4().to_simple().to_string();
+
+    db.invites.insert(code.as_bytes(), Invite {
+        created_by,
+        created_at: now,
+        expires_at: now + ttl.num_seconds(),
+    })?;
+
+    Ok(code)
+}
+
+/// Allows the user if the code is a valid invite, the invite is used up
+/// either way. Returns `false` for unknown or expired codes.
+pub fn redeem_invite(db: &Database, code: &str, from_id: i64) -> Result<bool, Error> {
+    let now = Utc::now().timestamp();
+
+    let mut batch = WriteBatch::default();
+    let redeemed = match db.invites.get(code.as_bytes())? {
+        Some(invite) if invite.expires_at > now => {
+            batch.insert(&db.access, from_id.to_be_bytes(), &AccessGrant { granted_at: now })?;
+            true
+        },
+        _ => false,
+    };
+
+    // Claiming the invite in the same batch makes sure it's only used once.
+    batch.claim(&db.invites, code);
+    Ok(batch.apply(db)? && redeemed)
+}
@@ -0,0 +1,25 @@
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// Whether the user asked for plain text replies, without formatting, emoji
+/// or progress updates, e.g. because they use a screen reader.
+pub fn is_enabled(db: &Database, from_id: i64) -> Result<bool, Error> {
+    Ok(db.accessibility.get(from_id.to_be_bytes())?.unwrap_or(false))
+}
+
+/// Turns plain text replies on or off, returns whether they were on before.
+pub fn set(db: &Database, from_id: i64, enabled: bool) -> Result<bool, Error> {
+    let previous = if enabled {
+        db.accessibility.insert(&from_id.to_be_bytes(), true)?
+    } else {
+        db.accessibility.remove(from_id.to_be_bytes())?
+    };
+
+    Ok(previous.unwrap_or(false))
+}
+
+/// Drops the setting of a purged user.
+pub fn forget(db: &Database, batch: &mut WriteBatch, from_id: i64) {
+    batch.remove(&db.accessibility, from_id.to_be_bytes());
+}
@@ -0,0 +1,73 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// The names of a user's Firefly III accounts of one type, kept in the
+/// database so account matching doesn't hit Firefly III on every message.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountList {
+    pub fetched_at: i64,
+    pub names: Vec<String>,
+}
+
+fn key(user_id: &str, account_type: &str) -> String {
+    format!("{}/{}", user_id, account_type)
+}
+
+/// Returns the cached account names unless they are older than `ttl_secs`.
+pub fn get_fresh(db: &Database, user_id: &str, account_type: &str, ttl_secs: i64) -> Result<Option<Vec<String>>, Error> {
+    let list = db.accounts.get(key(user_id, account_type).as_bytes())?;
+
+    Ok(list
+        .filter(|list| Utc::now().timestamp() - list.fetched_at < ttl_secs)
+        .map(|list| list.names))
+}
+
+pub fn store(db: &Database, user_id: &str, account_type: &str, names: Vec<String>) -> Result<(), Error> {
+    db.accounts.insert(key(user_id, account_type).as_bytes(), AccountList {
+        fetched_at: Utc::now().timestamp(),
+        names,
+    })?;
+
+    Ok(())
+}
+
+/// Drops every cached account list of the user.
+pub fn invalidate(db: &Database, batch: &mut WriteBatch, user_id: &str) -> Result<(), Error> {
+    batch.remove_prefix(&db.accounts, format!("{}/", user_id))
+}
+
+/// The asset account money is taken from when a message doesn't say, set
+/// with `/default source`.
+pub fn default_source(db: &Database, user_id: &str) -> Result<Option<String>, Error> {
+    Ok(db.default_sources.get(user_id.as_bytes())?)
+}
+
+/// Sets the default source account, `None` clears it.
+pub fn set_default_source(db: &Database, user_id: &str, name: Option<&str>) -> Result<(), Error> {
+    match name {
+        Some(name) => db.default_sources.insert(user_id.as_bytes(), name.to_owned())?,
+        None => db.default_sources.remove(user_id.as_bytes())?,
+    };
+
+    Ok(())
+}
+
+/// Drops the account lists fetched before `before`, returns how many were removed.
+pub fn purge_before(db: &Database, before: i64) -> Result<usize, Error> {
+    let stale = db.accounts
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, list)| list.fetched_at < before)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &stale {
+        db.accounts.remove(key)?;
+    }
+
+    Ok(stale.len())
+}
@@ -0,0 +1,92 @@
+use crate::accessibility;
+use crate::accounts;
+use crate::batch::WriteBatch;
+use crate::budget;
+use crate::merchants;
+use crate::profile;
+use crate::review;
+use crate::shortlist;
+use crate::telegram;
+
+use super::{Database, Error};
+
+/// Re-encrypts the secrets of every user with the current master key,
+/// returns how many users were rekeyed and how many failed.
+pub fn rekey(db: &Database) -> Result<(usize, usize), Error> {
+    let mut rekeyed = 0;
+    let mut failed = 0;
+
+    for item in db.users.iter() {
+        let (key, mut user) = item?;
+
+        match user.rekey() {
+            Ok(()) => {
+                db.users.insert(key, user)?;
+                rekeyed += 1;
+            },
+            Err(e) => {
+                log::error!("Failed to rekey: {}", e);
+                failed += 1;
+            },
+        }
+    }
+
+    Ok((rekeyed, failed))
+}
+
+/// Removes everything stored for a Telegram user, returns `false` if the
+/// user had no account.
+pub fn purge(db: &Database, from_id: i64) -> Result<bool, Error> {
+    let user_id = telegram::user_key(from_id);
+    let existed = db.users.contains_key(user_id.as_bytes())?;
+
+    let mut batch = WriteBatch::default();
+
+    // Every profile is stored like a separate account of the user.
+    let user_ids = std::iter::once(user_id).chain(profile::profile_user_ids(db, from_id)?).collect::<Vec<String>>();
+    for user_id in &user_ids {
+        forget_account(db, &mut batch, user_id)?;
+    }
+
+    let key = from_id.to_be_bytes();
+    batch.remove(&db.access, key);
+    batch.remove(&db.languages, key);
+    batch.remove(&db.confirm_modes, key);
+    batch.remove(&db.raw_descriptions, key);
+    accessibility::forget(db, &mut batch, from_id);
+    profile::forget(db, &mut batch, from_id)?;
+    batch.apply(db)?;
+
+    Ok(existed)
+}
+
+/// Removes everything stored for one account, the user record, its queued
+/// and scheduled posts and every per-account tree. `/reset` and `/purge`
+/// both go through here so neither falls behind when a tree is added.
+pub fn forget_account(db: &Database, batch: &mut WriteBatch, user_id: &str) -> Result<(), Error> {
+    let prefix = format!("{}/", user_id);
+
+    batch.remove(&db.users, user_id);
+    batch.remove(&db.categories, user_id);
+    batch.remove(&db.snapshots, user_id);
+    batch.remove(&db.wit_usage, user_id);
+    batch.remove(&db.digests, user_id);
+    batch.remove(&db.last_created, user_id);
+    batch.remove(&db.projects, user_id);
+    batch.remove_prefix(&db.pending, &prefix)?;
+    batch.remove_prefix(&db.created, &prefix)?;
+    batch.remove_prefix(&db.created_texts, &prefix)?;
+    batch.remove_prefix(&db.outbox, &prefix)?;
+    batch.remove_prefix(&db.scheduled, &prefix)?;
+    batch.remove_prefix(&db.last_texts, &prefix)?;
+    batch.remove(&db.default_sources, user_id);
+    batch.remove(&db.cold_starts, user_id);
+    batch.remove_prefix(&db.searches, &prefix)?;
+    accounts::invalidate(db, batch, user_id)?;
+    budget::forget(db, batch, user_id)?;
+    merchants::forget_all(db, batch, user_id)?;
+    review::forget(db, batch, user_id)?;
+    shortlist::forget(db, batch, user_id);
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::config::Variables;
+
+use super::Error;
+
+/// Somewhere operator alerts are delivered to, every configured sink gets
+/// each alert so one of them failing (e.g. Telegram itself) doesn't
+/// silence the others.
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, message: &str) -> Result<(), Error>;
+}
+
+/// Sends alerts to the master's Telegram chat.
+pub struct TelegramSink;
+
+#[async_trait]
+impl AlertSink for TelegramSink {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    async fn send(&self, message: &str) -> Result<(), Error> {
+        super::telegram_post("sendMessage", &serde_json::json!({
+            "chat_id": super::config().tg_master_id,
+            "text": message,
+        }))
+        .await?
+        .error_for_status()
+        .map_err(Error::Telegram)?;
+
+        Ok(())
+    }
+}
+
+/// Posts alerts as `{"text": "..."}`, which Slack, Mattermost and most
+/// chat webhooks accept.
+pub struct WebhookSink {
+    pub url: String,
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, message: &str) -> Result<(), Error> {
+        super::HTTP_CLIENTS.alerts
+            .post(&self.url)
+            .json(&serde_json::json!({ "text": message }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(Error::Upstream)?;
+
+        Ok(())
+    }
+}
+
+/// Publishes alerts to an ntfy topic, `url` includes the topic.
+pub struct NtfySink {
+    pub url: String,
+    pub token: Option<String>,
+}
+
+#[async_trait]
+impl AlertSink for NtfySink {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn send(&self, message: &str) -> Result<(), Error> {
+        let mut request = super::HTTP_CLIENTS.alerts
+            .post(&self.url)
+            .header("Title", "Firefly Bot")
+            .body(message.to_owned());
+
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(Error::Upstream)?;
+
+        Ok(())
+    }
+}
+
+/// Pushes alerts to a Gotify server with an application token.
+pub struct GotifySink {
+    pub url: String,
+    pub token: String,
+}
+
+#[async_trait]
+impl AlertSink for GotifySink {
+    fn name(&self) -> &'static str {
+        "gotify"
+    }
+
+    async fn send(&self, message: &str) -> Result<(), Error> {
+        super::HTTP_CLIENTS.alerts
+            .post(format!("{}/message", self.url.trim_end_matches('/')))
+            .header("X-Gotify-Key", &self.token)
+            .json(&serde_json::json!({
+                "title": "Firefly Bot",
+                "message": message,
+                "priority": 8,
+            }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(Error::Upstream)?;
+
+        Ok(())
+    }
+}
+
+/// Mails alerts through an SMTP relay that accepts them without
+/// authentication or TLS, such as a local Postfix or a mail sidecar.
+pub struct SmtpSink {
+    /// The relay as `host:port`.
+    pub relay: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+async fn smtp_expect(reader: &mut BufReader<TcpStream>, code: &str) -> Result<(), Error> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(Error::Config("The SMTP relay closed the connection".into()));
+        }
+
+        if !line.starts_with(code) {
+            return Err(Error::Config(format!("The SMTP relay replied {}", line.trim_end())));
+        }
+
+        // Multiline replies continue with a dash after the code.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+async fn smtp_command(reader: &mut BufReader<TcpStream>, command: &str, code: &str) -> Result<(), Error> {
+    reader.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await?;
+    smtp_expect(reader, code).await
+}
+
+#[async_trait]
+impl AlertSink for SmtpSink {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, message: &str) -> Result<(), Error> {
+        let stream = tokio::time::timeout(super::HTTP_CLIENTS.connect_timeout, TcpStream::connect(&self.relay))
+            .await
+            .map_err(|_| Error::Config(format!("Timed out connecting to the SMTP relay {}", self.relay)))??;
+        let mut reader = BufReader::new(stream);
+
+        smtp_expect(&mut reader, "220").await?;
+        smtp_command(&mut reader, "HELO firefly-tg", "250").await?;
+        smtp_command(&mut reader, &format!("MAIL FROM:<{}>", self.from), "250").await?;
+
+        for to in &self.to {
+            smtp_command(&mut reader, &format!("RCPT TO:<{}>", to), "250").await?;
+        }
+
+        smtp_command(&mut reader, "DATA", "354").await?;
+
+        // Lines starting with a dot are escaped so they don't end the message early.
+        let body = message
+            .lines()
+            .map(|line| if line.starts_with('.') { format!(".{}", line) } else { line.to_owned() })
+            .collect::<Vec<String>>()
+            .join("\r\n");
+
+        let data = format!(
+            "From: {}\r\nTo: {}\r\nSubject: Firefly Bot alert\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.",
+            self.from,
+            self.to.join(", "),
+            body,
+        );
+
+        smtp_command(&mut reader, &data, "250").await?;
+        smtp_command(&mut reader, "QUIT", "221").await?;
+
+        Ok(())
+    }
+}
+
+/// Builds the sinks configured with the `ALERT_*` variables, the master's
+/// Telegram chat is always one of them.
+pub fn sinks(vars: &Variables) -> Vec<Box<dyn AlertSink>> {
+    let mut sinks: Vec<Box<dyn AlertSink>> = vec![Box::new(TelegramSink)];
+
+    if let Some(url) = vars.text("ALERT_WEBHOOK_URL") {
+        sinks.push(Box::new(WebhookSink { url }));
+    }
+
+    if let Some(url) = vars.text("ALERT_NTFY_URL") {
+        sinks.push(Box::new(NtfySink { url, token: vars.text("ALERT_NTFY_TOKEN") }));
+    }
+
+    if let (Some(url), Some(token)) = (vars.text("ALERT_GOTIFY_URL"), vars.text("ALERT_GOTIFY_TOKEN")) {
+        sinks.push(Box::new(GotifySink { url, token }));
+    }
+
+    if let (Some(relay), Some(from), Some(to)) = (vars.text("ALERT_SMTP_RELAY"), vars.text("ALERT_SMTP_FROM"), vars.text("ALERT_SMTP_TO")) {
+        let to = to
+            .split(',')
+            .map(|address| address.trim().to_owned())
+            .filter(|address| !address.is_empty())
+            .collect();
+
+        sinks.push(Box::new(SmtpSink { relay, from, to }));
+    }
+
+    sinks
+}
+
+/// Delivers the alert to every sink, failures are logged.
+pub async fn send(message: &str) {
+    for sink in super::config().alert_sinks.iter() {
+        if let Err(e) = sink.send(message).await {
+            log::error!("Failed to send the alert to {}: {}", sink.name(), e);
+        }
+    }
+}
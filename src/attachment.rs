@@ -0,0 +1,114 @@
+use std::io::Cursor;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::GenericImageView;
+
+use crate::config::Variables;
+
+use super::Error;
+
+/// The lowest JPEG quality tried when an image is still too large.
+const MIN_QUALITY: u8 = 40;
+
+/// What Firefly III accepts as attachments, set to match the upload limit of
+/// the instances the bot is used with.
+pub struct AttachmentLimits {
+    /// Largest file uploaded, Firefly III's PHP default is 2 MiB.
+    pub max_bytes: usize,
+    /// Images are scaled down to fit a square of this size.
+    pub max_dimension: u32,
+    /// JPEG quality of scaled down images, lowered if they're still too large.
+    pub quality: u8,
+}
+
+impl AttachmentLimits {
+    /// Reads `ATTACHMENT_MAX_BYTES`, `ATTACHMENT_MAX_DIMENSION` and
+    /// `ATTACHMENT_JPEG_QUALITY`.
+    pub fn read(vars: &mut Variables) -> Self {
+        Self {
+            max_bytes: vars.number("ATTACHMENT_MAX_BYTES", 1, 2 * 1024 * 1024),
+            max_dimension: vars.number("ATTACHMENT_MAX_DIMENSION", 1, 1600),
+            quality: vars.number("ATTACHMENT_JPEG_QUALITY", 1, 80).min(100),
+        }
+    }
+}
+
+/// A file ready to be uploaded to Firefly III.
+pub struct Attachment {
+    pub filename: String,
+    pub bytes: Vec<u8>,
+    /// The size of the file as received, before any compression.
+    pub original_size: usize,
+}
+
+/// Tells the type of an image from its first bytes, only the types Firefly
+/// III accepts as attachments are recognized.
+fn mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else {
+        None
+    }
+}
+
+/// Checks the image can be attached, scaling it down and compressing it as
+/// JPEG when it's larger than the limits allow.
+pub fn prepare(name: &str, bytes: Vec<u8>, limits: &AttachmentLimits) -> Result<Attachment, Error> {
+    let original_size = bytes.len();
+    let mime_type = mime_type(&bytes)
+        .ok_or_else(|| Error::Parse("attachment_unsupported", serde_json::json!({})))?;
+
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| Error::Parse("attachment_unreadable", serde_json::json!({ "reason": e.to_string() })))?;
+    let (width, height) = image.dimensions();
+
+    if bytes.len() <= limits.max_bytes && width.max(height) <= limits.max_dimension {
+        let extension = if mime_type == "image/png" { "png" } else { "jpg" };
+        return Ok(Attachment { filename: format!("{}.{}", name, extension), bytes, original_size });
+    }
+
+    let image = if width.max(height) > limits.max_dimension {
+        image.resize(limits.max_dimension, limits.max_dimension, FilterType::Triangle)
+    } else {
+        image
+    };
+    // JPEG has no alpha channel.
+    let image = image.to_rgb8();
+
+    let mut quality = limits.quality;
+    loop {
+        let mut compressed = Cursor::new(Vec::new());
+        JpegEncoder::new_with_quality(&mut compressed, quality)
+            .encode_image(&image)
+            .map_err(|e| Error::Parse("attachment_not_compressed", serde_json::json!({ "reason": e.to_string() })))?;
+        let compressed = compressed.into_inner();
+
+        if compressed.len() <= limits.max_bytes {
+            return Ok(Attachment {
+                filename: format!("{}.jpg", name),
+                bytes: compressed,
+                original_size,
+            });
+        }
+
+        if quality <= MIN_QUALITY {
+            return Err(Error::Parse("attachment_too_large", serde_json::json!({
+                "size": describe_size(compressed.len()),
+                "limit": describe_size(limits.max_bytes),
+            })));
+        }
+
+        quality = quality.saturating_sub(15).max(MIN_QUALITY);
+    }
+}
+
+/// Formats a file size like `312 KB`.
+pub fn describe_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{} KB", bytes.div_ceil(1024))
+    }
+}
@@ -0,0 +1,143 @@
+use sled::{ConflictableTransactionError, ConflictableTransactionResult, TransactionError, Transactional, TransactionalTree};
+use sled_extensions::bincode::{BincodeEncoding, Tree};
+use sled_extensions::Encoding;
+
+use super::{Database, Error};
+
+/// Runs `write` in one transaction over however many trees the slice
+/// holds. sled only implements `Transactional` for tuples, so the slice is
+/// matched against every length up to the number of identifiers given,
+/// more than the database has trees.
+macro_rules! transaction {
+    ($trees:expr, $writes:expr; $only:ident) => {
+        match $trees {
+            [$only] => $only.transaction(|$only| write(&[$only], $writes)),
+            trees => return Err(Error::Config(format!("A write batch spans {} trees, more than the database has.", trees.len()))),
+        }
+    };
+    ($trees:expr, $writes:expr; $first:ident, $($rest:ident),+) => {
+        match $trees {
+            [$first, $($rest),+] => ($first, $($rest),+).transaction(|($first, $($rest),+)| write(&[$first, $($rest),+], $writes)),
+            trees => transaction!(trees, $writes; $($rest),+),
+        }
+    };
+}
+
+enum Write {
+    Insert(Vec<u8>),
+    Remove,
+    /// Removes a key that must still be there, aborting the whole batch otherwise.
+    Claim,
+}
+
+/// Inserts and removals spanning several trees, applied in a single sled
+/// transaction so a crash or a concurrent update never leaves only some of
+/// them written. Mutations touching more than one tree go through here.
+#[derive(Default)]
+pub struct WriteBatch {
+    trees: Vec<String>,
+    writes: Vec<(usize, Vec<u8>, Write)>,
+}
+
+impl WriteBatch {
+    fn tree_index<V>(&mut self, tree: &Tree<V>) -> usize
+    where
+        BincodeEncoding: Encoding<V>,
+    {
+        let name = tree.name();
+
+        match self.trees.iter().position(|t| *t == name) {
+            Some(index) => index,
+            None => {
+                self.trees.push(name);
+                self.trees.len() - 1
+            },
+        }
+    }
+
+    pub fn insert<V>(&mut self, tree: &Tree<V>, key: impl AsRef<[u8]>, value: &V) -> Result<(), Error>
+    where
+        BincodeEncoding: Encoding<V>,
+    {
+        let index = self.tree_index(tree);
+        let value = BincodeEncoding::encode(value)?;
+        self.writes.push((index, key.as_ref().to_vec(), Write::Insert(value)));
+
+        Ok(())
+    }
+
+    pub fn remove<V>(&mut self, tree: &Tree<V>, key: impl AsRef<[u8]>)
+    where
+        BincodeEncoding: Encoding<V>,
+    {
+        let index = self.tree_index(tree);
+        self.writes.push((index, key.as_ref().to_vec(), Write::Remove));
+    }
+
+    /// Removes the key, the batch is dropped unapplied if someone else
+    /// removed it first.
+    pub fn claim<V>(&mut self, tree: &Tree<V>, key: impl AsRef<[u8]>)
+    where
+        BincodeEncoding: Encoding<V>,
+    {
+        let index = self.tree_index(tree);
+        self.writes.push((index, key.as_ref().to_vec(), Write::Claim));
+    }
+
+    /// Removes every key of the tree starting with the prefix, as found when
+    /// the batch is built.
+    pub fn remove_prefix<V>(&mut self, tree: &Tree<V>, prefix: impl AsRef<[u8]>) -> Result<(), Error>
+    where
+        BincodeEncoding: Encoding<V>,
+    {
+        for key in tree.scan_prefix(prefix).keys() {
+            self.remove(tree, key?);
+        }
+
+        Ok(())
+    }
+
+    /// Writes everything at once, returns `false` if a claimed key was gone
+    /// and nothing was written.
+    pub fn apply(self, db: &Database) -> Result<bool, Error> {
+        let trees = self.trees
+            .iter()
+            .map(|name| db.store.open_tree(name))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sled_extensions::Error::from)?;
+
+        let writes = &self.writes;
+        let result = match trees.as_slice() {
+            [] => return Ok(true),
+            trees => transaction!(trees, writes; t63, t62, t61, t60, t59, t58, t57, t56, t55, t54, t53, t52, t51, t50, t49, t48, t47, t46, t45, t44, t43, t42, t41, t40, t39, t38, t37, t36, t35, t34, t33, t32, t31, t30, t29, t28, t27, t26, t25, t24, t23, t22, t21, t20, t19, t18, t17, t16, t15, t14, t13, t12, t11, t10, t9, t8, t7, t6, t5, t4, t3, t2, t1, t0),
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(TransactionError::Abort(())) => Ok(false),
+            Err(TransactionError::Storage(e)) => Err(sled_extensions::Error::from(e).into()),
+        }
+    }
+}
+
+fn write(trees: &[&TransactionalTree], writes: &[(usize, Vec<u8>, Write)]) -> ConflictableTransactionResult<()> {
+    for (index, key, write) in writes {
+        let tree = trees[*index];
+
+        match write {
+            Write::Insert(value) => {
+                tree.insert(key.as_slice(), value.as_slice())?;
+            },
+            Write::Remove => {
+                tree.remove(key.as_slice())?;
+            },
+            Write::Claim => {
+                if tree.remove(key.as_slice())?.is_none() {
+                    return Err(ConflictableTransactionError::Abort(()));
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
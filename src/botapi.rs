@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration, Instant};
+
+/// How many times a call rate limited by Telegram is made again.
+pub const MAX_RETRIES: u32 = 3;
+
+/// The longest `retry_after` a call waits out before being made again,
+/// longer ones are given up on so the update isn't held up for minutes.
+pub const MAX_RETRY_AFTER_SECS: u64 = 30;
+
+/// The chats tracked before the ones nothing is waiting on are dropped.
+const MAX_TRACKED_CHATS: usize = 10_000;
+
+/// The envelope of every Bot API response, the `result` is left to the
+/// caller.
+#[derive(Debug, Deserialize)]
+pub struct ApiResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub error_code: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseParameters {
+    /// Seconds to wait before calling again after being rate limited.
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    /// The new id of a group that was upgraded to a supergroup.
+    #[serde(default)]
+    pub migrate_to_chat_id: Option<i64>,
+}
+
+impl ApiResponse {
+    pub fn retry_after(&self) -> Option<u64> {
+        self.parameters.as_ref().and_then(|p| p.retry_after)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "{} {}",
+            self.error_code.map(|code| code.to_string()).unwrap_or_else(|| "?".into()),
+            self.description.as_deref().unwrap_or("no description"),
+        );
+
+        if let Some(chat_id) = self.parameters.as_ref().and_then(|p| p.migrate_to_chat_id) {
+            description.push_str(&format!(" (migrated to chat {})", chat_id));
+        }
+
+        description
+    }
+}
+
+/// The most calls the bot makes per second across all chats, Telegram
+/// allows about 30 messages a second.
+const GLOBAL_CALLS_PER_SEC: u32 = 30;
+
+/// The least time between two messages to the same chat.
+const CHAT_MESSAGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A chat action lasts 5 seconds, one sent again sooner is left out.
+const CHAT_ACTION_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Whether the call posts something new to the chat and counts towards the
+/// per-chat limit, e.g. `sendMessage` but not `sendChatAction`.
+fn is_message(endpoint: &str) -> bool {
+    endpoint.starts_with("send") && endpoint != "sendChatAction"
+}
+
+#[derive(Debug, Default)]
+struct ChatState {
+    /// Until when Telegram asked the bot to hold back after a 429.
+    blocked_until: Option<Instant>,
+    last_message: Option<Instant>,
+    last_chat_action: Option<Instant>,
+}
+
+/// Paces every call to the Bot API: one at a time per chat, at most one
+/// message a second to a chat and `GLOBAL_CALLS_PER_SEC` overall, holding
+/// a chat back while Telegram asked the bot to wait after a 429.
+pub struct SendQueue {
+    chats: Mutex<HashMap<i64, Arc<tokio::sync::Mutex<ChatState>>>>,
+    /// When the next call may be made, across all chats.
+    next_call: tokio::sync::Mutex<Instant>,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self {
+            chats: Mutex::default(),
+            next_call: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl SendQueue {
+    fn chat(&self, chat_id: i64) -> Arc<tokio::sync::Mutex<ChatState>> {
+        let mut chats = self.chats.lock().unwrap();
+
+        if chats.len() >= MAX_TRACKED_CHATS {
+            chats.retain(|_, chat| Arc::strong_count(chat) > 1);
+        }
+
+        chats.entry(chat_id).or_default().to_owned()
+    }
+
+    /// Waits for a free slot across all chats.
+    async fn pace(&self) {
+        let wait = {
+            let mut next_call = self.next_call.lock().await;
+            let now = Instant::now();
+            let at = (*next_call).max(now);
+            *next_call = at + Duration::from_secs(1) / GLOBAL_CALLS_PER_SEC;
+            at - now
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Waits for the turn of the call, `None` when it can be left out
+    /// because the same chat action was sent a moment ago. The turn of the
+    /// chat is held until the slot is dropped; calls without a chat only
+    /// wait for the overall pace.
+    pub async fn acquire(&self, endpoint: &str, chat_id: Option<i64>) -> Option<SendSlot> {
+        let chat = match chat_id {
+            Some(chat_id) => self.chat(chat_id),
+            None => {
+                self.pace().await;
+                return Some(SendSlot(None));
+            },
+        };
+        let mut state = chat.lock_owned().await;
+        let now = Instant::now();
+
+        if endpoint == "sendChatAction" {
+            if state.last_chat_action.is_some_and(|sent| now < sent + CHAT_ACTION_INTERVAL) {
+                return None;
+            }
+            state.last_chat_action = Some(now);
+        }
+
+        let mut ready_at = state.blocked_until.unwrap_or(now);
+        if is_message(endpoint) {
+            if let Some(sent) = state.last_message {
+                ready_at = ready_at.max(sent + CHAT_MESSAGE_INTERVAL);
+            }
+        }
+        if ready_at > now {
+            sleep(ready_at - now).await;
+        }
+
+        self.pace().await;
+
+        if is_message(endpoint) {
+            state.last_message = Some(Instant::now());
+        }
+
+        Some(SendSlot(Some(state)))
+    }
+}
+
+/// The turn of a call in the `SendQueue`.
+pub struct SendSlot(Option<tokio::sync::OwnedMutexGuard<ChatState>>);
+
+impl SendSlot {
+    /// Holds back the next calls to the chat for the seconds Telegram asked.
+    pub fn back_off(&mut self, secs: u64) {
+        if let Some(state) = self.0.as_mut() {
+            state.blocked_until = Some(Instant::now() + Duration::from_secs(secs));
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// How far back unbudgeted spending in a category is added up.
+const WINDOW_DAYS: i64 = 21;
+
+/// The number of unbudgeted expenses in a category before a budget is suggested.
+const MIN_EXPENSES: u32 = 3;
+
+/// How long to wait before suggesting a budget for the same category again.
+const COOLDOWN_DAYS: i64 = 30;
+
+/// Expenses logged in a category without a budget since `window_start`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CategorySpend {
+    window_start: i64,
+    total: f64,
+    count: u32,
+    currency_code: Option<String>,
+    suggested_at: Option<i64>,
+}
+
+/// A monthly budget offered to the user for a category they keep spending in.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BudgetSuggestion {
+    pub category: String,
+    pub spent: f64,
+    pub days: i64,
+    pub monthly_amount: f64,
+    pub currency_code: Option<String>,
+}
+
+impl BudgetSuggestion {
+    pub fn render(&self) -> String {
+        let currency = self.currency_code.as_deref().map(|c| format!("{} ", c)).unwrap_or_default();
+        let weeks = ((self.days as f64) / 7.0).ceil().max(1.0);
+
+        format!(
+            "You've spent {}{:.2} on {} in {} week(s) without a budget. Set a monthly budget of {}{:.0}?",
+            currency, self.spent, self.category, weeks, currency, self.monthly_amount,
+        )
+    }
+}
+
+/// Rounds a projected monthly spend up to a figure people would budget with.
+fn round_budget(amount: f64) -> f64 {
+    let step = if amount < 100.0 { 10.0 } else if amount < 1000.0 { 50.0 } else { 100.0 };
+    (amount / step).ceil() * step
+}
+
+/// Adds an expense logged without a budget to its category's tally, and
+/// returns a budget suggestion once the user keeps spending in it.
+pub fn record_expense(db: &Database, user_id: &str, category: &str, amount: f64, currency_code: Option<&str>) -> Result<Option<BudgetSuggestion>, Error> {
+    let key = format!("{}/{}", user_id, category.to_lowercase());
+    let now = Utc::now().timestamp();
+    let window_start = now - Duration::days(WINDOW_DAYS).num_seconds();
+
+    let previous = db.category_spend.get(key.as_bytes())?;
+    let suggested_at = previous.as_ref().and_then(|s| s.suggested_at);
+
+    let mut spend = match previous {
+        Some(spend) if spend.window_start >= window_start && spend.currency_code.as_deref() == currency_code => spend,
+        _ => CategorySpend {
+            window_start: now,
+            total: 0.0,
+            count: 0,
+            currency_code: currency_code.map(|c| c.to_owned()),
+            suggested_at,
+        },
+    };
+
+    spend.total += amount;
+    spend.count += 1;
+
+    let cooled_down = spend.suggested_at.is_none_or(|at| now - at >= Duration::days(COOLDOWN_DAYS).num_seconds());
+    let suggestion = if spend.count >= MIN_EXPENSES && cooled_down {
+        // Project the spend over a month, treating anything under a week as a week.
+        let days = ((now - spend.window_start) / (24 * 60 * 60)).max(7);
+        spend.suggested_at = Some(now);
+
+        Some(BudgetSuggestion {
+            category: category.to_owned(),
+            spent: spend.total,
+            days,
+            monthly_amount: round_budget(spend.total * 30.0 / days as f64),
+            currency_code: spend.currency_code.to_owned(),
+        })
+    } else {
+        None
+    };
+
+    db.category_spend.insert(key.as_bytes(), spend)?;
+
+    Ok(suggestion)
+}
+
+/// Drops every category tally of the user.
+pub fn forget(db: &Database, batch: &mut WriteBatch, user_id: &str) -> Result<(), Error> {
+    batch.remove_prefix(&db.category_spend, format!("{}/", user_id))
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use hyper::body::Bytes;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+
+use super::Error;
+
+/// The number of responses kept before the oldest ones are evicted.
+const MAX_ENTRIES: usize = 1024;
+
+struct CacheEntry {
+    body: Bytes,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: Instant,
+}
+
+/// A read-through cache for GET requests. Fresh entries are served without
+/// a round trip, stale ones are revalidated with `If-None-Match` or
+/// `If-Modified-Since` when the server provided validators.
+pub struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, key: String, mut request: RequestBuilder) -> Result<T, Error> {
+        let validators = {
+            let entries = self.entries.lock().unwrap();
+
+            match entries.get(&key) {
+                Some(entry) if entry.fetched_at.elapsed() < self.ttl => {
+                    return Ok(serde_json::from_slice(&entry.body)?);
+                },
+                Some(entry) => Some((entry.etag.to_owned(), entry.last_modified.to_owned())),
+                None => None,
+            }
+        };
+
+        if let Some((etag, last_modified)) = validators {
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+
+            if let Some(last_modified) = last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let resp = request.send().await.map_err(Error::Firefly)?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            let mut entries = self.entries.lock().unwrap();
+
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.fetched_at = Instant::now();
+                return Ok(serde_json::from_slice(&entry.body)?);
+            }
+        }
+
+        let resp = resp.error_for_status().map_err(Error::Firefly)?;
+        let header = |name| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_owned());
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = resp.bytes().await.map_err(Error::Firefly)?;
+        let value = serde_json::from_slice(&body)?;
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.fetched_at)
+                .map(|(key, _)| key.to_owned());
+
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, CacheEntry {
+            body,
+            etag,
+            last_modified,
+            fetched_at: Instant::now(),
+        });
+
+        Ok(value)
+    }
+
+    /// Drops the responses fetched longer than `age` ago, returns how many were removed.
+    pub fn purge_older_than(&self, age: Duration) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.fetched_at.elapsed() < age);
+
+        before - entries.len()
+    }
+
+    /// Drops every cached response whose key starts with the prefix.
+    pub fn invalidate_prefix(&self, prefix: &str) {
+        self.entries.lock().unwrap().retain(|key, _| !key.starts_with(prefix));
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// User-defined keyword to Firefly III category mappings.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CategoryMap {
+    mappings: BTreeMap<String, String>,
+}
+
+impl CategoryMap {
+    pub fn insert(&mut self, keyword: &str, category: &str) {
+        self.mappings.insert(keyword.trim().to_lowercase(), category.trim().to_owned());
+    }
+
+    pub fn remove(&mut self, keyword: &str) -> Option<String> {
+        self.mappings.remove(&keyword.trim().to_lowercase())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.mappings.iter()
+    }
+
+    /// Finds the category of the first keyword that appears in the text,
+    /// longer keywords take precedence over shorter ones.
+    pub fn find(&self, text: &str) -> Option<String> {
+        let text = text.to_lowercase();
+
+        self.mappings
+            .iter()
+            .filter(|(keyword, _)| text.contains(keyword.as_str()))
+            .max_by_key(|(keyword, _)| keyword.len())
+            .map(|(_, category)| category.to_owned())
+    }
+}
+
+/// Parses the `<keyword> -> <category>` syntax used by `/categories map`.
+pub fn parse_mapping(args: &str) -> Option<(&str, &str)> {
+    let mut parts = args.splitn(2, "->");
+    let keyword = parts.next()?.trim();
+    let category = parts.next()?.trim();
+
+    if keyword.is_empty() || category.is_empty() {
+        None
+    } else {
+        Some((keyword, category))
+    }
+}
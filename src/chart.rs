@@ -0,0 +1,147 @@
+use std::f64::consts::PI;
+use std::io::Cursor;
+use std::str::FromStr;
+use image::{ImageOutputFormat, RgbImage};
+use plotters::prelude::*;
+
+use super::Error;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 500;
+
+/// The colors of the slices, each with the emoji square standing for it in
+/// the legend. The chart has no text of its own, so no fonts are needed on
+/// the server.
+const COLORS: &[(RGBColor, &str)] = &[
+    (RGBColor(0xdd, 0x2e, 0x44), "🟥"),
+    (RGBColor(0xf4, 0x90, 0x0c), "🟧"),
+    (RGBColor(0xfd, 0xcb, 0x58), "🟨"),
+    (RGBColor(0x78, 0xb1, 0x59), "🟩"),
+    (RGBColor(0x55, 0xac, 0xee), "🟦"),
+    (RGBColor(0xaa, 0x8e, 0xd6), "🟪"),
+    (RGBColor(0xc1, 0x69, 0x4f), "🟫"),
+    (RGBColor(0x31, 0x37, 0x3d), "⬛"),
+];
+
+/// How the spending is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Bar,
+    Pie,
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "bar" => Ok(Self::Bar),
+            "pie" => Ok(Self::Pie),
+            _ => Err(Error::Parse("chart_style_invalid", serde_json::json!({ "style": value.trim() }))),
+        }
+    }
+}
+
+/// Keeps the largest amounts, folding the rest into "Other" so every slice
+/// gets a color of its own.
+pub fn top_slices(mut amounts: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    amounts.retain(|(_, amount)| *amount > 0.0);
+    amounts.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    if amounts.len() <= COLORS.len() {
+        return amounts;
+    }
+
+    let other = amounts.split_off(COLORS.len() - 1).iter().map(|(_, amount)| amount).sum();
+    amounts.push(("Other".to_owned(), other));
+    amounts
+}
+
+/// The caption explaining the colors, e.g. `🟥 Groceries: 230.00 (35%)`.
+pub fn legend(slices: &[(String, f64)], currency_code: Option<&str>) -> String {
+    let total = slices.iter().map(|(_, amount)| amount).sum::<f64>();
+
+    slices
+        .iter()
+        .zip(COLORS)
+        .map(|((name, amount), (_, square))| format!(
+            "{} {}: {:.2}{} ({:.0}%)",
+            square,
+            name,
+            amount,
+            currency_code.map(|c| format!(" {}", c)).unwrap_or_default(),
+            amount / total * 100.0,
+        ))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn draw_error<E: std::fmt::Debug>(e: E) -> Error {
+    Error::Chart(format!("{:?}", e))
+}
+
+fn draw_bars(area: &DrawingArea<BitMapBackend, plotters::coord::Shift>, slices: &[(String, f64)]) -> Result<(), Error> {
+    let max = slices.iter().map(|(_, amount)| *amount).fold(0.0, f64::max);
+    let margin = 40;
+    let slot = (WIDTH as i32 - 2 * margin) / slices.len() as i32;
+    let floor = HEIGHT as i32 - margin;
+
+    for (i, ((_, amount), (color, _))) in slices.iter().zip(COLORS).enumerate() {
+        let left = margin + i as i32 * slot + slot / 8;
+        let right = margin + (i as i32 + 1) * slot - slot / 8;
+        let top = floor - ((floor - margin) as f64 * amount / max) as i32;
+
+        area.draw(&Rectangle::new([(left, top), (right, floor)], color.filled())).map_err(draw_error)?;
+    }
+
+    area.draw(&PathElement::new(vec![(margin, floor), (WIDTH as i32 - margin, floor)], BLACK.stroke_width(2)))
+        .map_err(draw_error)
+}
+
+fn draw_pie(area: &DrawingArea<BitMapBackend, plotters::coord::Shift>, slices: &[(String, f64)]) -> Result<(), Error> {
+    let total = slices.iter().map(|(_, amount)| amount).sum::<f64>();
+    let center = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+    let radius = HEIGHT as f64 / 2.0 - 30.0;
+    let mut angle = -PI / 2.0;
+
+    for ((_, amount), (color, _)) in slices.iter().zip(COLORS) {
+        let sweep = 2.0 * PI * amount / total;
+        // One point per degree keeps the arc smooth.
+        let steps = ((sweep.to_degrees()).ceil() as usize).max(1);
+
+        let mut points = vec![(center.0 as i32, center.1 as i32)];
+        points.extend((0..=steps).map(|step| {
+            let a = angle + sweep * step as f64 / steps as f64;
+            ((center.0 + radius * a.cos()) as i32, (center.1 + radius * a.sin()) as i32)
+        }));
+
+        area.draw(&Polygon::new(points, color.filled())).map_err(draw_error)?;
+        angle += sweep;
+    }
+
+    Ok(())
+}
+
+/// Draws the slices as a PNG image.
+pub fn render(slices: &[(String, f64)], style: Style) -> Result<Vec<u8>, Error> {
+    let mut pixels = vec![0; (WIDTH * HEIGHT * 3) as usize];
+
+    {
+        let area = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        area.fill(&WHITE).map_err(draw_error)?;
+
+        match style {
+            Style::Bar => draw_bars(&area, slices)?,
+            Style::Pie => draw_pie(&area, slices)?,
+        }
+
+        area.present().map_err(draw_error)?;
+    }
+
+    let image = RgbImage::from_raw(WIDTH, HEIGHT, pixels)
+        .ok_or_else(|| Error::Chart("The chart doesn't fit its image.".into()))?;
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageOutputFormat::Png).map_err(draw_error)?;
+
+    Ok(png.into_inner())
+}
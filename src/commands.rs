@@ -0,0 +1,466 @@
+use super::Error;
+
+/// The commands the bot handles, see `TelegramContext::run_command`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Start,
+    Reset,
+    Help,
+    Test,
+    Currency,
+    Default,
+    Forget,
+    Categories,
+    Budgets,
+    Pending,
+    Report,
+    Chart,
+    Export,
+    ExportBot,
+    RunRules,
+    Accounts,
+    Snapshot,
+    Compare,
+    Forecast,
+    Project,
+    Batch,
+    Undo,
+    Again,
+    Find,
+    Scrub,
+    Link,
+    Bind,
+    Accessibility,
+    Language,
+    Confirm,
+    Timezone,
+    Settings,
+    Support,
+    Later,
+    Invite,
+    Admin,
+}
+
+/// Who sees a command in the command menu of Telegram clients.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Audience {
+    Everyone,
+    /// Only shown in the master's chat.
+    Master,
+    /// Works but is never shown.
+    Hidden,
+}
+
+pub struct Command {
+    pub kind: Kind,
+    /// The command without the slash.
+    pub name: &'static str,
+    pub audience: Audience,
+    /// Shown in the command menu.
+    pub description: &'static str,
+    /// The lines of `/help` about the command, if any.
+    pub help: &'static str,
+    /// Shown by `/help <command>`.
+    pub examples: &'static [&'static str],
+    /// Whether the command needs the account to be set up with `/start`.
+    pub requires_setup: bool,
+}
+
+/// Every command, in the order of the command menu and `/help`.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        kind: Kind::Start,
+        name: "start",
+        audience: Audience::Everyone,
+        description: "Set up your Firefly III account",
+        help: "",
+        examples: &["/start"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Help,
+        name: "help",
+        audience: Audience::Everyone,
+        description: "Show how to log transactions",
+        help: "",
+        examples: &["/help", "/help report"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Currency,
+        name: "currency",
+        audience: Audience::Everyone,
+        description: "View or change your default currency",
+        help: "Type /currency to view or change your default currency.",
+        examples: &["/currency", "/currency EUR"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Default,
+        name: "default",
+        audience: Audience::Everyone,
+        description: "Set the account used when a message doesn't name one",
+        help: "Type /default source <account> to pay from that asset account whenever a message doesn't say where the money came from, or /default source clear to stop.",
+        examples: &["/default source", "/default source Checking", "/default source clear"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Forget,
+        name: "forget",
+        audience: Audience::Everyone,
+        description: "Forget the account remembered for a merchant",
+        help: "Expenses are booked against the account last used for the merchant when a message doesn't name one. Type /forget to list the merchants remembered, or /forget <merchant> to forget one.",
+        examples: &["/forget", "/forget starbucks"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Categories,
+        name: "categories",
+        audience: Audience::Everyone,
+        description: "Manage your keyword to category mappings",
+        help: "Type /categories to manage your keyword to category mappings. Type /categories shortlist to pick the categories offered when a transaction has none.",
+        examples: &["/categories", "/categories map coffee -> Dining", "/categories shortlist"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Budgets,
+        name: "budgets",
+        audience: Audience::Everyone,
+        description: "List your budgets",
+        help: "Type /budgets to list your budgets.",
+        examples: &["/budgets"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Pending,
+        name: "pending",
+        audience: Audience::Everyone,
+        description: "Check the transactions waiting to be sent",
+        help: "Type /pending to check the transactions waiting to be sent.",
+        examples: &["/pending"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Report,
+        name: "report",
+        audience: Audience::Everyone,
+        description: "Summarize a month or set up a spending digest",
+        help: "Type /report [YYYY-MM] to get a summary of a month.\n\
+            Type /report daily|weekly [HH:MM] to get a spending digest in this chat, or /report off to stop it.",
+        examples: &["/report", "/report 2021-08", "/report weekly 08:00", "/report off"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Chart,
+        name: "chart",
+        audience: Audience::Everyone,
+        description: "Chart the spending of a month per category",
+        help: "Type /chart month [YYYY-MM] [bar|pie] to get a chart of the spending per category, the current month if none is given.",
+        examples: &["/chart month", "/chart month 2021-08 pie"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Export,
+        name: "export",
+        audience: Audience::Everyone,
+        description: "Download a month of transactions as CSV",
+        help: "Type /export [YYYY-MM] to get the transactions of a month as a CSV file, the current month if none is given.\n\
+            Type /export csv [YYYY-MM | YYYY-MM-DD..YYYY-MM-DD] to get only the transactions created by the bot, all of them if no period is given.",
+        examples: &["/export", "/export 2021-08", "/export csv", "/export csv 2021-08-01..2021-09-15"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::ExportBot,
+        name: "exportbot",
+        audience: Audience::Everyone,
+        description: "Download the transactions the bot created as JSON",
+        help: "Type /exportbot [YYYY-MM | YYYY-MM-DD..YYYY-MM-DD] to get the transactions created by the bot as JSON to import into another Firefly III instance, all of them if no period is given.",
+        examples: &["/exportbot", "/exportbot 2021-08"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::RunRules,
+        name: "runrules",
+        audience: Audience::Everyone,
+        description: "Run your Firefly III rules over a month",
+        help: "Type /runrules [account] [YYYY-MM] to run your Firefly III rules over a month.",
+        examples: &["/runrules", "/runrules Checking 2021-08"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Accounts,
+        name: "accounts",
+        audience: Audience::Everyone,
+        description: "List your asset accounts",
+        help: "Type /accounts to list your asset accounts, add --all to include archived ones.",
+        examples: &["/accounts", "/accounts --all"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Snapshot,
+        name: "snapshot",
+        audience: Audience::Everyone,
+        description: "Record your account balances",
+        help: "Type /snapshot to record your account balances and /compare to see what changed since.",
+        examples: &["/snapshot"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Compare,
+        name: "compare",
+        audience: Audience::Everyone,
+        description: "See what changed since your last snapshot",
+        help: "",
+        examples: &["/compare"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Forecast,
+        name: "forecast",
+        audience: Audience::Everyone,
+        description: "Project the balance of your main account",
+        help: "Type /forecast [days] to project the balance of your default source account, or of your largest asset account, using your bills, recurring transactions and average spending.",
+        examples: &["/forecast", "/forecast 60d"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Project,
+        name: "project",
+        audience: Audience::Everyone,
+        description: "Tag your transactions with a project",
+        help: "Type /project start \"<name>\" to tag your transactions with a project until /project stop, and /project report to see its total.",
+        examples: &["/project start \"Kitchen reno\"", "/project report", "/project stop"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Batch,
+        name: "batch",
+        audience: Audience::Everyone,
+        description: "Create several transactions at once",
+        help: "Type /batch followed by one transaction per line to create several at once.",
+        examples: &["/batch\ncoffee 3.50 from cash\nlunch 12 from checking"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Undo,
+        name: "undo",
+        audience: Audience::Everyone,
+        description: "Delete the last transaction you created",
+        help: "Type /undo to delete the last transaction you created.",
+        examples: &["/undo"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Scrub,
+        name: "scrub",
+        audience: Audience::Everyone,
+        description: "Purge what the bot kept of your recent messages",
+        help: "Type /scrub after deleting a message you didn't mean to send, e.g. a pasted access token, to purge what the bot kept of your recent messages. Transactions already in Firefly III are left as they are.",
+        examples: &["/scrub"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Find,
+        name: "find",
+        audience: Audience::Everyone,
+        description: "Search your transactions",
+        help: "Type /find followed by what to look for, e.g. /find coffee. Firefly III's search syntax works too, e.g. /find amount_more:50.",
+        examples: &["/find coffee", "/find amount_more:50"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Again,
+        name: "again",
+        audience: Audience::Everyone,
+        description: "Log your last transaction again for today",
+        help: "Type /again to log the last transaction you created once more, dated today. Add an amount to change it, e.g. /again 12.",
+        examples: &["/again", "/again 12"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Link,
+        name: "link",
+        audience: Audience::Everyone,
+        description: "Share your Firefly III account with someone",
+        help: "Type /link to let someone else log to your Firefly III account, they send me /link with the code you get.",
+        examples: &["/link", "/link <code>"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Bind,
+        name: "bind",
+        audience: Audience::Everyone,
+        description: "Use one of your profiles in this chat",
+        help: "Type /bind <profile> to log to a separate account in this chat, e.g. a business one in the office group, or /bind default to switch back.",
+        examples: &["/bind business", "/bind default"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Accessibility,
+        name: "accessibility",
+        audience: Audience::Everyone,
+        description: "Get plain text replies",
+        help: "Type /accessibility on to get plain text replies without formatting or emoji, e.g. for screen readers.",
+        examples: &["/accessibility on", "/accessibility off"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Language,
+        name: "language",
+        audience: Audience::Everyone,
+        description: "Pick the language of my replies",
+        help: "Type /language <code> to pick the language of my replies, or /language auto to follow your Telegram app.",
+        examples: &["/language", "/language de", "/language auto"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Confirm,
+        name: "confirm",
+        audience: Audience::Everyone,
+        description: "Choose whether repeated messages are held",
+        help: "Type /confirm repeats to be asked before a message sent twice in a row is logged again, /confirm off to log every message, or /confirm default to follow the bot's default.",
+        examples: &["/confirm", "/confirm off", "/confirm default"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Timezone,
+        name: "timezone",
+        audience: Audience::Everyone,
+        description: "Set your time zone",
+        help: "Type /timezone <Area/City> to date your transactions and send your digests by your own clock, or /timezone reset to go back to the bot's.",
+        examples: &["/timezone", "/timezone Europe/Berlin", "/timezone reset"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Settings,
+        name: "settings",
+        audience: Audience::Everyone,
+        description: "See and change your settings",
+        help: "Type /settings to see your settings. Type /settings rawdescriptions on to keep descriptions exactly as parsed, or off to have them tidied up. Type /settings coldstart on if your Firefly III instance takes a while to wake up.",
+        examples: &["/settings", "/settings rawdescriptions on", "/settings rawdescriptions off", "/settings coldstart on"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Support,
+        name: "support",
+        audience: Audience::Everyone,
+        description: "Send a report to the operator",
+        help: "Type /support to send a report of your recent messages and settings to the operator when something doesn't work.",
+        examples: &["/support"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Later,
+        name: "later",
+        audience: Audience::Everyone,
+        description: "Post a transaction later",
+        help: "Type /later <30m|2h|1d|tomorrow> <transaction> to post a transaction later, or reply /later 2h to one.",
+        examples: &["/later 2h coffee 3.50 from cash", "/later tomorrow rent 900 from checking"],
+        requires_setup: true,
+    },
+    Command {
+        kind: Kind::Reset,
+        name: "reset",
+        audience: Audience::Everyone,
+        description: "Forget your account and settings",
+        help: "",
+        examples: &["/reset"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Invite,
+        name: "invite",
+        audience: Audience::Master,
+        description: "Invite someone to use the bot",
+        help: "",
+        examples: &["/invite"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Admin,
+        name: "admin",
+        audience: Audience::Master,
+        description: "Operate the bot",
+        help: "",
+        examples: &["/admin", "/admin stats", "/admin templates", "/admin defaults", "/admin purge <user_id>"],
+        requires_setup: false,
+    },
+    Command {
+        kind: Kind::Test,
+        name: "test",
+        audience: Audience::Hidden,
+        description: "Check the bot is responding",
+        help: "",
+        examples: &["/test"],
+        requires_setup: false,
+    },
+];
+
+/// The commands not disabled with `DISABLED_COMMANDS`.
+pub fn enabled() -> impl Iterator<Item = &'static Command> {
+    COMMANDS.iter().filter(|c| !super::config().disabled_commands.contains(c.name))
+}
+
+/// Finds an enabled command by name, e.g. `report`.
+pub fn find(name: &str) -> Option<&'static Command> {
+    let name = name.to_lowercase();
+    enabled().find(|c| c.name == name)
+}
+
+/// The `/help` lines of the commands everyone can use.
+pub fn help_lines() -> Vec<&'static str> {
+    enabled()
+        .filter(|c| c.audience == Audience::Everyone && !c.help.is_empty())
+        .flat_map(|c| c.help.lines())
+        .collect()
+}
+
+/// The text of `/help <command>`.
+pub fn describe(command: &Command) -> String {
+    let mut text = format!("/{} - {}", command.name, command.description);
+
+    if !command.help.is_empty() {
+        text.push_str(&format!("\n\n{}", command.help));
+    }
+
+    if !command.examples.is_empty() {
+        text.push_str(&format!("\n\nExamples:\n{}", command.examples.join("\n")));
+    }
+
+    if command.requires_setup {
+        text.push_str("\n\nYour account needs to be set up with /start first.");
+    }
+
+    text
+}
+
+fn menu(audiences: &[Audience]) -> serde_json::Value {
+    enabled()
+        .filter(|c| audiences.contains(&c.audience))
+        .map(|c| serde_json::json!({ "command": c.name, "description": c.description }))
+        .collect()
+}
+
+/// Sets the command menu shown by Telegram clients, the master's chat also
+/// lists the operator commands.
+pub async fn register() -> Result<(), Error> {
+    let scopes = [
+        (serde_json::json!({ "type": "default" }), menu(&[Audience::Everyone])),
+        (
+            serde_json::json!({ "type": "chat", "chat_id": super::config().tg_master_id }),
+            menu(&[Audience::Everyone, Audience::Master]),
+        ),
+    ];
+
+    for (scope, commands) in scopes.iter() {
+        super::telegram_post("setMyCommands", &serde_json::json!({
+            "commands": commands,
+            "scope": scope,
+        }))
+        .await?
+        .error_for_status()
+        .map_err(Error::Telegram)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use serde::Deserialize;
+
+use crate::access::AccessMode;
+use crate::alert::{self, AlertSink};
+use crate::attachment::AttachmentLimits;
+use crate::dates::DayCutoff;
+use crate::format::ParseMode;
+use crate::http::HttpSettings;
+use crate::nlp::{self, NlpProvider};
+use crate::retention::RetentionPolicy;
+use crate::rules::RuleSet;
+use crate::secrets;
+use crate::settings;
+use crate::templates::Templates;
+
+use super::Error;
+
+/// The bot's settings, read once on startup from the environment and the
+/// file at `CONFIG_FILE`. Every problem is reported at once, so a deployment
+/// doesn't have to be restarted once per missing or mistyped variable.
+pub struct Config {
+    pub tg_bot_token: String,
+    pub tg_master_id: i64,
+    pub app_shared_storage_path: String,
+    pub wit_access_token: Option<String>,
+    pub config_file: Option<String>,
+    pub tg_bot_username: Option<String>,
+    pub group_transaction_tag: Option<String>,
+    pub nlp_provider: Box<dyn NlpProvider>,
+    pub firefly_cache_ttl_secs: u64,
+    pub account_cache_ttl_secs: i64,
+    pub wit_training_enabled: bool,
+    pub disabled_commands: HashSet<String>,
+    pub exchange_rate_api_url: String,
+    pub ocr_api_url: Option<String>,
+    pub ocr_api_key: Option<String>,
+    pub receipt_attachments: bool,
+    pub attachment_limits: AttachmentLimits,
+    pub translate_api_url: Option<String>,
+    pub translate_api_key: Option<String>,
+    pub leader_lease_path: Option<PathBuf>,
+    pub leader_lease_secs: i64,
+    pub instance_id: String,
+    pub selftest_firefly_url: Option<String>,
+    pub selftest_firefly_token: Option<String>,
+    pub access_mode: AccessMode,
+    pub allowed_user_ids: HashSet<i64>,
+    pub invite_ttl_hours: i64,
+    pub pending_action_ttl: chrono::Duration,
+    pub repeat_window_secs: i64,
+    pub merchant_memory_limit: usize,
+    pub readyz_check_telegram: bool,
+    /// The current master key first, followed by the previous one while rotating.
+    pub master_keys: Vec<[u8; 32]>,
+    pub rate_limit_burst: u32,
+    pub rate_limit_per_minute: u32,
+    pub wit_daily_quota: u32,
+    pub retention: RetentionPolicy,
+    pub app_fixture_dir: Option<String>,
+    pub app_debug_token: Option<String>,
+    pub alert_sinks: Vec<Box<dyn AlertSink>>,
+    pub app_rules: RuleSet,
+    pub day_cutoff: DayCutoff,
+    pub parse_mode: ParseMode,
+    pub terms_notice: Option<String>,
+    pub templates: Templates,
+    pub http: HttpSettings,
+    pub port: u16,
+    variables: HashMap<String, String>,
+}
+
+/// The variables the bot can't start without, checked by `Config::load`.
+#[derive(Deserialize)]
+struct RawConfig {
+    tg_bot_token: Option<String>,
+    tg_master_id: Option<String>,
+    app_shared_storage_path: Option<String>,
+    wit_access_token: Option<String>,
+}
+
+/// The variables as found, collecting what's wrong with them while the
+/// settings are read.
+pub struct Variables {
+    values: HashMap<String, String>,
+    problems: Vec<String>,
+}
+
+impl Variables {
+    /// The variable, unless it's unset or blank.
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.values.get(name).filter(|value| !value.trim().is_empty()).cloned()
+    }
+
+    /// Parses the variable, noting a problem if it isn't `expected`.
+    pub fn parse<T: FromStr>(&mut self, name: &str, expected: &str) -> Option<T> {
+        let value = self.text(name)?;
+
+        match value.trim().parse::<T>() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.problem(format!("{} must be {}, not {}.", name, expected, value.trim()));
+                None
+            },
+        }
+    }
+
+    /// A number of at least `min`, or `default` when the variable isn't set.
+    pub fn number<T: FromStr + PartialOrd + Display + Copy>(&mut self, name: &str, min: T, default: T) -> T {
+        match self.parse::<T>(name, &format!("a whole number of at least {}", min)) {
+            Some(number) if number >= min => number,
+            Some(number) => {
+                self.problem(format!("{} must be at least {}, not {}.", name, min, number));
+                default
+            },
+            None => default,
+        }
+    }
+
+    /// A switch written as `true`/`false` or `1`/`0`.
+    pub fn flag(&mut self, name: &str, default: bool) -> bool {
+        let value = match self.text(name) {
+            Some(value) => value,
+            None => return default,
+        };
+
+        match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => {
+                self.problem(format!("{} must be true or false, not {}.", name, value.trim()));
+                default
+            },
+        }
+    }
+
+    /// Keeps the value, or notes why it couldn't be read.
+    pub fn check<T>(&mut self, result: Result<T, Error>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(Error::Config(problem)) => {
+                self.problem(problem);
+                None
+            },
+            Err(e) => {
+                self.problem(e.to_string());
+                None
+            },
+        }
+    }
+
+    pub fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+}
+
+/// Reads the TOML file, keys are named like the environment variables
+/// (e.g. `TG_BOT_TOKEN = "..."`).
+fn read_file(path: &str) -> Result<HashMap<String, String>, Error> {
+    let table = toml::from_str::<toml::value::Table>(&fs::read_to_string(path)?)
+        .map_err(|e| Error::Config(format!("Invalid configuration file {}: {}", path, e)))?;
+
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => return Err(Error::Config(format!("{} in {} must be a string, number or boolean", key, path))),
+            };
+
+            Ok((key.to_uppercase(), value))
+        })
+        .collect()
+}
+
+/// Picks the NLP provider named by `NLP_PROVIDER`.
+fn nlp_provider(vars: &mut Variables) -> Box<dyn NlpProvider> {
+    match vars.text("NLP_PROVIDER").unwrap_or_else(|| "wit".into()).trim() {
+        "wit" => Box::new(nlp::WitProvider),
+        "local" => Box::new(nlp::LocalProvider),
+        "openai" => Box::new(nlp::OpenAiProvider {
+            url: vars.text("OPENAI_API_URL").unwrap_or_else(|| "https://api.openai.com/v1".into()),
+            api_key: vars.text("OPENAI_API_KEY"),
+            model: vars.text("OPENAI_MODEL").unwrap_or_else(|| "gpt-4o-mini".into()),
+        }),
+        other => {
+            vars.problem(format!("NLP_PROVIDER must be wit, local or openai, not {}.", other));
+            Box::new(nlp::WitProvider)
+        },
+    }
+}
+
+fn retention(vars: &mut Variables) -> RetentionPolicy {
+    let mut days = |name: &str, default: i64| {
+        let days = vars.number(name, 0, default);
+        Some(chrono::Duration::days(days)).filter(|_| days > 0)
+    };
+
+    RetentionPolicy {
+        history: days("RETENTION_HISTORY_DAYS", 90),
+        dead_letters: days("RETENTION_DEAD_LETTERS_DAYS", 14),
+        caches: days("RETENTION_CACHES_DAYS", 1),
+    }
+}
+
+impl Config {
+    /// Reads the configuration. The file at `CONFIG_FILE` may set any of the
+    /// variables, like `.env` the environment takes precedence over it.
+    pub fn load() -> Result<Self, Error> {
+        let config_file = env::var("CONFIG_FILE").ok();
+        let mut values = match &config_file {
+            Some(path) => read_file(path)?,
+            None => HashMap::new(),
+        };
+        values.extend(env::vars());
+
+        Self::from_variables(config_file, values)
+    }
+
+    fn from_variables(config_file: Option<String>, values: HashMap<String, String>) -> Result<Self, Error> {
+        let raw = envy::from_iter::<_, RawConfig>(values.clone())
+            .map_err(|e| Error::Config(format!("Invalid configuration: {}", e)))?;
+        let mut vars = Variables { values, problems: Vec::new() };
+
+        let mut required = |value: Option<String>, problem: &str| {
+            let value = value.filter(|v| !v.trim().is_empty());
+            if value.is_none() {
+                vars.problem(problem.to_owned());
+            }
+            value.unwrap_or_default()
+        };
+
+        let tg_bot_token = required(raw.tg_bot_token, "TG_BOT_TOKEN is not set, use the token BotFather gave you.");
+        let tg_master_id = required(raw.tg_master_id, "TG_MASTER_ID is not set, use your own Telegram user id.");
+        let app_shared_storage_path = required(raw.app_shared_storage_path, "APP_SHARED_STORAGE_PATH is not set, use a directory the bot can write to.");
+
+        let tg_master_id = match tg_master_id.trim().parse::<i64>() {
+            Ok(id) => id,
+            Err(_) if tg_master_id.is_empty() => 0,
+            Err(_) => {
+                vars.problem(format!("TG_MASTER_ID must be a Telegram user id, not {}.", tg_master_id));
+                0
+            },
+        };
+
+        let allowed_user_ids = vars.text("ALLOWED_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty())
+            .filter_map(|id| match id.parse::<i64>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    vars.problem(format!("ALLOWED_USER_IDS must be a list of Telegram user ids, {} isn't one.", id));
+                    None
+                },
+            })
+            .collect();
+
+        let mut master_keys = Vec::new();
+        for name in &["APP_MASTER_KEY", "APP_PREVIOUS_MASTER_KEY"] {
+            if let Some(value) = vars.text(name) {
+                match secrets::parse_master_key(&value) {
+                    Ok(key) => master_keys.push(key),
+                    Err(Error::Config(problem)) => vars.problem(format!("{}: {}", name, problem)),
+                    Err(e) => vars.problem(format!("{}: {}", name, e)),
+                }
+            }
+        }
+
+        let app_rules = match vars.text("APP_RULES_PATH") {
+            Some(path) => vars.check(RuleSet::load(&path)).unwrap_or_default(),
+            None => RuleSet::default(),
+        };
+
+        let terms_notice = vars.text("TERMS_NOTICE_PATH").and_then(|path| {
+            let notice = fs::read_to_string(&path).map_err(|e| Error::Config(format!("Failed to read the terms notice {}: {}", path, e)));
+            vars.check(notice)
+        });
+
+        let templates = Templates::load(vars.text("TEMPLATES_DIR"), vars.text("TEMPLATES_FILE"));
+        let templates = vars.check(templates);
+
+        let day_cutoff = DayCutoff::read(&mut vars);
+        let attachment_limits = AttachmentLimits::read(&mut vars);
+        let http = HttpSettings::read(&mut vars);
+        let alert_sinks = alert::sinks(&vars);
+        let nlp_provider = nlp_provider(&mut vars);
+        let retention = retention(&mut vars);
+        settings::check(&mut vars);
+
+        let access_mode = vars.text("ACCESS_MODE").map(|mode| mode.parse::<AccessMode>());
+        let access_mode = access_mode.and_then(|mode| vars.check(mode)).unwrap_or(AccessMode::Public);
+        let parse_mode = vars.text("MESSAGE_PARSE_MODE").map(|mode| mode.parse::<ParseMode>());
+        let parse_mode = parse_mode.and_then(|mode| vars.check(mode)).unwrap_or(ParseMode::MarkdownV2);
+
+        let config = Self {
+            tg_bot_token,
+            tg_master_id,
+            app_shared_storage_path,
+            wit_access_token: raw.wit_access_token.filter(|t| !t.trim().is_empty()),
+            config_file,
+            tg_bot_username: vars.text("TG_BOT_USERNAME").map(|name| name.trim().trim_start_matches('@').to_owned()),
+            group_transaction_tag: vars.text("GROUP_TRANSACTION_TAG"),
+            nlp_provider,
+            firefly_cache_ttl_secs: vars.number("FIREFLY_CACHE_TTL_SECS", 0, 60),
+            account_cache_ttl_secs: vars.number("ACCOUNT_CACHE_TTL_SECS", 0, 60 * 60),
+            wit_training_enabled: vars.flag("WIT_TRAINING_ENABLED", false),
+            disabled_commands: vars.text("DISABLED_COMMANDS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|c| c.trim().trim_start_matches('/').to_lowercase())
+                .filter(|c| !c.is_empty())
+                .collect(),
+            exchange_rate_api_url: vars.text("EXCHANGE_RATE_API_URL").unwrap_or_else(|| "https://api.frankfurter.app/latest".into()),
+            ocr_api_url: vars.text("OCR_API_URL"),
+            ocr_api_key: vars.text("OCR_API_KEY"),
+            receipt_attachments: vars.flag("RECEIPT_ATTACHMENTS", true),
+            attachment_limits,
+            translate_api_url: vars.text("TRANSLATE_API_URL"),
+            translate_api_key: vars.text("TRANSLATE_API_KEY"),
+            leader_lease_path: vars.text("LEADER_LEASE_PATH").map(PathBuf::from),
+            leader_lease_secs: vars.number("LEADER_LEASE_SECS", 1, 30),
+            instance_id: vars.text("INSTANCE_ID").unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            selftest_firefly_url: vars.text("SELFTEST_FIREFLY_URL"),
+            selftest_firefly_token: vars.text("SELFTEST_FIREFLY_TOKEN"),
+            access_mode,
+            allowed_user_ids,
+            invite_ttl_hours: vars.number("INVITE_TTL_HOURS", 1, 48),
+            pending_action_ttl: chrono::Duration::hours(vars.number("PENDING_ACTION_TTL_HOURS", 1, 24)),
+            repeat_window_secs: vars.number("REPEAT_WINDOW_SECS", 0, 30),
+            merchant_memory_limit: vars.number("MERCHANT_MEMORY_LIMIT", 0, 200),
+            readyz_check_telegram: vars.flag("READYZ_CHECK_TELEGRAM", false),
+            master_keys,
+            rate_limit_burst: vars.number("RATE_LIMIT_BURST", 0, 10),
+            rate_limit_per_minute: vars.number("RATE_LIMIT_PER_MINUTE", 0, 20),
+            wit_daily_quota: vars.number("WIT_DAILY_QUOTA", 0, 0),
+            retention,
+            app_fixture_dir: vars.text("APP_FIXTURE_DIR"),
+            app_debug_token: vars.text("APP_DEBUG_TOKEN"),
+            alert_sinks,
+            app_rules,
+            day_cutoff,
+            parse_mode,
+            terms_notice: terms_notice.map(|notice| notice.trim().to_owned()).filter(|notice| !notice.is_empty()),
+            templates: match templates {
+                Some(templates) => templates,
+                None => return Err(report(vars.problems)),
+            },
+            http,
+            port: vars.number("PORT", 1, 80),
+            variables: HashMap::new(),
+        };
+
+        if !vars.problems.is_empty() {
+            return Err(report(vars.problems));
+        }
+
+        Ok(Self { variables: vars.values, ..config })
+    }
+
+    /// The variable as set in the environment or the file, for settings
+    /// that are only shown or can be changed at runtime.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(|value| value.as_str()).filter(|value| !value.trim().is_empty())
+    }
+}
+
+fn report(problems: Vec<String>) -> Error {
+    let problems = problems.iter().map(|p| format!("  - {}", p)).collect::<Vec<String>>().join("\n");
+    Error::Config(format!("The bot is not configured correctly:\n{}", problems))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn reads_the_settings() {
+        let config = Config::from_variables(None, variables(&[
+            ("TG_BOT_TOKEN", "token"),
+            ("TG_MASTER_ID", "42"),
+            ("APP_SHARED_STORAGE_PATH", "/tmp"),
+            ("ACCESS_MODE", "invite-only"),
+            ("ALLOWED_USER_IDS", "1, 2"),
+            ("WIT_TRAINING_ENABLED", "true"),
+            ("DEFAULT_CURRENCY", "eur"),
+        ])).unwrap();
+
+        assert_eq!(config.tg_master_id, 42);
+        assert_eq!(config.access_mode, AccessMode::InviteOnly);
+        assert_eq!(config.allowed_user_ids, [1, 2].iter().cloned().collect());
+        assert!(config.wit_training_enabled);
+        assert_eq!(config.invite_ttl_hours, 48);
+        assert_eq!(config.var("DEFAULT_CURRENCY"), Some("eur"));
+    }
+
+    #[test]
+    fn lists_every_problem() {
+        let problems = match Config::from_variables(None, variables(&[
+            ("TG_MASTER_ID", "me"),
+            ("APP_SHARED_STORAGE_PATH", "/tmp"),
+            ("ACCESS_MODE", "friends"),
+            ("ALLOWED_USER_IDS", "1,two"),
+            ("APP_MASTER_KEY", "short"),
+            ("INVITE_TTL_HOURS", "0"),
+            ("MESSAGE_PARSE_MODE", "rtf"),
+            ("DAY_CUTOFF", "4am"),
+            ("DEFAULT_CURRENCY", "euros"),
+        ])) {
+            Err(Error::Config(problems)) => problems,
+            _ => panic!("The configuration should be rejected."),
+        };
+
+        for variable in &["TG_BOT_TOKEN", "TG_MASTER_ID", "access mode", "ALLOWED_USER_IDS", "APP_MASTER_KEY", "INVITE_TTL_HOURS", "parse mode", "DAY_CUTOFF", "DEFAULT_CURRENCY"] {
+            assert!(problems.contains(variable), "{} isn't reported in:\n{}", variable, problems);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use chrono::{NaiveDate, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::WriteBatch;
+use crate::currency;
+use crate::dates;
+use crate::wit::{Utterance, WitMessageResponse};
+
+use super::{Database, Error};
+
+lazy_static! {
+    static ref AMOUNT: Regex = Regex::new(r"\d+(?:[.,]\d{1,2})?").unwrap();
+    static ref NAMED_FIELD: Regex = Regex::new(
+        r"(?i)\b(category|budget|description|date)\s+(?:is\s+|to\s+|should\s+be\s+)?(.+)$"
+    ).unwrap();
+}
+
+/// A transaction created from a message, remembered under the bot's
+/// confirmation so replying to it can correct the transaction.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CreatedTransaction {
+    pub transaction_id: String,
+    pub created_at: i64,
+}
+
+fn key(user_id: &str, chat_id: i64, message_id: i64) -> String {
+    format!("{}/{}/{}", user_id, chat_id, message_id)
+}
+
+/// Remembers the transaction confirmed by the message, with the text it was
+/// parsed from when that is to be submitted along with a correction.
+pub fn remember(db: &Database, batch: &mut WriteBatch, user_id: &str, chat_id: i64, message_id: i64, transaction_id: &str, text: Option<&str>) -> Result<(), Error> {
+    let created = CreatedTransaction {
+        transaction_id: transaction_id.to_owned(),
+        created_at: Utc::now().timestamp(),
+    };
+
+    let key = key(user_id, chat_id, message_id);
+    batch.insert(&db.created, &key, &created)?;
+
+    match text {
+        Some(text) => batch.insert(&db.created_texts, &key, &text.to_owned()),
+        None => Ok(()),
+    }
+}
+
+/// The transaction confirmed by the message, if the bot sent one for the user.
+pub fn find(db: &Database, user_id: &str, chat_id: i64, message_id: i64) -> Result<Option<CreatedTransaction>, Error> {
+    Ok(db.created.get(key(user_id, chat_id, message_id).as_bytes())?)
+}
+
+/// The text the transaction confirmed by the message was parsed from, if it was kept.
+pub fn text(db: &Database, user_id: &str, chat_id: i64, message_id: i64) -> Result<Option<String>, Error> {
+    Ok(db.created_texts.get(key(user_id, chat_id, message_id).as_bytes())?)
+}
+
+/// Forgets the confirmations sent before `before`, returns how many were removed.
+pub fn purge_before(db: &Database, before: i64) -> Result<usize, Error> {
+    let stale = db.created
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, created)| created.created_at < before)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &stale {
+        db.created.remove(key)?;
+        db.created_texts.remove(key)?;
+    }
+
+    Ok(stale.len())
+}
+
+/// What a reply to a confirmation changes about the transaction.
+#[derive(Debug, Default, PartialEq)]
+pub struct Correction {
+    pub amount: Option<String>,
+    pub currency_code: Option<String>,
+    pub category_name: Option<String>,
+    pub budget_name: Option<String>,
+    pub description: Option<String>,
+    /// The day as `YYYY-MM-DD`.
+    pub date: Option<String>,
+}
+
+/// Parses replies like `actually 25`, `25 EUR`, `category Dining` or
+/// `description Lunch with Sam` or `date yesterday`, returns `None` if nothing was recognized.
+pub fn parse(text: &str, today: NaiveDate) -> Option<Correction> {
+    let mut correction = Correction::default();
+
+    match NAMED_FIELD.captures(text) {
+        Some(captures) => {
+            let value = captures[2].trim().trim_end_matches('.').to_owned();
+
+            match captures[1].to_lowercase().as_str() {
+                "category" => correction.category_name = Some(value),
+                "budget" => correction.budget_name = Some(value),
+                "date" => correction.date = dates::parse_day(&value, today).map(|d| d.format("%Y-%m-%d").to_string()),
+                _ => correction.description = Some(value),
+            }
+        },
+        None => {
+            correction.amount = AMOUNT.find(text).map(|m| m.as_str().replace(',', "."));
+            correction.currency_code = currency::detect_in_text(text);
+        },
+    }
+
+    if correction == Correction::default() {
+        None
+    } else {
+        Some(correction)
+    }
+}
+
+impl Correction {
+    /// The body of `PUT /api/v1/transactions/{id}` applying the correction.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut split = serde_json::Map::new();
+
+        let fields = [
+            ("amount", &self.amount),
+            ("currency_code", &self.currency_code),
+            ("category_name", &self.category_name),
+            ("budget_name", &self.budget_name),
+            ("description", &self.description),
+            ("date", &self.date),
+        ];
+
+        for (name, value) in fields.iter() {
+            if let Some(value) = value {
+                split.insert(name.to_string(), serde_json::Value::String(value.to_owned()));
+            }
+        }
+
+        serde_json::json!({
+            "apply_rules": false,
+            "transactions": [split],
+        })
+    }
+
+    /// The message as wit.ai read it, with the corrected values in place of
+    /// the ones it got wrong. `None` if it found no intent to train.
+    pub fn utterance(&self, response: &WitMessageResponse) -> Option<Utterance> {
+        let intent = response.intents.first()?;
+        let entities = &response.entities;
+        let corrected = |value: &Option<String>, original: Option<String>| value.to_owned().or(original);
+
+        let amount = corrected(&self.amount, entities.amount_of_money.first().map(|a| a.value.to_string()));
+        let deed = corrected(&self.description, entities.deed.as_ref().and_then(|d| d.first()).map(|d| d.value.to_owned()));
+        let category = corrected(&self.category_name, entities.category.as_ref().and_then(|c| c.first()).map(|c| c.value.to_owned()));
+        let budget = corrected(&self.budget_name, entities.budget.as_ref().and_then(|b| b.first()).map(|b| b.value.to_owned()));
+
+        let mut utterance = Utterance::new(&response.text, &intent.name);
+
+        let spans = [
+            ("wit$amount_of_money:amount_of_money", amount),
+            ("deed:deed", deed),
+            ("category:category", category),
+            ("budget:budget", budget),
+            ("account:origin", entities.origin.first().map(|a| a.value.to_owned())),
+            ("account:destination", entities.destination.first().map(|a| a.value.to_owned())),
+        ];
+
+        for (entity, body) in spans.iter() {
+            if let Some(body) = body {
+                utterance = utterance.with_entity(entity, body);
+            }
+        }
+
+        if let Some(flow) = response.traits.flow.first() {
+            utterance = utterance.with_trait("flow", &flow.value);
+        }
+
+        Some(utterance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response() -> WitMessageResponse {
+        serde_json::from_value(serde_json::json!({
+            "text": "Coffee 4.5 from Checking to Cafe as Shopping",
+            "intents": [{ "name": "transact", "confidence": 0.98 }],
+            "entities": {
+                "wit$amount_of_money:amount_of_money": [{ "role": "amount_of_money", "unit": "EUR", "value": 4.5 }],
+                "deed:deed": [{ "role": "deed", "value": "Coffee" }],
+                "category:category": [{ "role": "category", "value": "Shopping" }],
+                "account:origin": [{ "role": "origin", "value": "Checking" }],
+                "account:destination": [{ "role": "destination", "value": "Cafe" }],
+            },
+            "traits": { "flow": [{ "value": "withdrawal" }] },
+        }))
+        .unwrap()
+    }
+
+    fn bodies(utterance: &Utterance) -> Vec<(&str, &str)> {
+        utterance.entities.iter().map(|e| (e.entity.as_str(), e.body.as_str())).collect()
+    }
+
+    #[test]
+    fn trains_the_corrected_reading() {
+        let correction = Correction {
+            description: Some("Cafe".into()),
+            ..Default::default()
+        };
+
+        let utterance = correction.utterance(&response()).unwrap();
+
+        assert_eq!(utterance.intent, "transact");
+        assert_eq!(bodies(&utterance), vec![
+            ("wit$amount_of_money:amount_of_money", "4.5"),
+            ("deed:deed", "Cafe"),
+            ("category:category", "Shopping"),
+            ("account:origin", "Checking"),
+            ("account:destination", "Cafe"),
+        ]);
+        assert_eq!(utterance.traits[0].value, "withdrawal");
+    }
+
+    #[test]
+    fn drops_values_missing_from_the_text() {
+        let correction = Correction {
+            category_name: Some("Dining".into()),
+            ..Default::default()
+        };
+
+        let utterance = correction.utterance(&response()).unwrap();
+
+        assert!(!bodies(&utterance).iter().any(|(entity, _)| *entity == "category:category"));
+    }
+
+    #[test]
+    fn skips_messages_without_an_intent() {
+        let response = WitMessageResponse {
+            text: "hello".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(Correction::default().utterance(&response), None);
+    }
+}
@@ -0,0 +1,125 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Common currency symbols and the ISO 4217 code they map to.
+const CURRENCY_SYMBOLS: &[(&str, &str)] = &[
+    ("$", "USD"),
+    ("€", "EUR"),
+    ("£", "GBP"),
+    ("¥", "JPY"),
+    ("₱", "PHP"),
+    ("₹", "INR"),
+    ("₩", "KRW"),
+    ("₽", "RUB"),
+    ("₺", "TRY"),
+    ("₫", "VND"),
+    ("฿", "THB"),
+];
+
+/// ISO 4217 codes recognized next to an amount in a message, limited to
+/// common ones so words like `for` in `12 for lunch` are not mistaken for one.
+const KNOWN_CODES: &[&str] = &[
+    "USD", "EUR", "GBP", "JPY", "PHP", "INR", "KRW", "RUB", "TRY", "VND", "THB", "CHF", "CAD", "AUD",
+    "NZD", "SGD", "HKD", "CNY", "SEK", "NOK", "DKK", "PLN", "CZK", "HUF", "MXN", "BRL", "ZAR", "IDR", "MYR",
+];
+
+/// The active ISO 4217 codes, besides the testing and no-currency ones,
+/// sorted so they can be searched.
+const ISO_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN", "BHD",
+    "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF", "CHE", "CHF",
+    "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD",
+    "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD",
+    "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR",
+    "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA",
+    "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN", "NIO",
+    "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB",
+    "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL", "SOS", "SRD", "SSP", "STN", "SVC",
+    "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "USN",
+    "UYI", "UYU", "UYW", "UZS", "VED", "VES", "VND", "VUV", "WST", "XAF", "XAG", "XAU", "XBA", "XBB", "XBC",
+    "XBD", "XCD", "XCG", "XDR", "XOF", "XPD", "XPF", "XPT", "XSU", "XUA", "YER", "ZAR", "ZMW", "ZWG", "ZWL",
+];
+
+lazy_static! {
+    /// A currency symbol or code directly before or after a number, e.g.
+    /// `£12`, `12€` or `12 EUR`. Codes must be written in capitals, so words
+    /// like `try` in `try 2 cafes` aren't read as one.
+    static ref AMOUNT_CURRENCY: Regex = {
+        let units = CURRENCY_SYMBOLS
+            .iter()
+            .map(|(symbol, _)| regex::escape(symbol))
+            .chain(KNOWN_CODES.iter().map(|code| format!(r"\b{}\b", code)))
+            .collect::<Vec<String>>()
+            .join("|");
+
+        Regex::new(&format!(r"(?:({0})\s?\d|\d\s?({0}))", units)).unwrap()
+    };
+}
+
+/// Checks if the given value is an ISO 4217 currency code (e.g. `EUR`), in any case.
+pub fn is_currency_code(value: &str) -> bool {
+    ISO_CODES.binary_search(&value.to_uppercase().as_str()).is_ok()
+}
+
+/// Converts the unit detected by wit.ai (either a symbol or an ISO code)
+/// into a currency code Firefly III understands.
+pub fn currency_code_from_unit(unit: &str) -> Option<String> {
+    let unit = unit.trim();
+
+    if is_currency_code(unit) {
+        return Some(unit.to_uppercase());
+    }
+
+    CURRENCY_SYMBOLS
+        .iter()
+        .find(|(symbol, _)| *symbol == unit)
+        .map(|(_, code)| code.to_string())
+}
+
+/// Scans the original message for a currency written next to the amount,
+/// for when the NLP provider didn't pick one up.
+pub fn detect_in_text(text: &str) -> Option<String> {
+    let captures = AMOUNT_CURRENCY.captures(text)?;
+    let unit = captures.get(1).or_else(|| captures.get(2))?;
+
+    currency_code_from_unit(unit.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_currency_next_to_the_amount() {
+        assert_eq!(detect_in_text("Lunch £12 at the pub"), Some("GBP".into()));
+        assert_eq!(detect_in_text("Lunch 12€"), Some("EUR".into()));
+        assert_eq!(detect_in_text("Lunch 12 CAD"), Some("CAD".into()));
+        assert_eq!(detect_in_text("Lunch USD 12"), Some("USD".into()));
+    }
+
+    #[test]
+    fn ignores_lowercase_words() {
+        assert_eq!(detect_in_text("try 2 cafes downtown"), None);
+        assert_eq!(detect_in_text("Lunch 12 cad"), None);
+        assert_eq!(detect_in_text("Lunch 12 for two"), None);
+        assert_eq!(detect_in_text("Lunch 12"), None);
+    }
+
+    #[test]
+    fn converts_units() {
+        assert_eq!(currency_code_from_unit(" eur "), Some("EUR".into()));
+        assert_eq!(currency_code_from_unit("₱"), Some("PHP".into()));
+        assert_eq!(currency_code_from_unit("euros"), None);
+        assert_eq!(currency_code_from_unit("abc"), None);
+    }
+
+    #[test]
+    fn knows_iso_codes_only() {
+        assert!(is_currency_code("usd"));
+        assert!(is_currency_code("XAU"));
+        assert!(!is_currency_code("ABC"));
+        assert!(!is_currency_code("for"));
+        assert!(ISO_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(KNOWN_CODES.iter().all(|code| is_currency_code(code)));
+    }
+}
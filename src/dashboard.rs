@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use chrono::{TimeZone, Utc};
+
+use crate::health::Check;
+use crate::stats;
+
+use super::Database;
+
+/// How many of the latest errors the dashboard shows.
+const RECENT_ERRORS_CAPACITY: usize = 20;
+
+/// The latest errors caused by the bot or its upstreams, kept in memory
+/// only so the dashboard can show what went wrong since the last restart.
+#[derive(Default)]
+pub struct RecentErrors {
+    entries: Mutex<VecDeque<(i64, String)>>,
+}
+
+impl RecentErrors {
+    pub fn push(&self, message: &str) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= RECENT_ERRORS_CAPACITY {
+            entries.pop_front();
+        }
+
+        entries.push_back((Utc::now().timestamp(), message.to_owned()));
+    }
+
+    /// The errors, most recent first.
+    pub fn list(&self) -> Vec<(i64, String)> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn table(rows: &[(&str, String)]) -> String {
+    let rows = rows
+        .iter()
+        .map(|(name, value)| format!("<tr><th>{}</th><td>{}</td></tr>", escape(name), escape(value)))
+        .collect::<String>();
+
+    format!("<table>{}</table>", rows)
+}
+
+/// Renders the page served by `GET /dashboard`, it refreshes itself every
+/// 30 seconds.
+pub fn render(db: &Database, checks: &[Check]) -> String {
+    let ready = db.users
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, user)| user.is_ready())
+        .count();
+
+    let abandoned = db.outbox
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, entry)| entry.is_abandoned())
+        .count();
+
+    let metrics = table(&[
+        ("Transactions created", stats::get(db, stats::TRANSACTIONS_CREATED).to_string()),
+        ("Errors", stats::get(db, stats::ERRORS).to_string()),
+        ("User errors", stats::get(db, stats::USER_ERRORS).to_string()),
+        ("Updates seen", db.updates.len().to_string()),
+        ("Cached Firefly III responses", super::FIREFLY_CACHE.len().to_string()),
+    ]);
+
+    let queues = table(&[
+        ("Outbox", db.outbox.len().to_string()),
+        ("Outbox abandoned", abandoned.to_string()),
+        ("Scheduled", db.scheduled.len().to_string()),
+        ("Pending actions", db.pending.len().to_string()),
+        ("Digests", db.digests.len().to_string()),
+    ]);
+
+    let users = table(&[
+        ("Users", db.users.len().to_string()),
+        ("Set up", ready.to_string()),
+        ("Access grants", db.access.len().to_string()),
+        ("Open invites", db.invites.len().to_string()),
+    ]);
+
+    let health = checks
+        .iter()
+        .map(|c| {
+            let (class, status) = match &c.error {
+                None => ("ok", "OK".to_owned()),
+                Some(e) => ("failed", e.to_owned()),
+            };
+
+            format!("<tr><th>{}</th><td class=\"{}\">{}</td></tr>", c.name, class, escape(&status))
+        })
+        .collect::<String>();
+
+    let errors = super::RECENT_ERRORS.list();
+    let errors = if errors.is_empty() {
+        "<p>No errors since the last restart.</p>".to_owned()
+    } else {
+        let rows = errors
+            .iter()
+            .map(|(at, message)| format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                Utc.timestamp(*at, 0).format("%Y-%m-%d %H:%M:%S UTC"),
+                escape(message),
+            ))
+            .collect::<String>();
+
+        format!("<table>{}</table>", rows)
+    };
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"30\">\n\
+        <title>Firefly telegram bot</title>\n\
+        <style>body {{ font-family: sans-serif; margin: 2em; }} table {{ border-collapse: collapse; margin-bottom: 1.5em; }} \
+        th, td {{ text-align: left; padding: 0.3em 1em; border-bottom: 1px solid #ddd; }} .ok {{ color: #2a7; }} .failed {{ color: #c33; }}</style>\n\
+        </head>\n<body>\n<h1>Firefly telegram bot v{}</h1>\n\
+        <h2>Dependencies</h2>\n<table>{}</table>\n<h2>Metrics</h2>\n{}\n<h2>Queues</h2>\n{}\n<h2>Users</h2>\n{}\n<h2>Recent errors</h2>\n{}\n\
+        </body>\n</html>\n",
+        super::VERSION, health, metrics, queues, users, errors,
+    )
+}
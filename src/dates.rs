@@ -0,0 +1,159 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+use crate::config::Variables;
+
+/// Which day a transaction sent without a date is booked on. Messages sent
+/// before the cutoff, e.g. dinner logged at 00:30, count as the day before.
+pub struct DayCutoff {
+    offset: FixedOffset,
+    cutoff: Option<NaiveTime>,
+}
+
+/// Parses offsets like `+08:00` or `-05:30`.
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    let (sign, rest) = match value.chars().next()? {
+        '+' => (1, &value[1..]),
+        '-' => (-1, &value[1..]),
+        _ => (1, value),
+    };
+
+    let mut parts = rest.splitn(2, ':');
+    let hours = parts.next()?.parse::<i32>().ok()?;
+    let minutes = parts.next().map(|m| m.parse::<i32>().ok()).unwrap_or(Some(0))?;
+
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl DayCutoff {
+    /// Reads `DAY_CUTOFF` (e.g. `04:00`, unset to always book on the day the
+    /// message was sent) and `LOCAL_UTC_OFFSET` (e.g. `+08:00`, defaults to UTC).
+    pub fn read(vars: &mut Variables) -> Self {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let offset = match vars.text("LOCAL_UTC_OFFSET") {
+            Some(value) => parse_offset(&value).unwrap_or_else(|| {
+                vars.problem(format!("Invalid LOCAL_UTC_OFFSET {}, expected e.g. +08:00.", value.trim()));
+                utc
+            }),
+            None => utc,
+        };
+
+        let cutoff = vars.text("DAY_CUTOFF").and_then(|value| {
+            let cutoff = NaiveTime::parse_from_str(value.trim(), "%H:%M").ok();
+            if cutoff.is_none() {
+                vars.problem(format!("Invalid DAY_CUTOFF {}, expected e.g. 04:00.", value.trim()));
+            }
+            cutoff
+        });
+
+        Self { offset, cutoff }
+    }
+
+    /// The time at `now` in the user's time zone, or the operator's if the
+    /// user didn't set one with `/timezone`.
+    fn local(&self, now: DateTime<Utc>, timezone: Option<Tz>) -> NaiveDateTime {
+        match timezone {
+            Some(timezone) => now.with_timezone(&timezone).naive_local(),
+            None => now.with_timezone(&self.offset).naive_local(),
+        }
+    }
+
+    /// Today for the user.
+    pub fn today(&self, timezone: Option<Tz>) -> NaiveDate {
+        self.local(Utc::now(), timezone).date()
+    }
+
+    /// The day a transaction sent at `now` without a date is booked on.
+    pub fn booking_date(&self, now: DateTime<Utc>, timezone: Option<Tz>) -> NaiveDate {
+        let local = self.local(now, timezone);
+
+        match self.cutoff {
+            Some(cutoff) if local.time() < cutoff => local.date().pred(),
+            _ => local.date(),
+        }
+    }
+
+    pub fn describe_cutoff(&self) -> String {
+        self.cutoff.map(|c| c.format("%H:%M").to_string()).unwrap_or_else(|| "(not set)".into())
+    }
+
+    pub fn describe_offset(&self) -> String {
+        self.offset.to_string()
+    }
+}
+
+/// Reads the day of a `date yesterday` correction, `today`, `yesterday` or
+/// `YYYY-MM-DD`.
+pub fn parse_day(value: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match value.trim().to_lowercase().as_str() {
+        "today" => Some(today),
+        "yesterday" => Some(today.pred()),
+        value => NaiveDate::parse_from_str(value, "%Y-%m-%d").ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn cutoff(offset: &str, cutoff: Option<&str>) -> DayCutoff {
+        DayCutoff {
+            offset: parse_offset(offset).unwrap(),
+            cutoff: cutoff.map(|c| NaiveTime::parse_from_str(c, "%H:%M").unwrap()),
+        }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.ymd(year, month, day).and_hms(hour, minute, second)
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn books_before_the_cutoff_on_the_day_before() {
+        let day = cutoff("+08:00", Some("04:00"));
+
+        // 03:59:59 and 04:00 on the 11th at +08:00.
+        assert_eq!(day.booking_date(utc(2024, 3, 10, 19, 59, 59), None), date(2024, 3, 10));
+        assert_eq!(day.booking_date(utc(2024, 3, 10, 20, 0, 0), None), date(2024, 3, 11));
+        // Just after midnight, and later in the day.
+        assert_eq!(day.booking_date(utc(2024, 3, 10, 16, 30, 0), None), date(2024, 3, 10));
+        assert_eq!(day.booking_date(utc(2024, 3, 11, 1, 0, 0), None), date(2024, 3, 11));
+    }
+
+    #[test]
+    fn books_on_the_day_sent_without_a_cutoff() {
+        let day = cutoff("+08:00", None);
+
+        assert_eq!(day.booking_date(utc(2024, 3, 10, 16, 30, 0), None), date(2024, 3, 11));
+        assert_eq!(day.booking_date(utc(2024, 3, 10, 15, 59, 0), None), date(2024, 3, 10));
+    }
+
+    #[test]
+    fn uses_the_users_time_zone() {
+        let day = cutoff("+08:00", Some("04:00"));
+        let new_york = Some("America/New_York".parse::<Tz>().unwrap());
+
+        // 03:30 and 04:00 in New York, on daylight saving time since the 10th.
+        assert_eq!(day.booking_date(utc(2024, 3, 11, 7, 30, 0), new_york), date(2024, 3, 10));
+        assert_eq!(day.booking_date(utc(2024, 3, 11, 8, 0, 0), new_york), date(2024, 3, 11));
+    }
+
+    #[test]
+    fn reads_offsets() {
+        assert_eq!(parse_offset("+08:00"), FixedOffset::east_opt(8 * 3600));
+        assert_eq!(parse_offset("-05:30"), FixedOffset::east_opt(-(5 * 3600 + 30 * 60)));
+        assert_eq!(parse_offset("2"), FixedOffset::east_opt(2 * 3600));
+        assert_eq!(parse_offset("+15:00"), None);
+        assert_eq!(parse_offset("UTC"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::telegram::Update;
+
+use super::{Database, Error};
+
+/// Telegram gives up re-delivering an update well within this window, and
+/// only restarts update ids after a week of inactivity.
+const DEDUP_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// The last update processed for a chat.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct LastUpdate {
+    update_id: i64,
+    received_at: i64,
+}
+
+impl LastUpdate {
+    fn is_repeated_by(&self, current: &LastUpdate) -> bool {
+        self.update_id >= current.update_id && current.received_at - self.received_at < DEDUP_WINDOW_SECS
+    }
+}
+
+/// Records the update as processed, returning whether it had already been
+/// seen for its chat (i.e. it is a re-delivery by Telegram).
+pub fn check_and_record(db: &Database, update: &Update) -> Result<bool, Error> {
+    let chat_id = match update.chat_id() {
+        Some(chat_id) => chat_id,
+        None => return Ok(false),
+    };
+
+    let current = LastUpdate {
+        update_id: update.update_id,
+        received_at: Utc::now().timestamp(),
+    };
+
+    let previous = db.updates.fetch_and_update(chat_id.to_be_bytes(), |last| match last {
+        Some(last) if last.is_repeated_by(&current) => Some(last),
+        _ => Some(current),
+    })?;
+
+    Ok(previous.is_some_and(|last| last.is_repeated_by(&current)))
+}
+
+/// The last text a user sent in a chat, only its digest is kept.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LastText {
+    digest: Vec<u8>,
+    received_at: i64,
+}
+
+impl LastText {
+    fn is_repeated_by(&self, current: &LastText) -> bool {
+        self.digest == current.digest && current.received_at - self.received_at < super::config().repeat_window_secs
+    }
+}
+
+fn text_key(user_id: &str, chat_id: i64) -> String {
+    format!("{}/{}", user_id, chat_id)
+}
+
+/// Records the text as the last one of the user in the chat, returning
+/// whether it's the same as the one sent just before (e.g. a double-tapped
+/// send button), unlike re-deliveries these come with a new update id.
+pub fn is_repeated_text(db: &Database, user_id: &str, chat_id: i64, text: &str) -> Result<bool, Error> {
+    if super::config().repeat_window_secs <= 0 {
+        return Ok(false);
+    }
+
+    let current = LastText {
+        digest: Sha256::digest(text.trim().as_bytes()).to_vec(),
+        received_at: Utc::now().timestamp(),
+    };
+
+    let previous = db.last_texts.insert(text_key(user_id, chat_id).as_bytes(), current.clone())?;
+
+    Ok(previous.is_some_and(|last| last.is_repeated_by(&current)))
+}
+
+/// Forgets the last text of the user in the chat, so it can be sent again
+/// once the user confirmed the repeat.
+pub fn forget_text(db: &Database, user_id: &str, chat_id: i64) -> Result<(), Error> {
+    db.last_texts.remove(text_key(user_id, chat_id).as_bytes())?;
+    Ok(())
+}
+
+/// Drops the last updates and texts of chats that have been quiet since
+/// `before`, returns how many were removed.
+pub fn purge_before(db: &Database, before: i64) -> Result<usize, Error> {
+    let stale = db.updates
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, last)| last.received_at < before)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &stale {
+        db.updates.remove(key)?;
+    }
+
+    let stale_texts = db.last_texts
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, last)| last.received_at < before)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &stale_texts {
+        db.last_texts.remove(key)?;
+    }
+
+    Ok(stale.len() + stale_texts.len())
+}
@@ -0,0 +1,114 @@
+use regex::Regex;
+
+use crate::currency;
+
+/// Words left dangling at the end once the amount and accounts are taken
+/// out, e.g. the "for" of "lunch for 12.50".
+const TRAILING_CONNECTORS: &[&str] = &["for", "of", "at", "with", "from", "to", "into", "using", "via", "on", "paid"];
+
+/// Words kept lower case inside a title, e.g. "Dinner at the Port".
+const MINOR_WORDS: &[&str] = &["a", "an", "and", "at", "by", "for", "in", "of", "on", "or", "the", "to", "with"];
+
+/// Whether the word is the amount, written like `12.50`, `12,50` or `$12.50`.
+fn is_amount(word: &str, amount: f64) -> bool {
+    // A leading currency symbol and trailing punctuation, but not letters
+    // as in "7-eleven".
+    let number = word
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .trim_end_matches(|c: char| c.is_ascii_punctuation())
+        .replace(',', ".");
+
+    number.parse::<f64>().is_ok_and(|n| (n - amount).abs() < 0.005)
+}
+
+/// Drops phrases naming the account, e.g. "from checking" or "to my savings".
+fn strip_account(description: &str, account: &str) -> String {
+    if account.trim().is_empty() {
+        return description.to_owned();
+    }
+
+    let pattern = format!(r"(?i)\b(?:from|to|into|using|via|with|on)\s+(?:my\s+|the\s+)?{}\b", regex::escape(account.trim()));
+    match Regex::new(&pattern) {
+        Ok(phrase) => phrase.replace_all(description, "").into_owned(),
+        Err(_) => description.to_owned(),
+    }
+}
+
+fn title_case(description: &str) -> String {
+    description
+        .split(' ')
+        .enumerate()
+        .map(|(i, word)| {
+            let lower = word.to_lowercase();
+            if i > 0 && MINOR_WORDS.contains(&lower.as_str()) {
+                return lower;
+            }
+
+            let mut chars = lower.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => lower,
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Tidies the description picked out of the message before it becomes the
+/// Firefly III description: the echoed amount and account phrases and the
+/// trailing punctuation are dropped, and text written all in one case is
+/// title-cased. Descriptions with deliberate casing (e.g. "iPhone case")
+/// keep it. Falls back on the original if nothing would be left.
+pub fn clean(description: &str, amount: Option<f64>, currency_code: Option<&str>, source: Option<&str>, destination: Option<&str>) -> String {
+    let mut cleaned = description.to_owned();
+    for account in source.into_iter().chain(destination) {
+        cleaned = strip_account(&cleaned, account);
+    }
+
+    let mut words = Vec::new();
+    let mut after_amount = false;
+    for word in cleaned.split_whitespace() {
+        if amount.is_some_and(|amount| is_amount(word, amount)) {
+            after_amount = true;
+            continue;
+        }
+
+        // The currency of the amount, e.g. the "EUR" of "12.50 EUR".
+        let unit = word.trim_end_matches(|c: char| c.is_ascii_punctuation());
+        if after_amount && currency::currency_code_from_unit(unit).is_some_and(|code| Some(code.as_str()) == currency_code) {
+            after_amount = false;
+            continue;
+        }
+
+        after_amount = false;
+        words.push(word);
+    }
+
+    while let Some(last) = words.last() {
+        let word = last.trim_end_matches(|c: char| c.is_ascii_punctuation()).to_lowercase();
+        if TRAILING_CONNECTORS.contains(&word.as_str()) {
+            words.pop();
+        } else {
+            break;
+        }
+    }
+
+    let cleaned = words
+        .join(" ")
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() && c != ')' && c != '"')
+        .trim()
+        .to_owned();
+
+    if cleaned.is_empty() {
+        return description.trim().to_owned();
+    }
+
+    let letters = cleaned.chars().filter(|c| c.is_alphabetic());
+    let single_case = letters.clone().all(|c| c.is_lowercase()) || letters.clone().all(|c| c.is_uppercase());
+
+    if single_case {
+        title_case(&cleaned)
+    } else {
+        cleaned
+    }
+}
@@ -0,0 +1,160 @@
+use log::info;
+
+use crate::config::Config;
+use crate::retention;
+
+use super::Database;
+
+/// Hides all but the first few characters of a secret so operators can
+/// still tell which value is configured.
+pub fn mask(secret: &str) -> String {
+    if secret.chars().count() <= 8 {
+        "********".into()
+    } else {
+        format!("{}********", secret.chars().take(4).collect::<String>())
+    }
+}
+
+fn mask_optional(secret: &Option<String>) -> String {
+    secret.as_deref().map(mask).unwrap_or_else(|| "(not set)".into())
+}
+
+/// The effective configuration with secrets masked.
+pub fn settings() -> Vec<(&'static str, String)> {
+    let config = super::config();
+    let disabled_commands = {
+        let mut commands = super::config().disabled_commands.iter().cloned().collect::<Vec<String>>();
+        commands.sort();
+        commands.join(", ")
+    };
+
+    vec![
+        ("CONFIG_FILE", config.config_file.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("TG_BOT_TOKEN", mask(&config.tg_bot_token)),
+        ("TG_MASTER_ID", config.tg_master_id.to_string()),
+        ("TG_BOT_USERNAME", super::config().tg_bot_username.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("GROUP_TRANSACTION_TAG", super::config().group_transaction_tag.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("ACCESS_MODE", super::config().access_mode.to_string()),
+        ("ALLOWED_USER_IDS", format!("{} user(s)", super::config().allowed_user_ids.len())),
+        ("INVITE_TTL_HOURS", super::config().invite_ttl_hours.to_string()),
+        ("PENDING_ACTION_TTL_HOURS", super::config().pending_action_ttl.num_hours().to_string()),
+        ("NLP_PROVIDER", super::config().nlp_provider.name().to_owned()),
+        ("WIT_ACCESS_TOKEN", mask_optional(&config.wit_access_token)),
+        ("WIT_DAILY_QUOTA", super::config().wit_daily_quota.to_string()),
+        ("WIT_TRAINING_ENABLED", super::config().wit_training_enabled.to_string()),
+        ("APP_SHARED_STORAGE_PATH", config.app_shared_storage_path.to_owned()),
+        ("APP_RULES", format!("{} rule(s)", super::config().app_rules.len())),
+        ("DAY_CUTOFF", super::config().day_cutoff.describe_cutoff()),
+        ("LOCAL_UTC_OFFSET", super::config().day_cutoff.describe_offset()),
+        ("REPEAT_WINDOW_SECS", super::config().repeat_window_secs.to_string()),
+        ("MERCHANT_MEMORY_LIMIT", super::config().merchant_memory_limit.to_string()),
+        ("MESSAGE_PARSE_MODE", super::config().parse_mode.name().to_owned()),
+        ("TEMPLATES_DIR", format!("{} override(s)", super::config().templates.overrides())),
+        ("TERMS_NOTICE_PATH", config.var("TERMS_NOTICE_PATH").map(str::to_owned).unwrap_or_else(|| "(not set)".into())),
+        ("TEMPLATES_FILE", config.var("TEMPLATES_FILE").map(str::to_owned).unwrap_or_else(|| "(not set)".into())),
+        ("DEFAULT_LANGUAGE", config.var("DEFAULT_LANGUAGE").map(str::to_owned).unwrap_or_else(|| "(not set)".into())),
+        ("DEFAULT_CURRENCY", config.var("DEFAULT_CURRENCY").map(str::to_owned).unwrap_or_else(|| "(not set)".into())),
+        ("DEFAULT_CONFIRM_MODE", config.var("DEFAULT_CONFIRM_MODE").map(str::to_owned).unwrap_or_else(|| "repeats".into())),
+        ("DEFAULT_DIGEST_TIME", config.var("DEFAULT_DIGEST_TIME").map(str::to_owned).unwrap_or_else(|| "08:00".into())),
+        ("LEADER_LEASE_PATH", super::LEADER.lease_path().map(|p| p.display().to_string()).unwrap_or_else(|| "(not set)".into())),
+        ("LEADER_LEASE_SECS", super::LEADER.lease_secs().to_string()),
+        ("INSTANCE_ID", super::LEADER.instance_id().to_owned()),
+        ("READYZ_CHECK_TELEGRAM", super::config().readyz_check_telegram.to_string()),
+        ("MASTER_KEYS", format!("{} configured", super::config().master_keys.len())),
+        ("RATE_LIMIT_ENABLED", super::RATE_LIMITER.is_enabled().to_string()),
+        ("RETENTION_HISTORY_DAYS", retention::describe(super::config().retention.history)),
+        ("RETENTION_DEAD_LETTERS_DAYS", retention::describe(super::config().retention.dead_letters)),
+        ("RETENTION_CACHES_DAYS", retention::describe(super::config().retention.caches)),
+        ("APP_FIXTURE_DIR", super::config().app_fixture_dir.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("APP_DEBUG_TOKEN", mask_optional(&super::config().app_debug_token)),
+        ("OCR_API_URL", super::config().ocr_api_url.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("OCR_API_KEY", mask_optional(&super::config().ocr_api_key)),
+        ("RECEIPT_ATTACHMENTS", super::config().receipt_attachments.to_string()),
+        ("ATTACHMENT_MAX_BYTES", super::config().attachment_limits.max_bytes.to_string()),
+        ("ATTACHMENT_MAX_DIMENSION", super::config().attachment_limits.max_dimension.to_string()),
+        ("ATTACHMENT_JPEG_QUALITY", super::config().attachment_limits.quality.to_string()),
+        ("TRANSLATE_API_URL", super::config().translate_api_url.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("TRANSLATE_API_KEY", mask_optional(&super::config().translate_api_key)),
+        ("SELFTEST_FIREFLY_URL", super::config().selftest_firefly_url.to_owned().unwrap_or_else(|| "(not set)".into())),
+        ("SELFTEST_FIREFLY_TOKEN", mask_optional(&super::config().selftest_firefly_token)),
+        ("EXCHANGE_RATE_API_URL", super::config().exchange_rate_api_url.to_owned()),
+        ("ALERT_SINKS", config.alert_sinks.iter().map(|s| s.name()).collect::<Vec<&str>>().join(", ")),
+        ("DISABLED_COMMANDS", disabled_commands),
+        ("FIREFLY_CACHE_TTL_SECS", super::FIREFLY_CACHE.ttl().as_secs().to_string()),
+        ("ACCOUNT_CACHE_TTL_SECS", super::config().account_cache_ttl_secs.to_string()),
+        ("HTTP_TIMEOUT_SECS", super::HTTP_CLIENTS.timeout.as_secs().to_string()),
+        ("HTTP_CONNECT_TIMEOUT_SECS", super::HTTP_CLIENTS.connect_timeout.as_secs().to_string()),
+        ("HTTP_CLIENT_ID", config.var("HTTP_CLIENT_ID").map(str::to_owned).unwrap_or_else(|| "(not set)".into())),
+        ("FIREFLY_COLD_START_TIMEOUT_SECS", super::HTTP_CLIENTS.cold_start_timeout.as_secs().to_string()),
+    ]
+}
+
+/// Logs the version and effective configuration on startup.
+pub fn log_banner() {
+    info!("Firefly telegram bot v{}", super::VERSION);
+
+    for (key, value) in settings() {
+        info!("  {:<26} {}", key, value);
+    }
+}
+
+/// Collects the details served by `GET /debug/info`.
+pub fn info(db: &Database, config: &Config) -> serde_json::Value {
+    let settings = settings()
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), serde_json::Value::String(value)))
+        .collect::<serde_json::Map<String, serde_json::Value>>();
+
+    let abandoned = db.outbox
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, entry)| entry.is_abandoned())
+        .count();
+
+    let unencrypted_tokens = db.users
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, user)| user.is_ready() && !user.has_sealed_token())
+        .count();
+
+    serde_json::json!({
+        "build": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": super::VERSION,
+        },
+        "features": {
+            "nlp_provider": super::config().nlp_provider.name(),
+            "wit_training": super::config().wit_training_enabled,
+            "receipt_ocr": super::config().ocr_api_url.is_some(),
+            "translation": super::config().translate_api_url.is_some(),
+            "rules": super::config().app_rules.len(),
+            "disabled_commands": super::config().disabled_commands.len(),
+            "alert_sinks": config.alert_sinks.iter().map(|s| s.name()).collect::<Vec<&str>>(),
+        },
+        "backends": {
+            "telegram": "https://api.telegram.org",
+            "wit": "https://api.wit.ai",
+            "exchange_rates": super::config().exchange_rate_api_url,
+            "ocr": super::config().ocr_api_url,
+            "translate": super::config().translate_api_url,
+        },
+        "leader": super::LEADER.is_leader(),
+        "queues": {
+            "outbox": db.outbox.len(),
+            "outbox_abandoned": abandoned,
+            "scheduled": db.scheduled.len(),
+            "pending_actions": db.pending.len(),
+        },
+        "storage": {
+            "path": config.app_shared_storage_path,
+            "schema_version": super::migrations::version(&db.store).ok().flatten(),
+            "users": db.users.len(),
+            "unencrypted_tokens": unencrypted_tokens,
+            "categories": db.categories.len(),
+            "updates": db.updates.len(),
+            "access_grants": db.access.len(),
+            "firefly_cache_entries": super::FIREFLY_CACHE.len(),
+        },
+        "config": settings,
+    })
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::firefly::InsightEntry;
+use crate::format;
+use crate::language;
+use crate::leader;
+use crate::telegram::UserClue;
+
+use super::{Database, Error};
+
+/// How often the digest schedules are checked for one that is due.
+const POLL_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    /// Sent on Mondays, covering the previous seven days.
+    Weekly,
+}
+
+impl Frequency {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+/// A spending digest set up with `/report daily|weekly HH:MM`, the time is
+/// in the user's time zone, or UTC if they didn't set one.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DigestSchedule {
+    pub chat_id: i64,
+    pub frequency: Frequency,
+    pub time: NaiveTime,
+    pub next_at: i64,
+}
+
+impl DigestSchedule {
+    /// The values of the `digest_scheduled` template.
+    pub fn context(&self, timezone: Option<Tz>) -> serde_json::Value {
+        serde_json::json!({
+            "frequency": self.frequency.name(),
+            "time": self.time.format("%H:%M").to_string(),
+            "timezone": timezone.map(|tz| tz.name()).unwrap_or("UTC"),
+        })
+    }
+
+    /// The schedule in the built-in wording, for the operator.
+    pub fn describe(&self, timezone: Option<Tz>) -> String {
+        format::strip(&super::config().templates.render("digest_scheduled", None, self.context(timezone)))
+    }
+}
+
+/// Parses the arguments of `/report daily 08:00` or `/report weekly 08:00`.
+pub fn parse_schedule(args: &str, default_time: NaiveTime) -> Option<(Frequency, NaiveTime)> {
+    let mut parts = args.split_whitespace();
+
+    let frequency = match parts.next()?.to_lowercase().as_str() {
+        "daily" => Frequency::Daily,
+        "weekly" => Frequency::Weekly,
+        _ => return None,
+    };
+
+    let time = match parts.next() {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M").ok()?,
+        None => default_time,
+    };
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((frequency, time))
+}
+
+/// The first moment after `now` the digest should be sent.
+fn next_run(frequency: Frequency, time: NaiveTime, now: i64, timezone: Option<Tz>) -> i64 {
+    let timezone = timezone.unwrap_or(Tz::UTC);
+    let mut date = Utc.timestamp(now, 0).with_timezone(&timezone).naive_local().date();
+
+    loop {
+        let is_day = frequency == Frequency::Daily || date.weekday() == Weekday::Mon;
+        // A time skipped by a daylight saving change has no moment that day.
+        let at = timezone.from_local_datetime(&date.and_time(time)).earliest().map(|at| at.timestamp());
+
+        match at {
+            Some(at) if is_day && at > now => return at,
+            _ => date = date.succ(),
+        }
+    }
+}
+
+pub fn schedule(db: &Database, user_id: &str, chat_id: i64, frequency: Frequency, time: NaiveTime, timezone: Option<Tz>) -> Result<DigestSchedule, Error> {
+    let schedule = DigestSchedule {
+        chat_id,
+        frequency,
+        time,
+        next_at: next_run(frequency, time, Utc::now().timestamp(), timezone),
+    };
+
+    db.digests.insert(user_id.as_bytes(), schedule.to_owned())?;
+
+    Ok(schedule)
+}
+
+/// Moves the next digest of the user to their new time zone, if they have one.
+pub fn reschedule(db: &Database, user_id: &str, timezone: Option<Tz>) -> Result<(), Error> {
+    if let Some(schedule) = db.digests.get(user_id.as_bytes())? {
+        self::schedule(db, user_id, schedule.chat_id, schedule.frequency, schedule.time, timezone)?;
+    }
+
+    Ok(())
+}
+
+/// Stops the digest of a user, returns `false` if there was none.
+pub fn unschedule(db: &Database, user_id: &str) -> Result<bool, Error> {
+    Ok(db.digests.remove(user_id.as_bytes())?.is_some())
+}
+
+/// How much of a budget's limit was used within the digest period.
+#[derive(Debug)]
+pub struct BudgetStatus {
+    pub name: String,
+    pub spent: f64,
+    pub amount: f64,
+    pub currency_code: Option<String>,
+}
+
+fn format_amount(amount: f64, currency_code: &Option<String>) -> String {
+    format!("{:.2} {}", amount, currency_code.as_deref().unwrap_or_default()).trim().to_owned()
+}
+
+/// The values of the `digest` template.
+fn context(totals: &[InsightEntry], categories: &[InsightEntry], budgets: &[BudgetStatus]) -> serde_json::Value {
+    let spent = if totals.is_empty() {
+        "0.00".to_owned()
+    } else {
+        totals
+            .iter()
+            .map(|t| format_amount(t.difference_float.abs(), &t.currency_code))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let mut categories = categories.iter().collect::<Vec<_>>();
+    categories.sort_by(|a, b| a.difference_float.partial_cmp(&b.difference_float).unwrap_or(std::cmp::Ordering::Equal));
+
+    let top = categories
+        .iter()
+        .take(5)
+        .map(|c| serde_json::json!({ "name": c.name, "amount": format_amount(c.difference_float.abs(), &c.currency_code) }))
+        .collect::<Vec<serde_json::Value>>();
+
+    let budgets = budgets
+        .iter()
+        .map(|b| serde_json::json!({
+            "name": b.name,
+            "spent": format_amount(b.spent, &b.currency_code),
+            "amount": format_amount(b.amount, &b.currency_code),
+            "over": b.spent > b.amount,
+        }))
+        .collect::<Vec<serde_json::Value>>();
+
+    serde_json::json!({
+        "spent": spent,
+        "categories": top,
+        "budgets": budgets,
+    })
+}
+
+/// Pulls the spending of the period and the budgets of the current month
+/// from the user's Firefly III instance.
+async fn build(user: &UserClue, frequency: Frequency, today: NaiveDate) -> Result<serde_json::Value, Error> {
+    let end = today.pred();
+    let start = match frequency {
+        Frequency::Daily => end,
+        Frequency::Weekly => end - Duration::days(6),
+    };
+
+    let totals = user.firefly().get_expense_insight("total", start, end).await?;
+    let categories = user.firefly().get_expense_insight("category", start, end).await?;
+
+    let month_start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
+    let names = user.firefly().get_active_budgets()
+        .await?
+        .into_iter()
+        .map(|b| (b.id, b.attributes.name))
+        .collect::<HashMap<String, String>>();
+
+    let budgets = user.firefly().get_budget_limits(month_start, today)
+        .await?
+        .into_iter()
+        .filter_map(|limit| {
+            Some(BudgetStatus {
+                name: names.get(&limit.budget_id)?.to_owned(),
+                spent: limit.spent.and_then(|s| s.parse::<f64>().ok()).unwrap_or_default().abs(),
+                amount: limit.amount.parse::<f64>().ok()?,
+                currency_code: limit.currency_code,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut context = context(&totals, &categories, &budgets);
+    context["frequency"] = serde_json::Value::from(frequency.name());
+    context["day"] = serde_json::Value::from(end.format("%A, %B %-d").to_string());
+    context["start"] = serde_json::Value::from(start.format("%B %-d").to_string());
+    context["end"] = serde_json::Value::from(end.format("%B %-d").to_string());
+
+    Ok(context)
+}
+
+async fn send(db: &Database, user_id: &[u8], schedule: &DigestSchedule) -> Result<(), Error> {
+    let user = match db.users.get(user_id)? {
+        Some(user) if user.is_ready() => user,
+        // The user reset their account in the meantime, nothing to report on.
+        _ => return Ok(()),
+    };
+
+    let today = Utc::now().with_timezone(&user.timezone().unwrap_or(Tz::UTC)).naive_local().date();
+    let context = build(&user, schedule.frequency, today).await?;
+
+    super::telegram_post("sendMessage", &serde_json::json!({
+        "chat_id": schedule.chat_id,
+        "text": language::text(db, user.telegram_id(), "digest", context),
+    }))
+    .await?;
+
+    Ok(())
+}
+
+/// Periodically sends the digests that are due.
+pub async fn run_digest_loop(db: Arc<Database>) {
+    loop {
+        sleep(std::time::Duration::from_secs(POLL_INTERVAL_SECS)).await;
+
+        if !leader::is_leader() {
+            continue;
+        }
+
+        let now = Utc::now().timestamp();
+        let due = db.digests
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, schedule)| schedule.next_at <= now)
+            .collect::<Vec<_>>();
+
+        for (key, mut schedule) in due {
+            // Move on to the next run first, a failing digest is not retried
+            // until the next period.
+            let timezone = db.users.get(&key).ok().flatten().and_then(|user| user.timezone());
+            schedule.next_at = next_run(schedule.frequency, schedule.time, now, timezone);
+
+            if let Err(e) = db.digests.insert(&key, schedule.to_owned()) {
+                log::error!("Failed to reschedule digest: {}", e);
+                continue;
+            }
+
+            if let Err(e) = send(&db, &key, &schedule).await {
+                log::error!("Failed to send digest: {}", e);
+            }
+        }
+    }
+}
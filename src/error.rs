@@ -0,0 +1,141 @@
+use thiserror::Error as ThisError;
+
+use crate::format;
+
+/// The error type shared by the whole bot.
+#[derive(Debug, ThisError)]
+pub enum Error {
+    /// The Telegram Bot API could not be reached or rejected a call.
+    #[error("Telegram API error: {0}")]
+    Telegram(#[source] reqwest::Error),
+
+    /// The wit.ai API could not be reached or rejected a call.
+    #[error("wit.ai error: {0}")]
+    Wit(#[source] reqwest::Error),
+
+    /// No wit.ai access token is configured on this deployment.
+    #[error("WIT_ACCESS_TOKEN is not set")]
+    WitNotConfigured,
+
+    /// The OpenAI-compatible NLP endpoint could not be reached or rejected a call.
+    #[error("NLP provider error: {0}")]
+    Nlp(#[source] reqwest::Error),
+
+    /// The user's Firefly III instance could not be reached or rejected a call.
+    #[error("Firefly III error: {0}")]
+    Firefly(#[source] reqwest::Error),
+
+    /// The user's Firefly III instance refused the request as invalid,
+    /// holds the reasons it gave.
+    #[error("Firefly III rejected the request:\n{0}")]
+    FireflyRejected(String),
+
+    /// Any other upstream service (OCR, exchange rates) failed.
+    #[error("Upstream service error: {0}")]
+    Upstream(#[source] reqwest::Error),
+
+    /// Reading or writing the local database failed.
+    #[error("Storage error: {0}")]
+    Storage(#[from] sled_extensions::Error),
+
+    /// The user's message could not be turned into what was asked for,
+    /// holds the catalog entry telling them why and what it's rendered with.
+    #[error("{}", crate::templates::english(.0, .1))]
+    Parse(&'static str, serde_json::Value),
+
+    /// Telegram sent an update missing something the bot relies on.
+    #[error("Invalid update: {0}")]
+    InvalidUpdate(String),
+
+    /// The chat sent more messages than the rate limiter allows.
+    #[error("Rate limit exceeded")]
+    RateLimited,
+
+    /// The user has no record in the database.
+    #[error("Cannot find the user in the database")]
+    UserNotFound,
+
+    /// A user's secret could not be encrypted or decrypted.
+    #[error("Secret error: {0}")]
+    Secret(String),
+
+    /// The deployment is misconfigured.
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    /// A chart could not be drawn or encoded.
+    #[error("Chart error: {0}")]
+    Chart(String),
+
+    /// Work moved off the async workers, e.g. compressing an image, panicked.
+    #[error("Background task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP server error: {0}")]
+    Hyper(#[from] hyper::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] hyper::http::Error),
+
+    #[error("Routing error: {0}")]
+    Router(String),
+}
+
+impl Error {
+    /// Checks if the error was caused by what the user sent rather than by
+    /// the bot or its upstreams, such errors are not reported to the master.
+    pub fn is_user_error(&self) -> bool {
+        matches!(self, Self::Parse(..) | Self::UserNotFound | Self::RateLimited | Self::FireflyRejected(_))
+    }
+
+    /// Checks if the NLP provider cannot be used right now, i.e. it is
+    /// unreachable, rate limiting us, failing, or not configured at all.
+    pub fn is_nlp_unavailable(&self) -> bool {
+        match self {
+            Self::WitNotConfigured => true,
+            Self::Wit(e) | Self::Nlp(e) => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || e.status().is_some_and(|s| s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS)
+            },
+            _ => false,
+        }
+    }
+
+    /// A message explaining the failure to the user in their language,
+    /// `None` when there is nothing useful to tell them or no way to reach them.
+    pub fn user_message(&self, language_code: Option<&str>) -> Option<String> {
+        let text = |name: &str, context: serde_json::Value| {
+            format::strip(&super::config().templates.render(name, language_code, context))
+        };
+
+        match self {
+            Self::Parse(name, context) => Some(text(name, context.to_owned())),
+            Self::RateLimited => Some(text("error_rate_limited", serde_json::json!({}))),
+            Self::UserNotFound => Some(text("error_user_not_found", serde_json::json!({}))),
+            Self::Firefly(e) => match e.status() {
+                Some(status) if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN => {
+                    Some(text("error_token_rejected", serde_json::json!({})))
+                },
+                Some(status) if status.is_client_error() => Some(text("error_firefly_status", serde_json::json!({ "status": status.to_string() }))),
+                _ => Some(text("error_firefly_unreachable", serde_json::json!({}))),
+            },
+            Self::FireflyRejected(reasons) => Some(text("error_firefly_rejected", serde_json::json!({ "reasons": reasons }))),
+            Self::Wit(_) | Self::Nlp(_) => Some(text("error_nlp_unavailable", serde_json::json!({}))),
+            Self::Telegram(_) | Self::InvalidUpdate(_) => None,
+            _ => Some(text("error_internal", serde_json::json!({}))),
+        }
+    }
+}
+
+impl From<routerify::RouteError> for Error {
+    fn from(e: routerify::RouteError) -> Self {
+        Self::Router(e.to_string())
+    }
+}
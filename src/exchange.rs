@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+
+use super::Error;
+
+/// Exchange rates relative to a base currency, as returned by Frankfurter
+/// compatible providers (e.g. `{"base":"EUR","date":"2021-09-01","rates":{"USD":1.18}}`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rates {
+    pub base: String,
+    pub date: String,
+    pub rates: HashMap<String, f64>,
+}
+
+impl Rates {
+    /// Converts an amount expressed in `currency` into the base currency.
+    pub fn to_base(&self, amount: f64, currency: &str) -> Option<f64> {
+        if currency.eq_ignore_ascii_case(&self.base) {
+            return Some(amount);
+        }
+
+        self.rates
+            .get(&currency.to_uppercase())
+            .filter(|rate| **rate > 0.0)
+            .map(|rate| amount / rate)
+    }
+}
+
+/// Fetches the latest rates for the base currency from the provider at `EXCHANGE_RATE_API_URL`.
+pub async fn fetch_rates(base: &str) -> Result<Rates, Error> {
+    let rates = super::HTTP_CLIENTS.exchange
+        .get(&super::config().exchange_rate_api_url)
+        .query(&[("from", base)])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Upstream)?
+        .json::<Rates>()
+        .await
+        .map_err(Error::Upstream)?;
+
+    Ok(rates)
+}
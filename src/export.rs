@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use chrono::NaiveDate;
+use uuid::Uuid;
+
+use crate::firefly::{TransactionGroup, TransactionSplit};
+use crate::report;
+
+use super::Error;
+
+/// The largest document the Bot API lets bots upload.
+pub const MAX_DOCUMENT_BYTES: u64 = 50 * 1024 * 1024;
+
+const HEADER: &[&str] = &[
+    "date",
+    "type",
+    "amount",
+    "currency",
+    "description",
+    "source",
+    "destination",
+    "category",
+    "budget",
+    "foreign_amount",
+    "foreign_currency",
+];
+
+/// A file in the system's temporary directory, removed once dropped so an
+/// export that fails halfway doesn't stay behind.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(suffix: &str) -> Self {
+        let name = format!("firefly-tg-{}-{}", Uuid::new_v4().to_simple(), suffix);
+        Self { path: env::temp_dir().join(name) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Quotes the field if it holds a separator, quote or line break.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The prefix of the external id of every transaction created by the bot.
+pub const EXTERNAL_ID_PREFIX: &str = "tg-";
+
+fn csv_line<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    let mut line = fields.into_iter().map(csv_field).collect::<Vec<Cow<str>>>().join(",");
+    line.push_str("\r\n");
+    line
+}
+
+fn csv_row(split: &TransactionSplit) -> String {
+    // Firefly III dates carry the time, e.g. `2021-08-14T00:00:00+02:00`.
+    let date = split.date.as_deref().map(|d| d.get(..10).unwrap_or(d));
+    let fields = [
+        date,
+        Some(split.transact_type.as_str()),
+        Some(split.amount.as_str()),
+        split.currency_code.as_deref(),
+        split.description.as_deref(),
+        split.source_name.as_deref(),
+        split.destination_name.as_deref(),
+        split.category_name.as_deref(),
+        split.budget_name.as_deref(),
+        split.foreign_amount.as_deref(),
+        split.foreign_currency_code.as_deref(),
+    ];
+
+    csv_line(fields.iter().map(|f| f.unwrap_or_default()))
+}
+
+/// Parses the period of `/export csv`, either a month like `2021-08` or a
+/// range of days like `2021-08-01..2021-09-15`.
+pub fn parse_range(args: &str, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    match args.trim().split_once("..") {
+        Some((start, end)) => {
+            let start = NaiveDate::parse_from_str(start.trim(), "%Y-%m-%d").ok()?;
+            let end = NaiveDate::parse_from_str(end.trim(), "%Y-%m-%d").ok()?;
+            Some((start, end)).filter(|_| start <= end)
+        },
+        None => report::month_range(args, today),
+    }
+}
+
+/// Describes the period of an export, e.g. `2021-08-01 to 2021-08-31`.
+pub fn describe_range(range: Option<(NaiveDate, NaiveDate)>) -> String {
+    match range {
+        Some((start, end)) => format!("{} to {}", start.format("%Y-%m-%d"), end.format("%Y-%m-%d")),
+        None => "all time".to_owned(),
+    }
+}
+
+/// The name of an export of the transactions created by the bot.
+pub fn filename(range: Option<(NaiveDate, NaiveDate)>, extension: &str) -> String {
+    match range {
+        Some((start, end)) => format!("firefly-tg-{}-{}.{}", start.format("%Y%m%d"), end.format("%Y%m%d"), extension),
+        None => format!("firefly-tg.{}", extension),
+    }
+}
+
+/// The Firefly III search query for the transactions created by the bot,
+/// within the period if one is given.
+pub fn bot_created_query(range: Option<(NaiveDate, NaiveDate)>) -> String {
+    let mut query = format!("external_id_starts:{}", EXTERNAL_ID_PREFIX);
+
+    if let Some((start, end)) = range {
+        query.push_str(&format!(" date_after:{} date_before:{}", start.format("%Y-%m-%d"), end.format("%Y-%m-%d")));
+    }
+
+    query
+}
+
+/// The body of `POST /api/v1/transactions` creating the group again, so
+/// the history of the bot can be replayed into another Firefly III
+/// instance. The external ids are kept so nothing is created twice.
+pub fn import_payload(group: &TransactionGroup) -> serde_json::Value {
+    let transactions = group.attributes.transactions
+        .iter()
+        .map(|split| {
+            let transaction = serde_json::json!({
+                "type": split.transact_type,
+                "date": split.date,
+                "amount": split.amount,
+                "description": split.description,
+                "source_name": split.source_name,
+                "destination_name": split.destination_name,
+                "currency_code": split.currency_code,
+                "foreign_amount": split.foreign_amount,
+                "foreign_currency_code": split.foreign_currency_code,
+                "category_name": split.category_name,
+                "budget_name": split.budget_name,
+                "tags": split.tags,
+                "notes": split.notes,
+                "external_id": split.external_id,
+            });
+
+            match transaction {
+                serde_json::Value::Object(fields) => fields.into_iter().filter(|(_, value)| !value.is_null()).collect(),
+                transaction => transaction,
+            }
+        })
+        .collect::<Vec<serde_json::Value>>();
+
+    serde_json::json!({
+        "error_if_duplicate_hash": true,
+        "apply_rules": false,
+        "transactions": transactions,
+    })
+}
+
+/// Builds a CSV document in memory, for exports small enough to not need a
+/// temporary file.
+#[derive(Debug)]
+pub struct CsvBuffer {
+    bytes: Vec<u8>,
+    rows: usize,
+}
+
+impl CsvBuffer {
+    pub fn new() -> Self {
+        Self {
+            bytes: csv_line(HEADER.iter().copied()).into_bytes(),
+            rows: 0,
+        }
+    }
+
+    pub fn write<'a>(&mut self, splits: impl Iterator<Item = &'a TransactionSplit>) {
+        for split in splits {
+            self.bytes.extend_from_slice(csv_row(split).as_bytes());
+            self.rows += 1;
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Default for CsvBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes transactions to a CSV file as the pages come in from Firefly III,
+/// so only one page is ever held in memory.
+pub struct CsvExport {
+    file: BufWriter<File>,
+    rows: usize,
+}
+
+impl CsvExport {
+    /// Creates the file and writes the header.
+    pub async fn create(path: &Path) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        file.write_all(csv_line(HEADER.iter().copied()).as_bytes()).await?;
+
+        Ok(Self { file, rows: 0 })
+    }
+
+    pub async fn write<'a>(&mut self, splits: impl Iterator<Item = &'a TransactionSplit>) -> Result<(), Error> {
+        for split in splits {
+            let line = csv_row(split);
+            self.file.write_all(line.as_bytes()).await?;
+            self.rows += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes what's left to disk, returns how many rows were written.
+    pub async fn finish(mut self) -> Result<usize, Error> {
+        self.file.flush().await?;
+        Ok(self.rows)
+    }
+}
@@ -0,0 +1,680 @@
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::telegram::TransactPayload;
+
+use super::Error;
+
+/// The body Firefly III answers a rejected request with, e.g. a 422 listing
+/// the invalid fields as `{"transactions.0.source_name": ["..."]}`.
+#[derive(Debug, Deserialize)]
+pub struct ValidationError {
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+/// Names a payload field the way the bot's messages talk about it.
+fn describe_field(field: &str) -> String {
+    // Fields of a split are prefixed with its position, e.g. `transactions.0.`.
+    let name = field.rsplit('.').next().unwrap_or(field);
+
+    match name {
+        "source_name" | "source_id" => "source account".to_owned(),
+        "destination_name" | "destination_id" => "destination account".to_owned(),
+        "currency_code" | "currency_id" => "currency".to_owned(),
+        "category_name" | "category_id" => "category".to_owned(),
+        "budget_name" | "budget_id" => "budget".to_owned(),
+        name => name.replace('_', " "),
+    }
+}
+
+impl ValidationError {
+    /// One line per invalid field, or the general message if no field was
+    /// singled out.
+    pub fn explain(&self) -> Option<String> {
+        let reasons = self.errors
+            .iter()
+            .flat_map(|(field, messages)| messages.iter().map(move |m| format!("- {}: {}", describe_field(field), m)))
+            .collect::<Vec<String>>();
+
+        if !reasons.is_empty() {
+            Some(reasons.join("\n"))
+        } else if !self.message.is_empty() {
+            Some(self.message.to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns a failed response into an error, reading the reason out of the
+/// body when Firefly III explains why it rejected the request.
+pub async fn error_for_status(resp: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = resp.status();
+    if !status.is_client_error() || status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return resp.error_for_status().map_err(Error::Firefly);
+    }
+
+    let error = resp.error_for_status_ref().map_err(Error::Firefly).unwrap_err();
+
+    match resp.json::<ValidationError>().await.ok().and_then(|body| body.explain()) {
+        Some(reasons) => Err(Error::FireflyRejected(reasons)),
+        None => Err(error),
+    }
+}
+
+/// A client of a user's Firefly III instance, authenticated with their
+/// personal access token.
+pub struct FireflyClient {
+    base_url: String,
+    access_token: String,
+    cache_prefix: String,
+    timeout: Option<Duration>,
+}
+
+fn date_range(start: NaiveDate, end: NaiveDate) -> Vec<(&'static str, String)> {
+    vec![
+        ("start", start.format("%Y-%m-%d").to_string()),
+        ("end", end.format("%Y-%m-%d").to_string()),
+    ]
+}
+
+impl FireflyClient {
+    /// `cache_prefix` keeps the cached responses of different users apart.
+    pub fn new(base_url: &str, access_token: String, cache_prefix: String) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            access_token,
+            cache_prefix,
+            timeout: None,
+        }
+    }
+
+    /// Gives the instance `FIREFLY_COLD_START_TIMEOUT_SECS` to answer when
+    /// creating transactions, for instances that sleep between requests.
+    pub fn cold_start(mut self) -> Self {
+        self.timeout = Some(super::HTTP_CLIENTS.cold_start_timeout);
+        self
+    }
+
+    /// Wakes up a sleeping instance with a cheap request so the post that
+    /// follows doesn't run into the cold start. Hosts refusing connections
+    /// while they boot are asked again until the timeout of `cold_start`
+    /// runs out.
+    pub async fn warm_up(&self) -> Result<(), Error> {
+        let timeout = self.timeout.unwrap_or(super::HTTP_CLIENTS.timeout);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let result = super::HTTP_CLIENTS.firefly
+                .get(self.url("about"))
+                .bearer_auth(&self.access_token)
+                .timeout(deadline.saturating_duration_since(Instant::now()))
+                .send()
+                .await;
+
+            match result {
+                Err(e) if e.is_connect() && Instant::now() + Duration::from_secs(2) < deadline => {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                },
+                Err(e) => return Err(Error::Firefly(e)),
+                Ok(_) => return Ok(()),
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/public/api/v1/{}", self.base_url, path)
+    }
+
+    /// Sends a GET request to Firefly III through the shared response cache.
+    async fn get_cached<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, Error> {
+        let url = self.url(path);
+        let key = format!("{}{}?{}", self.cache_prefix, url, serde_json::to_string(query)?);
+
+        let request = super::HTTP_CLIENTS.firefly
+            .get(&url)
+            .query(query)
+            .bearer_auth(&self.access_token);
+
+        super::FIREFLY_CACHE.get_json(key, request).await
+    }
+
+    /// Sends a write request, dropping the cached responses of the user
+    /// since they may be stale afterwards.
+    async fn send(&self, method: reqwest::Method, path: &str, query: &[(&str, String)], payload: Option<&serde_json::Value>) -> Result<reqwest::Response, Error> {
+        super::FIREFLY_CACHE.invalidate_prefix(&self.cache_prefix);
+
+        let mut request = super::HTTP_CLIENTS.firefly
+            .request(method, self.url(path))
+            .query(query)
+            .bearer_auth(&self.access_token);
+
+        if let Some(payload) = payload {
+            request = request.json(payload);
+        }
+
+        let resp = request.send().await.map_err(Error::Firefly)?;
+        error_for_status(resp).await
+    }
+
+    pub async fn get_active_budgets(&self) -> Result<Vec<BudgetRead>, Error> {
+        let budgets = self.get_cached::<BudgetArray>("budgets", &[]).await?;
+
+        Ok(budgets.data
+            .into_iter()
+            .filter(|b| b.attributes.active.unwrap_or(true))
+            .collect())
+    }
+
+    pub async fn get_accounts(&self, account_type: &str) -> Result<Vec<AccountRead>, Error> {
+        let accounts = self.get_cached::<AccountArray>("accounts", &[("type", account_type.to_owned())]).await?;
+
+        Ok(accounts.data)
+    }
+
+    pub async fn get_categories(&self) -> Result<Vec<CategoryRead>, Error> {
+        let categories = self.get_cached::<CategoryArray>("categories", &[]).await?;
+
+        Ok(categories.data)
+    }
+
+    pub async fn get_active_rule_groups(&self) -> Result<Vec<RuleGroupRead>, Error> {
+        let rule_groups = self.get_cached::<RuleGroupArray>("rule-groups", &[]).await?;
+
+        Ok(rule_groups.data
+            .into_iter()
+            .filter(|g| g.attributes.active.unwrap_or(true))
+            .collect())
+    }
+
+    /// Lists the transactions the rule group would change, without changing them.
+    pub async fn test_rule_group(&self, id: &str, query: &[(&str, String)]) -> Result<TransactionArray, Error> {
+        let resp = super::HTTP_CLIENTS.firefly
+            .get(self.url(&format!("rule-groups/{}/test", id)))
+            .query(query)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await
+            .map_err(Error::Firefly)?;
+
+        error_for_status(resp)
+            .await?
+            .json::<TransactionArray>()
+            .await
+            .map_err(Error::Firefly)
+    }
+
+    pub async fn trigger_rule_group(&self, id: &str, query: &[(&str, String)]) -> Result<(), Error> {
+        self.send(reqwest::Method::POST, &format!("rule-groups/{}/trigger", id), query, None).await?;
+
+        Ok(())
+    }
+
+    /// Sums up the expenses of the period, `kind` is the insight grouping
+    /// e.g. `total` or `category`.
+    pub async fn get_expense_insight(&self, kind: &str, start: NaiveDate, end: NaiveDate) -> Result<Vec<InsightEntry>, Error> {
+        self.get_cached(&format!("insight/expense/{}", kind), &date_range(start, end)).await
+    }
+
+    /// Like `get_expense_insight` but only counting what left the account.
+    pub async fn get_account_expense_insight(&self, kind: &str, account_id: &str, start: NaiveDate, end: NaiveDate) -> Result<Vec<InsightEntry>, Error> {
+        let mut query = date_range(start, end);
+        query.push(("accounts[]", account_id.to_owned()));
+
+        self.get_cached(&format!("insight/expense/{}", kind), &query).await
+    }
+
+    /// The active bills with the dates they are expected to be paid on
+    /// within the period.
+    pub async fn get_active_bills(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<Bill>, Error> {
+        let bills = self.get_cached::<BillArray>("bills", &date_range(start, end)).await?;
+
+        Ok(bills.data
+            .into_iter()
+            .map(|b| b.attributes)
+            .filter(|b| b.active.unwrap_or(true))
+            .collect())
+    }
+
+    pub async fn get_active_recurrences(&self) -> Result<Vec<Recurrence>, Error> {
+        let recurrences = self.get_cached::<RecurrenceArray>("recurrences", &[]).await?;
+
+        Ok(recurrences.data
+            .into_iter()
+            .map(|r| r.attributes)
+            .filter(|r| r.active.unwrap_or(true))
+            .collect())
+    }
+
+    pub async fn get_budget_limits(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<BudgetLimit>, Error> {
+        let limits = self.get_cached::<BudgetLimitArray>("budget-limits", &date_range(start, end)).await?;
+
+        Ok(limits.data.into_iter().map(|l| l.attributes).collect())
+    }
+
+    pub async fn get_transactions(&self, start: NaiveDate, end: NaiveDate, page: u32) -> Result<TransactionArray, Error> {
+        let mut query = date_range(start, end);
+        query.push(("page", page.to_string()));
+
+        self.get_cached("transactions", &query).await
+    }
+
+    /// Like `get_transactions` but past the response cache, exports walk
+    /// every page once and would only crowd out what's worth caching.
+    pub async fn get_transactions_uncached(&self, start: NaiveDate, end: NaiveDate, page: u32) -> Result<TransactionArray, Error> {
+        let mut query = date_range(start, end);
+        query.push(("page", page.to_string()));
+
+        let resp = super::HTTP_CLIENTS.firefly
+            .get(self.url("transactions"))
+            .query(&query)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await
+            .map_err(Error::Firefly)?;
+
+        error_for_status(resp).await?.json().await.map_err(Error::Firefly)
+    }
+
+    pub async fn get_transaction(&self, id: &str) -> Result<TransactionGroup, Error> {
+        let group = self.get_cached::<TransactionGroupSingle>(&format!("transactions/{}", id), &[]).await?;
+
+        Ok(group.data)
+    }
+
+    /// Lists the transactions tagged with the tag, by name or id.
+    pub async fn get_tag_transactions(&self, tag: &str, page: u32) -> Result<TransactionArray, Error> {
+        self.get_cached(&format!("tags/{}/transactions", urlencoding::encode(tag)), &[("page", page.to_string())]).await
+    }
+
+    /// Finds the transaction created with the external id, returns its id.
+    pub async fn find_by_external_id(&self, external_id: &str) -> Result<Option<String>, Error> {
+        let found = self.get_cached::<TransactionArray>("search/transactions", &[
+            ("query", format!("external_id_is:\"{}\"", external_id)),
+        ])
+        .await?;
+
+        Ok(found.data.into_iter().next().map(|g| g.id))
+    }
+
+    /// Searches the transactions with Firefly III's search syntax, a page
+    /// of `limit` at a time.
+    pub async fn search_transactions(&self, query: &str, page: u32, limit: u32) -> Result<TransactionArray, Error> {
+        self.get_cached("search/transactions", &[
+            ("query", query.to_owned()),
+            ("page", page.to_string()),
+            ("limit", limit.to_string()),
+        ])
+        .await
+    }
+
+    pub async fn update_transaction(&self, id: &str, payload: &serde_json::Value) -> Result<(), Error> {
+        self.send(reqwest::Method::PUT, &format!("transactions/{}", id), &[], Some(payload)).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_transaction(&self, id: &str) -> Result<(), Error> {
+        self.send(reqwest::Method::DELETE, &format!("transactions/{}", id), &[], None).await?;
+
+        Ok(())
+    }
+
+    /// Creates a budget, returns its id.
+    pub async fn create_budget(&self, name: &str) -> Result<String, Error> {
+        let budget = self.send(reqwest::Method::POST, "budgets", &[], Some(&serde_json::json!({ "name": name })))
+            .await?
+            .json::<BudgetSingle>()
+            .await
+            .map_err(Error::Firefly)?;
+
+        Ok(budget.data.id)
+    }
+
+    pub async fn create_budget_limit(&self, budget_id: &str, payload: &serde_json::Value) -> Result<(), Error> {
+        self.send(reqwest::Method::POST, &format!("budgets/{}/limits", budget_id), &[], Some(payload)).await?;
+
+        Ok(())
+    }
+
+    /// Attaches the file to the first split of the transaction, returns the
+    /// id of the attachment.
+    pub async fn upload_attachment(&self, transaction_id: &str, filename: &str, title: &str, bytes: Vec<u8>) -> Result<String, Error> {
+        let group = self.send(reqwest::Method::GET, &format!("transactions/{}", transaction_id), &[], None)
+            .await?
+            .json::<TransactionGroupSingle>()
+            .await
+            .map_err(Error::Firefly)?;
+
+        let journal_id = group.data.attributes.transactions
+            .into_iter()
+            .find_map(|split| split.transaction_journal_id)
+            .ok_or_else(|| Error::Parse("attachment_no_transaction", serde_json::json!({})))?;
+
+        let attachment = self.send(reqwest::Method::POST, "attachments", &[], Some(&serde_json::json!({
+            "filename": filename,
+            "attachable_type": "TransactionJournal",
+            "attachable_id": journal_id,
+            "title": title,
+        })))
+        .await?
+        .json::<AttachmentSingle>()
+        .await
+        .map_err(Error::Firefly)?;
+
+        let resp = super::HTTP_CLIENTS.firefly
+            .post(self.url(&format!("attachments/{}/upload", attachment.data.id)))
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .bearer_auth(&self.access_token)
+            .body(bytes)
+            .send()
+            .await
+            .map_err(Error::Firefly)?;
+        error_for_status(resp).await?;
+
+        Ok(attachment.data.id)
+    }
+
+    pub async fn create_recurrence(&self, payload: &serde_json::Value) -> Result<(), Error> {
+        self.send(reqwest::Method::POST, "recurrences", &[], Some(payload)).await?;
+
+        Ok(())
+    }
+
+    /// Posts the transaction, the raw outcome is returned so callers can
+    /// tell transient failures worth a retry from rejections.
+    pub async fn create_transaction(&self, payload: &TransactPayload) -> Result<reqwest::Response, reqwest::Error> {
+        super::FIREFLY_CACHE.invalidate_prefix(&self.cache_prefix);
+
+        let mut request = super::HTTP_CLIENTS.firefly
+            .post(self.url("transactions"))
+            .json(payload)
+            .bearer_auth(&self.access_token);
+
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+
+        request.send().await
+    }
+}
+
+/// Amounts summed up per currency by the insight endpoints.
+#[derive(Debug, Deserialize)]
+pub struct InsightEntry {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub difference_float: f64,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryArray {
+    pub data: Vec<CategoryRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryRead {
+    pub attributes: Category,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Category {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionSingle {
+    pub data: TransactionRead,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionRead {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionGroupSingle {
+    pub data: TransactionGroup,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttachmentSingle {
+    pub data: AttachmentRead,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttachmentRead {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionArray {
+    pub data: Vec<TransactionGroup>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionGroup {
+    pub id: String,
+    pub attributes: TransactionGroupAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionGroupAttributes {
+    pub transactions: Vec<TransactionSplit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionSplit {
+    #[serde(rename = "type")]
+    pub transact_type: String,
+    pub amount: String,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+    #[serde(default)]
+    pub category_name: Option<String>,
+    #[serde(default)]
+    pub foreign_amount: Option<String>,
+    #[serde(default)]
+    pub foreign_currency_code: Option<String>,
+    #[serde(default)]
+    pub transaction_journal_id: Option<String>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub source_name: Option<String>,
+    #[serde(default)]
+    pub destination_name: Option<String>,
+    #[serde(default)]
+    pub budget_name: Option<String>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Meta {
+    pub pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    pub total: u64,
+    pub current_page: u32,
+    pub total_pages: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetArray {
+    pub data: Vec<BudgetRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetSingle {
+    pub data: BudgetRead,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetRead {
+    pub id: String,
+    pub attributes: Budget,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Budget {
+    pub name: String,
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetLimitArray {
+    pub data: Vec<BudgetLimitRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetLimitRead {
+    pub attributes: BudgetLimit,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetLimit {
+    pub budget_id: String,
+    pub amount: String,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+    #[serde(default)]
+    pub spent: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BillArray {
+    pub data: Vec<BillRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BillRead {
+    pub attributes: Bill,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Bill {
+    pub name: String,
+    pub amount_min: String,
+    pub amount_max: String,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+    #[serde(default)]
+    pub active: Option<bool>,
+    /// When the bill is expected within the requested period, as RFC 3339
+    /// timestamps.
+    #[serde(default)]
+    pub pay_dates: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurrenceArray {
+    pub data: Vec<RecurrenceRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurrenceRead {
+    pub attributes: Recurrence,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Recurrence {
+    pub title: String,
+    pub first_date: String,
+    #[serde(default)]
+    pub repeat_until: Option<String>,
+    #[serde(default)]
+    pub active: Option<bool>,
+    #[serde(default)]
+    pub repetitions: Vec<RecurrenceRepetition>,
+    #[serde(default)]
+    pub transactions: Vec<RecurrenceTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurrenceRepetition {
+    #[serde(rename = "type")]
+    pub repetition_type: String,
+    pub moment: String,
+    #[serde(default)]
+    pub skip: u32,
+    /// The next few dates worked out by Firefly III.
+    #[serde(default)]
+    pub occurrences: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurrenceTransaction {
+    pub amount: String,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(default)]
+    pub destination_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountArray {
+    pub data: Vec<AccountRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountRead {
+    pub id: String,
+    pub attributes: Account,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Account {
+    pub name: String,
+    /// Archived accounts are inactive, Firefly III omits the flag on older versions.
+    #[serde(default)]
+    pub active: Option<bool>,
+    #[serde(default)]
+    pub current_balance: Option<String>,
+    #[serde(default)]
+    pub currency_code: Option<String>,
+}
+
+impl Account {
+    pub fn is_active(&self) -> bool {
+        self.active.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleGroupArray {
+    pub data: Vec<RuleGroupRead>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleGroupRead {
+    pub id: String,
+    pub attributes: RuleGroup,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleGroup {
+    #[serde(default)]
+    pub active: Option<bool>,
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::Path;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::nlp::ParsedTransaction;
+use crate::telegram::{self, ParseContext};
+
+use super::Error;
+
+/// Fields of an update holding personal details, replaced when recording.
+const REDACTED_FIELDS: &[&str] = &["first_name", "last_name", "username", "title", "language_code", "phone_number", "vcard"];
+
+/// Fields of an update holding a user or a chat, wherever they're nested,
+/// e.g. the sender of the message replied to.
+const IDENTITY_FIELDS: &[&str] = &[
+    "from", "chat", "forward_from", "forward_from_chat", "sender_chat", "via_bot", "user",
+    "contact", "new_chat_members", "left_chat_member",
+];
+
+/// Fields of a user or chat holding its id.
+const ID_FIELDS: &[&str] = &["id", "user_id"];
+
+/// An anonymized trace of a single interaction, recorded when the operator
+/// sets `APP_FIXTURE_DIR` and replayed with `firefly_tg replay <file>`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Fixture {
+    pub update: serde_json::Value,
+    pub context: Option<ParseContext>,
+    pub parsed: Option<ParsedTransaction>,
+    pub firefly_payload: Option<serde_json::Value>,
+    pub outcome: String,
+    /// The chat the update came from as `chat_tag`, so `/scrub` can find
+    /// the fixtures of a chat without the ids being recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat: Option<String>,
+}
+
+/// Tags a chat with a digest keyed with the bot token, which readers of
+/// the fixtures can't turn back into the chat id.
+pub fn chat_tag(chat_id: i64) -> String {
+    let digest = Sha256::digest(format!("{}:{}", super::config().tg_bot_token, chat_id).as_bytes());
+    digest.iter().take(16).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Deletes the fixtures recorded in the chat, returns how many were deleted.
+pub fn scrub(dir: &str, chat_id: i64) -> Result<usize, Error> {
+    let tag = chat_tag(chat_id);
+    let mut deleted = 0;
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Nothing was recorded yet.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let recorded = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Fixture>(&bytes).ok())
+            .and_then(|fixture| fixture.chat);
+
+        if recorded.as_deref() == Some(tag.as_str()) {
+            fs::remove_file(&path)?;
+            deleted += 1;
+        }
+    }
+
+    Ok(deleted)
+}
+
+fn anonymize_value(value: &mut serde_json::Value, parent: Option<&str>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *field = serde_json::Value::String("redacted".into());
+                } else if ID_FIELDS.contains(&key.as_str()) && parent.is_some_and(|p| IDENTITY_FIELDS.contains(&p)) {
+                    *field = serde_json::json!(1);
+                } else {
+                    anonymize_value(field, Some(key));
+                }
+            }
+        },
+        serde_json::Value::Array(items) => {
+            for item in items {
+                anonymize_value(item, parent);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Strips names and the ids of users and chats from a Telegram update.
+pub fn anonymize(mut update: serde_json::Value) -> serde_json::Value {
+    anonymize_value(&mut update, None);
+    update
+}
+
+/// Writes the fixture as `<update_id>-<timestamp>.json` into the directory.
+pub fn save(dir: &str, update_id: i64, fixture: &Fixture) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+
+    let path = Path::new(dir).join(format!("{}-{}.json", update_id, Utc::now().timestamp()));
+    fs::write(&path, serde_json::to_vec_pretty(fixture)?)?;
+
+    log::info!("Recorded fixture {}", path.display());
+
+    Ok(())
+}
+
+/// Rebuilds the transaction from the recorded NLP provider output, standing
+/// in for the provider and the user's database records, and compares it
+/// with the recorded Firefly III payload. Returns whether they match.
+pub fn replay(path: &str) -> Result<bool, Error> {
+    let fixture = serde_json::from_slice::<Fixture>(&fs::read(path)?)?;
+
+    let context = match fixture.context {
+        Some(context) => context,
+        None => {
+            println!("The fixture has no parsed message to replay, recorded outcome: {}", fixture.outcome);
+            return Ok(true);
+        },
+    };
+
+    let replayed = match fixture.parsed.map(|parsed| telegram::build_transaction(parsed, &context)).transpose() {
+        Ok(Some(transaction)) => Some(serde_json::to_value(telegram::TransactPayload::single(transaction))?),
+        Ok(None) => None,
+        Err(e) => {
+            println!("Replay failed: {}", e);
+            None
+        },
+    };
+
+    let matched = replayed == fixture.firefly_payload;
+
+    println!("Recorded outcome: {}", fixture.outcome);
+    println!("Recorded payload:\n{}", serde_json::to_string_pretty(&fixture.firefly_payload)?);
+    println!("Replayed payload:\n{}", serde_json::to_string_pretty(&replayed)?);
+    println!("{}", if matched { "MATCH" } else { "MISMATCH" });
+
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_every_user_and_chat() {
+        let update = anonymize(serde_json::json!({
+            "update_id": 7,
+            "message": {
+                "message_id": 12,
+                "from": { "id": 100, "first_name": "Sam" },
+                "chat": { "id": -200, "title": "Household" },
+                "sender_chat": { "id": -300 },
+                "forward_from": { "id": 400 },
+                "forward_from_chat": { "id": -500 },
+                "via_bot": { "id": 600, "username": "some_bot" },
+                "contact": { "user_id": 700, "phone_number": "+100" },
+                "new_chat_members": [{ "id": 800 }],
+                "reply_to_message": {
+                    "message_id": 11,
+                    "from": { "id": 900 },
+                    "chat": { "id": -200 },
+                },
+            },
+        }));
+
+        let text = update.to_string();
+        for id in &["100", "200", "300", "400", "500", "600", "700", "800", "900", "Sam", "Household", "some_bot"] {
+            assert!(!text.contains(id), "{} is left in {}", id, text);
+        }
+
+        assert_eq!(update["update_id"], 7);
+        assert_eq!(update["message"]["message_id"], 12);
+        assert_eq!(update["message"]["reply_to_message"]["message_id"], 11);
+    }
+}
@@ -0,0 +1,287 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use crate::firefly::{Bill, Recurrence};
+use crate::recurrence::day_of_month;
+
+/// How far `/forecast` looks ahead unless told otherwise.
+pub const DEFAULT_DAYS: i64 = 30;
+
+/// How far `/forecast` looks ahead at most.
+pub const MAX_DAYS: i64 = 365;
+
+/// How many past days the everyday spending is averaged over.
+pub const HISTORY_DAYS: i64 = 90;
+
+/// Parses the horizon of `/forecast`, e.g. `30d` or `45`.
+pub fn parse_days(args: &str) -> Option<i64> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Some(DEFAULT_DAYS);
+    }
+
+    args.trim_end_matches(['d', 'D'])
+        .parse::<i64>()
+        .ok()
+        .filter(|days| (1..=MAX_DAYS).contains(days))
+}
+
+/// The date part of the dates and timestamps Firefly III sends.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Adds months to the date, keeping the day or the month's last one.
+fn add_months(date: NaiveDate, months: u32) -> NaiveDate {
+    let month0 = date.month0() + months;
+    day_of_month(date.year() + (month0 / 12) as i32, month0 % 12 + 1, date.day())
+}
+
+/// The dates a repetition falls on between `start` and `end`, both
+/// included. Repetitions the bot can't work out itself are taken from the
+/// few occurrences Firefly III lists.
+fn repetition_dates(repetition_type: &str, moment: &str, skip: u32, first_date: NaiveDate, occurrences: &[String], start: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+    let step = i64::from(skip) + 1;
+
+    let mut date = match repetition_type {
+        "daily" => first_date,
+        "weekly" => {
+            const WEEKDAYS: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+            // Firefly III counts the days of the week from 1 for Monday.
+            let weekday = match moment.parse::<usize>().ok().and_then(|d| WEEKDAYS.get(d.checked_sub(1)?)) {
+                Some(weekday) => weekday,
+                None => return vec![],
+            };
+            let days = (7 + weekday.num_days_from_monday() - first_date.weekday().num_days_from_monday()) % 7;
+            first_date + Duration::days(i64::from(days))
+        },
+        "monthly" => match moment.parse::<u32>() {
+            Ok(day) => {
+                let date = day_of_month(first_date.year(), first_date.month(), day);
+                if date < first_date { add_months(date, 1) } else { date }
+            },
+            Err(_) => return vec![],
+        },
+        "yearly" => match parse_date(moment) {
+            Some(moment) => {
+                let date = day_of_month(first_date.year(), moment.month(), moment.day());
+                if date < first_date { add_months(date, 12) } else { date }
+            },
+            None => return vec![],
+        },
+        _ => {
+            return occurrences
+                .iter()
+                .filter_map(|o| parse_date(o))
+                .filter(|date| *date >= start && *date <= end)
+                .collect();
+        },
+    };
+
+    let mut dates = vec![];
+    let mut count = 0;
+
+    while date <= end {
+        if date >= start {
+            dates.push(date);
+        }
+
+        count += 1;
+        date = match repetition_type {
+            "daily" => first_date + Duration::days(step * count),
+            "weekly" => date + Duration::weeks(step),
+            "monthly" => add_months(date, step as u32),
+            _ => add_months(date, 12 * step as u32),
+        };
+    }
+
+    dates
+}
+
+/// A change of the balance expected on a day.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub date: NaiveDate,
+    pub amount: f64,
+    pub description: String,
+    pub is_bill: bool,
+}
+
+/// The bills in the currency expected between `start` and `end`, paid from
+/// the account at the average of their amounts.
+pub fn bill_events(bills: &[Bill], currency_code: Option<&str>, start: NaiveDate, end: NaiveDate) -> Vec<Event> {
+    bills
+        .iter()
+        .filter(|bill| currency_code.is_none() || bill.currency_code.as_deref() == currency_code)
+        .flat_map(|bill| {
+            let min = bill.amount_min.parse::<f64>().unwrap_or_default();
+            let max = bill.amount_max.parse::<f64>().unwrap_or(min);
+
+            bill.pay_dates
+                .iter()
+                .filter_map(|date| parse_date(date))
+                .filter(move |date| *date >= start && *date <= end)
+                .map(move |date| Event {
+                    date,
+                    amount: -(min + max).abs() / 2.0,
+                    description: bill.name.to_owned(),
+                    is_bill: true,
+                })
+        })
+        .collect()
+}
+
+/// The recurring transactions moving money in or out of the account
+/// between `start` and `end`.
+pub fn recurrence_events(recurrences: &[Recurrence], account_id: &str, currency_code: Option<&str>, start: NaiveDate, end: NaiveDate) -> Vec<Event> {
+    let mut events = vec![];
+
+    for recurrence in recurrences {
+        let first_date = match parse_date(&recurrence.first_date) {
+            Some(date) => date,
+            None => continue,
+        };
+        let end = recurrence.repeat_until.as_deref().and_then(parse_date).map_or(end, |until| until.min(end));
+
+        // What a single occurrence does to the balance of the account.
+        let change = recurrence.transactions
+            .iter()
+            .filter(|t| currency_code.is_none() || t.currency_code.as_deref() == currency_code)
+            .map(|t| {
+                let amount = t.amount.parse::<f64>().unwrap_or_default().abs();
+                let outgoing = if t.source_id.as_deref() == Some(account_id) { amount } else { 0.0 };
+                let incoming = if t.destination_id.as_deref() == Some(account_id) { amount } else { 0.0 };
+                incoming - outgoing
+            })
+            .sum::<f64>();
+
+        if change == 0.0 {
+            continue;
+        }
+
+        for repetition in &recurrence.repetitions {
+            let dates = repetition_dates(
+                &repetition.repetition_type,
+                &repetition.moment,
+                repetition.skip,
+                first_date,
+                &repetition.occurrences,
+                start,
+                end,
+            );
+
+            events.extend(dates.into_iter().map(|date| Event {
+                date,
+                amount: change,
+                description: recurrence.title.to_owned(),
+                is_bill: false,
+            }));
+        }
+    }
+
+    events
+}
+
+/// The number of bills and recurring transactions listed by `/forecast`.
+const UPCOMING_LIMIT: usize = 5;
+
+/// The balance of an account projected day by day.
+#[derive(Debug)]
+pub struct Forecast {
+    pub account: String,
+    pub currency_code: Option<String>,
+    pub balance: f64,
+    pub days: i64,
+    pub bills: f64,
+    pub bill_count: usize,
+    pub recurring: f64,
+    pub recurring_count: usize,
+    pub daily_spend: f64,
+    pub final_balance: f64,
+    pub lowest: (NaiveDate, f64),
+    pub upcoming: Vec<Event>,
+    /// The first day the balance drops below zero, if it does.
+    pub negative_on: Option<NaiveDate>,
+}
+
+impl Forecast {
+    /// Walks from `today` through the next `days` days, applying the events
+    /// of each day and the average everyday spending.
+    pub fn project(account: String, currency_code: Option<String>, balance: f64, mut events: Vec<Event>, daily_spend: f64, today: NaiveDate, days: i64) -> Self {
+        events.sort_by_key(|e| e.date);
+
+        let bills = events.iter().filter(|e| e.is_bill).map(|e| e.amount).sum();
+        let bill_count = events.iter().filter(|e| e.is_bill).count();
+        let recurring = events.iter().filter(|e| !e.is_bill).map(|e| e.amount).sum();
+        let recurring_count = events.iter().filter(|e| !e.is_bill).count();
+
+        let mut running = balance;
+        let mut lowest = (today, balance);
+        let mut negative_on = None;
+
+        for offset in 0..=days {
+            let date = today + Duration::days(offset);
+
+            running += events.iter().filter(|e| e.date == date).map(|e| e.amount).sum::<f64>();
+            if offset > 0 {
+                running -= daily_spend;
+            }
+
+            if running < lowest.1 {
+                lowest = (date, running);
+            }
+            if running < 0.0 && negative_on.is_none() {
+                negative_on = Some(date);
+            }
+        }
+
+        Self {
+            account,
+            currency_code,
+            balance,
+            days,
+            bills,
+            bill_count,
+            recurring,
+            recurring_count,
+            daily_spend,
+            final_balance: running,
+            lowest,
+            upcoming: events.into_iter().take(UPCOMING_LIMIT).collect(),
+            negative_on,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let currency = self.currency_code.as_deref().map(|c| format!(" {}", c)).unwrap_or_default();
+
+        let mut lines = vec![
+            format!("Forecast for {} over the next {} days:", self.account, self.days),
+            String::new(),
+            format!("Now: {:.2}{}", self.balance, currency),
+            format!("Bills: {:+.2} ({})", self.bills, self.bill_count),
+            format!("Recurring transactions: {:+.2} ({})", self.recurring, self.recurring_count),
+            format!(
+                "Everyday spending: {:+.2} ({:.2} a day)",
+                -self.daily_spend * self.days as f64,
+                self.daily_spend,
+            ),
+            format!("In {} days: {:.2}{}", self.days, self.final_balance, currency),
+            format!("Lowest: {:.2}{} on {}", self.lowest.1, currency, self.lowest.0.format("%B %-d")),
+            String::new(),
+        ];
+
+        if !self.upcoming.is_empty() {
+            lines.push("Coming up:".to_owned());
+            lines.extend(self.upcoming.iter().map(|e| format!("- {}: {} {:+.2}", e.date.format("%B %-d"), e.description, e.amount)));
+            lines.push(String::new());
+        }
+
+        lines.push(match self.negative_on {
+            Some(date) => format!("⚠️ The balance is projected to go negative on {}.", date.format("%B %-d")),
+            None => "✅ The balance is projected to stay above zero.".to_owned(),
+        });
+
+        lines.join("\n")
+    }
+}
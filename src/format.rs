@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use super::Error;
+
+/// Private use characters marking formatted spans in rendered templates,
+/// they're turned into markup for the parse mode once the rest of the text
+/// is escaped.
+const BOLD_START: char = '\u{E000}';
+const BOLD_END: char = '\u{E001}';
+const CODE_START: char = '\u{E002}';
+const CODE_END: char = '\u{E003}';
+
+/// How formatted replies are marked up for Telegram, set with `MESSAGE_PARSE_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    MarkdownV2,
+    Html,
+}
+
+impl FromStr for ParseMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "markdownv2" | "markdown" => Ok(Self::MarkdownV2),
+            "html" => Ok(Self::Html),
+            _ => Err(Error::Config(format!("Unknown parse mode {}, expected markdownv2 or html.", value))),
+        }
+    }
+}
+
+impl ParseMode {
+    /// The `parse_mode` of the Bot API.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MarkdownV2 => "MarkdownV2",
+            Self::Html => "HTML",
+        }
+    }
+
+    fn escape(self, text: &str, in_code: bool) -> String {
+        match self {
+            Self::MarkdownV2 if in_code => escape_markdown_code(text),
+            Self::MarkdownV2 => escape_markdown(text),
+            Self::Html => escape_html(text),
+        }
+    }
+
+    fn markup(self, marker: char) -> &'static str {
+        match (self, marker) {
+            (Self::MarkdownV2, BOLD_START) | (Self::MarkdownV2, BOLD_END) => "*",
+            (Self::MarkdownV2, _) => "`",
+            (Self::Html, BOLD_START) => "<b>",
+            (Self::Html, BOLD_END) => "</b>",
+            (Self::Html, CODE_START) => "<code>",
+            (Self::Html, _) => "</code>",
+        }
+    }
+
+    /// Escapes the text for the parse mode, turning the spans marked with
+    /// `bold` and `code` into markup.
+    pub fn render(self, text: &str) -> String {
+        let mut rendered = String::new();
+        let mut plain = String::new();
+        let mut in_code = false;
+
+        for c in text.chars() {
+            if !is_marker(c) {
+                plain.push(c);
+                continue;
+            }
+
+            rendered.push_str(&self.escape(&plain, in_code));
+            plain.clear();
+            rendered.push_str(self.markup(c));
+            in_code = c == CODE_START;
+        }
+
+        rendered.push_str(&self.escape(&plain, in_code));
+        rendered
+    }
+}
+
+fn is_marker(c: char) -> bool {
+    (BOLD_START..=CODE_END).contains(&c)
+}
+
+/// Marks the text to be shown in bold.
+pub fn bold(text: &str) -> String {
+    format!("{}{}{}", BOLD_START, strip(text), BOLD_END)
+}
+
+/// Marks the text to be shown as inline code.
+pub fn code(text: &str) -> String {
+    format!("{}{}{}", CODE_START, strip(text), CODE_END)
+}
+
+/// The text without formatting, for replies sent as plain text and for
+/// values that must not carry markers of their own.
+pub fn strip(text: &str) -> String {
+    text.chars().filter(|c| !is_marker(*c)).collect()
+}
+
+/// Status emoji spelled out in plain text replies, other emoji are dropped.
+const SPELLED_EMOJI: &[(char, &str)] = &[
+    ('✅', "OK:"),
+    ('❌', "Failed:"),
+];
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // pictographs, emoticons, transport, flags
+        | 0x2600..=0x27BF // symbols and dingbats
+        | 0x2B00..=0x2BFF // arrows and shapes such as ⬛
+        | 0xFE0F // emoji presentation selector
+        | 0x200D // joiner of emoji sequences
+        | 0x20E3 // keycap
+    )
+}
+
+/// The text without emoji, for users who asked for plain text replies.
+pub fn without_emoji(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    // The space after a dropped emoji goes with it.
+    let mut dropped = false;
+
+    for c in text.chars() {
+        match SPELLED_EMOJI.iter().find(|(emoji, _)| *emoji == c) {
+            Some((_, spelled)) => plain.push_str(spelled),
+            None if is_emoji(c) => {
+                dropped = true;
+                continue;
+            },
+            None if c == ' ' && dropped => {},
+            None => plain.push(c),
+        }
+        dropped = false;
+    }
+
+    plain
+}
+
+/// Escapes every character MarkdownV2 gives a meaning to.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Escapes text inside MarkdownV2 code spans, where only backticks and
+/// backslashes need it.
+pub fn escape_markdown_code(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markdown() {
+        let special = "_*[]()~`>#+-=|{}.!\\";
+        let escaped = special.chars().map(|c| format!("\\{}", c)).collect::<String>();
+
+        assert_eq!(ParseMode::MarkdownV2.render(special), escaped);
+        assert_eq!(ParseMode::MarkdownV2.render("Coffee 4.50 (cash)!"), "Coffee 4\\.50 \\(cash\\)\\!");
+    }
+
+    #[test]
+    fn marks_up_markdown() {
+        let text = format!("{} at {}.", bold("Lunch_1"), code("a_b`c\\d"));
+
+        assert_eq!(ParseMode::MarkdownV2.render(&text), "*Lunch\\_1* at `a_b\\`c\\\\d`\\.");
+    }
+
+    #[test]
+    fn marks_up_html() {
+        let text = format!("{} <{}> & {}", bold("Fish & chips"), "x", code("a<b"));
+
+        assert_eq!(ParseMode::Html.render(&text), "<b>Fish &amp; chips</b> &lt;x&gt; &amp; <code>a&lt;b</code>");
+    }
+
+    #[test]
+    fn strips_the_markers() {
+        assert_eq!(strip(&format!("{} {}", bold("a"), code("b"))), "a b");
+        assert_eq!(bold(&bold("a")), bold("a"));
+    }
+}
@@ -0,0 +1,62 @@
+/// The number of single character edits turning one string into the other.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// How alike two names are, from 0 to 1, ignoring case. A name contained
+/// in the other (e.g. "savings" in "BPI Savings") counts as a full match.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (a.trim().to_lowercase(), b.trim().to_lowercase());
+
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    if a.contains(&b) || b.contains(&a) {
+        return 1.0;
+    }
+
+    let (a, b) = (a.chars().collect::<Vec<char>>(), b.chars().collect::<Vec<char>>());
+    let longest = a.len().max(b.len());
+
+    1.0 - edit_distance(&a, &b) as f64 / longest as f64
+}
+
+/// Scores every candidate against the name, best first.
+pub fn rank<'a>(name: &str, candidates: &'a [String]) -> Vec<(f64, &'a String)> {
+    let mut ranked = candidates
+        .iter()
+        .map(|candidate| (similarity(name, candidate), candidate))
+        .collect::<Vec<_>>();
+
+    ranked.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    ranked
+}
+
+/// The candidate a mistyped word was most likely meant to be, allowing one
+/// edit for short words and two otherwise. Swapped letters (e.g. "strat")
+/// count as two edits.
+pub fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let word = word.chars().collect::<Vec<char>>();
+    let allowed = if word.len() <= 3 { 1 } else { 2 };
+
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(&word, &candidate.chars().collect::<Vec<char>>()), *candidate))
+        .filter(|(distance, _)| *distance <= allowed)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
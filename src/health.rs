@@ -0,0 +1,52 @@
+use super::Database;
+
+/// The outcome of a single health check.
+pub struct Check {
+    pub name: &'static str,
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn from_result<E: std::fmt::Display>(name: &'static str, result: Result<(), E>) -> Self {
+        Self {
+            name,
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Verifies the database answers reads.
+pub fn check_storage(db: &Database) -> Check {
+    Check::from_result("storage", db.users.contains_key(b"healthz").map(|_| ()))
+}
+
+/// Verifies the bot token is accepted by Telegram with a `getMe` call.
+pub async fn check_telegram() -> Check {
+    let result = match super::telegram_post("getMe", &serde_json::json!({})).await {
+        Ok(resp) if resp.status().is_success() => Ok(()),
+        Ok(resp) => Err(format!("Telegram responded with {}", resp.status())),
+        Err(e) => Err(e.to_string()),
+    };
+
+    Check::from_result("telegram", result)
+}
+
+/// Renders the checks as the JSON body of `/healthz` and `/readyz`.
+pub fn to_json(checks: &[Check]) -> serde_json::Value {
+    let details = checks
+        .iter()
+        .map(|c| (c.name.to_owned(), serde_json::json!({
+            "ok": c.is_ok(),
+            "error": c.error,
+        })))
+        .collect::<serde_json::Map<String, serde_json::Value>>();
+
+    serde_json::json!({
+        "success": checks.iter().all(|c| c.is_ok()),
+        "checks": details,
+    })
+}
@@ -0,0 +1,70 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// A single-use code handed out with `/link`, letting another Telegram
+/// user log to the owner's Firefly III account once the owner approves.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LinkCode {
+    pub owner_id: i64,
+    pub expires_at: i64,
+}
+
+/// A request to share an account, waiting on the owner's approval.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LinkRequest {
+    pub member_id: i64,
+    pub member_name: String,
+}
+
+/// Drops the link codes that expired unused, returns how many were removed.
+pub fn purge_expired_codes(db: &Database) -> Result<usize, Error> {
+    let now = Utc::now().timestamp();
+
+    let expired = db.link_codes
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, code)| code.expires_at <= now)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &expired {
+        db.link_codes.remove(key)?;
+    }
+
+    Ok(expired.len())
+}
+
+/// Creates a link code to the owner's account valid for `ttl`, dropping
+/// the expired ones.
+pub fn create_code(db: &Database, owner_id: i64, ttl: Duration) -> Result<String, Error> {
+    purge_expired_codes(db)?;
+
+    let code = Uuid::new_v4().to_simple().to_string();
+
+    db.link_codes.insert(code.as_bytes(), LinkCode {
+        owner_id,
+        expires_at: Utc::now().timestamp() + ttl.num_seconds(),
+    })?;
+
+    Ok(code)
+}
+
+/// Uses up the code, returns the owner of the account it links to or
+/// `None` for unknown or expired codes.
+pub fn redeem_code(db: &Database, code: &str) -> Result<Option<i64>, Error> {
+    let link = db.link_codes.get(code.as_bytes())?;
+
+    // Claiming the code in a batch makes sure it's only used once.
+    let mut batch = WriteBatch::default();
+    batch.claim(&db.link_codes, code);
+    if !batch.apply(db)? {
+        return Ok(None);
+    }
+
+    Ok(link.filter(|l| l.expires_at > Utc::now().timestamp()).map(|l| l.owner_id))
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+use crate::config::Variables;
+
+use super::Error;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_COLD_START_TIMEOUT_SECS: u64 = 90;
+
+/// The bot's name and version, sent as the `User-Agent` of every request.
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Shared HTTP clients, one per upstream so each keeps its own connection pool.
+pub struct HttpClients {
+    pub telegram: reqwest::Client,
+    pub wit: reqwest::Client,
+    pub nlp: reqwest::Client,
+    pub firefly: reqwest::Client,
+    pub ocr: reqwest::Client,
+    pub translate: reqwest::Client,
+    pub exchange: reqwest::Client,
+    pub alerts: reqwest::Client,
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// How long a Firefly III instance that sleeps between requests gets to
+    /// wake up, for users with `/settings coldstart on`.
+    pub cold_start_timeout: Duration,
+}
+
+/// The timeouts and client id the clients are built with.
+pub struct HttpSettings {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub cold_start_timeout: Duration,
+    pub client_id: Option<HeaderValue>,
+}
+
+impl HttpSettings {
+    /// Reads `HTTP_TIMEOUT_SECS`, `HTTP_CONNECT_TIMEOUT_SECS`,
+    /// `FIREFLY_COLD_START_TIMEOUT_SECS` and `HTTP_CLIENT_ID`.
+    pub fn read(vars: &mut Variables) -> Self {
+        let mut secs = |name: &str, default: u64| Duration::from_secs(vars.number(name, 1, default));
+        let timeout = secs("HTTP_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS);
+        let connect_timeout = secs("HTTP_CONNECT_TIMEOUT_SECS", DEFAULT_CONNECT_TIMEOUT_SECS);
+        let cold_start_timeout = secs("FIREFLY_COLD_START_TIMEOUT_SECS", DEFAULT_COLD_START_TIMEOUT_SECS);
+
+        let client_id = vars.text("HTTP_CLIENT_ID").and_then(|client_id| {
+            let value = HeaderValue::from_str(client_id.trim()).ok();
+            if value.is_none() {
+                vars.problem("HTTP_CLIENT_ID must be a valid header value.".into());
+            }
+            value
+        });
+
+        Self { timeout, connect_timeout, cold_start_timeout, client_id }
+    }
+}
+
+impl HttpClients {
+    /// Builds the clients, requests say which upstream they're meant for in
+    /// the `User-Agent`, e.g. `firefly_tg/0.1.8 (firefly)`, and carry
+    /// `HTTP_CLIENT_ID` as the `X-Client` header when it's set.
+    pub fn new(settings: &HttpSettings) -> Result<Self, Error> {
+        let HttpSettings { timeout, connect_timeout, cold_start_timeout, .. } = *settings;
+
+        let mut headers = HeaderMap::new();
+        if let Some(value) = &settings.client_id {
+            headers.insert("X-Client", value.to_owned());
+        }
+
+        let build = |upstream: &str| reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(connect_timeout)
+            .user_agent(format!("{} ({})", USER_AGENT, upstream))
+            .default_headers(headers.to_owned())
+            .build()
+            .map_err(|e| Error::Config(e.to_string()));
+
+        Ok(Self {
+            telegram: build("telegram")?,
+            wit: build("wit")?,
+            nlp: build("nlp")?,
+            firefly: build("firefly")?,
+            ocr: build("ocr")?,
+            translate: build("translate")?,
+            exchange: build("exchange")?,
+            alerts: build("alerts")?,
+            timeout,
+            connect_timeout,
+            cold_start_timeout,
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use crate::format;
+
+use super::{Database, Error};
+
+/// Reads the language of `/language de`, an ISO 639-1 code optionally
+/// followed by a region such as `pt-br`.
+pub fn parse_code(args: &str) -> Option<String> {
+    let code = args.trim().to_lowercase().replace('_', "-");
+    let mut parts = code.splitn(2, '-');
+
+    let language = parts.next()?;
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && parts.next().is_none_or(|region| !region.is_empty() && region.len() <= 4 && region.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if valid {
+        Some(code)
+    } else {
+        None
+    }
+}
+
+/// The language the user picked with `/language`, `None` to follow their
+/// Telegram app.
+pub fn preferred(db: &Database, from_id: i64) -> Result<Option<String>, Error> {
+    Ok(db.languages.get(from_id.to_be_bytes())?)
+}
+
+pub fn set(db: &Database, from_id: i64, code: &str) -> Result<(), Error> {
+    db.languages.insert(&from_id.to_be_bytes(), code.to_owned())?;
+    Ok(())
+}
+
+/// Goes back to the language of the user's Telegram app.
+pub fn clear(db: &Database, from_id: i64) -> Result<Option<String>, Error> {
+    Ok(db.languages.remove(from_id.to_be_bytes())?)
+}
+
+/// A reply sent outside of the user's own update, e.g. by a background job,
+/// in the language they picked with `/language`.
+pub fn text(db: &Database, from_id: i64, name: &str, context: serde_json::Value) -> String {
+    let language = preferred(db, from_id).unwrap_or_default();
+    format::strip(&super::config().templates.render(name, language.as_deref(), context))
+}
@@ -0,0 +1,141 @@
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::Utc;
+use fs2::FileExt;
+use tokio::time::sleep;
+
+use super::Error;
+
+/// Decides which replica runs the background jobs, by holding a lease file
+/// on storage shared by all of them. Without a lease path there is only
+/// one instance and it always leads.
+///
+/// sled lets a single process open a database, so the replicas can't write
+/// to the same storage directory at once. The lease suits a standby that
+/// takes over the storage when the leader goes away, not replicas writing
+/// side by side.
+pub struct Election {
+    path: Option<PathBuf>,
+    instance_id: String,
+    lease_secs: i64,
+    leader: AtomicBool,
+}
+
+/// Reads the holder and expiry of the lease, stored as `<instance_id> <expires_at>`.
+fn read_lease(path: &Path) -> Option<(String, i64)> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut parts = content.split_whitespace();
+
+    Some((parts.next()?.to_owned(), parts.next()?.parse().ok()?))
+}
+
+impl Election {
+    pub fn new(path: Option<PathBuf>, instance_id: String, lease_secs: i64) -> Self {
+        Self {
+            leader: AtomicBool::new(path.is_none()),
+            path,
+            instance_id,
+            lease_secs,
+        }
+    }
+
+    pub fn lease_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Checks if this instance should run the background jobs right now.
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn lease_secs(&self) -> i64 {
+        self.lease_secs
+    }
+
+    /// Takes or renews the lease unless another instance holds it and it
+    /// hasn't expired yet, returns whether this instance holds it afterwards.
+    /// The check and the claim happen under an exclusive lock on a sibling
+    /// `.lock` file, so two instances can't both claim an expired lease.
+    fn try_acquire(&self, path: &Path) -> Result<bool, Error> {
+        let lock = OpenOptions::new().create(true).write(true).truncate(false).open(path.with_extension("lock"))?;
+        lock.lock_exclusive()?;
+
+        let now = Utc::now().timestamp();
+
+        if let Some((holder, expires_at)) = read_lease(path) {
+            if holder != self.instance_id && expires_at > now {
+                return Ok(false);
+            }
+        }
+
+        // Renamed into place so a crash mid-write never leaves a torn lease.
+        let claim = path.with_extension(format!("{}.tmp", self.instance_id));
+        fs::write(&claim, format!("{} {}", self.instance_id, now + self.lease_secs))?;
+        fs::rename(&claim, path)?;
+
+        // The lock is released when the file is closed.
+        Ok(true)
+    }
+}
+
+/// Checks if this instance runs the background jobs. Every job loop asks
+/// before each round, so when replicas share the work only the lease holder
+/// touches the queues and a new leader picks them up at its next round.
+pub fn is_leader() -> bool {
+    super::LEADER.is_leader()
+}
+
+/// Keeps trying to take or renew the lease, well before it expires.
+pub async fn run_election_loop() {
+    let election = &*super::LEADER;
+
+    let path = match &election.path {
+        Some(path) => path,
+        None => return,
+    };
+
+    loop {
+        let leader = election.try_acquire(path).unwrap_or_else(|e| {
+            log::error!("Failed to acquire the leader lease: {}", e);
+            false
+        });
+
+        if election.leader.swap(leader, Ordering::Relaxed) != leader {
+            if leader {
+                log::info!("Instance {} is now the leader and runs the background jobs", election.instance_id);
+            } else {
+                log::info!("Instance {} is no longer the leader", election.instance_id);
+            }
+        }
+
+        sleep(std::time::Duration::from_secs((election.lease_secs / 3).max(1) as u64)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_lease_from_other_instances_until_it_expires() {
+        let dir = std::env::temp_dir().join(format!("leader-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lease");
+
+        let first = Election::new(Some(path.to_owned()), "first".into(), 30);
+        let second = Election::new(Some(path.to_owned()), "second".into(), 30);
+        assert!(first.try_acquire(&path).unwrap());
+        assert!(!second.try_acquire(&path).unwrap());
+        assert!(first.try_acquire(&path).unwrap());
+
+        fs::write(&path, format!("first {}", Utc::now().timestamp() - 1)).unwrap();
+        assert!(second.try_acquire(&path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,40 +1,180 @@
+mod access;
+mod accessibility;
+mod accounts;
+mod admin;
+mod alert;
+mod attachment;
+mod batch;
+mod botapi;
+mod budget;
+mod cache;
+mod category;
+mod chart;
+mod commands;
+mod config;
+mod correction;
+mod dashboard;
+mod currency;
+mod dates;
+mod dedup;
+mod description;
+mod diagnostics;
+mod digest;
+mod error;
+mod exchange;
+mod export;
+mod firefly;
+mod fixture;
+mod forecast;
+mod format;
+mod fuzzy;
+mod health;
+mod household;
+mod http;
+mod language;
+mod merchants;
+mod leader;
+mod migrations;
+mod nlp;
+mod ocr;
+mod outbox;
+mod parser;
+mod pending;
+mod project;
+mod profile;
+mod quota;
+mod ratelimit;
+mod recurrence;
+mod report;
+mod retention;
+mod review;
+mod rules;
+mod scheduler;
+mod secrets;
+mod selftest;
+mod settings;
+mod shortlist;
+mod snapshot;
+mod stats;
+mod support;
 mod telegram;
+mod templates;
+mod terms;
+mod timezone;
+mod translate;
+mod typing;
+mod undo;
 mod wit;
 
-use std::{env, sync::Arc};
+use std::{env, sync::{Arc, OnceLock}};
 use log::{info, error};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use routerify::prelude::*;
 use routerify::{Middleware, Router, RouterService};
 use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
 use sled_extensions::DbExt;
+use subtle::ConstantTimeEq;
 use sled_extensions::bincode::Tree;
+use access::{AccessGrant, Invite};
+use accounts::AccountList;
+use botapi::{ApiResponse, SendQueue};
+use budget::CategorySpend;
+use cache::ResponseCache;
+use category::CategoryMap;
+use config::Config;
+use correction::CreatedTransaction;
+use dashboard::RecentErrors;
+use dedup::{LastText, LastUpdate};
+use digest::DigestSchedule;
+use household::LinkCode;
+use http::HttpClients;
+use leader::Election;
+use merchants::MerchantMemory;
+use outbox::OutboxEntry;
+use pending::PendingEntry;
+use quota::DailyUsage;
+use ratelimit::{Bucket, RateLimiter};
+use review::UnconfirmedItem;
+use settings::ConfirmMode;
+use scheduler::ScheduledEntry;
+use snapshot::Snapshot;
+use support::InteractionLog;
 use telegram::{TelegramContext, UserClue};
+use undo::LastCreated;
 
-pub type GenericError = Box<dyn std::error::Error + Send + Sync>;
+pub use error::Error;
 
-pub type ServiceResult<T> = std::result::Result<T, GenericError>;
+pub type ServiceResult<T> = std::result::Result<T, Error>;
 
 pub struct Database {
+    store: sled_extensions::Db,
     users: Tree<UserClue>,
+    categories: Tree<CategoryMap>,
+    outbox: Tree<OutboxEntry>,
+    scheduled: Tree<ScheduledEntry>,
+    updates: Tree<LastUpdate>,
+    access: Tree<AccessGrant>,
+    stats: Tree<u64>,
+    rate_limits: Tree<Bucket>,
+    snapshots: Tree<Snapshot>,
+    invites: Tree<Invite>,
+    wit_usage: Tree<DailyUsage>,
+    accounts: Tree<AccountList>,
+    category_spend: Tree<CategorySpend>,
+    pending: Tree<PendingEntry>,
+    digests: Tree<DigestSchedule>,
+    created: Tree<CreatedTransaction>,
+    created_texts: Tree<String>,
+    last_created: Tree<LastCreated>,
+    projects: Tree<String>,
+    link_codes: Tree<LinkCode>,
+    chat_profiles: Tree<String>,
+    last_texts: Tree<LastText>,
+    accessibility: Tree<bool>,
+    languages: Tree<String>,
+    operator_defaults: Tree<String>,
+    confirm_modes: Tree<ConfirmMode>,
+    raw_descriptions: Tree<bool>,
+    default_sources: Tree<String>,
+    cold_starts: Tree<bool>,
+    merchants: Tree<MerchantMemory>,
+    unconfirmed: Tree<UnconfirmedItem>,
+    reviews_sent: Tree<i64>,
+    category_shortlists: Tree<Vec<String>>,
+    searches: Tree<String>,
 }
 
 const JSON_MIME: &str = "application/json";
+const HTML_MIME: &str = "text/html; charset=utf-8";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Set once on startup, before anything else reads the configuration.
+static CONFIG: OnceLock<Arc<Config>> = OnceLock::new();
+
+/// The configuration loaded on startup, for the parts of the bot that run
+/// outside of a request such as the background loops.
+fn config() -> &'static Config {
+    CONFIG.get().expect("The configuration is read before it's loaded.")
+}
+
 lazy_static! {
-    static ref TG_BOT_TOKEN: String = {
-        env::var("TG_BOT_TOKEN").expect("Telegram bot token not set.")
+    static ref SEND_QUEUE: SendQueue = SendQueue::default();
+    static ref HTTP_CLIENTS: HttpClients = {
+        HttpClients::new(&config().http).expect("Failed to build the HTTP clients.")
     };
-    static ref TG_MASTER_ID: String = {
-        env::var("TG_MASTER_ID").expect("Telegram master id not set.")
+    static ref FIREFLY_CACHE: ResponseCache = {
+        ResponseCache::new(std::time::Duration::from_secs(config().firefly_cache_ttl_secs))
     };
-    static ref APP_SHARED_STORAGE_PATH: String = {
-        env::var("APP_SHARED_STORAGE_PATH").expect("App shared storage not set.")
+    static ref LEADER: Election = {
+        let config = config();
+        Election::new(config.leader_lease_path.to_owned(), config.instance_id.to_owned(), config.leader_lease_secs)
     };
-    static ref WIT_ACCESS_TOKEN: String = {
-        env::var("WIT_ACCESS_TOKEN").expect("Wit access token not set.")
+    static ref RATE_LIMITER: RateLimiter = {
+        RateLimiter::new(config().rate_limit_burst, config().rate_limit_per_minute)
     };
+    static ref RECENT_ERRORS: RecentErrors = RecentErrors::default();
+    static ref INTERACTIONS: InteractionLog = InteractionLog::default();
 }
 
 async fn hello_world(_: Request<Body>) -> ServiceResult<Response<Body>> {
@@ -53,9 +193,186 @@ async fn hello_world(_: Request<Body>) -> ServiceResult<Response<Body>> {
         .body(Body::from(data.to_string()))?)
 }
 
-async fn run_expensive_task(db: Arc<Database>, update: telegram::Update) -> ServiceResult<()> {
+fn health_response(checks: &[health::Check]) -> ServiceResult<Response<Body>> {
+    let status = if checks.iter().all(|c| c.is_ok()) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(
+            hyper::header::CONTENT_TYPE,
+            JSON_MIME,
+        )
+        .body(Body::from(health::to_json(checks).to_string()))?)
+}
+
+async fn healthz(req: Request<Body>) -> ServiceResult<Response<Body>> {
+    let db = req.data::<Arc<Database>>()
+        .ok_or_else(|| Error::Config("Unknown key-value store instance".into()))?;
+
+    health_response(&[health::check_storage(db)])
+}
+
+async fn readyz(req: Request<Body>) -> ServiceResult<Response<Body>> {
+    let db = req.data::<Arc<Database>>()
+        .ok_or_else(|| Error::Config("Unknown key-value store instance".into()))?;
+
+    let mut checks = vec![health::check_storage(db)];
+
+    if config().readyz_check_telegram {
+        checks.push(health::check_telegram().await);
+    }
+
+    health_response(&checks)
+}
+
+/// Checks the request carries the debug token, either as a bearer token or
+/// as the password of HTTP basic auth so browsers can prompt for it.
+/// Returns `None` when no token is configured.
+fn check_debug_token(req: &Request<Body>) -> Option<bool> {
+    let token = config().app_debug_token.as_deref()?;
+
+    let authorization = req.headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+
+    let authorized = match authorization {
+        Some(value) if value.starts_with("Bearer ") => value.strip_prefix("Bearer ").is_some_and(|v| token_matches(v, token)),
+        Some(value) if value.starts_with("Basic ") => value.strip_prefix("Basic ")
+            .and_then(|v| base64::decode(v.trim()).ok())
+            .and_then(|v| String::from_utf8(v).ok())
+            .and_then(|v| v.split_once(':').map(|(_, password)| token_matches(password, token)))
+            .unwrap_or(false),
+        _ => false,
+    };
+
+    Some(authorized)
+}
+
+/// Compares a presented token with the configured one in constant time.
+/// Both are hashed first so a mismatch doesn't reveal the token's length.
+fn token_matches(presented: &str, token: &str) -> bool {
+    Sha256::digest(presented.as_bytes()).as_slice().ct_eq(Sha256::digest(token.as_bytes()).as_slice()).into()
+}
+
+async fn debug_info(req: Request<Body>) -> ServiceResult<Response<Body>> {
+    // Without a token configured the endpoint does not exist at all.
+    let authorized = match check_debug_token(&req) {
+        Some(authorized) => authorized,
+        None => return handler_404(req).await,
+    };
+
+    if !authorized {
+        let data = serde_json::json!({
+            "success": false,
+            "message": "Unauthorized",
+        });
+
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(
+                hyper::header::CONTENT_TYPE,
+                JSON_MIME,
+            )
+            .body(Body::from(data.to_string()))?);
+    }
+
+    let db = req.data::<Arc<Database>>()
+        .ok_or_else(|| Error::Config("Unknown key-value store instance".into()))?;
+    let config = req.data::<Arc<Config>>()
+        .ok_or_else(|| Error::Config("Unknown configuration".into()))?;
+
+    let data = serde_json::json!({
+        "success": true,
+        "info": diagnostics::info(db, config),
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            hyper::header::CONTENT_TYPE,
+            JSON_MIME,
+        )
+        .body(Body::from(data.to_string()))?)
+}
+
+async fn dashboard(req: Request<Body>) -> ServiceResult<Response<Body>> {
+    // Shares the token of `/debug/info`, without one there is no dashboard.
+    let authorized = match check_debug_token(&req) {
+        Some(authorized) => authorized,
+        None => return handler_404(req).await,
+    };
+
+    if !authorized {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(hyper::header::WWW_AUTHENTICATE, "Basic realm=\"firefly-tg\"")
+            .body(Body::empty())?);
+    }
+
+    let db = req.data::<Arc<Database>>()
+        .ok_or_else(|| Error::Config("Unknown key-value store instance".into()))?;
+
+    let checks = [health::check_storage(db), health::check_telegram().await];
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            hyper::header::CONTENT_TYPE,
+            HTML_MIME,
+        )
+        .body(Body::from(dashboard::render(db, &checks)))?)
+}
+
+async fn run_expensive_task(db: Arc<Database>, update: telegram::Update, raw_update: Option<serde_json::Value>) -> ServiceResult<()> {
+    let chat_id = update.chat_id();
+    let update_id = update.update_id;
+    let summary = update.summary();
     let mut context = TelegramContext::new(db.to_owned());
+
+    if let Some(raw_update) = raw_update {
+        context.record_fixture(raw_update);
+    }
+
     let tg_resp = context.process_message(update).await;
+
+    if let Some(chat_id) = chat_id {
+        let outcome = match &tg_resp {
+            Ok(resp) => format!("Telegram responded with {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+
+        INTERACTIONS.record(chat_id, summary, outcome, tg_resp.is_err());
+    }
+
+    if let (Some(mut fixture), Some(dir)) = (context.take_fixture(), &config().app_fixture_dir) {
+        fixture.outcome = match &tg_resp {
+            Ok(resp) => format!("Telegram responded with {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+        fixture.chat = chat_id.map(fixture::chat_tag);
+
+        if let Err(e) = fixture::save(dir, update_id, &fixture) {
+            error!("Failed to record the fixture: {}", e);
+        }
+    }
+
+    if let (Err(e), Some(chat_id)) = (&tg_resp, chat_id) {
+        let language_code = context.language_code();
+        if let Some(message) = e.user_message(language_code) {
+            if let Err(e) = telegram_post("sendMessage", &serde_json::json!({
+                "chat_id": chat_id,
+                "text": format::strip(&config().templates.render("error", language_code, serde_json::json!({ "message": message }))),
+            }))
+            .await {
+                error!("Failed to notify the user of the error: {}", e);
+            }
+        }
+    }
+
     match tg_resp {
         Ok(t) => {
             if t.status() != StatusCode::OK {
@@ -69,7 +386,13 @@ async fn run_expensive_task(db: Arc<Database>, update: telegram::Update) -> Serv
                 error!("Fatal error occurred:\n{}", serde_json::to_string_pretty(&data)?);
             }
         },
+        Err(e) if e.is_user_error() => {
+            stats::record(&db, stats::USER_ERRORS);
+            info!("Could not process the message: {}", e);
+        },
         Err(e) => {
+            stats::record(&db, stats::ERRORS);
+            RECENT_ERRORS.push(&e.to_string());
             send_report(&e.to_string()).await;
 
             let data = serde_json::json!({
@@ -86,12 +409,23 @@ async fn run_expensive_task(db: Arc<Database>, update: telegram::Update) -> Serv
 }
 
 async fn handle_telegram_message(req: Request<Body>) -> ServiceResult<Response<Body>> {
-    let db = req.data::<Arc<Database>>().ok_or("Unknown key-value store instance")?.to_owned();
+    let db = req.data::<Arc<Database>>()
+        .ok_or_else(|| Error::Config("Unknown key-value store instance".into()))?
+        .to_owned();
     let (_, body) = req.into_parts();
     let body_raw = hyper::body::to_bytes(body).await?;
     let update = serde_json::from_slice::<telegram::Update>(&body_raw)?;
 
-    tokio::spawn(run_expensive_task(db, update));
+    if dedup::check_and_record(&db, &update)? {
+        info!("Skipping re-delivered update {}", update.update_id);
+    } else {
+        let raw_update = match &config().app_fixture_dir {
+            Some(_) => Some(serde_json::from_slice::<serde_json::Value>(&body_raw)?),
+            None => None,
+        };
+
+        tokio::spawn(run_expensive_task(db, update, raw_update));
+    }
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -100,34 +434,314 @@ async fn handle_telegram_message(req: Request<Body>) -> ServiceResult<Response<B
 }
 
 async fn send_report(error_message: &str) {
-    let message = format!("Firefly Bot Error: {}", error_message);
+    alert::send(&format!("Firefly Bot Error: {}", error_message)).await;
+}
 
-    let tg_resp = telegram_post("sendMessage", &serde_json::json!({
-        "chat_id": *TG_MASTER_ID,
-        "text": message,
-    }))
-    .await;
+/// Reads the Bot API envelope of the response, logging what Telegram
+/// rejected. The body was read, so an equivalent response is returned.
+async fn read_reply(endpoint: &str, resp: reqwest::Response) -> Result<(reqwest::Response, Option<ApiResponse>), Error> {
+    let status = resp.status();
+    let headers = resp.headers().to_owned();
+    let bytes = resp.bytes().await.map_err(Error::Telegram)?;
+
+    let reply = serde_json::from_slice::<ApiResponse>(&bytes).ok();
+    if let Some(reply) = reply.as_ref().filter(|reply| !reply.ok) {
+        log::warn!("Telegram rejected {}: {}", endpoint, reply.describe());
+    }
+
+    let mut rebuilt = hyper::http::Response::new(bytes);
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+
+    Ok((reqwest::Response::from(rebuilt), reply))
+}
 
-    tg_resp.expect("Failed to communicate with Telegram servers");
+/// Whether the call failed on the way rather than being refused, so it's
+/// worth making again.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout()
 }
 
-pub async fn telegram_post(endpoint: &str, payload: &serde_json::Value) -> Result<reqwest::Response, reqwest::Error> {
-    let url = format!("https://api.telegram.org/bot{}/{}", *TG_BOT_TOKEN, endpoint);
+/// What's handed back for a chat action left out by the `SendQueue`, as if
+/// Telegram had accepted it.
+fn skipped_reply() -> reqwest::Response {
+    let body = serde_json::json!({ "ok": true, "result": true }).to_string();
+    reqwest::Response::from(hyper::http::Response::new(body))
+}
+
+/// Calls the Bot API with a JSON payload, every call to Telegram goes
+/// through here or `telegram_post_multipart`. Calls wait for their turn in
+/// the `SendQueue`, and are made again once the wait Telegram asks for on
+/// a 429 is over or after failing on the way.
+pub async fn telegram_post(endpoint: &str, payload: &serde_json::Value) -> Result<reqwest::Response, Error> {
+    let url = format!("https://api.telegram.org/bot{}/{}", config().tg_bot_token, endpoint);
+    let mut slot = match SEND_QUEUE.acquire(endpoint, payload["chat_id"].as_i64()).await {
+        Some(slot) => slot,
+        None => return Ok(skipped_reply()),
+    };
+    let mut retries = 0;
+
+    loop {
+        let sent = HTTP_CLIENTS.telegram
+            .post(&url)
+            .json(payload)
+            .send()
+            .await;
+
+        let wait = match sent {
+            Err(e) if is_transient(&e) && retries < botapi::MAX_RETRIES => {
+                log::info!("Failed to reach Telegram, calling {} again: {}", endpoint, e);
+                2u64.pow(retries)
+            },
+            Err(e) => return Err(Error::Telegram(e)),
+            Ok(resp) if resp.status().is_server_error() && retries < botapi::MAX_RETRIES => {
+                log::info!("Telegram failed with {}, calling {} again", resp.status(), endpoint);
+                2u64.pow(retries)
+            },
+            Ok(resp) => {
+                let (resp, reply) = read_reply(endpoint, resp).await?;
+
+                let secs = match reply.filter(|reply| !reply.ok).and_then(|reply| reply.retry_after()) {
+                    Some(secs) => secs,
+                    None => return Ok(resp),
+                };
+                slot.back_off(secs);
+
+                if retries >= botapi::MAX_RETRIES || secs > botapi::MAX_RETRY_AFTER_SECS {
+                    return Ok(resp);
+                }
+
+                log::info!("Rate limited by Telegram, calling {} again in {}s", endpoint, secs);
+                secs
+            },
+        };
+
+        tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
+        retries += 1;
+    }
+}
 
-    reqwest::Client::new()
+/// Posts a multipart form to the Telegram Bot API, for the methods taking
+/// a file upload rather than JSON. Uploads can't be sent twice, so unlike
+/// `telegram_post` a failed call is only logged, though a 429 still holds
+/// back the next calls to the chat.
+pub async fn telegram_post_multipart(endpoint: &str, chat_id: i64, form: reqwest::multipart::Form) -> Result<reqwest::Response, Error> {
+    let url = format!("https://api.telegram.org/bot{}/{}", config().tg_bot_token, endpoint);
+    let mut slot = match SEND_QUEUE.acquire(endpoint, Some(chat_id)).await {
+        Some(slot) => slot,
+        None => return Ok(skipped_reply()),
+    };
+
+    let resp = HTTP_CLIENTS.telegram
         .post(&url)
-        .json(payload)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(Error::Telegram)?;
+
+    let (resp, reply) = read_reply(endpoint, resp).await?;
+    if let Some(secs) = reply.filter(|reply| !reply.ok).and_then(|reply| reply.retry_after()) {
+        slot.back_off(secs);
+    }
+
+    Ok(resp)
+}
+
+/// A file to upload with `sendDocument` or `sendPhoto`.
+pub struct InputFile(reqwest::multipart::Part);
+
+impl InputFile {
+    /// Streams the file from disk rather than reading it into memory first.
+    pub async fn from_path(path: &std::path::Path, filename: &str, mime: &str) -> Result<Self, Error> {
+        let file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let body = reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+
+        let part = reqwest::multipart::Part::stream_with_length(body, length)
+            .file_name(filename.to_owned())
+            .mime_str(mime)
+            .map_err(Error::Telegram)?;
+
+        Ok(Self(part))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>, filename: &str, mime: &str) -> Result<Self, Error> {
+        let part = reqwest::multipart::Part::bytes(bytes)
+            .file_name(filename.to_owned())
+            .mime_str(mime)
+            .map_err(Error::Telegram)?;
+
+        Ok(Self(part))
+    }
+}
+
+fn upload_form(chat_id: i64, caption: &str) -> reqwest::multipart::Form {
+    reqwest::multipart::Form::new()
+        .text("chat_id", chat_id.to_string())
+        .text("caption", caption.to_owned())
+}
+
+pub async fn telegram_send_document(chat_id: i64, document: InputFile, caption: &str) -> Result<reqwest::Response, Error> {
+    telegram_post_multipart("sendDocument", chat_id, upload_form(chat_id, caption).part("document", document.0)).await
+}
+
+pub async fn telegram_send_photo(chat_id: i64, photo: InputFile, caption: &str) -> Result<reqwest::Response, Error> {
+    telegram_post_multipart("sendPhoto", chat_id, upload_form(chat_id, caption).part("photo", photo.0)).await
+}
+
+pub async fn telegram_send_chat_action(chat_id: i64, action: typing::ChatAction) -> Result<reqwest::Response, Error> {
+    telegram_post("sendChatAction", &serde_json::json!({
+        "chat_id": chat_id,
+        "action": action.as_str(),
+    }))
+    .await
+}
+
+pub async fn telegram_download_file(file_id: &str) -> Result<Vec<u8>, Error> {
+    let file = telegram_post("getFile", &serde_json::json!({
+        "file_id": file_id,
+    }))
+    .await?
+    .error_for_status()
+    .map_err(Error::Telegram)?
+    .json::<serde_json::Value>()
+    .await
+    .map_err(Error::Telegram)?;
+
+    let file_path = file["result"]["file_path"]
+        .as_str()
+        .ok_or_else(|| Error::InvalidUpdate("No file path returned by Telegram".into()))?;
+    let url = format!("https://api.telegram.org/file/bot{}/{}", config().tg_bot_token, file_path);
+
+    let bytes = HTTP_CLIENTS.telegram
+        .get(&url)
         .send()
         .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Telegram)?
+        .bytes()
+        .await
+        .map_err(Error::Telegram)?;
+
+    Ok(bytes.to_vec())
 }
 
-pub async fn wit_message_get(query: &str) -> Result<reqwest::Response, reqwest::Error> {
-    reqwest::Client::new()
+pub async fn wit_message_get(query: &str) -> Result<reqwest::Response, Error> {
+    let token = config().wit_access_token.as_deref().ok_or(Error::WitNotConfigured)?;
+
+    HTTP_CLIENTS.wit
         .get("https://api.wit.ai/message")
         .query(&[("v", "20210902"), ("q", query)])
-        .bearer_auth(&*WIT_ACCESS_TOKEN)
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(Error::Wit)
+}
+
+/// Transcribes audio with wit.ai's `/speech` endpoint. Newer API versions
+/// stream partial transcriptions as consecutive JSON objects, the last one
+/// holding a `text` is the final transcription.
+pub async fn wit_speech_post(audio: Vec<u8>, content_type: &str) -> Result<String, Error> {
+    let token = config().wit_access_token.as_deref().ok_or(Error::WitNotConfigured)?;
+
+    let body = HTTP_CLIENTS.wit
+        .post("https://api.wit.ai/speech")
+        .query(&[("v", "20210902")])
+        .bearer_auth(token)
+        .header(reqwest::header::CONTENT_TYPE, content_type)
+        .body(audio)
         .send()
         .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Wit)?
+        .bytes()
+        .await
+        .map_err(Error::Wit)?;
+
+    let text = serde_json::Deserializer::from_slice(&body)
+        .into_iter::<serde_json::Value>()
+        .filter_map(|value| value.ok())
+        .filter_map(|value| value["text"].as_str().map(|text| text.to_owned()))
+        .last()
+        .unwrap_or_default();
+
+    Ok(text)
+}
+
+/// Fetches an entity of the wit.ai app along with its keywords.
+pub async fn wit_entity_get(entity: &str) -> Result<wit::Entity, Error> {
+    let token = config().wit_access_token.as_deref().ok_or(Error::WitNotConfigured)?;
+
+    HTTP_CLIENTS.wit
+        .get(format!("https://api.wit.ai/entities/{}", urlencoding::encode(entity)))
+        .query(&[("v", "20210902")])
+        .bearer_auth(token)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Wit)?
+        .json::<wit::Entity>()
+        .await
+        .map_err(Error::Wit)
+}
+
+/// Adds a keyword along with its synonyms to an entity of the wit.ai app.
+pub async fn wit_keyword_post(entity: &str, keyword: &wit::Keyword) -> Result<(), Error> {
+    let token = config().wit_access_token.as_deref().ok_or(Error::WitNotConfigured)?;
+
+    HTTP_CLIENTS.wit
+        .post(format!("https://api.wit.ai/entities/{}/keywords", urlencoding::encode(entity)))
+        .query(&[("v", "20210902")])
+        .bearer_auth(token)
+        .json(keyword)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Wit)?;
+
+    Ok(())
+}
+
+/// Adds a synonym to an existing keyword of an entity of the wit.ai app.
+pub async fn wit_synonym_post(entity: &str, keyword: &str, synonym: &str) -> Result<(), Error> {
+    let token = config().wit_access_token.as_deref().ok_or(Error::WitNotConfigured)?;
+
+    HTTP_CLIENTS.wit
+        .post(format!(
+            "https://api.wit.ai/entities/{}/keywords/{}/synonyms",
+            urlencoding::encode(entity),
+            urlencoding::encode(keyword),
+        ))
+        .query(&[("v", "20210902")])
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "synonym": synonym }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Wit)?;
+
+    Ok(())
+}
+
+/// Submits corrected utterances to wit.ai so the model learns from them,
+/// does nothing unless the operator enabled it with `WIT_TRAINING_ENABLED`.
+pub async fn wit_utterances_post(utterances: &[wit::Utterance]) -> Result<(), Error> {
+    let token = match &config().wit_access_token {
+        Some(token) if config().wit_training_enabled => token,
+        _ => return Ok(()),
+    };
+
+    HTTP_CLIENTS.wit
+        .post("https://api.wit.ai/utterances")
+        .query(&[("v", "20210902")])
+        .bearer_auth(token)
+        .json(utterances)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Wit)?;
+
+    Ok(())
 }
 
 async fn handler_404(req: Request<Body>) -> ServiceResult<Response<Body>> {
@@ -155,12 +769,56 @@ async fn handler_404(req: Request<Body>) -> ServiceResult<Response<Body>> {
     }
 }
 
-fn router() -> ServiceResult<Router<Body, GenericError>> {
+fn open_database(config: &Config) -> ServiceResult<Arc<Database>> {
     let db = sled_extensions::Config::default()
-        .path(&*APP_SHARED_STORAGE_PATH)
-        .open()?;
+        .path(&config.app_shared_storage_path)
+        .open()
+        .map_err(sled_extensions::Error::from)?;
+
+    // Runs before the trees are opened, their records may still need rewriting.
+    migrations::run(&db)?;
+
+    Ok(Arc::new(Database {
+        users: db.open_bincode_tree("users")?,
+        categories: db.open_bincode_tree("categories")?,
+        outbox: db.open_bincode_tree("outbox")?,
+        scheduled: db.open_bincode_tree("scheduled")?,
+        updates: db.open_bincode_tree("updates")?,
+        access: db.open_bincode_tree("access")?,
+        stats: db.open_bincode_tree("stats")?,
+        rate_limits: db.open_bincode_tree("rate_limits")?,
+        snapshots: db.open_bincode_tree("snapshots")?,
+        invites: db.open_bincode_tree("invites")?,
+        wit_usage: db.open_bincode_tree("wit_usage")?,
+        accounts: db.open_bincode_tree("accounts")?,
+        category_spend: db.open_bincode_tree("category_spend")?,
+        pending: db.open_bincode_tree("pending")?,
+        digests: db.open_bincode_tree("digests")?,
+        created: db.open_bincode_tree("created_transactions")?,
+        created_texts: db.open_bincode_tree("created_texts")?,
+        last_created: db.open_bincode_tree("last_created")?,
+        projects: db.open_bincode_tree("projects")?,
+        link_codes: db.open_bincode_tree("link_codes")?,
+        chat_profiles: db.open_bincode_tree("chat_profiles")?,
+        last_texts: db.open_bincode_tree("last_texts")?,
+        accessibility: db.open_bincode_tree("accessibility")?,
+        languages: db.open_bincode_tree("languages")?,
+        operator_defaults: db.open_bincode_tree("operator_defaults")?,
+        confirm_modes: db.open_bincode_tree("confirm_modes")?,
+        raw_descriptions: db.open_bincode_tree("raw_descriptions")?,
+        default_sources: db.open_bincode_tree("default_sources")?,
+        cold_starts: db.open_bincode_tree("cold_starts")?,
+        merchants: db.open_bincode_tree("merchants")?,
+        unconfirmed: db.open_bincode_tree("unconfirmed")?,
+        reviews_sent: db.open_bincode_tree("reviews_sent")?,
+        category_shortlists: db.open_bincode_tree("category_shortlists")?,
+        searches: db.open_bincode_tree("searches")?,
+        store: db,
+    }))
+}
 
-    Router::builder()
+fn router(db: Arc<Database>, config: Arc<Config>) -> ServiceResult<Router<Body, Error>> {
+    let router = Router::builder()
         .middleware(Middleware::pre(|req: Request<Body>| async move {
             let (parts, body) = req.into_parts();
             let body_raw = hyper::body::to_bytes(body).await?;
@@ -195,11 +853,20 @@ fn router() -> ServiceResult<Router<Body, GenericError>> {
             let (parts, body) = res.into_parts();
             let body_raw = hyper::body::to_bytes(body).await?;
 
+            let is_json = parts.headers
+                .get(hyper::header::CONTENT_TYPE)
+                .is_some_and(|v| v.as_bytes().starts_with(JSON_MIME.as_bytes()));
+
             if body_raw.is_empty() {
                 info!("RES {:?}", parts.status);
 
                 let response = Response::from_parts(parts, Body::empty());
                 Ok(response)
+            } else if !is_json {
+                info!("RES {:?} ({} bytes)", parts.status, body_raw.len());
+
+                let response = Response::from_parts(parts, Body::from(body_raw));
+                Ok(response)
             } else {
                 let cloned_body_raw = body_raw.clone();
                 let json_value: serde_json::Value = serde_json::from_slice(&cloned_body_raw)?;
@@ -214,17 +881,22 @@ fn router() -> ServiceResult<Router<Body, GenericError>> {
                 Ok(response)
             }
         }))
-        .data(Arc::new(Database {
-            users: db.open_bincode_tree("users")?,
-        }))
+        .data(db)
+        .data(config)
         .get("/", hello_world)
         .post("/hook", handle_telegram_message)
+        .get("/healthz", healthz)
+        .get("/readyz", readyz)
+        .get("/debug/info", debug_info)
+        .get("/dashboard", dashboard)
         .any(handler_404)
-        .build()
+        .build()?;
+
+    Ok(router)
 }
 
 #[tokio::main]
-async fn main() -> Result<(), GenericError> {
+async fn main() -> Result<(), Error> {
     dotenv::dotenv().ok();
 
     if env::var("RUST_LOG").is_err() {
@@ -236,16 +908,46 @@ async fn main() -> Result<(), GenericError> {
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    let router = router()?;
-    let service = RouterService::new(router)?;
+    let args = env::args().collect::<Vec<String>>();
+    if args.get(1).map(|a| a.as_str()) == Some("replay") {
+        let path = args.get(2).ok_or_else(|| Error::Config("Usage: firefly_tg replay <fixture.json>".into()))?;
+        let matched = fixture::replay(path)?;
 
-    let default_port = Some(80u16);
-    let port =  env::var("PORT")
-        .ok()
-        .and_then(|n| n.parse::<u16>().ok())
-        .or(default_port).unwrap();
+        std::process::exit(if matched { 0 } else { 1 });
+    }
+
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    assert!(CONFIG.set(config.clone()).is_ok(), "The configuration is loaded once.");
+
+    diagnostics::log_banner();
+
+    if config.master_keys.is_empty() {
+        log::warn!("APP_MASTER_KEY is not set, personal access tokens are stored unencrypted");
+    }
+
+    let db = open_database(&config)?;
+    tokio::spawn(leader::run_election_loop());
+    tokio::spawn(outbox::run_retry_loop(db.clone()));
+    tokio::spawn(scheduler::run_scheduler_loop(db.clone()));
+    tokio::spawn(retention::run_maintenance_loop(db.clone()));
+    tokio::spawn(digest::run_digest_loop(db.clone()));
+    tokio::spawn(review::run_review_loop(db.clone()));
+    tokio::spawn(async {
+        if let Err(e) = commands::register().await {
+            error!("Failed to register the command menu: {}", e);
+        }
+    });
+
+    let addr = ([0, 0, 0, 0], config.port).into();
+    let router = router(db, config)?;
+    let service = RouterService::new(router)?;
 
-    let addr = ([0, 0, 0, 0], port).into();
     info!("Firefly telegram bot service is now listening at {}", addr);
 
     let server = Server::bind(&addr).serve(service);
@@ -0,0 +1,120 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// The destination account the user booked a merchant against, so the next
+/// message about the merchant can leave the account out.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MerchantMemory {
+    pub destination: String,
+    pub used_at: i64,
+}
+
+/// Reduces a description or account name to the words identifying the
+/// merchant, so "Starbucks", "starbucks 5.40" and "STARBUCKS!" are the same.
+pub fn merchant_key(name: &str) -> Option<String> {
+    let words = name
+        .split_whitespace()
+        .filter(|word| !word.chars().any(|c| c.is_ascii_digit()))
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<String>>();
+
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+fn key(user_id: &str, merchant: &str) -> String {
+    format!("{}/{}", user_id, merchant)
+}
+
+/// The destination account remembered for the merchant, marking it as used.
+pub fn recall(db: &Database, user_id: &str, name: &str) -> Result<Option<String>, Error> {
+    let merchant = match merchant_key(name) {
+        Some(merchant) => merchant,
+        None => return Ok(None),
+    };
+
+    let key = key(user_id, &merchant);
+    match db.merchants.get(key.as_bytes())? {
+        Some(mut memory) => {
+            memory.used_at = Utc::now().timestamp();
+            db.merchants.insert(key.as_bytes(), memory.to_owned())?;
+            Ok(Some(memory.destination))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Remembers the destination account of the merchants, dropping the ones
+/// used least recently once the user has more than `limit`.
+pub fn remember(db: &Database, user_id: &str, names: &[&str], destination: &str, limit: usize) -> Result<(), Error> {
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let memory = MerchantMemory {
+        destination: destination.to_owned(),
+        used_at: Utc::now().timestamp(),
+    };
+
+    for merchant in names.iter().filter_map(|name| merchant_key(name)) {
+        db.merchants.insert(key(user_id, &merchant).as_bytes(), memory.to_owned())?;
+    }
+
+    let mut remembered = db.merchants
+        .scan_prefix(format!("{}/", user_id).as_bytes())
+        .filter_map(|item| item.ok())
+        .map(|(key, memory)| (memory.used_at, key))
+        .collect::<Vec<_>>();
+
+    if remembered.len() > limit {
+        remembered.sort_by_key(|(used_at, _)| *used_at);
+
+        for (_, key) in &remembered[..remembered.len() - limit] {
+            db.merchants.remove(key)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Forgets the merchant, returns the destination account it was booked
+/// against if one was remembered.
+pub fn forget(db: &Database, user_id: &str, name: &str) -> Result<Option<String>, Error> {
+    let merchant = match merchant_key(name) {
+        Some(merchant) => merchant,
+        None => return Ok(None),
+    };
+
+    Ok(db.merchants.remove(key(user_id, &merchant).as_bytes())?.map(|memory| memory.destination))
+}
+
+/// The remembered merchants of the user with their destination accounts,
+/// most recently used first.
+pub fn list(db: &Database, user_id: &str) -> Result<Vec<(String, String)>, Error> {
+    let prefix = format!("{}/", user_id);
+
+    let mut remembered = db.merchants
+        .scan_prefix(prefix.as_bytes())
+        .filter_map(|item| item.ok())
+        .map(|(key, memory)| {
+            let merchant = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+            (memory.used_at, merchant, memory.destination)
+        })
+        .collect::<Vec<_>>();
+    remembered.sort_by_key(|(used_at, ..)| std::cmp::Reverse(*used_at));
+
+    Ok(remembered.into_iter().map(|(_, merchant, destination)| (merchant, destination)).collect())
+}
+
+/// Drops every remembered merchant of the user.
+pub fn forget_all(db: &Database, batch: &mut WriteBatch, user_id: &str) -> Result<(), Error> {
+    batch.remove_prefix(&db.merchants, format!("{}/", user_id))
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sled_extensions::bincode::BincodeEncoding;
+use sled_extensions::{Db, DbExt, Encoding};
+
+use super::Error;
+
+/// The key of the schema version in the `meta` tree.
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// The tree sled creates in every database, it holds none of the bot's data.
+const DEFAULT_TREE: &[u8] = b"__sled__default";
+
+/// Brings the database from one schema version to the next.
+type Migration = fn(&Db) -> Result<(), Error>;
+
+/// The migrations in order, the one at index `i` brings the database from
+/// version `i` to `i + 1`. The position is the version, so new ones are
+/// appended and none is ever removed or reordered.
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("widen user ids and add the default currency", baseline),
+    ("move time zones onto the users", user_timezones),
+    ("move terms acceptances onto the users", user_terms),
+];
+
+/// A user as stored before the schema was versioned.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct LegacyUserClue {
+    id: i32,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+}
+
+/// A user as stored by the builds that added the default currency before
+/// user ids were widened. They left the schema unversioned.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct CurrencyUserClue {
+    id: i32,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+    default_currency: Option<String>,
+}
+
+/// A user as stored at version 1, and by the builds that widened user ids
+/// before the schema was versioned. Migrations keep their own copy of each
+/// layout, `UserClue` moves on with the code.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct UserClueV1 {
+    id: i64,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+    default_currency: Option<String>,
+}
+
+/// A user as stored at version 2.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct UserClueV2 {
+    id: i64,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+    default_currency: Option<String>,
+    timezone: Option<String>,
+}
+
+/// A user as stored at version 3.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct UserClueV3 {
+    id: i64,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+    default_currency: Option<String>,
+    timezone: Option<String>,
+    terms_version: Option<String>,
+    terms_accepted_at: Option<i64>,
+}
+
+/// Telegram user ids outgrew 32 bits and users got a default currency, the
+/// users stored before that can't be read as they are. Both changes landed a
+/// few builds before this migration, the users those builds stored are kept
+/// as close to what they were as their layout allows.
+fn baseline(store: &Db) -> Result<(), Error> {
+    rewrite_with(store, "users", |value| {
+        if let Some(user) = decode_exact::<UserClueV1>(value) {
+            return Ok(user);
+        }
+
+        if let Some(user) = decode_exact::<CurrencyUserClue>(value) {
+            return Ok(UserClueV1 {
+                id: i64::from(user.id),
+                state: user.state,
+                firefly_url: user.firefly_url,
+                firefly_pat: user.firefly_pat,
+                default_currency: user.default_currency,
+            });
+        }
+
+        let user = <BincodeEncoding as Encoding<LegacyUserClue>>::decode(value)?;
+        Ok(UserClueV1 {
+            id: i64::from(user.id),
+            state: user.state,
+            firefly_url: user.firefly_url,
+            firefly_pat: user.firefly_pat,
+            default_currency: None,
+        })
+    })?;
+
+    Ok(())
+}
+
+/// Time zones were kept in their own tree keyed by Telegram id, so profile
+/// and linked accounts could not have one of their own. Every account of a
+/// Telegram user starts with the zone they had set.
+fn user_timezones(store: &Db) -> Result<(), Error> {
+    let timezones = store
+        .open_bincode_tree::<String>("timezones")?
+        .iter()
+        .map(|item| {
+            let (key, timezone) = item?;
+            Ok((key.to_vec(), timezone))
+        })
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+    rewrite(store, "users", |user: UserClueV1| UserClueV2 {
+        timezone: timezones.get(&user.id.to_be_bytes()[..]).cloned(),
+        id: user.id,
+        state: user.state,
+        firefly_url: user.firefly_url,
+        firefly_pat: user.firefly_pat,
+        default_currency: user.default_currency,
+    })?;
+
+    // Dropped last, a run cut short before it still finds the zones.
+    store.drop_tree(b"timezones").map_err(sled_extensions::Error::from)?;
+
+    Ok(())
+}
+
+/// Terms acceptances were kept in their own tree keyed by Telegram id,
+/// without the version of the notice. They move onto every account of the
+/// user, with no version the notice is shown again on the next `/start`.
+fn user_terms(store: &Db) -> Result<(), Error> {
+    let accepted = store
+        .open_bincode_tree::<i64>("terms_accepted")?
+        .iter()
+        .map(|item| {
+            let (key, accepted_at) = item?;
+            Ok((key.to_vec(), accepted_at))
+        })
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+    rewrite(store, "users", |user: UserClueV2| UserClueV3 {
+        terms_version: None,
+        terms_accepted_at: accepted.get(&user.id.to_be_bytes()[..]).copied(),
+        id: user.id,
+        state: user.state,
+        firefly_url: user.firefly_url,
+        firefly_pat: user.firefly_pat,
+        default_currency: user.default_currency,
+        timezone: user.timezone,
+    })?;
+
+    // Dropped last, a run cut short before it still finds the acceptances.
+    store.drop_tree(b"terms_accepted").map_err(sled_extensions::Error::from)?;
+
+    Ok(())
+}
+
+/// The schema version of the database, `None` before it was versioned.
+pub fn version(store: &Db) -> Result<Option<u64>, Error> {
+    Ok(store.open_bincode_tree::<u64>("meta")?.get(SCHEMA_VERSION_KEY)?)
+}
+
+/// Brings the database up to the schema version of this build, one
+/// migration at a time. The version is recorded after each of them, so a
+/// migration cut short runs again on the next start.
+pub fn run(store: &Db) -> Result<(), Error> {
+    // Checked before `meta` is opened, which would make any database look used.
+    let fresh = store.tree_names().iter().all(|name| &name[..] == DEFAULT_TREE);
+    let meta = store.open_bincode_tree::<u64>("meta")?;
+    let latest = MIGRATIONS.len() as u64;
+
+    let current = match meta.get(SCHEMA_VERSION_KEY)? {
+        Some(version) => version,
+        // A new database is created with the latest layout.
+        None if fresh => latest,
+        None => 0,
+    };
+
+    if current > latest {
+        return Err(Error::Config(format!(
+            "The database has schema version {}, this build only knows up to {}. Run a newer build or restore a backup.",
+            current,
+            latest,
+        )));
+    }
+
+    for (version, (description, migrate)) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        log::info!("Migrating the database to schema version {}: {}", version + 1, description);
+
+        migrate(store)?;
+        meta.insert(SCHEMA_VERSION_KEY, version as u64 + 1)?;
+    }
+
+    meta.insert(SCHEMA_VERSION_KEY, latest)?;
+    meta.flush()?;
+
+    Ok(())
+}
+
+/// Rewrites every record of a tree stored with an older layout, e.g. after
+/// a field was added to `UserClue`. `Old` is a copy of the struct as it was
+/// stored.
+fn rewrite<Old, New>(store: &Db, name: &str, convert: impl Fn(Old) -> New) -> Result<usize, Error>
+where
+    Old: DeserializeOwned + Serialize + 'static,
+    New: DeserializeOwned + Serialize + 'static,
+{
+    rewrite_with(store, name, |value| Ok(convert(<BincodeEncoding as Encoding<Old>>::decode(value)?)))
+}
+
+/// Rewrites every record of a tree from its stored bytes, for trees holding
+/// more than one older layout. The records are written at once, a
+/// migration rewrites a single tree so it is never left half done.
+fn rewrite_with<New>(store: &Db, name: &str, convert: impl Fn(&[u8]) -> Result<New, Error>) -> Result<usize, Error>
+where
+    New: DeserializeOwned + Serialize + 'static,
+{
+    let tree = store.open_tree(name).map_err(sled_extensions::Error::from)?;
+    let mut batch = sled::Batch::default();
+    let mut rewritten = 0;
+
+    for item in tree.iter() {
+        let (key, value) = item.map_err(sled_extensions::Error::from)?;
+
+        batch.insert(key, <BincodeEncoding as Encoding<New>>::encode(&convert(&value)?)?);
+        rewritten += 1;
+    }
+
+    tree.apply_batch(batch).map_err(sled_extensions::Error::from)?;
+    log::info!("Rewrote {} record(s) of the {} tree", rewritten, name);
+
+    Ok(rewritten)
+}
+
+/// Decodes a record only if it is exactly the encoding of `T`. Decoding
+/// ignores trailing bytes, so a record of a wider layout would otherwise be
+/// taken for a narrower one with its last fields cut off.
+fn decode_exact<T>(value: &[u8]) -> Option<T>
+where
+    T: DeserializeOwned + Serialize + 'static,
+{
+    let decoded = <BincodeEncoding as Encoding<T>>::decode(value).ok()?;
+    let encoded = <BincodeEncoding as Encoding<T>>::encode(&decoded).ok()?;
+
+    (encoded[..] == *value).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::UserClue;
+
+    fn temporary_db() -> Db {
+        sled_extensions::Config::default().temporary(true).open().unwrap()
+    }
+
+    fn store_legacy<T: DeserializeOwned + Serialize + 'static>(store: &Db, key: &[u8], user: &T) {
+        let users = store.open_tree("users").unwrap();
+        users.insert(key, <BincodeEncoding as Encoding<T>>::encode(user).unwrap()).unwrap();
+    }
+
+    fn legacy_user(id: i32) -> LegacyUserClue {
+        LegacyUserClue {
+            id,
+            state: "ready".into(),
+            firefly_url: "https://firefly.example.com".into(),
+            firefly_pat: "sealed-token".into(),
+        }
+    }
+
+    #[test]
+    fn migrates_baseline_users() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-42", &legacy_user(42));
+
+        run(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClue>("users").unwrap();
+        let user = users.get(b"telegram-user-42").unwrap().unwrap();
+        assert_eq!(user.telegram_id(), 42);
+        assert!(user.is_ready());
+        assert_eq!(version(&store).unwrap(), Some(MIGRATIONS.len() as u64));
+    }
+
+    #[test]
+    fn stores_legacy_users_without_a_default_currency() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-42", &legacy_user(42));
+
+        baseline(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClueV1>("users").unwrap();
+        assert_eq!(users.get(b"telegram-user-42").unwrap(), Some(UserClueV1 {
+            id: 42,
+            state: "ready".into(),
+            firefly_url: "https://firefly.example.com".into(),
+            firefly_pat: "sealed-token".into(),
+            default_currency: None,
+        }));
+    }
+
+    #[test]
+    fn moves_time_zones_onto_every_account_of_a_user() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-42", &legacy_user(42));
+        store_legacy(&store, b"telegram-user-42@work", &legacy_user(42));
+        store_legacy(&store, b"telegram-user-7", &legacy_user(7));
+        let timezones = store.open_bincode_tree::<String>("timezones").unwrap();
+        timezones.insert(&42i64.to_be_bytes(), "Europe/Berlin".to_owned()).unwrap();
+
+        run(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClue>("users").unwrap();
+        let zone = |key: &[u8]| users.get(key).unwrap().unwrap().timezone().map(|tz| tz.name());
+        assert_eq!(zone(b"telegram-user-42"), Some("Europe/Berlin"));
+        assert_eq!(zone(b"telegram-user-42@work"), Some("Europe/Berlin"));
+        assert_eq!(zone(b"telegram-user-7"), None);
+        assert!(!store.tree_names().iter().any(|name| &name[..] == b"timezones"));
+    }
+
+    #[test]
+    fn keeps_the_default_currency_of_users_stored_before_the_migration() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-42", &CurrencyUserClue {
+            id: 42,
+            state: "ready".into(),
+            firefly_url: "https://firefly.example.com".into(),
+            firefly_pat: "sealed-token".into(),
+            default_currency: Some("EUR".into()),
+        });
+        store_legacy(&store, b"telegram-user-7", &legacy_user(7));
+
+        baseline(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClueV1>("users").unwrap();
+        let currency = |key: &[u8]| users.get(key).unwrap().unwrap().default_currency;
+        assert_eq!(currency(b"telegram-user-42"), Some("EUR".into()));
+        assert_eq!(currency(b"telegram-user-7"), None);
+    }
+
+    #[test]
+    fn keeps_users_stored_with_wide_ids_before_the_migration() {
+        let store = temporary_db();
+        let user = UserClueV1 {
+            id: 5_000_000_000,
+            state: "ready".into(),
+            firefly_url: "https://firefly.example.com".into(),
+            firefly_pat: "sealed-token".into(),
+            default_currency: Some("EUR".into()),
+        };
+        store_legacy(&store, b"telegram-user-big", &user);
+
+        baseline(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClueV1>("users").unwrap();
+        assert_eq!(users.get(b"telegram-user-big").unwrap(), Some(user));
+    }
+
+    #[test]
+    fn moves_terms_acceptances_onto_the_users() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-42", &legacy_user(42));
+        store_legacy(&store, b"telegram-user-7", &legacy_user(7));
+        let accepted = store.open_bincode_tree::<i64>("terms_accepted").unwrap();
+        accepted.insert(&42i64.to_be_bytes(), 1_700_000_000).unwrap();
+
+        run(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClueV3>("users").unwrap();
+        let accepted_at = |key: &[u8]| users.get(key).unwrap().unwrap().terms_accepted_at;
+        assert_eq!(accepted_at(b"telegram-user-42"), Some(1_700_000_000));
+        assert_eq!(accepted_at(b"telegram-user-7"), None);
+        assert_eq!(users.get(b"telegram-user-42").unwrap().unwrap().terms_version, None);
+        assert!(!store.tree_names().iter().any(|name| &name[..] == b"terms_accepted"));
+    }
+
+    #[test]
+    fn widens_legacy_user_ids() {
+        let store = temporary_db();
+        store_legacy(&store, b"telegram-user-max", &legacy_user(i32::MAX));
+
+        run(&store).unwrap();
+
+        let users = store.open_bincode_tree::<UserClue>("users").unwrap();
+        let user = users.get(b"telegram-user-max").unwrap().unwrap();
+        assert_eq!(user.telegram_id(), i64::from(i32::MAX));
+
+        // Ids past 32 bits round-trip once widened.
+        users.insert(b"telegram-user-big", UserClue::new(5_000_000_000)).unwrap();
+        assert_eq!(users.get(b"telegram-user-big").unwrap().unwrap().telegram_id(), 5_000_000_000);
+    }
+
+    #[test]
+    fn starts_new_databases_at_the_latest_version() {
+        let store = temporary_db();
+
+        run(&store).unwrap();
+
+        assert_eq!(version(&store).unwrap(), Some(MIGRATIONS.len() as u64));
+    }
+
+    #[test]
+    fn refuses_newer_databases() {
+        let store = temporary_db();
+        let meta = store.open_bincode_tree::<u64>("meta").unwrap();
+        meta.insert(SCHEMA_VERSION_KEY, MIGRATIONS.len() as u64 + 1).unwrap();
+
+        assert!(run(&store).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::parser;
+use crate::wit::WitMessageResponse;
+
+use super::Error;
+
+const OPENAI_SYSTEM_PROMPT: &str = "Extract the personal finance transaction described by the user's message. \
+Reply with the JSON object {\"transaction\": null} if the message does not describe one, otherwise with \
+{\"transaction\": {\"transact_type\": \"withdrawal\" | \"deposit\" | \"transfer\", \"description\": string, \
+\"amount\": number, \"unit\": currency symbol or ISO code or null, \"source_name\": string, \
+\"destination_name\": string, \"category_name\": string or null, \"budget_name\": string or null}}.";
+
+/// A transaction as understood by an NLP provider, before the user's
+/// defaults, categories and rules are applied.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ParsedTransaction {
+    /// The message the transaction was parsed from.
+    pub text: String,
+    pub transact_type: Option<String>,
+    pub description: Option<String>,
+    pub amount: Option<f64>,
+    pub unit: Option<String>,
+    pub source_name: Option<String>,
+    pub destination_name: Option<String>,
+    pub category_name: Option<String>,
+    pub budget_name: Option<String>,
+}
+
+/// Turns a message into a transaction, selected with `NLP_PROVIDER`.
+#[async_trait]
+pub trait NlpProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Parses the message, returns `None` if it doesn't describe a transaction.
+    async fn parse(&self, text: &str) -> Result<Option<ParsedTransaction>, Error>;
+}
+
+/// Parses messages with the wit.ai app linked in the README.
+pub struct WitProvider;
+
+#[async_trait]
+impl NlpProvider for WitProvider {
+    fn name(&self) -> &'static str {
+        "wit"
+    }
+
+    async fn parse(&self, text: &str) -> Result<Option<ParsedTransaction>, Error> {
+        let response = super::wit_message_get(text)
+            .await
+            .and_then(|r| r.error_for_status().map_err(Error::Wit))?
+            .json::<WitMessageResponse>()
+            .await
+            .map_err(Error::Wit)?;
+
+        if response.intents.is_empty() {
+            return Ok(None);
+        }
+
+        let entities = &response.entities;
+        let amount_of_money = entities.amount_of_money.first();
+
+        Ok(Some(ParsedTransaction {
+            text: response.text.to_owned(),
+            transact_type: response.traits.flow.first().map(|f| f.value.to_owned()),
+            description: entities.deed.as_ref().and_then(|d| d.first()).map(|d| d.value.to_owned()),
+            amount: amount_of_money.map(|a| a.value),
+            unit: amount_of_money.map(|a| a.unit.to_owned()),
+            source_name: entities.origin.first().map(|a| a.value.to_owned()),
+            destination_name: entities.destination.first().map(|a| a.value.to_owned()),
+            category_name: entities.category.as_ref().and_then(|c| c.first()).map(|c| c.value.to_owned()),
+            budget_name: entities.budget.as_ref().and_then(|b| b.first()).map(|b| b.value.to_owned()),
+        }))
+    }
+}
+
+/// Parses the structured `<description>. <amount> from <source> to
+/// <destination>` format without any upstream, every transaction is
+/// recorded as a withdrawal.
+pub struct LocalProvider;
+
+#[async_trait]
+impl NlpProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn parse(&self, text: &str) -> Result<Option<ParsedTransaction>, Error> {
+        let parsed = parser::parse_structured(text).ok_or_else(|| {
+            Error::Parse("structured_format_required", serde_json::json!({}))
+        })?;
+
+        Ok(Some(ParsedTransaction {
+            text: text.to_owned(),
+            transact_type: Some("withdrawal".into()),
+            description: Some(parsed.description),
+            amount: Some(parsed.amount),
+            unit: parsed.unit,
+            source_name: Some(parsed.source_name),
+            destination_name: Some(parsed.destination_name),
+            category_name: None,
+            budget_name: None,
+        }))
+    }
+}
+
+/// Parses messages with a chat completions endpoint compatible with the
+/// OpenAI API, e.g. OpenAI itself, a local llama.cpp or Ollama server.
+pub struct OpenAiProvider {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatReply {
+    transaction: Option<ParsedTransaction>,
+}
+
+#[async_trait]
+impl NlpProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn parse(&self, text: &str) -> Result<Option<ParsedTransaction>, Error> {
+        let mut request = super::HTTP_CLIENTS.nlp
+            .post(format!("{}/chat/completions", self.url.trim_end_matches('/')))
+            .json(&serde_json::json!({
+                "model": self.model,
+                "response_format": { "type": "json_object" },
+                "messages": [
+                    { "role": "system", "content": OPENAI_SYSTEM_PROMPT },
+                    { "role": "user", "content": text },
+                ],
+            }));
+
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let completion = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(Error::Nlp)?
+            .json::<ChatCompletion>()
+            .await
+            .map_err(Error::Nlp)?;
+
+        let content = match completion.choices.into_iter().next() {
+            Some(choice) => choice.message.content,
+            None => return Ok(None),
+        };
+
+        let reply = serde_json::from_str::<ChatReply>(&content)?;
+
+        Ok(reply.transaction.map(|transaction| ParsedTransaction {
+            text: text.to_owned(),
+            ..transaction
+        }))
+    }
+}
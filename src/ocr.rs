@@ -0,0 +1,98 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::Error;
+
+lazy_static! {
+    static ref AMOUNT_PATTERN: Regex = Regex::new(r"(\d{1,3}(?:[,.]\d{3})*|\d+)[.,](\d{2})\b").unwrap();
+    static ref TOTAL_PATTERN: Regex = Regex::new(r"(?i)\b(grand\s+total|total|amount\s+due|balance\s+due)\b").unwrap();
+    static ref SUBTOTAL_PATTERN: Regex = Regex::new(r"(?i)\bsub\s*-?\s*total\b").unwrap();
+}
+
+#[derive(Debug, Deserialize)]
+struct OcrResponse {
+    text: String,
+}
+
+/// The details pulled out of a scanned receipt.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Receipt {
+    pub merchant: Option<String>,
+    pub total: Option<f64>,
+    pub source_name: Option<String>,
+    pub currency_code: Option<String>,
+}
+
+/// Sends the image to the configured OCR API and returns the recognized text.
+///
+/// The API receives the raw image as the request body and may answer either
+/// with plain text or with a JSON object containing a `text` field.
+pub async fn recognize(api_url: &str, api_key: Option<&str>, image: Vec<u8>) -> Result<String, Error> {
+    let mut request = super::HTTP_CLIENTS.ocr
+        .post(api_url)
+        .header(reqwest::header::CONTENT_TYPE, "image/jpeg")
+        .body(image);
+
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+
+    let body = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Upstream)?
+        .text()
+        .await
+        .map_err(Error::Upstream)?;
+
+    match serde_json::from_str::<OcrResponse>(&body) {
+        Ok(resp) => Ok(resp.text),
+        Err(_) => Ok(body),
+    }
+}
+
+fn parse_amount(line: &str) -> Option<f64> {
+    AMOUNT_PATTERN
+        .captures_iter(line)
+        .filter_map(|c| {
+            let whole = c.get(1)?.as_str().replace([',', '.'], "");
+            format!("{}.{}", whole, c.get(2)?.as_str()).parse::<f64>().ok()
+        })
+        .last()
+}
+
+/// Extracts the merchant and total from OCR output.
+///
+/// The merchant is assumed to be the first line with words in it, the total
+/// is taken from the last line mentioning a total, falling back to the
+/// largest amount found on the receipt.
+pub fn parse_receipt(text: &str) -> Receipt {
+    let lines = text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<&str>>();
+
+    let merchant = lines
+        .iter()
+        .find(|l| l.chars().filter(|c| c.is_alphabetic()).count() >= 3)
+        .map(|l| l.to_string());
+
+    let total = lines
+        .iter()
+        .rev()
+        .filter(|l| TOTAL_PATTERN.is_match(l) && !SUBTOTAL_PATTERN.is_match(l))
+        .find_map(|l| parse_amount(l))
+        .or_else(|| lines
+            .iter()
+            .filter_map(|l| parse_amount(l))
+            .fold(None, |max: Option<f64>, a| Some(max.map_or(a, |m| m.max(a)))));
+
+    Receipt {
+        merchant,
+        total,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,280 @@
+use std::sync::Arc;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::firefly;
+use crate::language;
+use crate::leader;
+use crate::stats;
+use crate::telegram::TransactPayload;
+
+use super::{Database, Error};
+
+/// How often the outbox is checked for entries due for a retry.
+const POLL_INTERVAL_SECS: u64 = 30;
+
+/// The delay before the first retry, doubled on every failed attempt.
+const BASE_BACKOFF_SECS: i64 = 60;
+
+/// The longest delay between two retries.
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+/// The number of attempts after which a transaction is no longer retried.
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// Stores a payload as a JSON string. The payload skips its empty fields
+/// to match what Firefly III expects, which bincode can't read back.
+pub mod stored_payload {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::telegram::TransactPayload;
+
+    pub fn serialize<S: Serializer>(payload: &TransactPayload, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(payload).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TransactPayload, D::Error> {
+        let json = String::deserialize(deserializer)?;
+        serde_json::from_str(&json).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A transaction that failed to be created because Firefly III was unreachable.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OutboxEntry {
+    pub user_id: String,
+    pub chat_id: i64,
+    #[serde(with = "stored_payload")]
+    pub payload: TransactPayload,
+    pub attempts: u32,
+    pub next_attempt_at: i64,
+    pub last_error: String,
+    pub created_at: i64,
+}
+
+impl OutboxEntry {
+    pub fn is_abandoned(&self) -> bool {
+        self.attempts >= MAX_ATTEMPTS
+    }
+}
+
+/// Checks if a failed Firefly III call is worth retrying later, i.e. the
+/// instance could not be reached or answered with a server-side error.
+pub fn is_transient(result: &Result<reqwest::Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(resp) => resp.status().is_server_error() || resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        Err(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+    }
+}
+
+/// Describes a failed Firefly III call for display in `/pending`.
+pub fn describe_failure(result: &Result<reqwest::Response, reqwest::Error>) -> String {
+    match result {
+        Ok(resp) => format!("Firefly III responded with {}", resp.status()),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Checks the outcome of a call that is not worth retrying, returns why
+/// Firefly III rejected the transaction or `None` if it was created.
+pub async fn rejection(result: Result<reqwest::Response, reqwest::Error>) -> Option<String> {
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => return Some(e.to_string()),
+    };
+
+    match firefly::error_for_status(resp).await {
+        Ok(_) => None,
+        Err(Error::FireflyRejected(reasons)) => Some(reasons),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+fn backoff_secs(attempts: u32) -> i64 {
+    BASE_BACKOFF_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_BACKOFF_SECS)
+}
+
+/// Stores a failed transaction so it gets retried by the background task.
+pub fn enqueue(db: &Database, user_id: &str, chat_id: i64, payload: TransactPayload, last_error: String) -> Result<(), Error> {
+    let now = Utc::now().timestamp();
+    let key = format!("{}/{}", user_id, Uuid::new_v4());
+
+    db.outbox.insert(key.as_bytes(), OutboxEntry {
+        user_id: user_id.to_owned(),
+        chat_id,
+        payload,
+        attempts: 0,
+        next_attempt_at: now + backoff_secs(0),
+        last_error,
+        created_at: now,
+    })?;
+
+    Ok(())
+}
+
+/// Lists the queued transactions of a user along with their ids.
+pub fn list(db: &Database, user_id: &str) -> Result<Vec<(String, OutboxEntry)>, Error> {
+    let prefix = format!("{}/", user_id);
+
+    db.outbox
+        .scan_prefix(prefix.as_bytes())
+        .map(|item| {
+            let (key, entry) = item?;
+            Ok((String::from_utf8_lossy(&key[prefix.len()..]).into_owned(), entry))
+        })
+        .collect()
+}
+
+/// Retries a queued transaction right away, even if it had been given up on.
+/// Returns `false` if there is no such entry.
+pub async fn send_now(db: &Database, user_id: &str, id: &str) -> Result<bool, Error> {
+    let key = format!("{}/{}", user_id, id);
+
+    let mut entry = match db.outbox.get(key.as_bytes())? {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+
+    if entry.is_abandoned() {
+        entry.attempts = 0;
+    }
+
+    retry(db, key.as_bytes(), entry).await?;
+
+    Ok(true)
+}
+
+/// Drops a queued transaction, returns `false` if there is no such entry.
+pub fn discard(db: &Database, user_id: &str, id: &str) -> Result<bool, Error> {
+    let key = format!("{}/{}", user_id, id);
+
+    Ok(db.outbox.remove(key.as_bytes())?.is_some())
+}
+
+async fn retry(db: &Database, key: &[u8], mut entry: OutboxEntry) -> Result<(), Error> {
+    let user = match db.users.get(entry.user_id.as_bytes())? {
+        Some(user) => user,
+        None => {
+            // The user reset their account in the meantime, nothing to deliver to.
+            db.outbox.remove(key)?;
+            return Ok(());
+        },
+    };
+
+    // The earlier attempt may have reached Firefly III before failing.
+    if let Some(external_id) = entry.payload.external_id() {
+        if let Ok(Some(_)) = user.firefly().find_by_external_id(external_id).await {
+            db.outbox.remove(key)?;
+
+            return super::telegram_post("sendMessage", &serde_json::json!({
+                "chat_id": entry.chat_id,
+                "text": language::text(db, user.telegram_id(), "queued_created", serde_json::json!({ "description": entry.payload.description() })),
+            }))
+            .await
+            .map(|_| ());
+        }
+    }
+
+    let result = user.firefly_for_posting(db, &entry.user_id)?.create_transaction(&entry.payload).await;
+
+    if !is_transient(&result) {
+        db.outbox.remove(key)?;
+
+        let message = match rejection(result).await {
+            None => {
+                stats::record(db, stats::TRANSACTIONS_CREATED);
+                language::text(db, user.telegram_id(), "queued_created", serde_json::json!({ "description": entry.payload.description() }))
+            },
+            Some(e) => language::text(db, user.telegram_id(), "queued_rejected", serde_json::json!({
+                "description": entry.payload.description(),
+                "reasons": e,
+            })),
+        };
+
+        super::telegram_post("sendMessage", &serde_json::json!({
+            "chat_id": entry.chat_id,
+            "text": message,
+        }))
+        .await?;
+
+        return Ok(());
+    }
+
+    entry.attempts += 1;
+    entry.last_error = describe_failure(&result);
+    entry.next_attempt_at = Utc::now().timestamp() + backoff_secs(entry.attempts);
+
+    if entry.is_abandoned() {
+        super::telegram_post("sendMessage", &serde_json::json!({
+            "chat_id": entry.chat_id,
+            "text": language::text(db, user.telegram_id(), "queued_abandoned", serde_json::json!({
+                "description": entry.payload.description(),
+                "attempts": entry.attempts,
+            })),
+        }))
+        .await?;
+    }
+
+    db.outbox.insert(key, entry)?;
+
+    Ok(())
+}
+
+/// Periodically retries the due outbox entries with exponential backoff.
+pub async fn run_retry_loop(db: Arc<Database>) {
+    loop {
+        sleep(Duration::from_secs(POLL_INTERVAL_SECS)).await;
+
+        if !leader::is_leader() {
+            continue;
+        }
+
+        let now = Utc::now().timestamp();
+        let due = db.outbox
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, entry)| !entry.is_abandoned() && entry.next_attempt_at <= now)
+            .collect::<Vec<_>>();
+
+        for (key, entry) in due {
+            if let Err(e) = retry(&db, &key, entry).await {
+                log::error!("Failed to retry outbox entry: {}", e);
+            }
+        }
+    }
+}
+
+/// Drops every abandoned entry of the user, returns how many were removed.
+pub fn discard_dead_letters(db: &Database, user_id: &str) -> Result<usize, Error> {
+    let dead = list(db, user_id)?
+        .into_iter()
+        .filter(|(_, entry)| entry.is_abandoned())
+        .collect::<Vec<_>>();
+
+    for (id, _) in &dead {
+        discard(db, user_id, id)?;
+    }
+
+    Ok(dead.len())
+}
+
+/// Drops abandoned entries created before `before`, returns how many were removed.
+pub fn purge_dead_letters(db: &Database, before: i64) -> Result<usize, Error> {
+    let dead = db.outbox
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, entry)| entry.is_abandoned() && entry.created_at < before)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &dead {
+        db.outbox.remove(key)?;
+    }
+
+    Ok(dead.len())
+}
@@ -0,0 +1,90 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::currency;
+
+lazy_static! {
+    static ref STRUCTURED_PATTERN: Regex = Regex::new(
+        r"(?i)^\s*(?P<description>[^.]+?)\s*\.\s*(?P<symbol>[^\d\s]+)?\s*(?P<amount>\d+(?:[.,]\d{1,2})?)\s*(?P<code>[a-z]{3})?\s+from\s+(?P<source>.+?)\s+to\s+(?P<destination>.+?)\s*\.?\s*$"
+    ).unwrap();
+}
+
+/// A transaction written in the structured format the local parser understands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedMessage {
+    pub description: String,
+    pub amount: f64,
+    pub unit: Option<String>,
+    pub source_name: String,
+    pub destination_name: String,
+}
+
+/// Parses `<description>. <amount> from <source> to <destination>`, used
+/// when wit.ai is down, rate limited, or not configured. The amount may be
+/// preceded by a currency symbol or followed by an ISO 4217 currency code.
+pub fn parse_structured(text: &str) -> Option<ParsedMessage> {
+    let captures = STRUCTURED_PATTERN.captures(text)?;
+    let amount = captures.name("amount")?.as_str().replace(',', ".").parse::<f64>().ok()?;
+    if captures.name("code").is_some_and(|code| !currency::is_currency_code(code.as_str())) {
+        return None;
+    }
+    let unit = captures
+        .name("code")
+        .or_else(|| captures.name("symbol"))
+        .map(|u| u.as_str().to_owned());
+
+    Some(ParsedMessage {
+        description: captures.name("description")?.as_str().to_owned(),
+        amount,
+        unit,
+        source_name: captures.name("source")?.as_str().to_owned(),
+        destination_name: captures.name("destination")?.as_str().to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(description: &str, amount: f64, unit: Option<&str>, source: &str, destination: &str) -> Option<ParsedMessage> {
+        Some(ParsedMessage {
+            description: description.into(),
+            amount,
+            unit: unit.map(|u| u.into()),
+            source_name: source.into(),
+            destination_name: destination.into(),
+        })
+    }
+
+    #[test]
+    fn reads_the_structured_format() {
+        assert_eq!(parse_structured("Lunch. 12.50 from Cash to Cafe"), parsed("Lunch", 12.5, None, "Cash", "Cafe"));
+        assert_eq!(parse_structured("  Lunch . 12,5 from Cash to Corner Cafe. "), parsed("Lunch", 12.5, None, "Cash", "Corner Cafe"));
+        assert_eq!(parse_structured("Train ticket. 40 FROM Checking TO Railways"), parsed("Train ticket", 40.0, None, "Checking", "Railways"));
+    }
+
+    #[test]
+    fn keeps_the_currency() {
+        assert_eq!(parse_structured("Lunch. €12 from Cash to Cafe"), parsed("Lunch", 12.0, Some("€"), "Cash", "Cafe"));
+        assert_eq!(parse_structured("Lunch. 12 USD from Cash to Cafe"), parsed("Lunch", 12.0, Some("USD"), "Cash", "Cafe"));
+        assert_eq!(parse_structured("Lunch. $12 CAD from Cash to Cafe"), parsed("Lunch", 12.0, Some("CAD"), "Cash", "Cafe"));
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        for text in &[
+            "Lunch 12 from Cash to Cafe",
+            "Lunch. from Cash to Cafe",
+            "Lunch. 12 to Cafe",
+            "Lunch. 12.505 from Cash to Cafe",
+            // Thousands separators are read as decimals, so they're rejected
+            // rather than logging 1.2.
+            "Rent. 1,200 from checking to landlord",
+            "Rent. 1.200,00 from checking to landlord",
+            // Three letters after the amount must be a currency code.
+            "Lunch. 12 abc from Cash to Cafe",
+        ] {
+            assert_eq!(parse_structured(text), None, "{} was accepted", text);
+        }
+    }
+}
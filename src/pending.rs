@@ -0,0 +1,125 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::budget::BudgetSuggestion;
+use crate::household::LinkRequest;
+use crate::ocr::Receipt;
+use crate::review;
+use crate::telegram::{FailedBatch, PendingAccountChoice, PendingCurrencyChoice};
+
+use super::{Database, Error};
+
+/// What the bot is waiting on the user to answer, usually through an
+/// inline keyboard.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum PendingAction {
+    /// A scanned receipt waiting to be confirmed.
+    Receipt(Receipt),
+    /// A transaction waiting for the user to pick one of the suggested accounts.
+    AccountChoice(PendingAccountChoice),
+    /// A monthly budget offered for a category the user keeps spending in.
+    BudgetSuggestion(BudgetSuggestion),
+    /// The lines of a batch that failed, waiting to be retried.
+    BatchRetry(FailedBatch),
+    /// Another Telegram user asking to log to the user's Firefly III account.
+    LinkRequest(LinkRequest),
+    /// A text sent twice in a row, waiting for the user to confirm it's
+    /// meant to be logged again.
+    Repeat(String),
+    /// A transaction in another currency than its account, waiting for the
+    /// user to convert it or pick an account in that currency.
+    CurrencyChoice(PendingCurrencyChoice),
+}
+
+impl PendingAction {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Receipt(_) => "receipt",
+            Self::AccountChoice(_) => "account choice",
+            Self::BudgetSuggestion(_) => "budget suggestion",
+            Self::BatchRetry(_) => "batch retry",
+            Self::LinkRequest(_) => "link request",
+            Self::Repeat(_) => "repeated message",
+            Self::CurrencyChoice(_) => "currency choice",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PendingEntry {
+    action: PendingAction,
+    expires_at: i64,
+}
+
+fn key(user_id: &str, chat_id: i64) -> String {
+    format!("{}/{}", user_id, chat_id)
+}
+
+/// Waits on the user for the action, replacing whatever the chat was
+/// waiting on before since only the latest question can be answered.
+pub fn set(db: &Database, user_id: &str, chat_id: i64, action: PendingAction) -> Result<(), Error> {
+    let entry = PendingEntry {
+        action,
+        expires_at: Utc::now().timestamp() + super::config().pending_action_ttl.num_seconds(),
+    };
+
+    if let Some(previous) = db.pending.insert(key(user_id, chat_id).as_bytes(), entry)? {
+        log::debug!("Replaced the pending {} of {}", previous.action.kind(), user_id);
+    }
+
+    Ok(())
+}
+
+/// Takes the action the chat is waiting on if it's of the same kind as
+/// `expected`, answering an outdated keyboard leaves the current one alone.
+pub fn take(db: &Database, user_id: &str, chat_id: i64, expected: fn(&PendingAction) -> bool) -> Result<Option<PendingAction>, Error> {
+    let key = key(user_id, chat_id);
+
+    let entry = match db.pending.get(key.as_bytes())? {
+        Some(entry) if expected(&entry.action) => entry,
+        _ => return Ok(None),
+    };
+
+    db.pending.remove(key.as_bytes())?;
+
+    if entry.expires_at <= Utc::now().timestamp() {
+        return Ok(None);
+    }
+
+    Ok(Some(entry.action))
+}
+
+/// Drops whatever the user's chats are waiting on, returns how many
+/// actions were dropped.
+pub fn clear_user(db: &Database, user_id: &str) -> Result<usize, Error> {
+    let keys = db.pending
+        .scan_prefix(format!("{}/", user_id).as_bytes())
+        .keys()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for key in &keys {
+        db.pending.remove(key)?;
+    }
+
+    Ok(keys.len())
+}
+
+/// Drops the actions nobody answered in time, keeping the transactions
+/// they held for the weekly review. Returns how many were removed.
+pub fn purge_expired(db: &Database) -> Result<usize, Error> {
+    let now = Utc::now().timestamp();
+
+    let expired = db.pending
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, entry)| entry.expires_at <= now)
+        .collect::<Vec<_>>();
+
+    for (key, entry) in &expired {
+        if db.pending.remove(key)?.is_some() {
+            review::record_expired(db, key, entry.action.to_owned())?;
+        }
+    }
+
+    Ok(expired.len())
+}
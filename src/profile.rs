@@ -0,0 +1,75 @@
+use crate::batch::WriteBatch;
+use crate::telegram;
+
+use super::{Database, Error};
+
+/// Reads the profile name of `/bind business`, profile names are kept to
+/// letters, digits, dashes and underscores since they end up in storage keys.
+pub fn parse_name(args: &str) -> Option<String> {
+    let name = args.trim().to_lowercase();
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn key(chat_id: i64, from_id: i64) -> String {
+    format!("{}/{}", chat_id, from_id)
+}
+
+/// Makes the user's messages in the chat use the profile, overriding their
+/// default account.
+pub fn bind(db: &Database, chat_id: i64, from_id: i64, name: &str) -> Result<(), Error> {
+    db.chat_profiles.insert(key(chat_id, from_id).as_bytes(), name.to_owned())?;
+    Ok(())
+}
+
+/// Goes back to the user's default account in the chat, returns the profile
+/// that was bound.
+pub fn unbind(db: &Database, chat_id: i64, from_id: i64) -> Result<Option<String>, Error> {
+    Ok(db.chat_profiles.remove(key(chat_id, from_id).as_bytes())?)
+}
+
+/// The profile the user's messages in the chat use, `None` for their
+/// default account.
+pub fn bound(db: &Database, chat_id: i64, from_id: i64) -> Result<Option<String>, Error> {
+    Ok(db.chat_profiles.get(key(chat_id, from_id).as_bytes())?)
+}
+
+/// The key the records of a user's profile are stored under, each profile
+/// is set up and kept like a separate account.
+pub fn user_id(from_id: i64, profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("{}@{}", telegram::user_key(from_id), profile),
+        None => telegram::user_key(from_id),
+    }
+}
+
+/// The keys of every profile the user set up, besides their default account.
+pub fn profile_user_ids(db: &Database, from_id: i64) -> Result<Vec<String>, Error> {
+    db.users
+        .scan_prefix(format!("{}@", telegram::user_key(from_id)))
+        .keys()
+        .map(|key| Ok(String::from_utf8_lossy(&key?).into_owned()))
+        .collect()
+}
+
+/// Drops the profiles the user bound to chats.
+pub fn forget(db: &Database, batch: &mut WriteBatch, from_id: i64) -> Result<(), Error> {
+    let suffix = format!("/{}", from_id);
+
+    for key in db.chat_profiles.iter().keys() {
+        let key = key?;
+
+        if key.ends_with(suffix.as_bytes()) {
+            batch.remove(&db.chat_profiles, key);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use super::{Database, Error};
+
+/// Reads the project name of `/project start "Kitchen reno"`, the quotes
+/// are optional.
+pub fn parse_name(args: &str) -> Option<String> {
+    let name = args.trim().trim_matches(['"', '“', '”']).trim();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// Starts tagging the user's transactions with the project name, replacing
+/// the project they were tracking before.
+pub fn start(db: &Database, user_id: &str, name: &str) -> Result<Option<String>, Error> {
+    Ok(db.projects.insert(user_id.as_bytes(), name.to_owned())?)
+}
+
+/// Stops tagging, returns the project that was being tracked.
+pub fn stop(db: &Database, user_id: &str) -> Result<Option<String>, Error> {
+    Ok(db.projects.remove(user_id.as_bytes())?)
+}
+
+/// The project new transactions of the user are tagged with.
+pub fn active(db: &Database, user_id: &str) -> Result<Option<String>, Error> {
+    Ok(db.projects.get(user_id.as_bytes())?)
+}
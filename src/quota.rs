@@ -0,0 +1,44 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{Database, Error};
+
+/// The wit.ai calls a user made on a single day (UTC).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DailyUsage {
+    day: String,
+    calls: u32,
+}
+
+/// Where a user stands against their daily wit.ai quota.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quota {
+    Available,
+    /// This is the first call over the quota, the user should be told.
+    JustExhausted,
+    Exhausted,
+}
+
+/// Counts a wit.ai call against the user's daily quota, a quota of zero
+/// means unlimited.
+pub fn consume(db: &Database, user_id: &[u8], quota: u32) -> Result<Quota, Error> {
+    if quota == 0 {
+        return Ok(Quota::Available);
+    }
+
+    let today = Utc::now().format("%Y-%m-%d").to_string();
+
+    let usage = match db.wit_usage.get(user_id)? {
+        Some(usage) if usage.day == today => usage,
+        _ => DailyUsage { day: today, calls: 0 },
+    };
+
+    let calls = usage.calls.saturating_add(1);
+    db.wit_usage.insert(user_id, DailyUsage { calls, ..usage })?;
+
+    Ok(match calls {
+        calls if calls <= quota => Quota::Available,
+        calls if calls == quota + 1 => Quota::JustExhausted,
+        _ => Quota::Exhausted,
+    })
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{Database, Error};
+
+/// The tokens left for a chat, refilled continuously over time.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct Bucket {
+    tokens: f64,
+    updated_at: i64,
+}
+
+/// A token bucket per chat limiting how many messages reach wit.ai. Buckets
+/// are kept in memory and written through to the database so restarts don't
+/// hand out fresh quotas.
+pub struct RateLimiter {
+    burst: f64,
+    per_minute: f64,
+    buckets: Mutex<HashMap<i64, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allows `burst` messages at once, refilled at `per_minute`. A rate of
+    /// zero disables the limiter.
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Self {
+            burst: f64::from(burst.max(1)),
+            per_minute: f64::from(per_minute),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_minute > 0.0
+    }
+
+    /// Takes a token for the chat, returns `false` if the chat ran out.
+    pub fn check(&self, db: &Database, chat_id: i64) -> Result<bool, Error> {
+        if !self.is_enabled() {
+            return Ok(true);
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = match buckets.get(&chat_id) {
+            Some(bucket) => *bucket,
+            None => db.rate_limits.get(chat_id.to_be_bytes())?.unwrap_or(Bucket {
+                tokens: self.burst,
+                updated_at: now,
+            }),
+        };
+
+        let elapsed_minutes = (now - bucket.updated_at).max(0) as f64 / 60_000.0;
+        let tokens = (bucket.tokens + elapsed_minutes * self.per_minute).min(self.burst);
+        let allowed = tokens >= 1.0;
+
+        let bucket = Bucket {
+            tokens: if allowed { tokens - 1.0 } else { tokens },
+            updated_at: now,
+        };
+
+        buckets.insert(chat_id, bucket);
+        db.rate_limits.insert(&chat_id.to_be_bytes(), bucket)?;
+
+        Ok(allowed)
+    }
+
+    /// Forgets the buckets of chats idle since `before`, they would have
+    /// refilled completely by now anyway. Returns how many were removed.
+    pub fn purge_idle(&self, db: &Database, before: i64) -> Result<usize, Error> {
+        let before_millis = before * 1000;
+        self.buckets.lock().unwrap().retain(|_, bucket| bucket.updated_at >= before_millis);
+
+        let idle = db.rate_limits
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, bucket)| bucket.updated_at < before_millis)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+
+        for key in &idle {
+            db.rate_limits.remove(key)?;
+        }
+
+        Ok(idle.len())
+    }
+}
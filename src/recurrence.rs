@@ -0,0 +1,205 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref RECURRENCE_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:every\s+(day|week|month|year|monday|tuesday|wednesday|thursday|friday|saturday|sunday)(?:\s+on\s+the\s+(\d{1,2})(?:st|nd|rd|th)?)?|(daily|weekly|monthly|yearly|annually))\b"
+    ).unwrap();
+}
+
+const WEEKDAYS: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+/// How often a recurring transaction repeats, mirroring the repetition
+/// types of Firefly III.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repetition {
+    Daily,
+    Weekly(Weekday),
+    /// On that day of the month.
+    Monthly(u32),
+    /// Every year on the first date.
+    Yearly,
+}
+
+impl Repetition {
+    /// The first day on or after `today` the transaction falls on.
+    pub fn first_date(self, today: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Daily | Self::Yearly => today,
+            Self::Weekly(weekday) => {
+                let days = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+                today + chrono::Duration::days(i64::from(days))
+            },
+            Self::Monthly(day) => {
+                let this_month = day_of_month(today.year(), today.month(), day);
+
+                if this_month >= today {
+                    this_month
+                } else if today.month() == 12 {
+                    day_of_month(today.year() + 1, 1, day)
+                } else {
+                    day_of_month(today.year(), today.month() + 1, day)
+                }
+            },
+        }
+    }
+
+    pub fn describe(self) -> String {
+        match self {
+            Self::Daily => "every day".to_owned(),
+            Self::Weekly(weekday) => format!("every {}", WEEKDAYS[weekday.num_days_from_monday() as usize]),
+            Self::Monthly(day) => format!("every month on day {}", day),
+            Self::Yearly => "every year".to_owned(),
+        }
+    }
+
+    /// The repetition as expected by `POST /api/v1/recurrences`.
+    fn to_json(self, first_date: NaiveDate) -> serde_json::Value {
+        let (repetition_type, moment) = match self {
+            Self::Daily => ("daily", String::new()),
+            Self::Weekly(weekday) => ("weekly", weekday.number_from_monday().to_string()),
+            Self::Monthly(day) => ("monthly", day.to_string()),
+            Self::Yearly => ("yearly", first_date.format("%Y-%m-%d").to_string()),
+        };
+
+        serde_json::json!({
+            "type": repetition_type,
+            "moment": moment,
+            "skip": 0,
+            "weekend": 1,
+        })
+    }
+}
+
+/// The day of the month, or the month's last day if it's shorter.
+pub fn day_of_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    (1..=day.clamp(1, 31))
+        .rev()
+        .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .unwrap()
+}
+
+/// Finds a recurrence phrase like `every month on the 1st` or `weekly` in
+/// the message, returns it along with the message without the phrase.
+pub fn detect(text: &str, today: NaiveDate) -> Option<(Repetition, String)> {
+    let captures = RECURRENCE_PATTERN.captures(text)?;
+
+    let unit = captures.get(1)
+        .or_else(|| captures.get(3))
+        .map(|m| m.as_str().to_lowercase())?;
+    let day = captures.get(2).and_then(|m| m.as_str().parse::<u32>().ok());
+
+    let repetition = match unit.as_str() {
+        "day" | "daily" => Repetition::Daily,
+        "week" | "weekly" => Repetition::Weekly(today.weekday()),
+        "month" | "monthly" => match day {
+            Some(day) if (1..=31).contains(&day) => Repetition::Monthly(day),
+            Some(_) => return None,
+            None => Repetition::Monthly(today.day()),
+        },
+        "year" | "yearly" | "annually" => Repetition::Yearly,
+        weekday => Repetition::Weekly(weekday.parse::<Weekday>().ok()?),
+    };
+
+    let whole = captures.get(0)?;
+    let rest = format!("{} {}", &text[..whole.start()], &text[whole.end()..])
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    Some((repetition, rest.trim_end_matches(['.', ',']).trim_end().to_owned()))
+}
+
+/// Builds the body of `POST /api/v1/recurrences` from a transaction as it
+/// would have been posted to `/api/v1/transactions`.
+pub fn payload(transaction: serde_json::Value, repetition: Repetition, first_date: NaiveDate) -> serde_json::Value {
+    let mut split = transaction;
+    let fields = split.as_object_mut();
+
+    let transact_type = fields.as_ref().and_then(|f| f.get("type").cloned()).unwrap_or_else(|| "withdrawal".into());
+    let title = fields.as_ref().and_then(|f| f.get("description").cloned()).unwrap_or_default();
+    let notes = fields.as_ref().and_then(|f| f.get("notes").cloned());
+
+    if let Some(fields) = fields {
+        fields.remove("type");
+        fields.remove("date");
+        fields.remove("notes");
+    }
+
+    serde_json::json!({
+        "type": transact_type,
+        "title": title,
+        "first_date": first_date.format("%Y-%m-%d").to_string(),
+        "notes": notes,
+        "apply_rules": true,
+        "active": true,
+        "repetitions": [repetition.to_json(first_date)],
+        "transactions": [split],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn detects_the_phrase() {
+        // A Wednesday.
+        let today = date(2024, 1, 10);
+
+        assert_eq!(detect("Netflix 15 every month on the 3rd.", today), Some((Repetition::Monthly(3), "Netflix 15".into())));
+        assert_eq!(detect("Gym 30 monthly", today), Some((Repetition::Monthly(10), "Gym 30".into())));
+        assert_eq!(detect("Cleaner 50 every Friday from Checking", today), Some((Repetition::Weekly(Weekday::Fri), "Cleaner 50 from Checking".into())));
+        assert_eq!(detect("Allowance 5 weekly", today), Some((Repetition::Weekly(Weekday::Wed), "Allowance 5".into())));
+        assert_eq!(detect("Coffee 3 every day", today), Some((Repetition::Daily, "Coffee 3".into())));
+        assert_eq!(detect("Domain 12 annually", today), Some((Repetition::Yearly, "Domain 12".into())));
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let today = date(2024, 1, 10);
+
+        assert_eq!(detect("Coffee 3 from Cash to Cafe", today), None);
+        assert_eq!(detect("Everyday items 20", today), None);
+        assert_eq!(detect("Rent 900 every month on the 32nd", today), None);
+    }
+
+    #[test]
+    fn finds_the_first_date() {
+        let today = date(2024, 1, 10);
+
+        assert_eq!(Repetition::Daily.first_date(today), today);
+        assert_eq!(Repetition::Weekly(Weekday::Wed).first_date(today), today);
+        assert_eq!(Repetition::Weekly(Weekday::Mon).first_date(today), date(2024, 1, 15));
+        assert_eq!(Repetition::Monthly(10).first_date(today), today);
+        assert_eq!(Repetition::Monthly(3).first_date(today), date(2024, 2, 3));
+        assert_eq!(Repetition::Monthly(31).first_date(date(2024, 2, 10)), date(2024, 2, 29));
+        assert_eq!(Repetition::Monthly(5).first_date(date(2024, 12, 20)), date(2025, 1, 5));
+    }
+
+    #[test]
+    fn builds_the_recurrence() {
+        let transaction = serde_json::json!({
+            "type": "withdrawal",
+            "date": "2024-01-10",
+            "description": "Netflix",
+            "notes": "Family plan",
+            "amount": "15",
+        });
+
+        let payload = payload(transaction, Repetition::Monthly(3), date(2024, 2, 3));
+
+        assert_eq!(payload["type"], "withdrawal");
+        assert_eq!(payload["title"], "Netflix");
+        assert_eq!(payload["first_date"], "2024-02-03");
+        assert_eq!(payload["notes"], "Family plan");
+        assert_eq!(payload["repetitions"][0]["type"], "monthly");
+        assert_eq!(payload["repetitions"][0]["moment"], "3");
+        assert_eq!(payload["transactions"][0], serde_json::json!({ "description": "Netflix", "amount": "15" }));
+    }
+}
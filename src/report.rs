@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{Datelike, NaiveDate};
+
+use crate::exchange::Rates;
+use crate::firefly::TransactionSplit;
+
+/// Resolves the `YYYY-MM` argument of `/report` into the first and last day
+/// of that month, defaulting to the month of `today`.
+pub fn month_range(args: &str, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    let start = if args.is_empty() {
+        NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?
+    } else {
+        NaiveDate::parse_from_str(&format!("{}-01", args.trim()), "%Y-%m-%d").ok()?
+    };
+
+    let next_month = if start.month() == 12 {
+        NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1)?
+    };
+
+    Some((start, next_month.pred()))
+}
+
+/// Spending and earnings of a period, kept separate per currency until
+/// converted into the user's base currency.
+#[derive(Debug, Default)]
+pub struct Report {
+    base_currency: Option<String>,
+    count: usize,
+    spent: BTreeMap<String, f64>,
+    earned: BTreeMap<String, f64>,
+    categories: BTreeMap<(String, String), f64>,
+    conversion_note: Option<String>,
+}
+
+fn convert_amounts<K: Ord + Clone>(
+    amounts: &BTreeMap<K, f64>,
+    rates: &Rates,
+    currency_of: impl Fn(&K) -> &str,
+    with_currency: impl Fn(&K, &str) -> K,
+) -> (BTreeMap<K, f64>, bool) {
+    let mut converted = BTreeMap::new();
+    let mut is_complete = true;
+
+    for (key, amount) in amounts {
+        match rates.to_base(*amount, currency_of(key)) {
+            Some(value) => *converted.entry(with_currency(key, &rates.base)).or_default() += value,
+            None => {
+                is_complete = false;
+                *converted.entry(key.clone()).or_default() += amount;
+            },
+        }
+    }
+
+    (converted, is_complete)
+}
+
+impl Report {
+    pub fn new(base_currency: Option<String>) -> Self {
+        Self {
+            base_currency,
+            ..Default::default()
+        }
+    }
+
+    pub fn base_currency(&self) -> Option<&str> {
+        self.base_currency.as_deref()
+    }
+
+    /// Checks if the amounts span more than one currency.
+    pub fn is_multi_currency(&self) -> bool {
+        self.spent.keys().chain(self.earned.keys()).collect::<BTreeSet<_>>().len() > 1
+    }
+
+    pub fn add(&mut self, split: &TransactionSplit) {
+        // Firefly III already stores the converted amount when the foreign
+        // currency of the transaction is the base currency, prefer that.
+        let (amount, currency) = match (&split.foreign_amount, &split.foreign_currency_code, &self.base_currency) {
+            (Some(amount), Some(currency), Some(base)) if currency == base => (amount, currency.to_owned()),
+            _ => (&split.amount, split.currency_code.to_owned().unwrap_or_default()),
+        };
+        let amount = amount.parse::<f64>().unwrap_or_default();
+
+        self.count += 1;
+
+        match split.transact_type.as_str() {
+            "withdrawal" => {
+                *self.spent.entry(currency.to_owned()).or_default() += amount;
+
+                let category = split.category_name.to_owned().unwrap_or_else(|| "Uncategorized".into());
+                *self.categories.entry((category, currency)).or_default() += amount;
+            },
+            "deposit" => *self.earned.entry(currency).or_default() += amount,
+            _ => {},
+        }
+    }
+
+    /// Converts every amount into the base currency of the rates, amounts
+    /// in currencies without a known rate are left as they are.
+    pub fn convert(&mut self, rates: &Rates) {
+        let (spent, spent_complete) = convert_amounts(&self.spent, rates, |c| c, |_, base| base.to_owned());
+        let (earned, earned_complete) = convert_amounts(&self.earned, rates, |c| c, |_, base| base.to_owned());
+        let (categories, _) = convert_amounts(
+            &self.categories,
+            rates,
+            |(_, c)| c,
+            |(category, _), base| (category.to_owned(), base.to_owned()),
+        );
+
+        self.spent = spent;
+        self.earned = earned;
+        self.categories = categories;
+
+        let mut note = format!("Amounts converted to {} using rates from {}.", rates.base, rates.date);
+        if !(spent_complete && earned_complete) {
+            note.push_str(" Some currencies had no known rate and are shown separately.");
+        }
+
+        self.conversion_note = Some(note);
+    }
+
+    pub fn render(&self, title: &str) -> String {
+        let totals = |amounts: &BTreeMap<String, f64>| {
+            if amounts.is_empty() {
+                "0.00".to_owned()
+            } else {
+                amounts
+                    .iter()
+                    .map(|(currency, amount)| format!("{:.2} {}", amount, currency).trim().to_owned())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            }
+        };
+
+        let mut categories = self.categories.iter().collect::<Vec<_>>();
+        categories.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let top_categories = categories
+            .iter()
+            .take(5)
+            .map(|((category, currency), amount)| format!("- {}: {:.2} {}", category, amount, currency).trim().to_owned())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut message = format!(
+            "{}\n\nTransactions: {}\nSpent: {}\nEarned: {}",
+            title,
+            self.count,
+            totals(&self.spent),
+            totals(&self.earned),
+        );
+
+        if !top_categories.is_empty() {
+            message.push_str(&format!("\n\nTop spending categories:\n{}", top_categories));
+        }
+
+        if let Some(note) = &self.conversion_note {
+            message.push_str(&format!("\n\n{}", note));
+        }
+
+        message
+    }
+}
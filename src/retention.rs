@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use tokio::time::sleep;
+
+use crate::access;
+use crate::accounts;
+use crate::correction;
+use crate::dedup;
+use crate::household;
+use crate::leader;
+use crate::outbox;
+use crate::pending;
+use crate::stats;
+
+use super::{Database, Error};
+
+/// How often the maintenance job enforces the retention policy.
+const MAINTENANCE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// How long each kind of record is kept, `None` keeps it forever.
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+    /// The last update seen per chat, used to drop Telegram re-deliveries,
+    /// and the confirmations that can be replied to with corrections.
+    pub history: Option<Duration>,
+    /// Outbox entries that ran out of retries.
+    pub dead_letters: Option<Duration>,
+    /// Cached Firefly III responses, account names and idle rate limit buckets.
+    pub caches: Option<Duration>,
+}
+
+/// A kind of record purged by the retention policy.
+pub struct Purged {
+    pub name: &'static str,
+    pub counter: &'static str,
+    pub count: usize,
+}
+
+pub const PURGED_HISTORY: &str = "purged_history";
+pub const PURGED_DEAD_LETTERS: &str = "purged_dead_letters";
+pub const PURGED_CACHES: &str = "purged_caches";
+pub const PURGED_INVITES: &str = "purged_invites";
+pub const PURGED_PENDING_ACTIONS: &str = "purged_pending_actions";
+
+/// Formats a retention period the way it is configured, in days.
+pub fn describe(period: Option<Duration>) -> String {
+    match period {
+        Some(period) => format!("{}d", period.num_days()),
+        None => "forever".into(),
+    }
+}
+
+/// Removes every record older than the policy allows.
+pub fn enforce(db: &Database, policy: &RetentionPolicy) -> Result<Vec<Purged>, Error> {
+    let now = Utc::now();
+    let before = |period: Duration| (now - period).timestamp();
+    let mut purged = vec![];
+
+    if let Some(period) = policy.history {
+        let count = dedup::purge_before(db, before(period))? + correction::purge_before(db, before(period))?;
+        purged.push(Purged { name: "history", counter: PURGED_HISTORY, count });
+    }
+
+    if let Some(period) = policy.dead_letters {
+        purged.push(Purged { name: "dead letters", counter: PURGED_DEAD_LETTERS, count: outbox::purge_dead_letters(db, before(period))? });
+    }
+
+    if let Some(period) = policy.caches {
+        let count = super::RATE_LIMITER.purge_idle(db, before(period))?
+            + accounts::purge_before(db, before(period))?
+            + super::FIREFLY_CACHE.purge_older_than(period.to_std().unwrap_or_default());
+        purged.push(Purged { name: "caches", counter: PURGED_CACHES, count });
+    }
+
+    let count = access::purge_expired_invites(db)? + household::purge_expired_codes(db)?;
+    purged.push(Purged { name: "invites", counter: PURGED_INVITES, count });
+    purged.push(Purged { name: "pending actions", counter: PURGED_PENDING_ACTIONS, count: pending::purge_expired(db)? });
+
+    for item in &purged {
+        stats::add(db, item.counter, item.count as u64);
+    }
+
+    Ok(purged)
+}
+
+/// Periodically enforces the retention policy.
+pub async fn run_maintenance_loop(db: Arc<Database>) {
+    loop {
+        sleep(std::time::Duration::from_secs(MAINTENANCE_INTERVAL_SECS)).await;
+
+        if !leader::is_leader() {
+            continue;
+        }
+
+        match enforce(&db, &super::config().retention) {
+            Ok(purged) => {
+                let total = purged.iter().map(|p| p.count).sum::<usize>();
+
+                if total > 0 {
+                    let summary = purged
+                        .iter()
+                        .map(|p| format!("{} {}", p.count, p.name))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+
+                    log::info!("Retention purged {}", summary);
+                }
+            },
+            Err(e) => log::error!("Failed to enforce the retention policy: {}", e),
+        }
+    }
+}
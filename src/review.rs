@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::batch::WriteBatch;
+use crate::language;
+use crate::leader;
+use crate::outbox;
+use crate::pending::PendingAction;
+
+use super::{Database, Error};
+
+/// How often the bot checks whose weekly review is due.
+const POLL_INTERVAL_SECS: u64 = 60 * 60;
+
+/// How long after the last review the next one is sent.
+const REVIEW_INTERVAL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Queued transactions younger than this are still being retried and left
+/// out of the review.
+const MIN_QUEUED_AGE_SECS: i64 = 24 * 60 * 60;
+
+/// Unconfirmed items older than this are dropped without a word.
+const MAX_UNCONFIRMED_AGE_SECS: i64 = 28 * 24 * 60 * 60;
+
+/// The number of items listed in one review, the rest wait for the next.
+const MAX_REVIEW_ITEMS: usize = 20;
+
+/// A question about a transaction nobody answered before it expired, kept
+/// for the weekly review.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UnconfirmedItem {
+    pub chat_id: i64,
+    pub action: PendingAction,
+    pub expired_at: i64,
+}
+
+impl UnconfirmedItem {
+    pub fn describe(&self, db: &Database, user_id: &str) -> String {
+        match &self.action {
+            PendingAction::Receipt(receipt) => text(db, user_id, "review_receipt", serde_json::json!({ "merchant": receipt.merchant })),
+            PendingAction::AccountChoice(choice) => choice.description(),
+            PendingAction::CurrencyChoice(choice) => choice.description(),
+            PendingAction::Repeat(text) => text.to_owned(),
+            _ => text(db, user_id, "review_unanswered", serde_json::json!({})),
+        }
+    }
+}
+
+/// A review text in the language of the user, the reviews are sent outside
+/// of any update of theirs.
+fn text(db: &Database, user_id: &str, name: &str, context: serde_json::Value) -> String {
+    // A user gone in the meantime has no language picked either.
+    let from_id = db.users.get(user_id.as_bytes()).ok().flatten().map(|user| user.telegram_id()).unwrap_or_default();
+    language::text(db, from_id, name, context)
+}
+
+/// Keeps an expired action if it held a transaction, `key` is the key of
+/// the action in the `pending` tree.
+pub fn record_expired(db: &Database, key: &[u8], action: PendingAction) -> Result<(), Error> {
+    if !matches!(action, PendingAction::Receipt(_) | PendingAction::AccountChoice(_) | PendingAction::Repeat(_) | PendingAction::CurrencyChoice(_)) {
+        return Ok(());
+    }
+
+    let key = String::from_utf8_lossy(key);
+    let (user_id, chat_id) = match key.rsplit_once('/').and_then(|(user_id, chat_id)| Some((user_id, chat_id.parse::<i64>().ok()?))) {
+        Some(parts) => parts,
+        None => return Ok(()),
+    };
+
+    db.unconfirmed.insert(format!("{}/{}", user_id, Uuid::new_v4()).as_bytes(), UnconfirmedItem {
+        chat_id,
+        action,
+        expired_at: Utc::now().timestamp(),
+    })?;
+
+    Ok(())
+}
+
+/// Lists the unconfirmed items of a user along with their ids.
+pub fn list(db: &Database, user_id: &str) -> Result<Vec<(String, UnconfirmedItem)>, Error> {
+    let prefix = format!("{}/", user_id);
+
+    db.unconfirmed
+        .scan_prefix(prefix.as_bytes())
+        .map(|item| {
+            let (key, item) = item?;
+            Ok((String::from_utf8_lossy(&key[prefix.len()..]).into_owned(), item))
+        })
+        .collect()
+}
+
+/// Removes an unconfirmed item, returning it for a retry.
+pub fn take(db: &Database, user_id: &str, id: &str) -> Result<Option<UnconfirmedItem>, Error> {
+    Ok(db.unconfirmed.remove(format!("{}/{}", user_id, id).as_bytes())?)
+}
+
+/// Drops the unconfirmed items of the user, returns how many were dropped.
+pub fn clear(db: &Database, user_id: &str) -> Result<usize, Error> {
+    let items = list(db, user_id)?;
+
+    for (id, _) in &items {
+        take(db, user_id, id)?;
+    }
+
+    Ok(items.len())
+}
+
+/// Drops everything kept for the reviews of the user.
+pub fn forget(db: &Database, batch: &mut WriteBatch, user_id: &str) -> Result<(), Error> {
+    batch.remove(&db.reviews_sent, user_id);
+    batch.remove_prefix(&db.unconfirmed, format!("{}/", user_id))
+}
+
+/// The queued transactions worth reviewing, i.e. given up on or retried
+/// for over a day.
+fn stuck(db: &Database, user_id: &str, now: i64) -> Result<Vec<(String, String)>, Error> {
+    Ok(outbox::list(db, user_id)?
+        .into_iter()
+        .filter(|(_, entry)| entry.is_abandoned() || entry.created_at <= now - MIN_QUEUED_AGE_SECS)
+        .map(|(id, entry)| {
+            let description = text(db, user_id, "review_queued_item", serde_json::json!({
+                "description": entry.payload.description(),
+                "abandoned": entry.is_abandoned(),
+                "error": entry.last_error,
+            }));
+            (id, description)
+        })
+        .collect())
+}
+
+/// Builds the Retry/Discard buttons of every item left to review, `None`
+/// once nothing is.
+pub fn keyboard(db: &Database, user_id: &str) -> Result<Option<serde_json::Value>, Error> {
+    let now = Utc::now().timestamp();
+    let queued = stuck(db, user_id, now)?
+        .into_iter()
+        .map(|(id, description)| ("queued", id, description));
+    let unconfirmed = list(db, user_id)?
+        .into_iter()
+        .map(|(id, item)| ("unconfirmed", id, item.describe(db, user_id)));
+
+    let discard = text(db, user_id, "button_discard", serde_json::json!({}));
+    let keyboard = queued
+        .chain(unconfirmed)
+        .take(MAX_REVIEW_ITEMS)
+        .map(|(kind, id, description)| {
+            let label = description.chars().take(40).collect::<String>();
+            serde_json::json!([
+                { "text": format!("🔁 {}", label), "callback_data": format!("review:retry:{}:{}", kind, id) },
+                { "text": discard, "callback_data": format!("review:discard:{}:{}", kind, id) },
+            ])
+        })
+        .collect::<Vec<serde_json::Value>>();
+
+    if keyboard.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(serde_json::json!({ "inline_keyboard": keyboard })))
+    }
+}
+
+fn render(db: &Database, user_id: &str, now: i64) -> Result<Option<String>, Error> {
+    let queued = stuck(db, user_id, now)?;
+    let unconfirmed = list(db, user_id)?;
+
+    if queued.is_empty() && unconfirmed.is_empty() {
+        return Ok(None);
+    }
+
+    let queued = queued.into_iter().map(|(_, description)| description).collect::<Vec<String>>();
+    let unconfirmed = unconfirmed.iter().map(|(_, item)| item.describe(db, user_id)).collect::<Vec<String>>();
+
+    Ok(Some(text(db, user_id, "review", serde_json::json!({
+        "queued": queued,
+        "unconfirmed": unconfirmed,
+    }))))
+}
+
+/// Sends the review of the user to the chat of their latest item, returns
+/// whether there was anything to review.
+async fn send(db: &Database, user_id: &str, now: i64) -> Result<bool, Error> {
+    let message = match render(db, user_id, now)? {
+        Some(message) => message,
+        None => return Ok(false),
+    };
+
+    let chat_id = list(db, user_id)?
+        .into_iter()
+        .max_by_key(|(_, item)| item.expired_at)
+        .map(|(_, item)| item.chat_id)
+        .or_else(|| outbox::list(db, user_id).ok()?.into_iter().max_by_key(|(_, e)| e.created_at).map(|(_, e)| e.chat_id));
+
+    let chat_id = match chat_id {
+        Some(chat_id) => chat_id,
+        None => return Ok(false),
+    };
+
+    let mut payload = serde_json::json!({
+        "chat_id": chat_id,
+        "text": message,
+    });
+
+    if let Some(keyboard) = keyboard(db, user_id)? {
+        payload["reply_markup"] = keyboard;
+    }
+
+    super::telegram_post("sendMessage", &payload).await?;
+
+    Ok(true)
+}
+
+/// Drops the unconfirmed items nobody acted on for weeks, returns how many
+/// were removed.
+fn purge_old(db: &Database, now: i64) -> Result<usize, Error> {
+    let old = db.unconfirmed
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter(|(_, item)| item.expired_at < now - MAX_UNCONFIRMED_AGE_SECS)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    for key in &old {
+        db.unconfirmed.remove(key)?;
+    }
+
+    Ok(old.len())
+}
+
+/// Periodically sends a review of the queued, failed and unconfirmed
+/// transactions to each user who has any, at most once a week.
+pub async fn run_review_loop(db: Arc<Database>) {
+    loop {
+        sleep(Duration::from_secs(POLL_INTERVAL_SECS)).await;
+
+        if !leader::is_leader() {
+            continue;
+        }
+
+        let now = Utc::now().timestamp();
+
+        if let Err(e) = purge_old(&db, now) {
+            log::error!("Failed to purge unconfirmed items: {}", e);
+        }
+
+        // Every user with an item in either tree.
+        let mut user_ids = db.outbox
+            .iter()
+            .filter_map(|item| item.ok())
+            .map(|(_, entry)| entry.user_id)
+            .collect::<BTreeSet<String>>();
+        for key in db.unconfirmed.iter().keys().filter_map(|key| key.ok()) {
+            if let Some((user_id, _)) = String::from_utf8_lossy(&key).rsplit_once('/') {
+                user_ids.insert(user_id.to_owned());
+            }
+        }
+
+        for user_id in user_ids {
+            let last_sent = db.reviews_sent.get(user_id.as_bytes()).ok().flatten();
+            if last_sent.is_some_and(|sent| sent > now - REVIEW_INTERVAL_SECS) {
+                continue;
+            }
+
+            match send(&db, &user_id, now).await {
+                Ok(true) => {
+                    if let Err(e) = db.reviews_sent.insert(user_id.as_bytes(), now) {
+                        log::error!("Failed to record the review: {}", e);
+                    }
+                },
+                Ok(false) => {},
+                Err(e) => log::error!("Failed to send the review: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::profile;
+
+use super::Error;
+
+/// A rule as written by the operator in the rules file.
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    #[serde(default)]
+    name: String,
+    when: ConditionConfig,
+    then: Action,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConditionConfig {
+    /// Case-insensitive regular expression matched against the message text.
+    pattern: Option<String>,
+
+    /// Only match messages sent in this chat.
+    chat_id: Option<i64>,
+
+    /// Only match messages sent by this user.
+    from_id: Option<i64>,
+}
+
+/// What to do with a message that matches a rule.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Action {
+    /// Extra tags added to the created transaction.
+    pub tags: Vec<String>,
+
+    /// Category to use when none was detected in the message.
+    pub category: Option<String>,
+
+    /// Reply with this text instead of creating a transaction.
+    pub reply: Option<String>,
+
+    /// Profile of the sender the transaction is logged into, unless they
+    /// bound the chat to one with `/bind`.
+    pub profile: Option<String>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    pattern: Option<Regex>,
+    chat_id: Option<i64>,
+    from_id: Option<i64>,
+    action: Action,
+}
+
+impl Rule {
+    fn matches(&self, chat_id: i64, from_id: i64, text: &str) -> bool {
+        self.chat_id.is_none_or(|id| id == chat_id)
+            && self.from_id.is_none_or(|id| id == from_id)
+            && self.pattern.as_ref().is_none_or(|p| p.is_match(text))
+    }
+}
+
+/// The set of operator-defined rules, evaluated before a message reaches the NLP stage.
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Loads the rules from a JSON file containing an array of rules.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let raw = fs::read_to_string(path)?;
+        let configs = serde_json::from_str::<Vec<RuleConfig>>(&raw)?;
+
+        let rules = configs
+            .into_iter()
+            .map(|mut c| {
+                let pattern = match &c.when.pattern {
+                    Some(p) => Some(Regex::new(&format!("(?i){}", p)).map_err(|e| e.to_string())?),
+                    None => None,
+                };
+
+                if let Some(name) = c.then.profile.take() {
+                    let parsed = profile::parse_name(&name).ok_or_else(|| format!("Rule \"{}\" has an invalid profile name: {}", c.name, name))?;
+                    c.then.profile = Some(parsed);
+                }
+
+                Ok(Rule {
+                    name: c.name,
+                    pattern,
+                    chat_id: c.when.chat_id,
+                    from_id: c.when.from_id,
+                    action: c.then,
+                })
+            })
+            .collect::<Result<Vec<Rule>, String>>()
+            .map_err(Error::Config)?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Merges the actions of every rule matching the message, the first reply wins.
+    pub fn evaluate(&self, chat_id: i64, from_id: i64, text: &str) -> Action {
+        let mut outcome = Action::default();
+
+        for rule in self.rules.iter().filter(|r| r.matches(chat_id, from_id, text)) {
+            log::debug!("Message matched rule \"{}\"", rule.name);
+
+            outcome.tags.extend(rule.action.tags.iter().cloned());
+            outcome.category = outcome.category.or_else(|| rule.action.category.to_owned());
+            outcome.reply = outcome.reply.or_else(|| rule.action.reply.to_owned());
+            outcome.profile = outcome.profile.or_else(|| rule.action.profile.to_owned());
+        }
+
+        outcome
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+use crate::language;
+use crate::leader;
+use crate::outbox;
+use crate::stats;
+use crate::telegram::TransactPayload;
+
+use super::{Database, Error};
+
+/// How often the schedule is checked for transactions due for posting.
+const POLL_INTERVAL_SECS: u64 = 30;
+
+/// A transaction the user asked to post to Firefly III at a later time.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduledEntry {
+    pub user_id: String,
+    pub chat_id: i64,
+    #[serde(with = "outbox::stored_payload")]
+    pub payload: TransactPayload,
+    pub post_at: i64,
+    pub created_at: i64,
+}
+
+/// Parses the delay given to `/later`, either `tomorrow` or a number
+/// followed by `m`, `h` or `d` (e.g. `30m`, `2h`, `1d`).
+pub fn parse_delay(value: &str) -> Option<Duration> {
+    let value = value.trim().to_lowercase();
+
+    if value == "tomorrow" {
+        return Some(Duration::days(1));
+    }
+
+    let unit = value.chars().last()?;
+    let amount = value[..value.len() - unit.len_utf8()].parse::<i64>().ok().filter(|n| *n > 0)?;
+
+    match unit {
+        'm' => Some(Duration::minutes(amount)),
+        'h' => Some(Duration::hours(amount)),
+        'd' => Some(Duration::days(amount)),
+        _ => None,
+    }
+}
+
+/// Stores a transaction to be posted once `post_at` has passed.
+pub fn schedule(db: &Database, user_id: &str, chat_id: i64, payload: TransactPayload, post_at: i64) -> Result<(), Error> {
+    let key = format!("{}/{}", user_id, Uuid::new_v4());
+
+    db.scheduled.insert(key.as_bytes(), ScheduledEntry {
+        user_id: user_id.to_owned(),
+        chat_id,
+        payload,
+        post_at,
+        created_at: Utc::now().timestamp(),
+    })?;
+
+    Ok(())
+}
+
+/// Lists the scheduled transactions of a user along with their ids.
+pub fn list(db: &Database, user_id: &str) -> Result<Vec<(String, ScheduledEntry)>, Error> {
+    let prefix = format!("{}/", user_id);
+
+    db.scheduled
+        .scan_prefix(prefix.as_bytes())
+        .map(|item| {
+            let (key, entry) = item?;
+            Ok((String::from_utf8_lossy(&key[prefix.len()..]).into_owned(), entry))
+        })
+        .collect()
+}
+
+/// Posts a scheduled transaction right away, returns `false` if there is no such entry.
+pub async fn post_now(db: &Database, user_id: &str, id: &str) -> Result<bool, Error> {
+    let key = format!("{}/{}", user_id, id);
+
+    match db.scheduled.get(key.as_bytes())? {
+        Some(entry) => {
+            post(db, key.as_bytes(), entry).await?;
+            Ok(true)
+        },
+        None => Ok(false),
+    }
+}
+
+/// Cancels a scheduled transaction, returns `false` if there is no such entry.
+pub fn cancel(db: &Database, user_id: &str, id: &str) -> Result<bool, Error> {
+    let key = format!("{}/{}", user_id, id);
+
+    Ok(db.scheduled.remove(key.as_bytes())?.is_some())
+}
+
+async fn post(db: &Database, key: &[u8], entry: ScheduledEntry) -> Result<(), Error> {
+    db.scheduled.remove(key)?;
+
+    let user = match db.users.get(entry.user_id.as_bytes())? {
+        Some(user) => user,
+        // The user reset their account in the meantime, nothing to deliver to.
+        None => return Ok(()),
+    };
+
+    let description = entry.payload.description();
+    let result = user.firefly_for_posting(db, &entry.user_id)?.create_transaction(&entry.payload).await;
+
+    let message = if outbox::is_transient(&result) {
+        outbox::enqueue(db, &entry.user_id, entry.chat_id, entry.payload, outbox::describe_failure(&result))?;
+        language::text(db, user.telegram_id(), "scheduled_queued", serde_json::json!({ "description": description }))
+    } else {
+        match outbox::rejection(result).await {
+            None => {
+                stats::record(db, stats::TRANSACTIONS_CREATED);
+                language::text(db, user.telegram_id(), "scheduled_created", serde_json::json!({ "description": description }))
+            },
+            Some(e) => language::text(db, user.telegram_id(), "scheduled_rejected", serde_json::json!({
+                "description": description,
+                "reasons": e,
+            })),
+        }
+    };
+
+    super::telegram_post("sendMessage", &serde_json::json!({
+        "chat_id": entry.chat_id,
+        "text": message,
+    }))
+    .await?;
+
+    Ok(())
+}
+
+/// Periodically posts the scheduled transactions that are due.
+pub async fn run_scheduler_loop(db: Arc<Database>) {
+    loop {
+        sleep(std::time::Duration::from_secs(POLL_INTERVAL_SECS)).await;
+
+        if !leader::is_leader() {
+            continue;
+        }
+
+        let now = Utc::now().timestamp();
+        let due = db.scheduled
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, entry)| entry.post_at <= now)
+            .collect::<Vec<_>>();
+
+        for (key, entry) in due {
+            if let Err(e) = post(&db, &key, entry).await {
+                log::error!("Failed to post scheduled transaction: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_units() {
+        assert_eq!(parse_delay("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_delay("2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_delay("1d"), Some(Duration::days(1)));
+        assert_eq!(parse_delay(" 3H "), Some(Duration::hours(3)));
+        assert_eq!(parse_delay("Tomorrow"), Some(Duration::days(1)));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for value in &["", "m", "0h", "-1d", "2w", "1.5h", "h2", "2 h", "soon", "1ñ"] {
+            assert_eq!(parse_delay(value), None, "{} was accepted", value);
+        }
+    }
+}
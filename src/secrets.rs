@@ -0,0 +1,87 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::Error;
+
+/// Marks a value sealed with a per-user key, anything else is plaintext
+/// written before a master key was configured.
+const SEALED_PREFIX: &str = "enc:v1:";
+
+const NONCE_LEN: usize = 12;
+
+/// Parses a base64 encoded 32-byte master key.
+pub fn parse_master_key(value: &str) -> Result<[u8; 32], Error> {
+    let bytes = base64::decode(value.trim()).map_err(|e| Error::Config(format!("The master key is not valid base64: {}", e)))?;
+
+    if bytes.len() != 32 {
+        return Err(Error::Config("The master key must be 32 bytes long.".into()));
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+
+    Ok(key)
+}
+
+/// Derives the key of a single user so a leaked record cannot be used to
+/// decrypt the secrets of anyone else.
+fn derive_key(master_key: &[u8; 32], user_id: i64) -> Key {
+    let mut key = [0u8; 32];
+
+    Hkdf::<Sha256>::new(None, master_key)
+        .expand(format!("firefly-tg/user-secret/{}", user_id).as_bytes(), &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    Key::clone_from_slice(&key)
+}
+
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+/// Encrypts a user's secret with the current master key, the secret is
+/// kept as is when no master key is configured.
+pub fn seal(user_id: i64, secret: &str) -> Result<String, Error> {
+    let master_key = match super::config().master_keys.first() {
+        Some(key) => key,
+        None => return Ok(secret.to_owned()),
+    };
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(master_key, user_id));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: secret.as_bytes(), aad: &user_id.to_be_bytes() })
+        .map_err(|_| Error::Secret(format!("Cannot encrypt the secret of user {}", user_id)))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+
+    Ok(format!("{}{}", SEALED_PREFIX, base64::encode(sealed)))
+}
+
+/// Decrypts a user's secret with the current or a previous master key.
+pub fn open(user_id: i64, value: &str) -> Result<String, Error> {
+    let encoded = match value.strip_prefix(SEALED_PREFIX) {
+        Some(encoded) => encoded,
+        None => return Ok(value.to_owned()),
+    };
+
+    let sealed = base64::decode(encoded).map_err(|_| Error::Secret(format!("The secret of user {} is corrupted", user_id)))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(Error::Secret(format!("The secret of user {} is corrupted", user_id)));
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    super::config().master_keys
+        .iter()
+        .find_map(|master_key| {
+            ChaCha20Poly1305::new(&derive_key(master_key, user_id))
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &user_id.to_be_bytes() })
+                .ok()
+        })
+        .and_then(|plaintext| String::from_utf8(plaintext).ok())
+        .ok_or_else(|| Error::Secret(format!("None of the configured master keys can decrypt the secret of user {}", user_id)))
+}
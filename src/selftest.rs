@@ -0,0 +1,96 @@
+use chrono::Utc;
+
+use crate::firefly::{self, FireflyClient, TransactionSingle};
+use crate::health::Check;
+use crate::rules;
+use crate::telegram::{self, ParseContext, TransactPayload};
+
+use super::Error;
+
+/// The message sent through the NLP provider, it names its accounts so any
+/// provider can parse it.
+const UTTERANCE: &str = "Self-test coffee. 1.00 from Self-test wallet to Self-test cafe";
+
+/// Parses the canned message and builds the transaction the bot would post.
+async fn check_nlp() -> Result<TransactPayload, String> {
+    let parsed = super::config().nlp_provider
+        .parse(UTTERANCE)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("{} found no transaction in \"{}\"", super::config().nlp_provider.name(), UTTERANCE))?;
+
+    let context = ParseContext {
+        default_currency: None,
+        categories: None,
+        rules: rules::Action {
+            tags: vec!["selftest".to_owned()],
+            ..Default::default()
+        },
+        date: Utc::now().format("%Y-%m-%d").to_string(),
+        clean_description: true,
+        default_source: None,
+        remembered_destination: None,
+    };
+
+    telegram::build_transaction(parsed, &context)
+        .map(TransactPayload::single)
+        .map_err(|e| e.to_string())
+}
+
+/// Posts the transaction to the test instance, returns its id.
+async fn check_create(client: &FireflyClient, payload: &TransactPayload) -> Result<String, Error> {
+    let resp = client.create_transaction(payload).await.map_err(Error::Firefly)?;
+
+    let created = firefly::error_for_status(resp)
+        .await?
+        .json::<TransactionSingle>()
+        .await
+        .map_err(Error::Firefly)?;
+
+    Ok(created.data.id)
+}
+
+/// Runs the pipeline stage by stage, a stage that cannot run because an
+/// earlier one failed is reported as skipped.
+pub async fn run() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let payload = check_nlp().await;
+    checks.push(Check { name: "nlp", error: payload.as_ref().err().cloned() });
+
+    let client = match (&super::config().selftest_firefly_url, &super::config().selftest_firefly_token) {
+        (Some(url), Some(token)) => Ok(FireflyClient::new(url, token.to_owned(), "selftest|".to_owned())),
+        _ => Err("SELFTEST_FIREFLY_URL and SELFTEST_FIREFLY_TOKEN are not set".to_owned()),
+    };
+
+    let created = match (&client, &payload) {
+        (Ok(client), Ok(payload)) => check_create(client, payload).await.map_err(|e| e.to_string()),
+        (Err(e), _) => Err(e.to_owned()),
+        (_, Err(_)) => Err("skipped, the NLP stage failed".to_owned()),
+    };
+    checks.push(Check { name: "firefly create", error: created.as_ref().err().cloned() });
+
+    let cleanup = match (&client, &created) {
+        (Ok(client), Ok(id)) => client.delete_transaction(id).await.map_err(|e| e.to_string()),
+        _ => Err("skipped, no transaction was created".to_owned()),
+    };
+    checks.push(Check { name: "firefly cleanup", error: cleanup.err() });
+
+    checks
+}
+
+/// Renders the stages as the reply to `/admin selftest`.
+pub fn render(checks: &[Check]) -> String {
+    let stages = checks
+        .iter()
+        .map(|c| match &c.error {
+            None => format!("PASS {}", c.name),
+            Some(e) => format!("FAIL {}: {}", c.name, e),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let verdict = if checks.iter().all(|c| c.is_ok()) { "passed" } else { "failed" };
+
+    format!("Self-test {}.\n\n{}", verdict, stages)
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::str::FromStr;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Variables;
+use crate::currency;
+use crate::language;
+
+use super::{Database, Error};
+
+/// Whether a message sent twice in a row is held until the user confirms
+/// it's meant to be logged again.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum ConfirmMode {
+    /// Hold repeated messages.
+    Repeats,
+    /// Log every message as it comes.
+    Off,
+}
+
+impl FromStr for ConfirmMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "repeats" => Ok(Self::Repeats),
+            "off" => Ok(Self::Off),
+            _ => Err(Error::Parse("confirm_mode_invalid", serde_json::json!({ "value": value.trim() }))),
+        }
+    }
+}
+
+impl fmt::Display for ConfirmMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Repeats => write!(f, "repeats"),
+            Self::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// The settings `/admin defaults` adjusts, with the variable of the config
+/// file each starts from.
+pub const SETTINGS: &[(&str, &str)] = &[
+    ("language", "DEFAULT_LANGUAGE"),
+    ("currency", "DEFAULT_CURRENCY"),
+    ("confirm", "DEFAULT_CONFIRM_MODE"),
+    ("digest_time", "DEFAULT_DIGEST_TIME"),
+];
+
+/// What new users start with. Language and currency are copied to a user
+/// when they set up, confirmation mode and digest time apply to every user
+/// who hasn't chosen their own.
+#[derive(Debug, Clone)]
+pub struct Defaults {
+    pub language: Option<String>,
+    pub currency: Option<String>,
+    pub confirm: ConfirmMode,
+    pub digest_time: NaiveTime,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|_| Error::Parse("time_invalid", serde_json::json!({ "value": value.trim() })))
+}
+
+/// Checks a value of the setting, returns it the way it's stored.
+fn normalize(name: &str, value: &str) -> Result<String, Error> {
+    match name {
+        "language" => language::parse_code(value)
+            .ok_or_else(|| Error::Parse("language_code_invalid", serde_json::json!({ "value": value.trim() }))),
+        "currency" if currency::is_currency_code(value.trim()) => Ok(value.trim().to_uppercase()),
+        "currency" => Err(Error::Parse("currency_code_invalid", serde_json::json!({ "value": value.trim() }))),
+        "confirm" => Ok(value.parse::<ConfirmMode>()?.to_string()),
+        "digest_time" => Ok(parse_time(value)?.format("%H:%M").to_string()),
+        _ => Err(Error::Parse("setting_unknown", serde_json::json!({ "name": name, "names": names() }))),
+    }
+}
+
+fn names() -> String {
+    SETTINGS.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ")
+}
+
+/// Where the value of a setting in effect comes from.
+enum Source {
+    Runtime,
+    Config(&'static str),
+    BuiltIn,
+}
+
+/// The value of the setting in effect, a change made with `/admin
+/// defaults` takes precedence over the config file.
+fn lookup(db: &Database, name: &str) -> Result<(Option<String>, Source), Error> {
+    if let Some(value) = db.operator_defaults.get(name.as_bytes())? {
+        return Ok((Some(value), Source::Runtime));
+    }
+
+    let variable = match SETTINGS.iter().find(|(n, _)| *n == name) {
+        Some((_, variable)) => *variable,
+        None => return Err(Error::Parse("setting_unknown", serde_json::json!({ "name": name, "names": names() }))),
+    };
+
+    match super::config().var(variable) {
+        Some(value) => Ok((Some(normalize(name, value)?), Source::Config(variable))),
+        None => Ok((None, Source::BuiltIn)),
+    }
+}
+
+/// Checks the defaults set in the config file, so a mistyped one is
+/// reported on startup.
+pub fn check(vars: &mut Variables) {
+    for (name, variable) in SETTINGS {
+        if let Some(value) = vars.text(variable) {
+            if let Err(e) = normalize(name, &value) {
+                vars.problem(format!("Invalid {}: {}", variable, e));
+            }
+        }
+    }
+}
+
+pub fn defaults(db: &Database) -> Result<Defaults, Error> {
+    let confirm = match lookup(db, "confirm")?.0 {
+        Some(mode) => mode.parse()?,
+        None => ConfirmMode::Repeats,
+    };
+    let digest_time = match lookup(db, "digest_time")?.0 {
+        Some(time) => parse_time(&time)?,
+        None => NaiveTime::from_hms(8, 0, 0),
+    };
+
+    Ok(Defaults {
+        language: lookup(db, "language")?.0,
+        currency: lookup(db, "currency")?.0,
+        confirm,
+        digest_time,
+    })
+}
+
+/// Changes a default until the bot is told otherwise, `None` goes back to
+/// the value of the config file. Returns the value now in effect.
+pub fn set_default(db: &Database, name: &str, value: Option<&str>) -> Result<Option<String>, Error> {
+    match value {
+        Some(value) => {
+            db.operator_defaults.insert(name.as_bytes(), normalize(name, value)?)?;
+        },
+        None => {
+            // Checks the name before dropping anything.
+            lookup(db, name)?;
+            db.operator_defaults.remove(name.as_bytes())?;
+        },
+    }
+
+    Ok(lookup(db, name)?.0)
+}
+
+/// Lists the defaults and where each comes from, for `/admin defaults`.
+pub fn describe(db: &Database) -> Result<String, Error> {
+    let defaults = defaults(db)?;
+    let mut lines = Vec::new();
+
+    for (name, _) in SETTINGS {
+        let value = match *name {
+            "language" => defaults.language.to_owned().unwrap_or_else(|| "the user's Telegram app".into()),
+            "currency" => defaults.currency.to_owned().unwrap_or_else(|| "not set".into()),
+            "confirm" => defaults.confirm.to_string(),
+            _ => defaults.digest_time.format("%H:%M in each user's time zone, UTC without one").to_string(),
+        };
+
+        let source = match lookup(db, name)?.1 {
+            Source::Runtime => "set with /admin defaults".to_owned(),
+            Source::Config(variable) => format!("from {}", variable),
+            Source::BuiltIn => "built-in".to_owned(),
+        };
+
+        lines.push(format!("{}: {} ({})", name, value, source));
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// How the user wants repeated messages handled, the operator's default
+/// unless they chose with `/confirm`.
+pub fn confirm_mode(db: &Database, from_id: i64) -> Result<ConfirmMode, Error> {
+    match db.confirm_modes.get(from_id.to_be_bytes())? {
+        Some(mode) => Ok(mode),
+        None => Ok(defaults(db)?.confirm),
+    }
+}
+
+/// Overrides the confirmation mode for the user, `None` follows the
+/// operator's default again.
+pub fn set_confirm_mode(db: &Database, from_id: i64, mode: Option<ConfirmMode>) -> Result<(), Error> {
+    match mode {
+        Some(mode) => db.confirm_modes.insert(&from_id.to_be_bytes(), mode)?,
+        None => db.confirm_modes.remove(from_id.to_be_bytes())?,
+    };
+
+    Ok(())
+}
+
+/// Whether the user turned off the tidying of descriptions with
+/// `/settings rawdescriptions on`.
+pub fn raw_descriptions(db: &Database, from_id: i64) -> Result<bool, Error> {
+    Ok(db.raw_descriptions.get(from_id.to_be_bytes())?.unwrap_or(false))
+}
+
+pub fn set_raw_descriptions(db: &Database, from_id: i64, raw: bool) -> Result<(), Error> {
+    if raw {
+        db.raw_descriptions.insert(&from_id.to_be_bytes(), true)?;
+    } else {
+        db.raw_descriptions.remove(from_id.to_be_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Whether the Firefly III instance of the user's account sleeps between
+/// requests, set with `/settings coldstart on`. Transactions are then
+/// posted after a warm-up request and with a longer timeout.
+pub fn cold_start(db: &Database, user_id: &str) -> Result<bool, Error> {
+    Ok(db.cold_starts.get(user_id.as_bytes())?.unwrap_or(false))
+}
+
+pub fn set_cold_start(db: &Database, user_id: &str, cold_start: bool) -> Result<(), Error> {
+    if cold_start {
+        db.cold_starts.insert(user_id.as_bytes(), true)?;
+    } else {
+        db.cold_starts.remove(user_id.as_bytes())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// The number of categories on one page of the `/categories shortlist` picker.
+pub const PAGE_SIZE: usize = 8;
+
+/// The Firefly III categories the user picked to be offered after creating
+/// a transaction without one, in the order they were picked.
+pub fn get(db: &Database, user_id: &str) -> Result<Vec<String>, Error> {
+    Ok(db.category_shortlists.get(user_id.as_bytes())?.unwrap_or_default())
+}
+
+/// Adds the category to the shortlist or removes it if it's already there,
+/// returns whether it's on the shortlist now.
+pub fn toggle(db: &Database, user_id: &str, category: &str) -> Result<bool, Error> {
+    let mut shortlist = get(db, user_id)?;
+
+    let picked = match shortlist.iter().position(|c| c == category) {
+        Some(i) => {
+            shortlist.remove(i);
+            false
+        },
+        None => {
+            shortlist.push(category.to_owned());
+            true
+        },
+    };
+
+    if shortlist.is_empty() {
+        db.category_shortlists.remove(user_id.as_bytes())?;
+    } else {
+        db.category_shortlists.insert(user_id.as_bytes(), shortlist)?;
+    }
+
+    Ok(picked)
+}
+
+pub fn forget(db: &Database, batch: &mut WriteBatch, user_id: &str) {
+    batch.remove(&db.category_shortlists, user_id);
+}
+
+/// The number of pages of the picker for `count` categories.
+pub fn pages(count: usize) -> usize {
+    count.div_ceil(PAGE_SIZE).max(1)
+}
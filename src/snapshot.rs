@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The balance of an account when the snapshot was taken.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountBalance {
+    pub balance: f64,
+    pub currency_code: Option<String>,
+}
+
+/// Account balances recorded with `/snapshot`, keyed by account name.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Snapshot {
+    pub taken_at: i64,
+    pub balances: BTreeMap<String, AccountBalance>,
+}
+
+impl Snapshot {
+    pub fn new(balances: BTreeMap<String, AccountBalance>) -> Self {
+        Self {
+            taken_at: Utc::now().timestamp(),
+            balances,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let items = self.balances
+            .iter()
+            .map(|(name, b)| format!("- {}: {:.2} {}", name, b.balance, b.currency_code.to_owned().unwrap_or_default()).trim_end().to_owned())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!("Snapshot of {} account(s) taken.\n\n{}", self.balances.len(), items)
+    }
+
+    /// Summarizes how every account changed between this snapshot and the
+    /// current balances, with the total inflows and outflows per currency.
+    pub fn compare(&self, current: &Snapshot) -> String {
+        let mut lines = vec![];
+        let mut inflows = BTreeMap::<String, f64>::new();
+        let mut outflows = BTreeMap::<String, f64>::new();
+
+        for (name, now) in &current.balances {
+            let currency = now.currency_code.to_owned().unwrap_or_default();
+
+            let change = match self.balances.get(name) {
+                Some(before) => {
+                    let change = now.balance - before.balance;
+                    lines.push(format!("- {}: {:.2} → {:.2} ({:+.2}) {}", name, before.balance, now.balance, change, currency).trim_end().to_owned());
+                    change
+                },
+                None => {
+                    lines.push(format!("- {}: new account, {:.2} {}", name, now.balance, currency).trim_end().to_owned());
+                    continue;
+                },
+            };
+
+            if change > 0.0 {
+                *inflows.entry(currency).or_default() += change;
+            } else if change < 0.0 {
+                *outflows.entry(currency).or_default() += -change;
+            }
+        }
+
+        for name in self.balances.keys().filter(|name| !current.balances.contains_key(*name)) {
+            lines.push(format!("- {}: no longer an asset account", name));
+        }
+
+        let totals = |amounts: &BTreeMap<String, f64>| {
+            if amounts.is_empty() {
+                "0.00".to_owned()
+            } else {
+                amounts
+                    .iter()
+                    .map(|(currency, amount)| format!("{:.2} {}", amount, currency).trim().to_owned())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            }
+        };
+
+        format!(
+            "Changes since {} UTC:\n\n{}\n\nInflows: {}\nOutflows: {}",
+            Utc.timestamp(self.taken_at, 0).format("%Y-%m-%d %H:%M"),
+            lines.join("\n"),
+            totals(&inflows),
+            totals(&outflows),
+        )
+    }
+}
@@ -0,0 +1,29 @@
+use super::Database;
+
+/// Transactions created in Firefly III, including retried and scheduled ones.
+pub const TRANSACTIONS_CREATED: &str = "transactions_created";
+
+/// Failures caused by the bot or its upstreams.
+pub const ERRORS: &str = "errors";
+
+/// Failures caused by what the user sent.
+pub const USER_ERRORS: &str = "user_errors";
+
+/// Bumps a usage counter. Counters are best-effort, a failure to store one
+/// is logged rather than failing the request that triggered it.
+pub fn record(db: &Database, counter: &str) {
+    add(db, counter, 1);
+}
+
+/// Adds to a usage counter, best-effort like `record`.
+pub fn add(db: &Database, counter: &str, amount: u64) {
+    let result = db.stats.fetch_and_update(counter.as_bytes(), |count| Some(count.unwrap_or_default() + amount));
+
+    if let Err(e) = result {
+        log::warn!("Failed to record the {} counter: {}", counter, e);
+    }
+}
+
+pub fn get(db: &Database, counter: &str) -> u64 {
+    db.stats.get(counter.as_bytes()).ok().flatten().unwrap_or_default()
+}
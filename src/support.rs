@@ -0,0 +1,120 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use chrono::{TimeZone, Utc};
+
+use crate::outbox;
+use crate::scheduler;
+use crate::telegram::UserClue;
+
+use super::{Database, Error};
+
+/// How many of the latest interactions are kept per chat.
+const HISTORY_CAPACITY: usize = 10;
+
+/// Telegram refuses messages over 4096 characters, the report is cut
+/// short well before that.
+const REPORT_MAX_CHARS: usize = 3500;
+
+/// An update the bot handled and how it went.
+#[derive(Debug, Clone)]
+pub struct Interaction {
+    pub at: i64,
+    pub summary: String,
+    pub outcome: String,
+    pub failed: bool,
+}
+
+/// The latest interactions of each chat, kept in memory only so `/support`
+/// can show what happened recently without storing conversations.
+#[derive(Default)]
+pub struct InteractionLog {
+    chats: Mutex<HashMap<i64, VecDeque<Interaction>>>,
+}
+
+impl InteractionLog {
+    pub fn record(&self, chat_id: i64, summary: String, outcome: String, failed: bool) {
+        let mut chats = self.chats.lock().unwrap();
+        let history = chats.entry(chat_id).or_default();
+
+        if history.len() >= HISTORY_CAPACITY {
+            history.pop_front();
+        }
+
+        history.push_back(Interaction {
+            at: Utc::now().timestamp(),
+            summary,
+            outcome,
+            failed,
+        });
+    }
+
+    /// Forgets the interactions of the chat, returns how many there were.
+    pub fn clear(&self, chat_id: i64) -> usize {
+        self.chats.lock().unwrap().remove(&chat_id).map(|history| history.len()).unwrap_or_default()
+    }
+
+    /// The interactions of the chat, oldest first.
+    pub fn list(&self, chat_id: i64) -> Vec<Interaction> {
+        self.chats
+            .lock()
+            .unwrap()
+            .get(&chat_id)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+fn format_time(at: i64) -> String {
+    Utc.timestamp(at, 0).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Packages what the operator needs to look into a user's issue: their
+/// settings with secrets left out, queued work and recent interactions.
+pub fn build_report(db: &Database, user_id: &str, chat_id: i64, user: Option<&UserClue>) -> Result<String, Error> {
+    let mut report = format!("Bot version: {}\nNLP provider: {}\n", super::VERSION, super::config().nlp_provider.name());
+
+    match user {
+        Some(user) => {
+            for (name, value) in user.support_details() {
+                report.push_str(&format!("{}: {}\n", name, value));
+            }
+        },
+        None => report.push_str("Account: not set up\n"),
+    }
+
+    let mappings = db.categories.get(user_id.as_bytes())?.map(|c| c.iter().count()).unwrap_or_default();
+    report.push_str(&format!("Category mappings: {}\n", mappings));
+
+    if let Some(schedule) = db.digests.get(user_id.as_bytes())? {
+        report.push_str(&format!("Digest: {}\n", schedule.describe(user.and_then(|user| user.timezone()))));
+    }
+
+    let queued = outbox::list(db, user_id)?;
+    if !queued.is_empty() {
+        report.push_str("\nQueued transactions:\n");
+
+        for (_, entry) in &queued {
+            report.push_str(&format!("- {} attempt(s), last error: {}\n", entry.attempts, entry.last_error));
+        }
+    }
+
+    report.push_str(&format!("Scheduled transactions: {}\n", scheduler::list(db, user_id)?.len()));
+
+    let interactions = super::INTERACTIONS.list(chat_id);
+    report.push_str("\nRecent interactions:\n");
+
+    if interactions.is_empty() {
+        report.push_str("(none since the last restart)\n");
+    }
+
+    for interaction in &interactions {
+        let mark = if interaction.failed { "FAILED" } else { "ok" };
+        report.push_str(&format!("{} {} [{}] {}\n", format_time(interaction.at), interaction.summary, mark, interaction.outcome));
+    }
+
+    if report.chars().count() > REPORT_MAX_CHARS {
+        report = report.chars().take(REPORT_MAX_CHARS).collect::<String>() + "\n(cut short)";
+    }
+
+    Ok(report)
+}
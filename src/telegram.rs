@@ -1,49 +1,154 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
-use tokio::time::{sleep, Duration};
+use sha2::{Digest, Sha256};
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
 
-use crate::wit::{Deed, WitMessageResponse};
+use crate::access;
+use crate::accessibility;
+use crate::accounts;
+use crate::admin;
+use crate::attachment;
+use crate::batch::WriteBatch;
+use crate::budget::{self, BudgetSuggestion};
+use crate::category::{self, CategoryMap};
+use crate::chart;
+use crate::commands::{self, Audience, Kind};
+use crate::correction;
+use crate::dedup;
+use crate::description;
+use crate::currency;
+use crate::digest;
+use crate::exchange;
+use crate::export;
+use crate::firefly::{self, FireflyClient, TransactionRead, TransactionSingle, TransactionSplit};
+use crate::fixture::{self, Fixture};
+use crate::forecast::{self, Forecast};
+use crate::format;
+use crate::fuzzy;
+use crate::household::{self, LinkRequest};
+use crate::language;
+use crate::review;
+use crate::shortlist;
+use crate::merchants;
+use crate::nlp::{LocalProvider, NlpProvider, ParsedTransaction};
+use crate::ocr;
+use crate::outbox;
+use crate::pending::{self, PendingAction};
+use crate::profile;
+use crate::project;
+use crate::quota::{self, Quota};
+use crate::recurrence::{self, Repetition};
+use crate::report;
+use crate::retention;
+use crate::typing::{ChatAction, InterimReply, TypingIndicator};
+use crate::undo;
+use crate::rules;
+use crate::scheduler;
+use crate::secrets;
+use crate::selftest;
+use crate::settings::{self, ConfirmMode};
+use crate::snapshot::{AccountBalance, Snapshot};
+use crate::stats;
+use crate::support;
+use crate::terms;
+use crate::timezone;
+use crate::translate;
+use crate::wit;
 
-use super::{Database, GenericError};
+use super::{Database, Error};
 
 /// This object represents a Telegram user or bot.
 #[derive(Debug, Deserialize)]
 pub struct User {
     /// Unique identifier for this user or bot. This number may have more than 32 significant bits and some programming languages may have difficulty/silent defects in interpreting it. But it has at most 52 significant bits, so a 64-bit integer or double-precision float type are safe for storing this identifier.
-    pub id: i32,
+    pub id: i64,
 
     /// True, if this user is a bot
+    #[serde(default)]
     pub is_bot: bool,
 
     /// User's or bot's first name
     pub first_name: String,
 
     /// User's or bot's last name
+    #[allow(unused)]
     pub last_name: Option<String>,
 
     /// User's or bot's username
+    #[allow(unused)]
     pub username: Option<String>,
+
+    /// IETF language tag of the user's language
+    pub language_code: Option<String>,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// This object represents a chat.
 #[derive(Debug, Deserialize)]
 pub struct Chat {
     /// Unique identifier for this chat. This number may have more than 32 significant bits and some programming languages may have difficulty/silent defects in interpreting it. But it has at most 52 significant bits, so a signed 64-bit integer or double-precision float type are safe for storing this identifier.
-    pub id: i32,
+    pub id: i64,
 
     /// Type of chat, can be either “private”, “group”, “supergroup” or “channel”.
     #[serde(rename = "type")]
     pub chat_type: String,
 
     /// Username, for private chats, supergroups and channels if available
+    #[allow(unused)]
     pub username: Option<String>,
 
     /// First name of the other party in a private chat
+    #[allow(unused)]
     pub first_name: Option<String>,
 
     /// Last name of the other party in a private chat
+    #[allow(unused)]
     pub last_name: Option<String>,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Chat {
+    pub fn is_group(&self) -> bool {
+        matches!(self.chat_type.as_str(), "group" | "supergroup")
+    }
+}
+
+/// This object represents one size of a photo or a file / sticker thumbnail.
+#[derive(Debug, Deserialize)]
+pub struct PhotoSize {
+    /// Identifier for this file, which can be used to download or reuse the file
+    pub file_id: String,
+
+    /// Photo width
+    pub width: i32,
+
+    /// Photo height
+    pub height: i32,
+
+    /// File size in bytes
+    #[allow(unused)]
+    pub file_size: Option<i64>,
+}
+
+/// This object represents a voice note.
+#[derive(Debug, Deserialize)]
+pub struct Voice {
+    /// Identifier for this file, which can be used to download or reuse the file
+    pub file_id: String,
+
+    /// Duration of the audio in seconds as defined by sender
+    pub duration: i32,
+
+    /// MIME type of the file as defined by sender
+    pub mime_type: Option<String>,
 }
 
 /// This object represents a message.
@@ -53,6 +158,7 @@ pub struct Message {
     pub message_id: i32,
 
     /// Date the message was sent in Unix time
+    #[allow(unused)]
     pub date: i64,
 
     /// For text messages, the actual UTF-8 text of the message, 0-4096 characters.
@@ -63,33 +169,219 @@ pub struct Message {
 
     /// Sender, empty for messages sent to channels
     pub from: Option<User>,
+
+    /// Message is a photo, available sizes of the photo
+    pub photo: Option<Vec<PhotoSize>>,
+
+    /// Message is a voice message, information about the file
+    pub voice: Option<Voice>,
+
+    /// Caption for the animation, audio, document, photo, video or voice, 0-1024 characters
+    pub caption: Option<String>,
+
+    /// For replies, the original message
+    pub reply_to_message: Option<Box<Message>>,
+
+    /// For forwarded messages, date the original message was sent in Unix time
+    pub forward_date: Option<i64>,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// This object represents an incoming callback query from a callback button in an inline keyboard.
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    /// Unique identifier for this query
+    pub id: String,
+
+    /// Sender
+    pub from: User,
+
+    /// Message with the callback button that originated the query
+    pub message: Option<Message>,
+
+    /// Data associated with the callback button.
+    pub data: Option<String>,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// This object represents an incoming inline query. When the user sends an empty query, your bot could return some default or trending results.
+#[derive(Debug, Deserialize)]
+pub struct InlineQuery {
+    /// Unique identifier for this query
+    pub id: String,
+
+    /// Sender
+    pub from: User,
+
+    /// Text of the query (up to 256 characters)
+    pub query: String,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Represents a result of an inline query that was chosen by the user and sent to their chat partner.
+#[derive(Debug, Deserialize)]
+pub struct ChosenInlineResult {
+    /// The unique identifier for the result that was chosen
+    pub result_id: String,
+
+    /// The user that chose the result
+    pub from: User,
+
+    /// The query that was used to obtain the result
+    pub query: String,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// This object represents an incoming update.
 #[derive(Debug, Deserialize)]
 pub struct Update {
     /// The update's unique identifier. Update identifiers start from a certain positive number and increase sequentially. This ID becomes especially handy if you're using Webhooks, since it allows you to ignore repeated updates or to restore the correct update sequence, should they get out of order. If there are no new updates for at least a week, then identifier of the next update will be chosen randomly instead of sequentially.
-    pub update_id: i32,
+    pub update_id: i64,
 
     /// New incoming message of any kind -- text, photo, sticker, etc.
     pub message: Option<Message>,
+
+    /// New incoming callback query
+    pub callback_query: Option<CallbackQuery>,
+
+    /// New incoming inline query
+    pub inline_query: Option<InlineQuery>,
+
+    /// The result of an inline query that was chosen by a user and sent to their chat partner.
+    pub chosen_inline_result: Option<ChosenInlineResult>,
+
+    /// Fields not modeled by this struct, kept around for diagnostics.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Update {
+    /// The chat this update originated from, if any. Logging an inline result
+    /// is answered in the user's private chat with the bot.
+    pub fn chat_id(&self) -> Option<i64> {
+        self.message
+            .as_ref()
+            .or_else(|| self.callback_query.as_ref().and_then(|c| c.message.as_ref()))
+            .map(|m| m.chat.id)
+            .or_else(|| self.chosen_inline_result.as_ref().map(|c| c.from.id))
+    }
+
+    /// A short description of the update for `/support` reports.
+    pub fn summary(&self) -> String {
+        if let Some(callback_query) = &self.callback_query {
+            return format!("Tapped {}", callback_query.data.as_deref().unwrap_or_default());
+        }
+
+        if let Some(chosen) = &self.chosen_inline_result {
+            return format!("Logged \"{}\" inline", chosen.query);
+        }
+
+        match &self.message {
+            Some(Message { text: Some(text), .. }) => {
+                let mut summary = text.chars().take(100).collect::<String>();
+                if summary.len() < text.len() {
+                    summary.push('…');
+                }
+
+                format!("Sent \"{}\"", summary)
+            },
+            Some(Message { photo: Some(_), .. }) => "Sent a photo".to_owned(),
+            Some(Message { voice: Some(_), .. }) => "Sent a voice message".to_owned(),
+            _ => "Sent an unsupported update".to_owned(),
+        }
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct State {
-    from_id: i32,
-    chat_id: i32,
+    from_id: i64,
+    chat_id: i64,
+    /// The message being handled, or the one holding the tapped keyboard.
+    message_id: i32,
+    /// The inline result being logged, it stands in for the message.
+    inline_result_id: Option<String>,
+    language_code: Option<String>,
+    /// Whether the message being handled was forwarded from someone else.
+    forwarded: bool,
+    /// Whether the update comes from a group chat shared with other members.
+    in_group: bool,
+    /// The profile bound to the chat with `/bind`, `None` for the user's default account.
+    profile: Option<String>,
+    /// Whether the user turned on plain text replies with `/accessibility`.
+    plain_text: bool,
+    /// The time zone the user set with `/timezone`.
+    timezone: Option<Tz>,
+    /// The largest size of the photo sent with the message, attached to the
+    /// transaction it logs.
+    photo_id: Option<String>,
+    /// The text or caption of the message, or the query of the inline result.
+    text: Option<String>,
 }
 
 impl State {
     pub fn user_id(&self) -> String {
-        format!("telegram-user-{}", self.from_id)
+        profile::user_id(self.from_id, self.profile.as_deref())
+    }
+
+    /// The external id of the transactions created while handling the update.
+    fn external_id(&self) -> String {
+        match &self.inline_result_id {
+            Some(result_id) => format!("tg-inline-{}", result_id),
+            None => external_id(self.chat_id, self.message_id),
+        }
+    }
+}
+
+/// The body of a Bot API call with the emoji of its texts and buttons
+/// dropped, and without a parse mode.
+fn plain_text_body(body: &serde_json::Value) -> serde_json::Value {
+    let mut body = body.clone();
+
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("parse_mode");
+
+        for key in ["text", "caption"] {
+            if let Some(serde_json::Value::String(text)) = fields.get_mut(key) {
+                *text = format::without_emoji(text);
+            }
+        }
+    }
+
+    if let Some(rows) = body.pointer_mut("/reply_markup/inline_keyboard").and_then(|k| k.as_array_mut()) {
+        for button in rows.iter_mut().filter_map(|row| row.as_array_mut()).flatten() {
+            if let Some(serde_json::Value::String(text)) = button.get_mut("text") {
+                *text = format::without_emoji(text);
+            }
+        }
     }
+
+    body
+}
+
+/// The key a Telegram user's records are stored under.
+pub fn user_key(from_id: i64) -> String {
+    format!("telegram-user-{}", from_id)
 }
 
 pub struct TelegramContext {
     db: Arc<Database>,
     state: Arc<State>,
+    fixture: Option<Mutex<Fixture>>,
+    /// The interim message of the update being handled, the first reply
+    /// to the chat is edited into it.
+    interim: Option<InterimReply>,
 }
 
 impl TelegramContext {
@@ -97,6 +389,34 @@ impl TelegramContext {
         Self {
             db,
             state: Arc::new(Default::default()),
+            fixture: None,
+            interim: None,
+        }
+    }
+
+    /// Starts recording the interaction as a fixture for `firefly_tg replay`.
+    pub fn record_fixture(&mut self, update: serde_json::Value) {
+        self.fixture = Some(Mutex::new(Fixture {
+            update: fixture::anonymize(update),
+            ..Default::default()
+        }));
+    }
+
+    /// The language replies to the update are sent in, set once the update
+    /// is being handled.
+    pub fn language_code(&self) -> Option<&str> {
+        self.state.language_code.as_deref()
+    }
+
+    pub fn take_fixture(&mut self) -> Option<Fixture> {
+        self.fixture.take().and_then(|f| f.into_inner().ok())
+    }
+
+    fn record(&self, f: impl FnOnce(&mut Fixture)) {
+        if let Some(fixture) = &self.fixture {
+            if let Ok(mut fixture) = fixture.lock() {
+                f(&mut fixture);
+            }
         }
     }
 
@@ -108,303 +428,4643 @@ impl TelegramContext {
         self.state.user_id().as_bytes().to_owned()
     }
 
-    pub async fn process_message(&mut self, update: Update) -> Result<reqwest::Response, GenericError> {
-        let message = update.message.ok_or("No message")?;
-        let text_payload = message.text.ok_or("Empty text payload")?;
-        let chat = message.chat;
+    /// Today in the user's time zone.
+    fn today(&self) -> NaiveDate {
+        super::config().day_cutoff.today(self.state.timezone)
+    }
 
-        let from_id = message.from.ok_or("No user from included in payload")?.id;
-        self.set_state(State {
-            from_id,
-            chat_id: chat.id,
-        });
+    /// The day a transaction sent now is booked on, in the user's time zone
+    /// and with the day cutoff applied.
+    fn booking_date(&self) -> NaiveDate {
+        super::config().day_cutoff.booking_date(Utc::now(), self.state.timezone)
+    }
 
-        super::telegram_post("sendChatAction", &serde_json::json!({
-            "chat_id": self.state.chat_id,
-            "action": "typing",
-        })).await?;
+    /// A reply in the language of the user being handled, as plain text.
+    fn text(&self, name: &str) -> String {
+        self.text_with(name, serde_json::json!({}))
+    }
 
-        sleep(Duration::from_secs(5)).await;
+    fn text_with(&self, name: &str, context: serde_json::Value) -> String {
+        format::strip(&super::config().templates.render(name, self.state.language_code.as_deref(), context))
+    }
 
-        match text_payload.as_str() {
-            "/start" => self.cmd_start().await,
-            "/reset" => self.cmd_reset().await,
-            "/help" => self.cmd_help().await,
-            "/test" => self.cmd_test().await,
-            _ => self.cmd_transact(&text_payload).await,
+    /// A `sendMessage` of a formatted reply, escaped for `MESSAGE_PARSE_MODE`.
+    fn formatted_message(&self, name: &str, context: serde_json::Value) -> serde_json::Value {
+        if self.state.plain_text {
+            return serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text_with(name, context),
+            });
         }
+
+        let text = super::config().templates.render(name, self.state.language_code.as_deref(), context);
+
+        serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "parse_mode": super::config().parse_mode.name(),
+            "text": super::config().parse_mode.render(&text),
+        })
     }
 
-    async fn cmd_start(&self) -> Result<reqwest::Response, GenericError> {
-        let exists = self.db.users.contains_key(self.get_user_id())?;
+    /// Calls the Bot API on behalf of the user being handled, users who asked
+    /// for plain text replies get them without emoji or formatting.
+    async fn post(&self, method: &str, body: &serde_json::Value) -> Result<reqwest::Response, Error> {
+        self.post_outgoing(method, self.outgoing(body.clone())).await
+    }
 
-        if exists {
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-                "chat_id": self.state.chat_id,
-                "text": "Type /reset to reset your account.",
-            }))
-            .await
-            .map_err(|e| e.into());
+    /// Posts a body already made `outgoing`. The first message to the chat
+    /// takes the place of the interim message, if one was sent.
+    async fn post_outgoing(&self, method: &str, mut body: serde_json::Value) -> Result<reqwest::Response, Error> {
+        let interim = match &self.interim {
+            Some(interim) if method == "sendMessage" && body["chat_id"].as_i64() == Some(self.state.chat_id) => interim.take().await,
+            _ => None,
+        };
 
-            tg_resp
-        } else {
-            self.db.users.insert(self.get_user_id(), UserClue::new(self.state.from_id))?;
+        match interim {
+            // Only inline keyboards can be added to an edited message.
+            Some(message_id) if body["reply_markup"].is_null() || body["reply_markup"]["inline_keyboard"].is_array() => {
+                body["message_id"] = message_id.into();
+                super::telegram_post("editMessageText", &body).await
+            },
+            Some(message_id) => {
+                let tg_resp = super::telegram_post("deleteMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                }))
+                .await;
 
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-                "chat_id": self.state.chat_id,
-                "parse_mode": "Markdown",
-                "text": "Please enter your *Firefly III* server's URL (e.g. https://my-firefly-iii.com).\n\nIt must start with HTTP/s protocol scheme.",
-            }))
-            .await
-            .map_err(|e| e.into());
+                if let Err(e) = tg_resp {
+                    log::warn!("Failed to delete the interim message: {}", e);
+                }
 
-            tg_resp
+                super::telegram_post(method, &body).await
+            },
+            None => super::telegram_post(method, &body).await,
         }
     }
 
-    async fn cmd_reset(&self) -> Result<reqwest::Response, GenericError> {
-        self.db.users.remove(self.get_user_id())?;
+    /// Posts a body already made `outgoing` like `post_outgoing`, also
+    /// returning the id of the message sent.
+    async fn post_tracked(&self, method: &str, body: serde_json::Value) -> Result<(reqwest::Response, Option<i64>), Error> {
+        let resp = self.post_outgoing(method, body).await?;
+        let status = resp.status();
+        let bytes = resp.bytes().await.map_err(Error::Telegram)?;
 
-        let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-            "chat_id": self.state.chat_id,
-            "text": "Reset complete.",
-        }))
-        .await
-        .map_err(|e| e.into());
+        let message_id = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .ok()
+            .and_then(|sent| sent["result"]["message_id"].as_i64());
 
-        tg_resp
-    }
+        // The body was read, hand the caller an equivalent response.
+        let resp = hyper::http::Response::builder().status(status).body(bytes)?;
 
-    async fn cmd_help(&self) -> Result<reqwest::Response, GenericError> {
-        let is_exists = self.db.users.contains_key(self.get_user_id())?;
+        Ok((reqwest::Response::from(resp), message_id))
+    }
 
-        if !is_exists {
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-                "chat_id": self.state.chat_id,
-                "text": "Type /start to initiate the setup process.",
-            }))
-            .await
-            .map_err(|e| e.into());
+    /// Shows the chat action while the update is handled, with an interim
+    /// message if it takes long. Screen reader users get none, the edit
+    /// into the reply might not be announced.
+    fn start_typing(&mut self, action: ChatAction) -> TypingIndicator {
+        let interim_text = if self.state.plain_text { None } else { Some(self.text("working_on_it")) };
+        let typing = TypingIndicator::start(self.state.chat_id, action, interim_text);
+        self.interim = Some(typing.interim());
+        typing
+    }
 
-            tg_resp
+    /// The body as `post` would send it, for calls made some other way.
+    fn outgoing(&self, body: serde_json::Value) -> serde_json::Value {
+        if self.state.plain_text {
+            plain_text_body(&body)
         } else {
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-                "chat_id": self.state.chat_id,
-                "parse_mode": "Markdown",
-                "text": "
-                Send a message in the following format \
-                \n`The deed. And the transaction.`
-                ",
-            }))
-            .await
-            .map_err(|e| e.into());
-
-            tg_resp
+            body
         }
     }
 
-    async fn cmd_test(&self) -> Result<reqwest::Response, GenericError> {
-        let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-            "chat_id": self.state.chat_id,
-            "text": "Message Ack",
-        }))
-        .await
-        .map_err(|e| e.into());
+    /// The profile the update is logged into: the one bound to the chat with
+    /// `/bind`, otherwise the one an operator rule picks.
+    fn resolve_profile(&self, chat_id: i64, from_id: i64, text: &str) -> Result<Option<String>, Error> {
+        match profile::bound(&self.db, chat_id, from_id)? {
+            Some(profile) => Ok(Some(profile)),
+            None => Ok(super::config().app_rules.evaluate(chat_id, from_id, text).profile),
+        }
+    }
 
-        tg_resp
+    /// The time zone of the account the update is logged into.
+    fn account_timezone(&self, from_id: i64, profile: Option<&str>) -> Result<Option<Tz>, Error> {
+        Ok(self.db.users.get(profile::user_id(from_id, profile))?.and_then(|user| user.timezone()))
     }
 
-    async fn cmd_transact(&self, payload: &str) -> Result<reqwest::Response, GenericError> {
-        let exist = self.db.users.get(self.get_user_id())?;
+    pub async fn process_message(&mut self, update: Update) -> Result<reqwest::Response, Error> {
+        log_unknown_fields("update", &update.extra);
 
-        if let Some(user) = exist {
-            if user.is_ready() {
-                self.transact(user, payload).await
-            } else {
-                match user.state.as_str() {
-                    "upload-url" => self.upload_url(payload).await,
-                    "upload-pat" => self.upload_pat(payload).await,
-                    _ => Err("Unknown user state".into()),
-                }
-            }
-        } else {
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
-                "chat_id": self.state.chat_id,
-                "text": "Type /start to initiate the setup process.",
-            }))
-            .await
-            .map_err(|e| e.into());
+        if let Some(callback_query) = update.callback_query {
+            return self.process_callback_query(callback_query).await;
+        }
 
-            tg_resp
+        if let Some(inline_query) = update.inline_query {
+            return self.process_inline_query(inline_query).await;
         }
-    }
 
-    async fn transact(&self, user: UserClue, payload: &str) -> Result<reqwest::Response, GenericError> {
-        let wit_response = super::wit_message_get(payload)
-            .await?
-            .json::<WitMessageResponse>()
-            .await?;
+        if let Some(chosen) = update.chosen_inline_result {
+            return self.process_chosen_inline_result(chosen).await;
+        }
 
-        if wit_response.intents.len().gt(&0) {
-            let description = wit_response.entities.deed
-                .unwrap_or(vec![])
-                .get(0)
-                .unwrap_or(&Deed {
-                    value: wit_response.text,
-                    ..Default::default()
-                })
-                .value
-                .to_owned();
-            let amount = wit_response.entities.amount_of_money
-                .get(0)
-                .ok_or("The amount of money is empty.")?
-                .value
-                .to_string();
-            let source_name = wit_response.entities.origin
-                .get(0)
-                .ok_or("The account origin is empty.")?
-                .value
-                .to_owned();
-            let destination_name = wit_response.entities.destination
-                .get(0)
-                .ok_or("The account destination is empty.")?
-                .value
-                .to_owned();
-            let transact_type = wit_response.traits.flow
-                .get(0)
-                .ok_or("The transact type is empty.")?
-                .value
-                .to_owned();
-
-            let transact = Transaction {
-                transact_type,
-                amount,
-                description,
-                source_name,
-                destination_name,
-                date: Utc::now().format("%Y-%m-%d").to_string(),
-            };
+        let message = update.message.ok_or_else(|| Error::InvalidUpdate("No message".into()))?;
+        log_unknown_fields("message", &message.extra);
+
+        let chat = message.chat;
+        log_unknown_fields("chat", &chat.extra);
+
+        let from = message.from.ok_or_else(|| Error::InvalidUpdate("No user from included in payload".into()))?;
+        log_unknown_fields("user", &from.extra);
+        let from_id = from.id;
+        let profile = self.resolve_profile(chat.id, from_id, message.text.as_deref().or(message.caption.as_deref()).unwrap_or_default())?;
+        self.set_state(State {
+            from_id,
+            chat_id: chat.id,
+            message_id: message.message_id,
+            inline_result_id: None,
+            language_code: language::preferred(&self.db, from_id)?.or(from.language_code),
+            forwarded: message.forward_date.is_some(),
+            in_group: chat.is_group(),
+            plain_text: accessibility::is_enabled(&self.db, from_id)?,
+            timezone: self.account_timezone(from_id, profile.as_deref())?,
+            profile,
+            photo_id: message.photo
+                .as_ref()
+                .and_then(|photo| photo.iter().max_by_key(|p| p.width * p.height))
+                .map(|p| p.file_id.to_owned()),
+            text: message.text.as_ref().or(message.caption.as_ref()).cloned(),
+        });
+
+        if let Some(code) = message.text.as_deref().and_then(|t| t.trim().strip_prefix("/start ")) {
+            if access::redeem_invite(&self.db, code.trim(), from_id)? {
+                log::info!("User {} joined with an invite", from_id);
+            }
+        }
+
+        // Group members talk to each other too, only what's meant for the bot is handled.
+        let reply_to_bot = message.reply_to_message
+            .as_ref()
+            .and_then(|m| m.from.as_ref())
+            .is_some_and(|u| u.is_bot);
+        let addressed = message.text.as_deref().or(message.caption.as_deref()).and_then(addressed_to_bot);
 
-            user.create_transaction(TransactPayload { transactions: vec![transact] }).await?;
+        if self.state.in_group && !reply_to_bot && addressed.is_none() {
+            log::debug!("Ignoring group message of user {} not addressed to the bot", from_id);
+            return Ok(ignored());
+        }
 
-            log::info!("Transaction created");
+        if !access::is_allowed(&self.db, from_id)? {
+            log::info!("Ignoring message from user {} not allowed by the access mode", from_id);
 
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
+            return self.post("sendMessage", &serde_json::json!({
                 "chat_id": self.state.chat_id,
-                "text": "Transaction created.",
+                "text": self.text("access_denied"),
             }))
-            .await
-            .map_err(|e| e.into());
+            .await;
+        }
 
-            tg_resp
-        } else {
-            let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
+        if let Some(photo) = message.photo {
+            let _typing = self.start_typing(ChatAction::Typing);
+            let caption = message.caption.map(|c| addressed_to_bot(&c).unwrap_or(c));
+
+            if super::config().receipt_attachments {
+                if let Some(reply_to) = &message.reply_to_message {
+                    if let Some(created) = correction::find(&self.db, &self.state.user_id(), self.state.chat_id, i64::from(reply_to.message_id))? {
+                        return self.cmd_attach(&created.transaction_id, reply_to.message_id).await;
+                    }
+                }
+            }
+
+            if let Some(caption) = caption.as_deref().filter(|c| is_transaction_caption(c)) {
+                return self.cmd_text(caption).await;
+            }
+
+            return self.cmd_receipt(photo, caption).await;
+        }
+
+        let reply_to_id = message.reply_to_message.as_ref().map(|m| m.message_id);
+        let reply_text = message.reply_to_message.and_then(|m| m.text);
+        let text_payload = match (message.text, message.voice) {
+            (Some(text), _) => text,
+            (None, Some(voice)) => {
+                let _typing = self.start_typing(ChatAction::Typing);
+                return self.cmd_voice(voice).await;
+            },
+            (None, None) => return Err(Error::InvalidUpdate("Empty text payload".into())),
+        };
+        let text_payload = addressed.unwrap_or(text_payload);
+        let mut parts = text_payload.trim().splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or_default();
+        let args = parts.next().unwrap_or_default().trim();
+        let _typing = self.start_typing(ChatAction::for_command(command));
+
+        if command.starts_with('/') {
+            return self.run_command(command, args, &from.first_name, reply_text).await;
+        }
+
+        match reply_to_id {
+            Some(reply_to_id) => self.cmd_reply(reply_to_id, &text_payload).await,
+            None => self.cmd_text(&text_payload).await,
+        }
+    }
+
+    /// Runs the command, `name` is the first name of whoever sent it.
+    async fn run_command(&self, command: &str, args: &str, name: &str, reply_text: Option<String>) -> Result<reqwest::Response, Error> {
+        if super::config().disabled_commands.contains(&command[1..].to_lowercase()) {
+            return self.post("sendMessage", &serde_json::json!({
                 "chat_id": self.state.chat_id,
-                "text": "Type /help to check the proper way of creating a transaction.",
+                "text": self.text_with("command_disabled", serde_json::json!({ "command": command })),
             }))
-            .await
-            .map_err(|e| e.into());
+            .await;
+        }
 
-            tg_resp
+        let kind = match commands::find(&command[1..]) {
+            Some(found) => found.kind,
+            None => return self.cmd_unknown(command).await,
+        };
+
+        match kind {
+            Kind::Start => self.cmd_start().await,
+            Kind::Reset => self.cmd_reset().await,
+            Kind::Help => self.cmd_help(args).await,
+            Kind::Test => self.cmd_test().await,
+            Kind::Currency => self.cmd_currency(args).await,
+            Kind::Default => self.cmd_default(args).await,
+            Kind::Again => self.cmd_again(args).await,
+            Kind::Find => self.cmd_find(args).await,
+            Kind::Scrub => self.cmd_scrub().await,
+            Kind::Forget => self.cmd_forget(args).await,
+            Kind::Categories => self.cmd_categories(args).await,
+            Kind::Budgets => self.cmd_budgets().await,
+            Kind::Pending => self.cmd_pending().await,
+            Kind::Report => self.cmd_report(args).await,
+            Kind::Chart => self.cmd_chart(args).await,
+            Kind::Export => self.cmd_export(args).await,
+            Kind::ExportBot => self.cmd_exportbot(args).await,
+            Kind::Later => self.cmd_later(args, reply_text).await,
+            Kind::Admin => self.cmd_admin(args).await,
+            Kind::RunRules => self.cmd_runrules(args).await,
+            Kind::Invite => self.cmd_invite().await,
+            Kind::Accounts => self.cmd_accounts(args).await,
+            Kind::Snapshot => self.cmd_snapshot().await,
+            Kind::Compare => self.cmd_compare().await,
+            Kind::Forecast => self.cmd_forecast(args).await,
+            Kind::Support => self.cmd_support().await,
+            Kind::Undo => self.cmd_undo().await,
+            Kind::Batch => self.cmd_batch(args).await,
+            Kind::Project => self.cmd_project(args).await,
+            Kind::Link => self.cmd_link(args, name).await,
+            Kind::Bind => self.cmd_bind(args).await,
+            Kind::Accessibility => self.cmd_accessibility(args).await,
+            Kind::Language => self.cmd_language(args).await,
+            Kind::Confirm => self.cmd_confirm(args).await,
+            Kind::Timezone => self.cmd_timezone(args).await,
+            Kind::Settings => self.cmd_settings(args).await,
         }
     }
 
-    async fn upload_url(&self, payload: &str) -> Result<reqwest::Response, GenericError> {
-        let firefly_url = payload.trim();
+    /// Suggests the closest command to a mistyped one, with a button to run it.
+    async fn cmd_unknown(&self, command: &str) -> Result<reqwest::Response, Error> {
+        let enabled = commands::enabled().map(|c| c.name).collect::<Vec<&str>>();
 
-        let mut user = self.db.users.get(self.get_user_id())?.ok_or("Cannot find the user in the database")?;
-        user.firefly_url = firefly_url.to_owned();
-        user.state = "upload-pat".into();
-        self.db.users.insert(self.get_user_id(), user)?;
+        match fuzzy::closest(&command[1..].to_lowercase(), &enabled) {
+            Some(suggestion) => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text_with("command_unknown_suggest", serde_json::json!({ "command": command, "suggestion": suggestion })),
+                    "reply_markup": {
+                        "inline_keyboard": [[{ "text": format!("/{}", suggestion), "callback_data": format!("command:{}", suggestion) }]],
+                    },
+                }))
+                .await
+            },
+            None => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text_with("command_unknown", serde_json::json!({ "command": command })),
+                }))
+                .await
+            },
+        }
+    }
+
+    /// Treats a reply to a transaction confirmation as a correction of the
+    /// transaction, any other reply as a new message.
+    async fn cmd_reply(&self, reply_to_id: i32, text: &str) -> Result<reqwest::Response, Error> {
+        let created = match correction::find(&self.db, &self.state.user_id(), self.state.chat_id, i64::from(reply_to_id))? {
+            Some(created) => created,
+            None => return self.cmd_text(text).await,
+        };
+
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => return self.cmd_text(text).await,
+        };
+
+        let message = match correction::parse(text, self.today()) {
+            Some(correction) => {
+                user.firefly().update_transaction(&created.transaction_id, &correction.to_json()).await?;
 
-        let message = format!("Your *Firefly III* URL's been saved!\n\nNow please enter your firefly *Personal Access Token* (PAT), you can generate it from PAT section here - {}/profile", firefly_url);
-        let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
+                if let Err(e) = self.submit_correction(reply_to_id, &correction).await {
+                    log::warn!("Failed to submit the corrected utterance to wit.ai: {}", e);
+                }
+
+                self.text_with("transaction_updated", serde_json::json!({
+                    "amount": correction.amount,
+                    "currency": correction.currency_code,
+                    "category": correction.category_name,
+                    "budget": correction.budget_name,
+                    "description": correction.description,
+                    "date": correction.date,
+                }))
+            },
+            None => self.text("correction_unclear"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
             "chat_id": self.state.chat_id,
-            "parse_mode": "Markdown",
             "text": message,
+            "reply_to_message_id": reply_to_id,
         }))
         .await
-        .map_err(|e| e.into());
+    }
+
+    /// Teaches wit.ai the corrected reading of the message the transaction
+    /// was logged from. Its text is only kept with `WIT_TRAINING_ENABLED`.
+    async fn submit_correction(&self, reply_to_id: i32, correction: &correction::Correction) -> Result<(), Error> {
+        let text = match correction::text(&self.db, &self.state.user_id(), self.state.chat_id, i64::from(reply_to_id))? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        let response = super::wit_message_get(&text)
+            .await
+            .and_then(|r| r.error_for_status().map_err(Error::Wit))?
+            .json::<wit::WitMessageResponse>()
+            .await
+            .map_err(Error::Wit)?;
 
-        tg_resp
+        match correction.utterance(&response) {
+            Some(utterance) => super::wit_utterances_post(&[utterance]).await,
+            None => Ok(()),
+        }
     }
 
-    async fn upload_pat(&self, payload: &str) -> Result<reqwest::Response, GenericError> {
-        let firefly_pat = payload.trim();
+    /// Attaches a photo sent in reply to a transaction confirmation to the
+    /// transaction.
+    async fn cmd_attach(&self, transaction_id: &str, reply_to_id: i32) -> Result<reqwest::Response, Error> {
+        let photo_id = self.state.photo_id
+            .as_deref()
+            .ok_or_else(|| Error::InvalidUpdate("No photo sizes included in payload".into()))?;
 
-        let mut user = self.db.users.get(self.get_user_id())?.ok_or("Cannot find the user in the database")?;
-        user.firefly_pat = firefly_pat.to_owned();
-        user.state = "ready".into();
-        self.db.users.insert(self.get_user_id(), user)?;
+        let message = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => self.attach_receipt(&user, transaction_id, photo_id).await,
+            Some(_) => self.text("setup_unfinished"),
+            None => self.text("setup_required"),
+        };
 
-        let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
+        self.post("sendMessage", &serde_json::json!({
             "chat_id": self.state.chat_id,
-            "text": "Setup complete. You can now use the telegram bot to store your transaction.",
+            "text": message,
+            "reply_to_message_id": reply_to_id,
         }))
         .await
-        .map_err(|e| e.into());
-
-        tg_resp
     }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct TransactPayload {
-    transactions: Vec<Transaction>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Transaction {
-    #[serde(rename = "type")]
-    transact_type: String,
-    description: String,
-    date: String,
-    amount: String,
-    source_name: String,
-    destination_name: String,
-}
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
-pub struct UserClue {
-    id: i32,
-    state: String,
-    firefly_url: String,
-    firefly_pat: String,
-}
+    /// Runs the operator rules over a message, then treats it as a transaction
+    /// unless a rule replied to it.
+    async fn cmd_text(&self, text: &str) -> Result<reqwest::Response, Error> {
+        let outcome = super::config().app_rules.evaluate(self.state.chat_id, self.state.from_id, text);
 
-impl UserClue {
-    pub fn new(id: i32) -> Self {
-        Self {
-            id,
-            state: "upload-url".into(),
-            ..Default::default()
+        match outcome.reply {
+            Some(reply) => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": reply,
+                }))
+                .await
+            },
+            None => self.cmd_transact(text, outcome).await,
         }
     }
 
-    pub fn is_ready(&self) -> bool {
-        self.state.eq("ready".into())
-    }
+    /// Offers to log the query as a transaction, nothing is parsed until the
+    /// user picks the result so typing doesn't use up their quota.
+    async fn process_inline_query(&mut self, inline_query: InlineQuery) -> Result<reqwest::Response, Error> {
+        log_unknown_fields("inline query", &inline_query.extra);
+        log_unknown_fields("user", &inline_query.from.extra);
 
-    #[allow(unused)]
-    async fn get_accounts(&self, account_type: &str) -> Result<reqwest::Response, reqwest::Error> {
-        let url = format!("{}/public/api/v1/accounts", self.firefly_url.to_owned());
-
-        reqwest::Client::new()
-            .get(&url)
-            .query(&[("type", account_type)])
-            .bearer_auth(self.firefly_pat.to_owned())
-            .send()
-            .await
+        let query = inline_query.query.trim();
+        let results = if query.is_empty() || !access::is_allowed(&self.db, inline_query.from.id)? {
+            vec![]
+        } else {
+            vec![serde_json::json!({
+                "type": "article",
+                // Query ids are unique, so logging the result is never mistaken for a repeat.
+                "id": inline_query.id,
+                "title": "Log transaction",
+                "description": query,
+                "input_message_content": {
+                    "message_text": format!("💸 {}", query),
+                },
+            })]
+        };
+
+        self.post("answerInlineQuery", &serde_json::json!({
+            "inline_query_id": inline_query.id,
+            "results": results,
+            "cache_time": 0,
+            "is_personal": true,
+        }))
+        .await
     }
 
-    async fn create_transaction(&self, payload: TransactPayload) -> Result<reqwest::Response, reqwest::Error> {
-        let url = format!("{}/public/api/v1/transactions", self.firefly_url.to_owned());
+    /// Logs the query of the picked result, replies go to the user's private
+    /// chat with the bot rather than to the chat the card was sent in.
+    async fn process_chosen_inline_result(&mut self, chosen: ChosenInlineResult) -> Result<reqwest::Response, Error> {
+        log_unknown_fields("chosen inline result", &chosen.extra);
+        log_unknown_fields("user", &chosen.from.extra);
 
-        reqwest::Client::new()
-            .post(&url)
-            .json(&payload)
-            .bearer_auth(self.firefly_pat.to_owned())
-            .send()
+        let profile = self.resolve_profile(chosen.from.id, chosen.from.id, &chosen.query)?;
+        self.set_state(State {
+            from_id: chosen.from.id,
+            chat_id: chosen.from.id,
+            inline_result_id: Some(chosen.result_id),
+            language_code: language::preferred(&self.db, chosen.from.id)?.or(chosen.from.language_code),
+            plain_text: accessibility::is_enabled(&self.db, chosen.from.id)?,
+            timezone: self.account_timezone(chosen.from.id, profile.as_deref())?,
+            profile,
+            text: Some(chosen.query.trim().to_owned()),
+            ..Default::default()
+        });
+
+        if !access::is_allowed(&self.db, self.state.from_id)? {
+            log::info!("Ignoring inline result of user {} not allowed by the access mode", self.state.from_id);
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("access_denied"),
+            }))
+            .await;
+        }
+
+        match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => self.cmd_text(chosen.query.trim()).await,
+            _ => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await
+            },
+        }
+    }
+
+    async fn process_callback_query(&mut self, callback_query: CallbackQuery) -> Result<reqwest::Response, Error> {
+        log_unknown_fields("callback query", &callback_query.extra);
+
+        log_unknown_fields("user", &callback_query.from.extra);
+
+        let message = callback_query.message.ok_or_else(|| Error::InvalidUpdate("No message included in callback query".into()))?;
+        log_unknown_fields("chat", &message.chat.extra);
+        // The keyboard's message is the bot's, rules only match on the chat and sender.
+        let profile = self.resolve_profile(message.chat.id, callback_query.from.id, "")?;
+        self.set_state(State {
+            from_id: callback_query.from.id,
+            chat_id: message.chat.id,
+            message_id: message.message_id,
+            inline_result_id: None,
+            language_code: language::preferred(&self.db, callback_query.from.id)?.or(callback_query.from.language_code),
+            forwarded: false,
+            in_group: message.chat.is_group(),
+            plain_text: accessibility::is_enabled(&self.db, callback_query.from.id)?,
+            timezone: self.account_timezone(callback_query.from.id, profile.as_deref())?,
+            profile,
+            photo_id: None,
+            text: None,
+        });
+
+        if !access::is_allowed(&self.db, self.state.from_id)? {
+            return self.post("answerCallbackQuery", &serde_json::json!({
+                "callback_query_id": callback_query.id,
+                "text": self.text("access_denied"),
+            }))
+            .await;
+        }
+
+        let data = callback_query.data.unwrap_or_default();
+        let mut parts = data.split(':');
+        let kind = parts.next().unwrap_or_default();
+
+        // These buttons act on the account of the user they were sent to,
+        // whose id follows the kind, a tap by anyone else in the chat is refused.
+        if OWNED_CALLBACKS.contains(&kind) && parts.next().and_then(|id| id.parse::<i64>().ok()) != Some(self.state.from_id) {
+            return self.post("answerCallbackQuery", &serde_json::json!({
+                "callback_query_id": callback_query.id,
+                "text": self.text("callback_not_yours"),
+            }))
+            .await;
+        }
+
+        self.post("answerCallbackQuery", &serde_json::json!({
+            "callback_query_id": callback_query.id,
+        })).await?;
+
+        match kind {
+            "budget" => {
+                let transaction_id = parts.next().ok_or_else(|| Error::InvalidUpdate("No transaction id in callback data".into()))?;
+                let budget_id = parts.next().ok_or_else(|| Error::InvalidUpdate("No budget id in callback data".into()))?;
+                self.assign_budget(message.message_id, transaction_id, budget_id).await
+            },
+            "account" => self.resolve_account_choice(message.message_id, parts.next().unwrap_or_default()).await,
+            "terms" => self.resolve_terms(message.message_id, parts.next() == Some("accept")).await,
+            "currency" => self.resolve_currency_choice(message.message_id, parts.next().unwrap_or_default()).await,
+            "newbudget" => {
+                let confirmed = parts.next() == Some("create");
+                self.resolve_budget_suggestion(message.message_id, confirmed).await
+            },
+            "receipt" => {
+                let confirmed = parts.next() == Some("confirm");
+                // The question is asked in reply to the photo, which is attached once confirmed.
+                let photo_id = message.reply_to_message
+                    .as_ref()
+                    .and_then(|m| m.photo.as_ref())
+                    .and_then(|photo| photo.iter().max_by_key(|p| p.width * p.height))
+                    .map(|p| p.file_id.to_owned());
+                self.resolve_receipt(message.message_id, confirmed, photo_id).await
+            },
+            "pending" => {
+                let action = parts.next().unwrap_or_default();
+                self.resolve_pending(message.message_id, action, parts.next()).await
+            },
+            "batch" => self.retry_batch(message.message_id).await,
+            "support" => self.resolve_support(message.message_id, parts.next() == Some("send")).await,
+            "link" => self.resolve_link(message.message_id, parts.next() == Some("approve")).await,
+            "repeat" => self.resolve_repeat(message.message_id, parts.next() == Some("log")).await,
+            "shortlist" => {
+                let action = parts.next().unwrap_or_default();
+                let page = parts.next().and_then(|p| p.parse::<usize>().ok()).unwrap_or_default();
+                let index = parts.next().and_then(|i| i.parse::<usize>().ok());
+                self.resolve_shortlist(message.message_id, action, page, index).await
+            },
+            "category" => {
+                let transaction_id = parts.next().ok_or_else(|| Error::InvalidUpdate("No transaction id in callback data".into()))?;
+                let index = parts.next().and_then(|i| i.parse::<usize>().ok());
+                self.assign_category(message.message_id, transaction_id, index).await
+            },
+            "find" => {
+                let page = parts.next().and_then(|p| p.parse::<u32>().ok()).unwrap_or(1);
+                self.page_search(message.message_id, page).await
+            },
+            "review" => {
+                let action = parts.next().unwrap_or_default();
+                let kind = parts.next().unwrap_or_default();
+                let id = parts.next().ok_or_else(|| Error::InvalidUpdate("No item id in callback data".into()))?;
+                self.resolve_review(message.message_id, action, kind, id).await
+            },
+            "command" => {
+                self.post("editMessageReplyMarkup", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message.message_id,
+                    "reply_markup": { "inline_keyboard": [] },
+                }))
+                .await?;
+
+                let command = format!("/{}", parts.next().unwrap_or_default());
+                self.run_command(&command, "", &callback_query.from.first_name, None).await
+            },
+            _ => Err(Error::InvalidUpdate("Unknown callback query data".into())),
+        }
+    }
+
+    async fn cmd_start(&self) -> Result<reqwest::Response, Error> {
+        if self.state.in_group {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("group_setup"),
+            }))
+            .await;
+        }
+
+        let user = self.db.users.get(self.get_user_id())?;
+
+        // Nothing is stored for a new user before they accept the notice,
+        // and everyone accepts it again once the operator changes it.
+        if terms::is_required(user.as_ref()) {
+            let notice = super::config().terms_notice.as_deref().unwrap_or_default();
+            let name = if user.is_some() { "terms_updated" } else { "terms_notice" };
+
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text_with(name, serde_json::json!({ "notice": notice })),
+                "reply_markup": {
+                    "inline_keyboard": [[
+                        { "text": self.text("button_accept_terms"), "callback_data": "terms:accept" },
+                        { "text": self.text("button_decline_terms"), "callback_data": "terms:decline" },
+                    ]],
+                },
+            }))
+            .await;
+        }
+
+        if user.is_some() {
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("reset_required"),
+            }))
+            .await
+        } else {
+            self.set_up_account(None).await
+        }
+    }
+
+    /// Creates the account of a new user, with the version of the terms
+    /// notice they accepted if the operator set one.
+    async fn set_up_account(&self, terms_version: Option<String>) -> Result<reqwest::Response, Error> {
+        // New users start with the operator's defaults, changed later
+        // with /currency and /language.
+        let defaults = settings::defaults(&self.db)?;
+        let mut user = UserClue::new(self.state.from_id);
+        user.default_currency = defaults.currency;
+        if let Some(version) = terms_version {
+            user.accept_terms(version);
+        }
+        self.db.users.insert(self.get_user_id(), user)?;
+
+        if let Some(code) = defaults.language {
+            if language::preferred(&self.db, self.state.from_id)?.is_none() {
+                language::set(&self.db, self.state.from_id, &code)?;
+            }
+        }
+
+        self.post("sendMessage", &self.formatted_message("setup_url", serde_json::json!({})))
+            .await
+    }
+
+    /// Records the acceptance of the terms notice on the user and carries
+    /// on with the setup, or leaves the user without an account when declined.
+    async fn resolve_terms(&self, message_id: i32, accepted: bool) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?;
+
+        if !accepted {
+            let name = if user.is_some() { "terms_updated_declined" } else { "terms_declined" };
+            return self.edit_progress(i64::from(message_id), &self.text(name)).await;
+        }
+
+        let version = match terms::current_version() {
+            Some(version) => version,
+            // The operator took the notice down in the meantime.
+            None => return self.cmd_start().await,
+        };
+
+        match user {
+            Some(mut user) => {
+                user.accept_terms(version);
+                self.db.users.insert(self.get_user_id(), user)?;
+                self.edit_progress(i64::from(message_id), &self.text("terms_accepted")).await
+            },
+            None => {
+                self.edit_progress(i64::from(message_id), &self.text("terms_accepted")).await?;
+                self.set_up_account(Some(version)).await
+            },
+        }
+    }
+
+    async fn cmd_reset(&self) -> Result<reqwest::Response, Error> {
+        let mut batch = WriteBatch::default();
+        admin::forget_account(&self.db, &mut batch, &self.state.user_id())?;
+        batch.apply(&self.db)?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text("reset_complete"),
+        }))
+        .await
+    }
+
+    async fn cmd_help(&self, args: &str) -> Result<reqwest::Response, Error> {
+        if !args.is_empty() {
+            let name = args.trim_start_matches('/');
+            let message = match commands::find(name) {
+                Some(command) if command.audience != Audience::Master || access::is_master(self.state.from_id) => commands::describe(command),
+                _ => self.text_with("command_unknown", serde_json::json!({ "command": format!("/{}", name) })),
+            };
+
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": message,
+            }))
+            .await;
+        }
+
+        let is_exists = self.db.users.contains_key(self.get_user_id())?;
+
+        if !is_exists {
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("setup_required"),
+            }))
+            .await
+        } else {
+            let context = serde_json::json!({ "commands": commands::help_lines() });
+            self.post("sendMessage", &self.formatted_message("help", context))
+                .await
+        }
+    }
+
+    async fn cmd_test(&self) -> Result<reqwest::Response, Error> {
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": "Message Ack",
+        }))
+        .await
+    }
+
+    async fn cmd_currency(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let exist = self.db.users.get(self.get_user_id())?;
+
+        let message = match exist {
+            None => self.text("setup_required"),
+            Some(user) if args.is_empty() => match user.default_currency {
+                Some(code) => self.text_with("currency_current", serde_json::json!({ "code": code })),
+                None => self.text("currency_not_set"),
+            },
+            Some(_) if !currency::is_currency_code(args) => {
+                self.text("currency_invalid")
+            },
+            Some(mut user) => {
+                let code = args.to_uppercase();
+                user.default_currency = Some(code.to_owned());
+                self.db.users.insert(self.get_user_id(), user)?;
+
+                self.text_with("currency_set", serde_json::json!({ "code": code }))
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_default(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let role = parts.next().unwrap_or_default().to_lowercase();
+        let account = parts.next().unwrap_or_default().trim();
+        let user_id = self.state.user_id();
+
+        let message = match (role.as_str(), account) {
+            ("source", "") => match accounts::default_source(&self.db, &user_id)? {
+                Some(account) => self.text_with("default_source_current", serde_json::json!({ "account": account })),
+                None => self.text("default_source_none"),
+            },
+            ("source", "clear") => {
+                accounts::set_default_source(&self.db, &user_id, None)?;
+                self.text("default_source_cleared")
+            },
+            ("source", name) => {
+                let assets = self.account_names(&user, "asset").await?;
+
+                match match_account(name, &assets) {
+                    Some(account) => {
+                        accounts::set_default_source(&self.db, &user_id, Some(account))?;
+                        self.text_with("default_source_set", serde_json::json!({ "account": account }))
+                    },
+                    None => self.text_with("default_source_unknown", serde_json::json!({ "account": name, "accounts": assets.join(", ") })),
+                }
+            },
+            _ => self.text("default_usage"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_forget(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user_id = self.state.user_id();
+
+        let message = match args.trim() {
+            "" => {
+                let remembered = merchants::list(&self.db, &user_id)?
+                    .into_iter()
+                    .take(FORGET_LIST_LIMIT)
+                    .map(|(merchant, destination)| format!("{} → {}", merchant, destination))
+                    .collect::<Vec<String>>();
+
+                if remembered.is_empty() {
+                    self.text("forget_none")
+                } else {
+                    self.text_with("forget_list", serde_json::json!({ "merchants": remembered.join("\n") }))
+                }
+            },
+            merchant => match merchants::forget(&self.db, &user_id, merchant)? {
+                Some(destination) => self.text_with("forget_done", serde_json::json!({ "merchant": merchant, "destination": destination })),
+                None => self.text_with("forget_unknown", serde_json::json!({ "merchant": merchant })),
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_categories(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let exists = self.db.users.contains_key(self.get_user_id())?;
+        let mut categories = self.db.categories.get(self.get_user_id())?.unwrap_or_default();
+
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let action = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default().trim();
+
+        let message = match action {
+            _ if !exists => self.text("setup_required"),
+            "map" => match category::parse_mapping(rest) {
+                Some((keyword, category_name)) => {
+                    categories.insert(keyword, category_name);
+                    self.db.categories.insert(self.get_user_id(), categories)?;
+
+                    // Firefly III creates missing categories on the fly, the
+                    // note only catches typos of existing ones.
+                    let unknown = match self.db.users.get(self.get_user_id())? {
+                        Some(user) if user.is_ready() => match user.firefly().get_categories().await {
+                            Ok(known) => !known.iter().any(|c| c.attributes.name.eq_ignore_ascii_case(category_name)),
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+
+                    self.text_with("categories_mapped", serde_json::json!({
+                        "keyword": keyword,
+                        "category": category_name,
+                        "unknown": unknown,
+                    }))
+                },
+                None => self.text("categories_usage"),
+            },
+            "shortlist" => {
+                let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                let (text, keyboard) = self.shortlist_picker(&user, 0).await?;
+
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": text,
+                    "reply_markup": keyboard,
+                }))
+                .await;
+            },
+            "unmap" => match categories.remove(rest) {
+                Some(category_name) => {
+                    self.db.categories.insert(self.get_user_id(), categories)?;
+
+                    self.text_with("categories_unmapped", serde_json::json!({ "keyword": rest, "category": category_name }))
+                },
+                None => self.text_with("categories_no_mapping", serde_json::json!({ "keyword": rest })),
+            },
+            _ if categories.is_empty() => {
+                self.text("categories_empty")
+            },
+            _ => {
+                let mappings = categories
+                    .iter()
+                    .map(|(keyword, category_name)| serde_json::json!({ "keyword": keyword, "category": category_name }))
+                    .collect::<Vec<serde_json::Value>>();
+
+                self.text_with("categories_list", serde_json::json!({ "mappings": mappings }))
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// The Firefly III category names in the order the picker lists them.
+    async fn category_names(&self, user: &UserClue) -> Result<Vec<String>, Error> {
+        let mut names = user.firefly()
+            .get_categories()
+            .await?
+            .into_iter()
+            .map(|c| c.attributes.name)
+            .collect::<Vec<String>>();
+        names.sort_by_key(|name| name.to_lowercase());
+
+        Ok(names)
+    }
+
+    /// A page of the picker toggling which categories are on the shortlist.
+    async fn shortlist_picker(&self, user: &UserClue, page: usize) -> Result<(String, serde_json::Value), Error> {
+        let names = self.category_names(user).await?;
+        let shortlist = shortlist::get(&self.db, &self.state.user_id())?;
+        let pages = shortlist::pages(names.len());
+        let page = page.min(pages - 1);
+
+        let mut keyboard = names
+            .iter()
+            .enumerate()
+            .skip(page * shortlist::PAGE_SIZE)
+            .take(shortlist::PAGE_SIZE)
+            .map(|(i, name)| {
+                let mark = if shortlist.contains(name) { "✅" } else { "⬜" };
+                vec![serde_json::json!({
+                    "text": format!("{} {}", mark, name),
+                    "callback_data": format!("shortlist:toggle:{}:{}", page, i),
+                })]
+            })
+            .collect::<Vec<Vec<serde_json::Value>>>();
+
+        let mut navigation = Vec::new();
+        if page > 0 {
+            navigation.push(serde_json::json!({ "text": "◀", "callback_data": format!("shortlist:page:{}", page - 1) }));
+        }
+        if page + 1 < pages {
+            navigation.push(serde_json::json!({ "text": "▶", "callback_data": format!("shortlist:page:{}", page + 1) }));
+        }
+        if !navigation.is_empty() {
+            keyboard.push(navigation);
+        }
+        keyboard.push(vec![serde_json::json!({ "text": self.text("button_done"), "callback_data": "shortlist:done" })]);
+
+        let text = if names.is_empty() {
+            self.text("shortlist_no_categories")
+        } else {
+            self.text_with("shortlist_picker", serde_json::json!({
+                "picked": shortlist.len(),
+                "page": page + 1,
+                "pages": pages,
+            }))
+        };
+
+        Ok((text, serde_json::json!({ "inline_keyboard": keyboard })))
+    }
+
+    async fn resolve_shortlist(&self, message_id: i32, action: &str, page: usize, index: Option<usize>) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("editMessageText", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        match (action, index) {
+            ("toggle", Some(index)) => {
+                if let Some(name) = self.category_names(&user).await?.get(index) {
+                    shortlist::toggle(&self.db, &self.state.user_id(), name)?;
+                }
+            },
+            ("page", _) => {},
+            _ => {
+                let shortlist = shortlist::get(&self.db, &self.state.user_id())?;
+                let message = if shortlist.is_empty() {
+                    self.text("shortlist_done_empty")
+                } else {
+                    self.text_with("shortlist_done", serde_json::json!({ "categories": shortlist.join(", ") }))
+                };
+
+                return self.post("editMessageText", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "text": message,
+                }))
+                .await;
+            },
+        }
+
+        let (text, keyboard) = self.shortlist_picker(&user, page).await?;
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": text,
+            "reply_markup": keyboard,
+        }))
+        .await
+    }
+
+    /// Offers the shortlisted categories for a transaction created without one.
+    async fn offer_categories(&self, transaction_id: &str) -> Result<Option<reqwest::Response>, Error> {
+        let shortlist = shortlist::get(&self.db, &self.state.user_id())?;
+        if shortlist.is_empty() {
+            return Ok(None);
+        }
+
+        let mut keyboard = shortlist
+            .chunks(2)
+            .enumerate()
+            .map(|(row, names)| names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| serde_json::json!({
+                    "text": name,
+                    "callback_data": format!("category:{}:{}:{}", self.state.from_id, transaction_id, row * 2 + i),
+                }))
+                .collect::<Vec<serde_json::Value>>())
+            .collect::<Vec<Vec<serde_json::Value>>>();
+        keyboard.push(vec![serde_json::json!({ "text": self.text("button_skip"), "callback_data": format!("category:{}:{}", self.state.from_id, transaction_id) })]);
+
+        let resp = self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text("category_pick"),
+            "reply_markup": { "inline_keyboard": keyboard },
+        }))
+        .await?;
+
+        Ok(Some(resp))
+    }
+
+    async fn assign_category(&self, message_id: i32, transaction_id: &str, index: Option<usize>) -> Result<reqwest::Response, Error> {
+        let category = index.and_then(|i| shortlist::get(&self.db, &self.state.user_id()).ok()?.into_iter().nth(i));
+
+        let message = match category {
+            Some(category) => {
+                let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                user.firefly().update_transaction(transaction_id, &serde_json::json!({
+                    "apply_rules": false,
+                    "transactions": [{ "category_name": category }],
+                }))
+                .await?;
+
+                self.text_with("category_assigned", serde_json::json!({ "category": category }))
+            },
+            None => self.text("category_skipped"),
+        };
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_budgets(&self) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let budgets = user.firefly().get_active_budgets().await?;
+        let message = if budgets.is_empty() {
+            self.text("budgets_empty")
+        } else {
+            let names = budgets.iter().map(|b| b.attributes.name.as_str()).collect::<Vec<&str>>();
+
+            self.text_with("budgets_list", serde_json::json!({ "budgets": names }))
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_project(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let action = parts.next().unwrap_or_default().to_lowercase();
+        let name = project::parse_name(parts.next().unwrap_or_default());
+        let active = project::active(&self.db, &self.state.user_id())?;
+
+        let message = match (action.as_str(), name) {
+            ("start", Some(name)) => match project::start(&self.db, &self.state.user_id(), &name)? {
+                Some(previous) if previous != name => self.text_with("project_started", serde_json::json!({ "name": name, "previous": previous })),
+                _ => self.text_with("project_started", serde_json::json!({ "name": name })),
+            },
+            ("stop", _) => match project::stop(&self.db, &self.state.user_id())? {
+                Some(name) => self.text_with("project_stopped", serde_json::json!({ "name": name })),
+                None => self.text("project_not_tracking"),
+            },
+            ("report", Some(name)) => return self.project_report(&user, &name).await,
+            ("report", None) => match active {
+                Some(name) => return self.project_report(&user, &name).await,
+                None => self.text("project_report_usage"),
+            },
+            _ => {
+                let status = match active {
+                    Some(name) => self.text_with("project_active", serde_json::json!({ "name": name })),
+                    None => self.text("project_not_tracking"),
+                };
+
+                format!("{}\n\n{}", status, self.text("project_usage"))
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Sums up every transaction tagged with the project, whenever it happened.
+    async fn project_report(&self, user: &UserClue, name: &str) -> Result<reqwest::Response, Error> {
+        let message_id = self.send_progress("Generating the report…").await?;
+
+        let mut report = report::Report::new(user.default_currency.to_owned());
+        let mut page = 1;
+
+        loop {
+            let transactions = user.firefly().get_tag_transactions(name, page).await?;
+
+            transactions.data
+                .iter()
+                .flat_map(|group| group.attributes.transactions.iter())
+                .for_each(|split| report.add(split));
+
+            let pagination = transactions.meta.pagination;
+            if pagination.current_page >= pagination.total_pages {
+                break;
+            }
+
+            page += 1;
+        }
+
+        if let Some(base) = report.base_currency().filter(|_| report.is_multi_currency()) {
+            match exchange::fetch_rates(base).await {
+                Ok(rates) => report.convert(&rates),
+                Err(e) => log::warn!("Failed to fetch exchange rates, reporting per currency: {}", e),
+            }
+        }
+
+        self.edit_progress(message_id, &report.render(&self.text_with("project_report_title", serde_json::json!({ "name": name })))).await
+    }
+
+    /// Creates a transaction out of every line of the message.
+    async fn cmd_batch(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let items = args
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .enumerate()
+            .collect::<Vec<(usize, String)>>();
+
+        if items.is_empty() || items.len() > BATCH_MAX_ITEMS {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text_with("batch_usage", serde_json::json!({ "max": BATCH_MAX_ITEMS })),
+            }))
+            .await;
+        }
+
+        let batch = FailedBatch {
+            external_id: self.state.external_id(),
+            items,
+        };
+
+        self.run_batch(&user, batch).await
+    }
+
+    /// Retries the items of a batch that failed, from the button under its report.
+    async fn retry_batch(&self, message_id: i32) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+
+        match pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::BatchRetry(_)))? {
+            Some(PendingAction::BatchRetry(batch)) => self.run_batch(&user, batch).await,
+            _ => {
+                self.post("editMessageReplyMarkup", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "reply_markup": { "inline_keyboard": [] },
+                }))
+                .await
+            },
+        }
+    }
+
+    /// Creates the transaction of a batch line, returns what came of it.
+    async fn batch_item(&self, user: &UserClue, external_id: String, line: &str) -> Result<&'static str, Error> {
+        let outcome = super::config().app_rules.evaluate(self.state.chat_id, self.state.from_id, line);
+        if let Some(reply) = outcome.reply {
+            return Err(Error::Parse("rule_reply", serde_json::json!({ "reply": reply })));
+        }
+
+        let transact = self.parse_transaction(user, line, outcome)
+            .await?
+            .ok_or_else(|| Error::Parse("batch_no_transaction", serde_json::json!({})))?;
+
+        let mut payload = TransactPayload::single(transact);
+        payload.set_external_id(&external_id);
+
+        let description = payload.description();
+
+        match self.create_or_enqueue(user, payload).await? {
+            Some(created) => {
+                self.remember_created(&created, description, None)?;
+                Ok("created")
+            },
+            None => Ok("queued for a retry"),
+        }
+    }
+
+    /// Reports how each item of the batch went, offering to retry the
+    /// failed ones without resending the others.
+    async fn run_batch(&self, user: &UserClue, batch: FailedBatch) -> Result<reqwest::Response, Error> {
+        let mut report = vec![];
+        let mut failed = vec![];
+
+        for (index, line) in batch.items {
+            // Each line gets its own id so lines don't pass for one another.
+            let external_id = format!("{}-{}", batch.external_id, index);
+
+            match self.batch_item(user, external_id, &line).await {
+                Ok(outcome) => report.push(format!("✅ {} — {}", line, outcome)),
+                Err(e) => {
+                    let reason = e.user_message(self.state.language_code.as_deref()).unwrap_or_else(|| e.to_string());
+                    report.push(format!("❌ {} — {}", line, reason));
+                    failed.push((index, line));
+                },
+            }
+        }
+
+        let mut payload = serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": report.join("\n"),
+        });
+
+        if !failed.is_empty() {
+            let retry = FailedBatch { external_id: batch.external_id, items: failed };
+            pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::BatchRetry(retry))?;
+
+            payload["reply_markup"] = serde_json::json!({
+                "inline_keyboard": [[{ "text": self.text("button_retry_failed"), "callback_data": "batch:retry" }]],
+            });
+        }
+
+        self.post("sendMessage", &payload).await
+    }
+
+    /// Deletes the latest transaction the bot created for the user.
+    async fn cmd_undo(&self) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let message = match undo::take(&self.db, &self.state.user_id())? {
+            Some(last) => match user.firefly().delete_transaction(&last.transaction_id).await {
+                Ok(()) => self.text_with("undo_deleted", serde_json::json!({ "description": last.description })),
+                // Most likely deleted in Firefly III already, nothing left to undo.
+                Err(Error::FireflyRejected(reasons)) => self.text_with("undo_rejected", serde_json::json!({
+                    "description": last.description,
+                    "reasons": reasons,
+                })),
+                Err(e) => {
+                    undo::restore(&self.db, &self.state.user_id(), last)?;
+                    return Err(e);
+                },
+            },
+            None => self.text("undo_nothing"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Books the user's last transaction again for today, optionally with
+    /// another amount, e.g. `/again 12`.
+    async fn cmd_again(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let amount = match args.trim() {
+            "" => None,
+            amount => match amount.replace(',', ".").parse::<f64>() {
+                Ok(amount) if amount > 0.0 => Some(amount.to_string()),
+                _ => {
+                    return self.post("sendMessage", &serde_json::json!({
+                        "chat_id": self.state.chat_id,
+                        "text": self.text_with("again_invalid_amount", serde_json::json!({ "amount": amount })),
+                    }))
+                    .await;
+                },
+            },
+        };
+
+        let last = match undo::last(&self.db, &self.state.user_id())? {
+            Some(last) => last,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("again_nothing"),
+                }))
+                .await;
+            },
+        };
+
+        let date = self.booking_date().format("%Y-%m-%d").to_string();
+        let transact = user.firefly()
+            .get_transaction(&last.transaction_id)
+            .await?
+            .attributes
+            .transactions
+            .into_iter()
+            .next()
+            .and_then(|split| Transaction::repeat_of(split, date, amount));
+
+        match transact {
+            Some(transact) => self.finish_transaction(&user, transact).await,
+            None => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text_with("again_unavailable", serde_json::json!({ "description": last.description })),
+                }))
+                .await
+            },
+        }
+    }
+
+    fn search_key(&self) -> String {
+        format!("{}/{}", self.state.user_id(), self.state.chat_id)
+    }
+
+    /// A page of the transactions matching the query, with buttons to move
+    /// between pages.
+    async fn search_page(&self, user: &UserClue, query: &str, page: u32) -> Result<(String, serde_json::Value), Error> {
+        let found = user.firefly().search_transactions(query, page, FIND_PAGE_SIZE).await?;
+        let pagination = found.meta.pagination;
+
+        let lines = found.data
+            .iter()
+            .flat_map(|group| group.attributes.transactions.iter())
+            .map(|split| {
+                let date = split.date.as_deref().map(|d| d.chars().take(10).collect::<String>()).unwrap_or_default();
+                let amount = split.amount.parse::<f64>().map(|a| format!("{:.2}", a)).unwrap_or_else(|_| split.amount.to_owned());
+                let currency = split.currency_code.as_deref().unwrap_or_default();
+                format!("{} {} {} {}", date, amount, currency, split.description.as_deref().unwrap_or_default())
+            })
+            .collect::<Vec<String>>();
+
+        if lines.is_empty() {
+            return Ok((self.text_with("find_nothing", serde_json::json!({ "query": query })), serde_json::json!({ "inline_keyboard": [] })));
+        }
+
+        let mut navigation = Vec::new();
+        if pagination.current_page > 1 {
+            navigation.push(serde_json::json!({ "text": "◀", "callback_data": format!("find:{}", pagination.current_page - 1) }));
+        }
+        if pagination.current_page < pagination.total_pages {
+            navigation.push(serde_json::json!({ "text": "▶", "callback_data": format!("find:{}", pagination.current_page + 1) }));
+        }
+
+        let text = self.text_with("find_results", serde_json::json!({
+            "query": query,
+            "total": pagination.total,
+            "page": pagination.current_page,
+            "pages": pagination.total_pages.max(1),
+            "results": lines.join("\n"),
+        }));
+
+        Ok((text, serde_json::json!({ "inline_keyboard": [navigation] })))
+    }
+
+    /// Searches the user's transactions, e.g. `/find coffee` or any query
+    /// of Firefly III's search syntax such as `/find amount_more:50`.
+    async fn cmd_find(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let query = args.trim();
+        if query.is_empty() {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("find_usage"),
+            }))
+            .await;
+        }
+
+        // Kept so the page buttons know what was searched for.
+        self.db.searches.insert(self.search_key().as_bytes(), query.to_owned())?;
+        let (text, keyboard) = self.search_page(&user, query, 1).await?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": text,
+            "reply_markup": keyboard,
+        }))
+        .await
+    }
+
+    async fn page_search(&self, message_id: i32, page: u32) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+
+        let (text, keyboard) = match self.db.searches.get(self.search_key().as_bytes())? {
+            Some(query) => self.search_page(&user, &query, page).await?,
+            None => (self.text("find_expired"), serde_json::json!({ "inline_keyboard": [] })),
+        };
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": text,
+            "reply_markup": keyboard,
+        }))
+        .await
+    }
+
+    /// Purges the raw content of the user's recent messages the bot kept:
+    /// the interaction history of the chat, the questions waiting on an
+    /// answer, the transactions given up on and the recorded fixtures.
+    async fn cmd_scrub(&self) -> Result<reqwest::Response, Error> {
+        let user_id = self.state.user_id();
+
+        let history = super::INTERACTIONS.clear(self.state.chat_id);
+        let pending = pending::clear_user(&self.db, &user_id)? + review::clear(&self.db, &user_id)?;
+        let dead_letters = outbox::discard_dead_letters(&self.db, &user_id)?;
+        let fixtures = match &super::config().app_fixture_dir {
+            Some(dir) => fixture::scrub(dir, self.state.chat_id)?,
+            None => 0,
+        };
+        self.db.searches.remove(self.search_key().as_bytes())?;
+        dedup::forget_text(&self.db, &user_id, self.state.chat_id)?;
+
+        log::info!("Scrubbed the messages of {}", user_id);
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text_with("scrub_done", serde_json::json!({
+                "history": history,
+                "pending": pending,
+                "dead_letters": dead_letters,
+                "fixtures": fixtures,
+            })),
+        }))
+        .await
+    }
+
+    /// Shows the user what a support report would contain, it is only sent
+    /// to the operator once they agree.
+    async fn cmd_support(&self) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?;
+        let report = support::build_report(&self.db, &self.state.user_id(), self.state.chat_id, user.as_ref())?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text_with("support_preview", serde_json::json!({ "report": report })),
+            "reply_markup": {
+                "inline_keyboard": [[
+                    { "text": self.text("button_send_to_operator"), "callback_data": "support:send" },
+                    { "text": self.text("button_cancel"), "callback_data": "support:cancel" },
+                ]],
+            },
+        }))
+        .await
+    }
+
+    async fn resolve_support(&self, message_id: i32, consented: bool) -> Result<reqwest::Response, Error> {
+        let message = if consented {
+            let user = self.db.users.get(self.get_user_id())?;
+            let report = support::build_report(&self.db, &self.state.user_id(), self.state.chat_id, user.as_ref())?;
+
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": super::config().tg_master_id,
+                "text": format!("Support request from user {}:\n\n{}", self.state.from_id, report),
+            }))
+            .await?
+            .error_for_status()
+            .map_err(Error::Telegram)?;
+
+            "The report was sent to the operator, they'll get back to you."
+        } else {
+            "The report was not sent."
+        };
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Hands out a code to share the user's account with `/link`, or asks
+    /// the owner of the account of the code given to approve the link.
+    async fn cmd_link(&self, code: &str, name: &str) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?;
+
+        if code.is_empty() {
+            let message = match user {
+                Some(user) if user.is_ready() => {
+                    let code = household::create_code(&self.db, self.state.from_id, Duration::hours(super::config().invite_ttl_hours))?;
+                    format!(
+                        "Ask the person you want to share your Firefly III account with to send me:\n\n/link {}\n\nThe code can be used once and expires in {} hour(s), you'll be asked to approve them.",
+                        code, super::config().invite_ttl_hours,
+                    )
+                },
+                _ => self.text("setup_required"),
+            };
+
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": message,
+            }))
+            .await;
+        }
+
+        let message = match (user, household::redeem_code(&self.db, code)?) {
+            (Some(user), _) if user.is_ready() => self.text("link_already_set_up"),
+            (_, None) => self.text("link_code_invalid"),
+            (_, Some(owner_id)) if owner_id == self.state.from_id => self.text("link_own_code"),
+            (_, Some(owner_id)) => {
+                let request = LinkRequest {
+                    member_id: self.state.from_id,
+                    member_name: name.to_owned(),
+                };
+                // The owner answers in their private chat, which may be bound to a profile.
+                let owner_key = profile::user_id(owner_id, profile::bound(&self.db, owner_id, owner_id)?.as_deref());
+                pending::set(&self.db, &owner_key, owner_id, PendingAction::LinkRequest(request))?;
+
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": owner_id,
+                    "text": format!("{} wants to log transactions to your Firefly III account through this bot, they won't see your access token.", name),
+                    "reply_markup": {
+                        "inline_keyboard": [[
+                            { "text": self.text("button_approve"), "callback_data": "link:approve" },
+                            { "text": self.text("button_deny"), "callback_data": "link:deny" },
+                        ]],
+                    },
+                }))
+                .await?
+                .error_for_status()
+                .map_err(Error::Telegram)?;
+
+                self.text("link_requested")
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Pins one of the user's profiles to the chat, e.g. a business account
+    /// for the office group, or shows the one in use.
+    async fn cmd_bind(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let message = match args.trim() {
+            "" => match &self.state.profile {
+                Some(name) => self.text_with("bind_profile_in_use", serde_json::json!({ "name": name })),
+                None => self.text("bind_default_in_use"),
+            },
+            "default" => match profile::unbind(&self.db, self.state.chat_id, self.state.from_id)? {
+                Some(name) => self.text_with("bind_unbound", serde_json::json!({ "name": name })),
+                None => self.text("bind_already_default"),
+            },
+            args => match profile::parse_name(args) {
+                Some(name) => {
+                    profile::bind(&self.db, self.state.chat_id, self.state.from_id, &name)?;
+
+                    let user_id = profile::user_id(self.state.from_id, Some(&name));
+                    let setup = match self.db.users.get(user_id.as_bytes())? {
+                        Some(user) if user.is_ready() => String::new(),
+                        _ if self.state.in_group => self.text("bind_not_set_up_group"),
+                        _ => self.text("bind_not_set_up"),
+                    };
+
+                    format!("{}{}", self.text_with("bind_bound", serde_json::json!({ "name": name })), setup)
+                },
+                None => self.text("bind_invalid_name"),
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_language(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let available = super::config().templates.languages();
+        let languages = available.iter().cloned().collect::<Vec<_>>().join(", ");
+
+        let (code, name, context) = match args.trim().to_lowercase().as_str() {
+            "" => {
+                let preferred = language::preferred(&self.db, self.state.from_id)?;
+                let language = self.state.language_code.clone().unwrap_or_else(|| "en".into());
+                let context = serde_json::json!({ "language": language, "automatic": preferred.is_none(), "languages": languages });
+                (self.state.language_code.clone(), "language_current", context)
+            },
+            "auto" => {
+                language::clear(&self.db, self.state.from_id)?;
+                (None, "language_auto", serde_json::json!({}))
+            },
+            args => match language::parse_code(args) {
+                Some(code) if available.contains(code.split('-').next().unwrap_or_default()) => {
+                    language::set(&self.db, self.state.from_id, &code)?;
+                    (Some(code.clone()), "language_set", serde_json::json!({ "language": code }))
+                },
+                Some(code) => (self.state.language_code.clone(), "language_unavailable", serde_json::json!({ "language": code, "languages": languages })),
+                None => (self.state.language_code.clone(), "language_usage", serde_json::json!({})),
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            // Rendered in the language just picked rather than the previous one.
+            "text": format::strip(&super::config().templates.render(name, code.as_deref(), context)),
+        }))
+        .await
+    }
+
+    async fn cmd_confirm(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let message = match args.trim().to_lowercase().as_str() {
+            "" => {
+                let chosen = self.db.confirm_modes.get(self.state.from_id.to_be_bytes())?;
+                let mode = settings::confirm_mode(&self.db, self.state.from_id)?;
+                self.text_with("confirm_current", serde_json::json!({ "mode": mode.to_string(), "automatic": chosen.is_none() }))
+            },
+            "default" => {
+                settings::set_confirm_mode(&self.db, self.state.from_id, None)?;
+                self.text("confirm_default")
+            },
+            args => match args.parse::<ConfirmMode>() {
+                Ok(mode) => {
+                    settings::set_confirm_mode(&self.db, self.state.from_id, Some(mode))?;
+                    match mode {
+                        ConfirmMode::Repeats => self.text("confirm_repeats"),
+                        ConfirmMode::Off => self.text("confirm_off"),
+                    }
+                },
+                Err(_) => self.text("confirm_usage"),
+            },
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_settings(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let mut parts = args.split_whitespace().map(|a| a.to_lowercase());
+
+        let message = match (parts.next().as_deref(), parts.next().as_deref(), parts.next()) {
+            (None, _, _) => self.text_with("settings_overview", serde_json::json!({
+                "language": self.state.language_code.clone().unwrap_or_else(|| "en".into()),
+                "timezone": self.state.timezone.map(|tz| tz.name().to_owned()),
+                "confirm": settings::confirm_mode(&self.db, self.state.from_id)?.to_string(),
+                "plain_text": self.state.plain_text,
+                "raw_descriptions": settings::raw_descriptions(&self.db, self.state.from_id)?,
+                "cold_start": settings::cold_start(&self.db, &self.state.user_id())?,
+            })),
+            (Some("rawdescriptions"), Some("on"), None) => {
+                settings::set_raw_descriptions(&self.db, self.state.from_id, true)?;
+                self.text("settings_raw_descriptions_on")
+            },
+            (Some("rawdescriptions"), Some("off"), None) => {
+                settings::set_raw_descriptions(&self.db, self.state.from_id, false)?;
+                self.text("settings_raw_descriptions_off")
+            },
+            (Some("coldstart"), Some("on"), None) => {
+                settings::set_cold_start(&self.db, &self.state.user_id(), true)?;
+                self.text_with("settings_cold_start_on", serde_json::json!({
+                    "timeout": super::HTTP_CLIENTS.cold_start_timeout.as_secs(),
+                }))
+            },
+            (Some("coldstart"), Some("off"), None) => {
+                settings::set_cold_start(&self.db, &self.state.user_id(), false)?;
+                self.text("settings_cold_start_off")
+            },
+            _ => self.text("settings_usage"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_timezone(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let offset = super::config().day_cutoff.describe_offset();
+        let local_time = |tz: Tz| Utc::now().with_timezone(&tz).format("%H:%M").to_string();
+
+        let mut user = match self.db.users.get(self.get_user_id())? {
+            Some(user) => user,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let (timezone, message) = match args.trim() {
+            "" => {
+                let message = match self.state.timezone {
+                    Some(tz) => self.text_with("timezone_current", serde_json::json!({ "timezone": tz.name(), "time": local_time(tz) })),
+                    None => self.text_with("timezone_none", serde_json::json!({ "offset": offset })),
+                };
+
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": message,
+                }))
+                .await;
+            },
+            name if name.eq_ignore_ascii_case("reset") => {
+                (None, self.text_with("timezone_reset", serde_json::json!({ "offset": offset })))
+            },
+            name => match timezone::parse(name) {
+                Some(tz) => (Some(tz), self.text_with("timezone_set", serde_json::json!({ "timezone": tz.name(), "time": local_time(tz) }))),
+                None => {
+                    return self.post("sendMessage", &serde_json::json!({
+                        "chat_id": self.state.chat_id,
+                        "text": self.text_with("timezone_unknown", serde_json::json!({ "timezone": name })),
+                    }))
+                    .await;
+                },
+            },
+        };
+
+        user.set_timezone(timezone);
+        self.db.users.insert(self.get_user_id(), user)?;
+
+        // The account's digest follows the new clock.
+        digest::reschedule(&self.db, &self.state.user_id(), timezone)?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_accessibility(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let message = match args.trim().to_lowercase().as_str() {
+            "" if self.state.plain_text => self.text("accessibility_on"),
+            "" => self.text("accessibility_off"),
+            "on" => {
+                accessibility::set(&self.db, self.state.from_id, true)?;
+                self.text("accessibility_turned_on")
+            },
+            "off" => match accessibility::set(&self.db, self.state.from_id, false)? {
+                true => self.text("accessibility_turned_off"),
+                false => self.text("accessibility_already_off"),
+            },
+            _ => self.text("accessibility_usage"),
+        };
+
+        // Sent directly so turning it on or off applies to this reply too.
+        super::telegram_post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Links the requesting user to the owner's account once approved.
+    async fn resolve_link(&self, message_id: i32, approved: bool) -> Result<reqwest::Response, Error> {
+        let request = match pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::LinkRequest(_)))? {
+            Some(PendingAction::LinkRequest(request)) => request,
+            _ => {
+                return self.post("editMessageText", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "text": self.text("link_request_handled"),
+                }))
+                .await;
+            },
+        };
+
+        let (message, notice) = match self.db.users.get(self.get_user_id())? {
+            Some(owner) if approved && owner.is_ready() => {
+                self.db.users.insert(user_key(request.member_id).as_bytes(), owner.linked_to(request.member_id)?)?;
+
+                (
+                    self.text_with("link_approved", serde_json::json!({ "member": request.member_name })),
+                    "link_member_approved",
+                )
+            },
+            Some(_) if approved => (self.text("setup_required"), "link_member_owner_gone"),
+            _ => (self.text_with("link_denied", serde_json::json!({ "member": request.member_name })), "link_member_denied"),
+        };
+
+        // The member gets the notice in their own language.
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": request.member_id,
+            "text": language::text(&self.db, request.member_id, notice, serde_json::json!({})),
+        }))
+        .await?;
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_pending(&self) -> Result<reqwest::Response, Error> {
+        let entries = outbox::list(&self.db, &self.state.user_id())?;
+
+        let message = if entries.is_empty() {
+            self.text("pending_empty")
+        } else {
+            let items = entries
+                .iter()
+                .map(|(_, e)| serde_json::json!({
+                    "description": e.payload.description(),
+                    "attempts": e.attempts,
+                    "abandoned": e.is_abandoned(),
+                    "next_attempt": Utc.timestamp(e.next_attempt_at, 0).format("%Y-%m-%d %H:%M").to_string(),
+                    "error": e.last_error,
+                }))
+                .collect::<Vec<serde_json::Value>>();
+
+            self.text_with("pending_list", serde_json::json!({ "items": items }))
+        };
+
+        let scheduled = scheduler::list(&self.db, &self.state.user_id())?;
+        let message = if scheduled.is_empty() {
+            message
+        } else {
+            let items = scheduled
+                .iter()
+                .map(|(_, e)| serde_json::json!({
+                    "description": e.payload.description(),
+                    "post_at": Utc.timestamp(e.post_at, 0).format("%Y-%m-%d %H:%M").to_string(),
+                }))
+                .collect::<Vec<serde_json::Value>>();
+
+            format!("{}\n\n{}", message, self.text_with("scheduled_list", serde_json::json!({ "items": items })))
+        };
+
+        let mut payload = serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        });
+
+        if let Some(keyboard) = self.pending_keyboard()? {
+            payload["reply_markup"] = keyboard;
+        }
+
+        self.post("sendMessage", &payload).await
+    }
+
+    /// Builds the Confirm/Discard buttons of the `/pending` list, `None` once
+    /// nothing is left to resolve.
+    fn pending_keyboard(&self) -> Result<Option<serde_json::Value>, Error> {
+        let queued = outbox::list(&self.db, &self.state.user_id())?
+            .into_iter()
+            .map(|(id, e)| (id, e.payload.description()));
+        let scheduled = scheduler::list(&self.db, &self.state.user_id())?
+            .into_iter()
+            .map(|(id, e)| (id, e.payload.description()));
+
+        let mut keyboard = queued
+            .chain(scheduled)
+            .map(|(id, description)| serde_json::json!([
+                { "text": format!("✅ {}", description), "callback_data": format!("pending:confirm:{}", id) },
+                { "text": self.text("button_discard"), "callback_data": format!("pending:discard:{}", id) },
+            ]))
+            .collect::<Vec<serde_json::Value>>();
+
+        if keyboard.is_empty() {
+            return Ok(None);
+        }
+
+        if keyboard.len() > 1 {
+            keyboard.push(serde_json::json!([
+                { "text": self.text("button_confirm_all"), "callback_data": "pending:confirm-all" },
+                { "text": self.text("button_discard_all"), "callback_data": "pending:discard-all" },
+            ]));
+        }
+
+        Ok(Some(serde_json::json!({ "inline_keyboard": keyboard })))
+    }
+
+    /// Handles the Retry/Discard buttons of the weekly review. Retrying an
+    /// unconfirmed item asks its question again.
+    async fn resolve_review(&self, message_id: i32, action: &str, kind: &str, id: &str) -> Result<reqwest::Response, Error> {
+        let user_id = self.state.user_id();
+
+        let unconfirmed = match (kind, action) {
+            ("queued", "retry") => {
+                outbox::send_now(&self.db, &user_id, id).await?;
+                None
+            },
+            ("queued", "discard") => {
+                outbox::discard(&self.db, &user_id, id)?;
+                None
+            },
+            ("unconfirmed", "retry") => review::take(&self.db, &user_id, id)?,
+            ("unconfirmed", "discard") => {
+                review::take(&self.db, &user_id, id)?;
+                None
+            },
+            _ => return Err(Error::InvalidUpdate("Unknown review action in callback data".into())),
+        };
+
+        let resp = match review::keyboard(&self.db, &user_id)? {
+            Some(keyboard) => {
+                self.post("editMessageReplyMarkup", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "reply_markup": keyboard,
+                }))
+                .await?
+            },
+            None => {
+                self.post("editMessageText", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "text": self.text("review_all_handled"),
+                }))
+                .await?
+            },
+        };
+
+        match unconfirmed.map(|item| item.action) {
+            Some(PendingAction::Repeat(text)) => {
+                dedup::forget_text(&self.db, &user_id, self.state.chat_id)?;
+                self.cmd_text(&text).await
+            },
+            Some(PendingAction::CurrencyChoice(choice)) => match choice.payload.transactions.into_iter().next() {
+                Some(transact) => {
+                    let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                    self.check_currency(&user, transact).await
+                },
+                None => Ok(resp),
+            },
+            Some(PendingAction::AccountChoice(choice)) => match choice.payload.transactions.into_iter().next() {
+                Some(transact) => {
+                    let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                    self.check_accounts(&user, transact).await
+                },
+                None => Ok(resp),
+            },
+            Some(PendingAction::Receipt(receipt)) => {
+                let (merchant, total, source_name) = match (&receipt.merchant, receipt.total, &receipt.source_name) {
+                    (Some(merchant), Some(total), Some(source_name)) => (merchant.to_owned(), total, source_name.to_owned()),
+                    _ => return Ok(resp),
+                };
+                pending::set(&self.db, &user_id, self.state.chat_id, PendingAction::Receipt(receipt))?;
+
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text_with("receipt_confirm", serde_json::json!({
+                        "merchant": merchant,
+                        "total": format!("{:.2}", total),
+                        "source": source_name,
+                    })),
+                    "reply_markup": {
+                        "inline_keyboard": [[
+                            { "text": self.text("button_confirm"), "callback_data": "receipt:confirm" },
+                            { "text": self.text("button_cancel"), "callback_data": "receipt:cancel" },
+                        ]],
+                    },
+                }))
+                .await
+            },
+            _ => Ok(resp),
+        }
+    }
+
+    async fn resolve_pending(&self, message_id: i32, action: &str, id: Option<&str>) -> Result<reqwest::Response, Error> {
+        let user_id = self.state.user_id();
+
+        match (action, id) {
+            ("confirm", Some(id)) => {
+                if !outbox::send_now(&self.db, &user_id, id).await? {
+                    scheduler::post_now(&self.db, &user_id, id).await?;
+                }
+            },
+            ("discard", Some(id)) => {
+                if !outbox::discard(&self.db, &user_id, id)? {
+                    scheduler::cancel(&self.db, &user_id, id)?;
+                }
+            },
+            ("confirm-all", _) => {
+                for (id, _) in outbox::list(&self.db, &user_id)? {
+                    outbox::send_now(&self.db, &user_id, &id).await?;
+                }
+
+                for (id, _) in scheduler::list(&self.db, &user_id)? {
+                    scheduler::post_now(&self.db, &user_id, &id).await?;
+                }
+            },
+            ("discard-all", _) => {
+                for (id, _) in outbox::list(&self.db, &user_id)? {
+                    outbox::discard(&self.db, &user_id, &id)?;
+                }
+
+                for (id, _) in scheduler::list(&self.db, &user_id)? {
+                    scheduler::cancel(&self.db, &user_id, &id)?;
+                }
+            },
+            _ => return Err(Error::InvalidUpdate("Unknown pending action in callback data".into())),
+        }
+
+        match self.pending_keyboard()? {
+            Some(keyboard) => {
+                self.post("editMessageReplyMarkup", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "reply_markup": keyboard,
+                }))
+                .await
+            },
+            None => {
+                self.post("editMessageText", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "message_id": message_id,
+                    "text": self.text("pending_all_handled"),
+                }))
+                .await
+            },
+        }
+    }
+
+    async fn cmd_report(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        if let Some(frequency) = args.split_whitespace().next().filter(|f| ["daily", "weekly", "off"].contains(&f.to_lowercase().as_str())) {
+            return self.cmd_report_schedule(frequency, args).await;
+        }
+
+        let (start, end) = match report::month_range(args, self.today()) {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("report_usage"),
+                }))
+                .await;
+            },
+        };
+
+        let message_id = self.send_progress("Generating the report…").await?;
+
+        let mut report = report::Report::new(user.default_currency.to_owned());
+        let mut page = 1;
+
+        loop {
+            let transactions = user.firefly().get_transactions(start, end, page).await?;
+
+            transactions.data
+                .iter()
+                .flat_map(|group| group.attributes.transactions.iter())
+                .for_each(|split| report.add(split));
+
+            let pagination = transactions.meta.pagination;
+            if pagination.current_page >= pagination.total_pages {
+                break;
+            }
+
+            // Screen readers would announce every page.
+            if !self.state.plain_text {
+                self.edit_progress(message_id, &format!(
+                    "Generating the report… (fetched page {} of {})",
+                    pagination.current_page,
+                    pagination.total_pages,
+                ))
+                .await?;
+            }
+
+            page += 1;
+        }
+
+        if let Some(base) = report.base_currency().filter(|_| report.is_multi_currency()) {
+            match exchange::fetch_rates(base).await {
+                Ok(rates) => report.convert(&rates),
+                Err(e) => log::warn!("Failed to fetch exchange rates, reporting per currency: {}", e),
+            }
+        }
+
+        let title = self.text_with("report_title", serde_json::json!({ "month": start.format("%B %Y").to_string() }));
+        self.edit_progress(message_id, &report.render(&title)).await
+    }
+
+    async fn cmd_chart(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let mut words = args.split_whitespace().collect::<Vec<&str>>();
+        if words.first().is_some_and(|w| w.eq_ignore_ascii_case("month")) {
+            words.remove(0);
+        }
+
+        let style = match words.iter().position(|w| ["bar", "pie"].contains(&w.to_lowercase().as_str())) {
+            Some(i) => words.remove(i).parse::<chart::Style>()?,
+            None => chart::Style::Bar,
+        };
+
+        let range = match words.as_slice() {
+            [] => report::month_range("", self.today()),
+            [month] => report::month_range(month, self.today()),
+            _ => None,
+        };
+        let (start, end) = match range {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("chart_usage"),
+                }))
+                .await;
+            },
+        };
+        let month = start.format("%B %Y").to_string();
+
+        let entries = user.firefly().get_expense_insight("category", start, end).await?;
+
+        // Amounts in different currencies can't share a chart, keep the
+        // default currency or else the one the most was spent in.
+        let currency_code = user.default_currency
+            .to_owned()
+            .filter(|code| entries.iter().any(|e| e.currency_code.as_deref() == Some(code)))
+            .or_else(|| {
+                entries.iter()
+                    .max_by(|a, b| a.difference_float.abs().partial_cmp(&b.difference_float.abs()).unwrap_or(std::cmp::Ordering::Equal))
+                    .and_then(|e| e.currency_code.to_owned())
+            });
+
+        let slices = chart::top_slices(entries
+            .into_iter()
+            .filter(|e| e.currency_code == currency_code)
+            .map(|e| (e.name.unwrap_or_else(|| "Uncategorized".to_owned()), e.difference_float.abs()))
+            .collect());
+
+        if slices.is_empty() {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text_with("chart_empty", serde_json::json!({ "month": month })),
+            }))
+            .await;
+        }
+
+        let legend = chart::legend(&slices, currency_code.as_deref());
+        // Drawing and encoding the chart is CPU bound, it's kept off the
+        // async workers.
+        let png = tokio::task::spawn_blocking(move || chart::render(&slices, style)).await??;
+        let caption = format!(
+            "{}\n\n{}",
+            self.text_with("chart_caption", serde_json::json!({ "month": month })),
+            legend,
+        );
+
+        let photo = super::InputFile::from_bytes(png, "chart.png", "image/png")?;
+        super::telegram_send_photo(self.state.chat_id, photo, &caption)
+            .await?
+            .error_for_status()
+            .map_err(Error::Telegram)
+    }
+
+    async fn cmd_export(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        if let Some(range) = args.strip_prefix("csv").filter(|rest| rest.is_empty() || rest.starts_with(' ')) {
+            return self.cmd_export_csv(&user, range.trim()).await;
+        }
+
+        let (start, end) = match report::month_range(args, self.today()) {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("export_usage"),
+                }))
+                .await;
+            },
+        };
+        let month = start.format("%B %Y").to_string();
+
+        let message_id = self.send_progress(&self.text("export_progress")).await?;
+
+        let file = export::TempFile::new("export.csv");
+        let mut csv = export::CsvExport::create(file.path()).await?;
+        let firefly = user.firefly();
+        let mut page = 1;
+
+        loop {
+            let transactions = firefly.get_transactions_uncached(start, end, page).await?;
+
+            csv.write(transactions.data.iter().flat_map(|group| group.attributes.transactions.iter())).await?;
+
+            let pagination = transactions.meta.pagination;
+            if pagination.current_page >= pagination.total_pages {
+                break;
+            }
+
+            if !self.state.plain_text {
+                self.edit_progress(message_id, &self.text_with("export_page_progress", serde_json::json!({
+                    "page": pagination.current_page,
+                    "pages": pagination.total_pages,
+                })))
+                .await?;
+            }
+
+            page += 1;
+        }
+
+        let rows = csv.finish().await?;
+        if rows == 0 {
+            return self.edit_progress(message_id, &self.text_with("export_empty", serde_json::json!({ "month": month }))).await;
+        }
+
+        let size = tokio::fs::metadata(file.path()).await?.len();
+        if size > export::MAX_DOCUMENT_BYTES {
+            return self.edit_progress(message_id, &self.text_with("export_too_large", serde_json::json!({
+                "size": attachment::describe_size(size as usize),
+                "limit": attachment::describe_size(export::MAX_DOCUMENT_BYTES as usize),
+            })))
+            .await;
+        }
+
+        let filename = format!("firefly-{}.csv", start.format("%Y-%m"));
+        let caption = self.text_with("export_caption", serde_json::json!({ "rows": rows, "month": month }));
+        let document = super::InputFile::from_path(file.path(), &filename, "text/csv").await?;
+        super::telegram_send_document(self.state.chat_id, document, &caption)
+            .await?
+            .error_for_status()
+            .map_err(Error::Telegram)?;
+
+        self.edit_progress(message_id, &self.text("export_sent")).await
+    }
+
+    /// Parses the optional period of `/export csv` and `/exportbot`, `None`
+    /// inside when none was given and `None` if it can't be parsed.
+    fn export_range(&self, args: &str) -> Option<Option<(NaiveDate, NaiveDate)>> {
+        if args.is_empty() {
+            Some(None)
+        } else {
+            export::parse_range(args, self.today()).map(Some)
+        }
+    }
+
+    /// Fetches every transaction group created by the bot, within the
+    /// period if one is given, showing the progress in the message.
+    async fn bot_created_groups(&self, user: &UserClue, range: Option<(NaiveDate, NaiveDate)>, message_id: i64) -> Result<Vec<firefly::TransactionGroup>, Error> {
+        let query = export::bot_created_query(range);
+        let firefly = user.firefly();
+        let mut groups = vec![];
+        let mut page = 1;
+
+        loop {
+            let transactions = firefly.search_transactions(&query, page, EXPORT_SEARCH_PAGE_SIZE).await?;
+            groups.extend(transactions.data);
+
+            let pagination = transactions.meta.pagination;
+            if pagination.current_page >= pagination.total_pages {
+                return Ok(groups);
+            }
+
+            if !self.state.plain_text {
+                self.edit_progress(message_id, &self.text_with("export_page_progress", serde_json::json!({
+                    "page": pagination.current_page,
+                    "pages": pagination.total_pages,
+                })))
+                .await?;
+            }
+
+            page += 1;
+        }
+    }
+
+    /// Sends an export built in memory, unless it's too large for Telegram.
+    async fn send_export(&self, message_id: i64, bytes: Vec<u8>, filename: &str, mime: &str, caption: &str) -> Result<reqwest::Response, Error> {
+        if bytes.len() as u64 > export::MAX_DOCUMENT_BYTES {
+            return self.edit_progress(message_id, &self.text_with("export_too_large", serde_json::json!({
+                "size": attachment::describe_size(bytes.len()),
+                "limit": attachment::describe_size(export::MAX_DOCUMENT_BYTES as usize),
+            })))
+            .await;
+        }
+
+        let document = super::InputFile::from_bytes(bytes, filename, mime)?;
+        super::telegram_send_document(self.state.chat_id, document, caption)
+            .await?
+            .error_for_status()
+            .map_err(Error::Telegram)?;
+
+        self.edit_progress(message_id, &self.text("export_sent")).await
+    }
+
+    /// Exports the transactions created by the bot, all of them or those
+    /// within the period, building the CSV in memory.
+    async fn cmd_export_csv(&self, user: &UserClue, args: &str) -> Result<reqwest::Response, Error> {
+        let range = match self.export_range(args) {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("export_usage"),
+                }))
+                .await;
+            },
+        };
+        let period = export::describe_range(range);
+
+        let message_id = self.send_progress(&self.text("export_progress")).await?;
+
+        let groups = self.bot_created_groups(user, range, message_id).await?;
+        let mut csv = export::CsvBuffer::new();
+        csv.write(groups.iter().flat_map(|group| group.attributes.transactions.iter()));
+
+        let rows = csv.rows();
+        if rows == 0 {
+            return self.edit_progress(message_id, &self.text_with("export_csv_empty", serde_json::json!({ "period": period }))).await;
+        }
+
+        let filename = export::filename(range, "csv");
+        let caption = self.text_with("export_csv_caption", serde_json::json!({ "rows": rows, "period": period }));
+        self.send_export(message_id, csv.into_bytes(), &filename, "text/csv", &caption).await
+    }
+
+    /// Exports the transactions created by the bot as the bodies Firefly III
+    /// takes to create them, for replaying them into another instance.
+    async fn cmd_exportbot(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let range = match self.export_range(args.trim()) {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("exportbot_usage"),
+                }))
+                .await;
+            },
+        };
+        let period = export::describe_range(range);
+
+        let message_id = self.send_progress(&self.text("export_progress")).await?;
+
+        let groups = self.bot_created_groups(&user, range, message_id).await?;
+        if groups.is_empty() {
+            return self.edit_progress(message_id, &self.text_with("export_csv_empty", serde_json::json!({ "period": period }))).await;
+        }
+
+        let payloads = groups.iter().map(export::import_payload).collect::<Vec<serde_json::Value>>();
+        let json = serde_json::to_vec_pretty(&payloads)?;
+
+        let filename = export::filename(range, "json");
+        let caption = self.text_with("exportbot_caption", serde_json::json!({ "count": payloads.len(), "period": period }));
+        self.send_export(message_id, json, &filename, "application/json", &caption).await
+    }
+
+    async fn cmd_report_schedule(&self, frequency: &str, args: &str) -> Result<reqwest::Response, Error> {
+        let message = if frequency.eq_ignore_ascii_case("off") {
+            if digest::unschedule(&self.db, &self.state.user_id())? {
+                self.text("digest_stopped")
+            } else {
+                self.text("digest_none")
+            }
+        } else {
+            match digest::parse_schedule(args, settings::defaults(&self.db)?.digest_time) {
+                Some((frequency, time)) => {
+                    let schedule = digest::schedule(&self.db, &self.state.user_id(), self.state.chat_id, frequency, time, self.state.timezone)?;
+                    self.text_with("digest_scheduled", schedule.context(self.state.timezone))
+                },
+                None => self.text("digest_usage"),
+            }
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn current_balances(&self, user: &UserClue) -> Result<Snapshot, Error> {
+        let balances = user.firefly().get_accounts("asset")
+            .await?
+            .into_iter()
+            .map(|a| {
+                let balance = a.attributes.current_balance
+                    .and_then(|b| b.parse::<f64>().ok())
+                    .unwrap_or_default();
+
+                (a.attributes.name, AccountBalance {
+                    balance,
+                    currency_code: a.attributes.currency_code,
+                })
+            })
+            .collect();
+
+        Ok(Snapshot::new(balances))
+    }
+
+    async fn cmd_accounts(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let include_archived = args.split_whitespace().any(|a| a == "--all");
+
+        let accounts = user.firefly().get_accounts("asset")
+            .await?
+            .into_iter()
+            .filter(|a| include_archived || a.attributes.is_active())
+            .map(|a| serde_json::json!({
+                "name": a.attributes.name,
+                "balance": a.attributes.current_balance.as_deref().unwrap_or("0"),
+                "currency": a.attributes.currency_code,
+                "archived": !a.attributes.is_active(),
+            }))
+            .collect::<Vec<serde_json::Value>>();
+
+        let message = if accounts.is_empty() {
+            self.text("accounts_empty")
+        } else {
+            self.text_with("accounts_list", serde_json::json!({ "accounts": accounts, "all": include_archived }))
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_snapshot(&self) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let snapshot = self.current_balances(&user).await?;
+        let message = format!("{}\n\nType /compare later on to see what changed.", snapshot.render());
+        self.db.snapshots.insert(self.get_user_id(), snapshot)?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_compare(&self) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let message = match self.db.snapshots.get(self.get_user_id())? {
+            Some(previous) => previous.compare(&self.current_balances(&user).await?),
+            None => self.text("compare_no_snapshot"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_forecast(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let days = match forecast::parse_days(args) {
+            Some(days) => days,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text_with("forecast_usage", serde_json::json!({ "max": forecast::MAX_DAYS })),
+                }))
+                .await;
+            },
+        };
+
+        let firefly = user.firefly();
+        let accounts = firefly.get_accounts("asset")
+            .await?
+            .into_iter()
+            .filter(|a| a.attributes.is_active())
+            .collect::<Vec<_>>();
+        let balance_of = |a: &firefly::AccountRead| a.attributes.current_balance.as_deref().and_then(|b| b.parse::<f64>().ok()).unwrap_or_default();
+
+        // The default source account is the main one, or else the one
+        // holding the most money.
+        let default_source = accounts::default_source(&self.db, &self.state.user_id())?;
+        let account = accounts
+            .iter()
+            .find(|a| default_source.as_deref().is_some_and(|name| a.attributes.name.eq_ignore_ascii_case(name)))
+            .or_else(|| accounts.iter().max_by(|a, b| balance_of(a).partial_cmp(&balance_of(b)).unwrap_or(std::cmp::Ordering::Equal)));
+
+        let account = match account {
+            Some(account) => account,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("accounts_empty"),
+                }))
+                .await;
+            },
+        };
+        let currency_code = account.attributes.currency_code.as_deref();
+
+        let message_id = self.send_progress(&self.text("forecast_progress")).await?;
+
+        let today = self.today();
+        let end = today + Duration::days(days);
+
+        let mut events = forecast::bill_events(&firefly.get_active_bills(today, end).await?, currency_code, today, end);
+        events.extend(forecast::recurrence_events(&firefly.get_active_recurrences().await?, &account.id, currency_code, today, end));
+
+        // Bills are already in the events, only what's spent outside of
+        // them is averaged.
+        let history_start = today - Duration::days(forecast::HISTORY_DAYS);
+        let spent = firefly.get_account_expense_insight("no-bill", &account.id, history_start, today - Duration::days(1))
+            .await?
+            .into_iter()
+            .filter(|e| currency_code.is_none() || e.currency_code.as_deref() == currency_code)
+            .map(|e| e.difference_float.abs())
+            .sum::<f64>();
+
+        let forecast = Forecast::project(
+            account.attributes.name.to_owned(),
+            currency_code.map(str::to_owned),
+            balance_of(account),
+            events,
+            spent / forecast::HISTORY_DAYS as f64,
+            today,
+            days,
+        );
+
+        self.edit_progress(message_id, &forecast.render()).await
+    }
+
+    async fn cmd_runrules(&self, args: &str) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        // The period is optional and comes last, everything before it names the account.
+        let mut words = args.rsplitn(2, char::is_whitespace);
+        let last = words.next().unwrap_or_default();
+        let (account_name, period) = if last.is_empty() || report::month_range(last, self.today()).is_none() {
+            (args, "")
+        } else {
+            (words.next().unwrap_or_default().trim(), last)
+        };
+
+        let (start, end) = match report::month_range(period, self.today()) {
+            Some(range) => range,
+            None => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("runrules_usage"),
+                }))
+                .await;
+            },
+        };
+
+        let mut query = vec![
+            ("start", start.format("%Y-%m-%d").to_string()),
+            ("end", end.format("%Y-%m-%d").to_string()),
+        ];
+
+        if !account_name.is_empty() {
+            let account = user.firefly().get_accounts("asset")
+                .await?
+                .into_iter()
+                .find(|a| a.attributes.name.eq_ignore_ascii_case(account_name));
+
+            match account {
+                Some(account) => query.push(("accounts[]", account.id)),
+                None => {
+                    return self.post("sendMessage", &serde_json::json!({
+                        "chat_id": self.state.chat_id,
+                        "text": self.text_with("account_not_found", serde_json::json!({ "name": account_name })),
+                    }))
+                    .await;
+                },
+            }
+        }
+
+        let message_id = self.send_progress("Running your rules…").await?;
+        let rule_groups = user.firefly().get_active_rule_groups().await?;
+        let mut changed = 0;
+
+        for rule_group in &rule_groups {
+            changed += user.firefly().test_rule_group(&rule_group.id, &query).await?.meta.pagination.total;
+            user.firefly().trigger_rule_group(&rule_group.id, &query).await?;
+        }
+
+        let scope = if account_name.is_empty() {
+            String::new()
+        } else {
+            format!(" on {}", account_name)
+        };
+
+        let message = format!(
+            "Ran {} rule group(s) over {} to {}{}.\n\n{} transaction(s) matched and were updated.",
+            rule_groups.len(),
+            start.format("%Y-%m-%d"),
+            end.format("%Y-%m-%d"),
+            scope,
+            changed,
+        );
+
+        self.edit_progress(message_id, &message).await
+    }
+
+    /// Sends a message meant to be edited later on, returning its message id.
+    async fn send_progress(&self, text: &str) -> Result<i64, Error> {
+        let sent = self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": text,
+        }))
+        .await?
+        .error_for_status()
+        .map_err(Error::Telegram)?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(Error::Telegram)?;
+
+        sent["result"]["message_id"].as_i64().ok_or_else(|| Error::InvalidUpdate("No message id returned by Telegram".into()))
+    }
+
+    async fn edit_progress(&self, message_id: i64, text: &str) -> Result<reqwest::Response, Error> {
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": text,
+        }))
+        .await
+    }
+
+    async fn cmd_invite(&self) -> Result<reqwest::Response, Error> {
+        if !access::is_master(self.state.from_id) {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("invite_master_only"),
+            }))
+            .await;
+        }
+
+        let bot = self.post("getMe", &serde_json::json!({}))
+            .await
+            .and_then(|r| r.error_for_status().map_err(Error::Telegram))?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(Error::Telegram)?;
+
+        let username = bot["result"]["username"]
+            .as_str()
+            .ok_or_else(|| Error::InvalidUpdate("No username in getMe response".into()))?;
+
+        let ttl = Duration::hours(super::config().invite_ttl_hours);
+        let code = access::create_invite(&self.db, self.state.from_id, ttl)?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": format!(
+                "Share this link, it can be used once and expires in {} hour(s):\n\nhttps://t.me/{}?start={}",
+                super::config().invite_ttl_hours, username, code,
+            ),
+        }))
+        .await
+    }
+
+    async fn cmd_admin(&self, args: &str) -> Result<reqwest::Response, Error> {
+        if !access::is_master(self.state.from_id) {
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("admin_master_only"),
+            }))
+            .await;
+        }
+
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let subcommand = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default().trim();
+        let user_id = rest.parse::<i64>().ok();
+
+        let message = match (subcommand, user_id) {
+            ("stats", _) => self.admin_stats(),
+            ("users", _) => self.admin_users(),
+            ("selftest", _) => selftest::render(&selftest::run().await),
+            ("commands", _) => match commands::register().await {
+                Ok(()) => "The command menu was updated.".to_owned(),
+                Err(e) => format!("Failed to update the command menu: {}", e),
+            },
+            ("templates", _) => match super::config().templates.reload() {
+                Ok(overrides) => format!("Reloaded the message templates, {} override(s).", overrides),
+                Err(e) => format!("Kept the current message templates, the new ones failed to load: {}", e),
+            },
+            ("defaults", _) if rest.is_empty() => settings::describe(&self.db)?,
+            ("defaults", _) => {
+                let mut parts = rest.splitn(2, char::is_whitespace);
+                let name = parts.next().unwrap_or_default();
+                let value = match parts.next().map(|v| v.trim()) {
+                    Some(value) if value.eq_ignore_ascii_case("reset") => None,
+                    Some(value) => Some(value),
+                    None => return self.post("sendMessage", &serde_json::json!({
+                        "chat_id": self.state.chat_id,
+                        "text": "Usage: /admin defaults <setting> <value>, or /admin defaults <setting> reset to go back to the config file.",
+                    }))
+                    .await,
+                };
+
+                match (value, settings::set_default(&self.db, name, value)) {
+                    (_, Err(e)) => e.to_string(),
+                    (Some(_), Ok(current)) => format!("The default {} is now {}.", name, current.unwrap_or_default()),
+                    (None, Ok(Some(current))) => format!("The default {} is back to {} from the config file.", name, current),
+                    (None, Ok(None)) => format!("The default {} is back to the built-in one.", name),
+                }
+            },
+            ("broadcast", _) if !rest.is_empty() => self.admin_broadcast(rest).await,
+            ("wit-synonym", _) => self.admin_wit_synonym(rest).await,
+            ("rekey", _) if super::config().master_keys.is_empty() => "Set APP_MASTER_KEY before rekeying.".to_owned(),
+            ("rekey", _) => {
+                let (rekeyed, failed) = admin::rekey(&self.db)?;
+
+                if failed > 0 {
+                    format!("Rekeyed {} user(s), {} could not be decrypted with the configured keys.", rekeyed, failed)
+                } else {
+                    format!("Rekeyed {} user(s). APP_PREVIOUS_MASTER_KEY can now be removed.", rekeyed)
+                }
+            },
+            ("purge", Some(user_id)) => {
+                if admin::purge(&self.db, user_id)? {
+                    format!("Removed everything stored for user {}.", user_id)
+                } else {
+                    format!("User {} has no account, removed any leftover records.", user_id)
+                }
+            },
+            ("allow", Some(user_id)) => {
+                access::allow(&self.db, user_id)?;
+                format!("User {} can now use the bot.", user_id)
+            },
+            ("revoke", Some(user_id)) => {
+                let revoked = access::revoke(&self.db, user_id)?;
+
+                if super::config().allowed_user_ids.contains(&user_id) {
+                    format!("User {} is listed in ALLOWED_USER_IDS and keeps access until it's removed from there.", user_id)
+                } else if revoked {
+                    format!("User {} can no longer use the bot.", user_id)
+                } else {
+                    format!("User {} had not been allowed.", user_id)
+                }
+            },
+            _ => format!(
+                "Access mode: {}\n\nUsage:\n/admin stats\n/admin users\n/admin selftest\n/admin commands\n/admin templates\n/admin defaults [<setting> <value>|reset]\n/admin broadcast <message>\n/admin wit-synonym <entity> \"<synonym>\" -> \"<keyword>\"\n/admin purge <user_id>\n/admin rekey\n/admin allow <user_id>\n/admin revoke <user_id>",
+                super::config().access_mode,
+            ),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Adds a synonym to a keyword of a wit.ai entity, creating the keyword
+    /// if the entity doesn't have it yet.
+    async fn admin_wit_synonym(&self, args: &str) -> String {
+        let synonym = match wit::Synonym::parse(args) {
+            Some(synonym) => synonym,
+            None => return "Usage: /admin wit-synonym <entity> \"<synonym>\" -> \"<keyword>\", e.g. /admin wit-synonym account:origin \"chequing\" -> \"checking\"".to_owned(),
+        };
+
+        let added = async {
+            let entity = super::wit_entity_get(&synonym.entity).await?;
+
+            let message = match entity.keywords.iter().find(|k| k.keyword.eq_ignore_ascii_case(&synonym.keyword)) {
+                Some(keyword) if keyword.synonyms.iter().any(|s| s.eq_ignore_ascii_case(&synonym.synonym)) => format!(
+                    "\"{}\" already stands for \"{}\" in the {} entity.",
+                    synonym.synonym, keyword.keyword, entity.name,
+                ),
+                Some(keyword) => {
+                    super::wit_synonym_post(&entity.name, &keyword.keyword, &synonym.synonym).await?;
+                    format!("\"{}\" now stands for \"{}\" in the {} entity.", synonym.synonym, keyword.keyword, entity.name)
+                },
+                None => {
+                    super::wit_keyword_post(&entity.name, &wit::Keyword {
+                        keyword: synonym.keyword.to_owned(),
+                        synonyms: vec![synonym.keyword.to_owned(), synonym.synonym.to_owned()],
+                    })
+                    .await?;
+                    format!("Added the keyword \"{}\" to the {} entity, with \"{}\" standing for it.", synonym.keyword, entity.name, synonym.synonym)
+                },
+            };
+
+            Ok::<_, Error>(message)
+        }
+        .await;
+
+        added.unwrap_or_else(|e| format!("Failed to add the synonym: {}", e))
+    }
+
+    fn admin_stats(&self) -> String {
+        let ready = self.db.users
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, user)| user.is_ready())
+            .count();
+
+        format!(
+            "Users: {} ({} set up)\nTransactions created: {}\nErrors: {}\nUser errors: {}\nQueued transactions: {}\nScheduled transactions: {}\nOpen invites: {}\n\n\
+            Retention (kept, stored, purged so far):\n\
+            - history: {}, {}, {}\n\
+            - dead letters: {}, {}, {}\n\
+            - caches: {}, {}, {}\n\
+            - expired invites: {}\n\
+            - expired pending actions: {}",
+            self.db.users.len(),
+            ready,
+            stats::get(&self.db, stats::TRANSACTIONS_CREATED),
+            stats::get(&self.db, stats::ERRORS),
+            stats::get(&self.db, stats::USER_ERRORS),
+            self.db.outbox.len(),
+            self.db.scheduled.len(),
+            self.db.invites.len(),
+            retention::describe(super::config().retention.history),
+            self.db.updates.len(),
+            stats::get(&self.db, retention::PURGED_HISTORY),
+            retention::describe(super::config().retention.dead_letters),
+            self.db.outbox.iter().filter_map(|item| item.ok()).filter(|(_, entry)| entry.is_abandoned()).count(),
+            stats::get(&self.db, retention::PURGED_DEAD_LETTERS),
+            retention::describe(super::config().retention.caches),
+            self.db.rate_limits.len() + super::FIREFLY_CACHE.len(),
+            stats::get(&self.db, retention::PURGED_CACHES),
+            stats::get(&self.db, retention::PURGED_INVITES),
+            stats::get(&self.db, retention::PURGED_PENDING_ACTIONS),
+        )
+    }
+
+    fn admin_users(&self) -> String {
+        let users = self.db.users
+            .iter()
+            .filter_map(|item| item.ok())
+            .map(|(_, user)| user)
+            .collect::<Vec<UserClue>>();
+
+        if users.is_empty() {
+            return "There are no users yet.".to_owned();
+        }
+
+        let items = users
+            .iter()
+            .take(ADMIN_USERS_LIMIT)
+            .map(|user| format!("- {}: {}", user.id, user.state))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        if users.len() > ADMIN_USERS_LIMIT {
+            format!("Users ({}, showing the first {}):\n\n{}", users.len(), ADMIN_USERS_LIMIT, items)
+        } else {
+            format!("Users ({}):\n\n{}", users.len(), items)
+        }
+    }
+
+    async fn admin_broadcast(&self, text: &str) -> String {
+        let user_ids = self.db.users
+            .iter()
+            .filter_map(|item| item.ok())
+            .map(|(_, user)| user.id)
+            .collect::<Vec<i64>>();
+
+        let mut delivered = 0;
+
+        for user_id in &user_ids {
+            let result = self.post("sendMessage", &serde_json::json!({
+                "chat_id": user_id,
+                "text": text,
+            }))
+            .await;
+
+            match result {
+                Ok(resp) if resp.status().is_success() => delivered += 1,
+                Ok(resp) => log::warn!("Failed to broadcast to user {}: {}", user_id, resp.status()),
+                Err(e) => log::warn!("Failed to broadcast to user {}: {}", user_id, e),
+            }
+        }
+
+        format!("Broadcast delivered to {} of {} user(s).", delivered, user_ids.len())
+    }
+
+    async fn cmd_later(&self, args: &str, reply_text: Option<String>) -> Result<reqwest::Response, Error> {
+        let user = match self.db.users.get(self.get_user_id())? {
+            Some(user) if user.is_ready() => user,
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("setup_required"),
+                }))
+                .await;
+            },
+        };
+
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let delay = parts.next().and_then(scheduler::parse_delay);
+        let text = parts
+            .next()
+            .map(|t| t.trim().to_owned())
+            .filter(|t| !t.is_empty())
+            .or(reply_text);
+
+        let (delay, text) = match (delay, text) {
+            (Some(delay), Some(text)) => (delay, text),
+            _ => {
+                return self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("later_usage"),
+                }))
+                .await;
+            },
+        };
+
+        let outcome = super::config().app_rules.evaluate(self.state.chat_id, self.state.from_id, &text);
+        let message = match self.parse_transaction(&user, &text, outcome).await? {
+            Some(transact) => {
+                let post_at = Utc::now() + delay;
+                let payload = TransactPayload::single(transact);
+                let description = payload.description();
+
+                scheduler::schedule(&self.db, &self.state.user_id(), self.state.chat_id, payload, post_at.timestamp())?;
+                format!("\"{}\" will be posted to Firefly III at {} UTC.", description, post_at.format("%Y-%m-%d %H:%M"))
+            },
+            None => self.text("no_transaction"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Transcribes a voice message with wit.ai and handles the text like a
+    /// typed transaction.
+    async fn cmd_voice(&self, voice: Voice) -> Result<reqwest::Response, Error> {
+        if voice.duration > MAX_VOICE_DURATION_SECS {
+            return Err(Error::Parse("voice_too_long", serde_json::json!({ "max": MAX_VOICE_DURATION_SECS })));
+        }
+
+        if !super::RATE_LIMITER.check(&self.db, self.state.chat_id)? {
+            return Err(Error::RateLimited);
+        }
+
+        if self.consume_wit_quota().await? != Quota::Available {
+            return Err(Error::Parse("voice_quota_exhausted", serde_json::json!({})));
+        }
+
+        let audio = super::telegram_download_file(&voice.file_id).await?;
+        let content_type = voice.mime_type.as_deref().unwrap_or("audio/ogg");
+
+        let text = match super::wit_speech_post(audio, content_type).await {
+            Err(Error::WitNotConfigured) => return Err(Error::Parse("voice_disabled", serde_json::json!({}))),
+            result => result?,
+        };
+
+        if text.trim().is_empty() {
+            return Err(Error::Parse("voice_empty", serde_json::json!({})));
+        }
+
+        log::info!("Transcribed a voice message");
+
+        self.cmd_text(&text).await
+    }
+
+    async fn cmd_receipt(&self, photo: Vec<PhotoSize>, caption: Option<String>) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?;
+
+        let message = match (user, &super::config().ocr_api_url) {
+            (Some(user), Some(api_url)) if user.is_ready() => {
+                let largest = photo
+                    .iter()
+                    .max_by_key(|p| p.width * p.height)
+                    .ok_or_else(|| Error::InvalidUpdate("No photo sizes included in payload".into()))?;
+                let image = super::telegram_download_file(&largest.file_id).await?;
+                let text = ocr::recognize(api_url, super::config().ocr_api_key.as_deref(), image).await?;
+
+                let mut receipt = ocr::parse_receipt(&text);
+                receipt.source_name = match caption.map(|c| c.trim().to_owned()).filter(|c| !c.is_empty()) {
+                    Some(caption) => Some(caption),
+                    None => accounts::default_source(&self.db, &self.state.user_id())?,
+                };
+                receipt.currency_code = user.default_currency.to_owned();
+
+                match (&receipt.merchant, receipt.total) {
+                    (Some(merchant), Some(total)) => {
+                        let summary = serde_json::json!({
+                            "merchant": merchant,
+                            "total": format!("{:.2}", total),
+                            "source": receipt.source_name,
+                        });
+
+                        if receipt.source_name.is_some() {
+                            pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::Receipt(receipt))?;
+
+                            return self.post("sendMessage", &serde_json::json!({
+                                "chat_id": self.state.chat_id,
+                                "reply_to_message_id": self.state.message_id,
+                                "text": self.text_with("receipt_confirm", summary),
+                                "reply_markup": {
+                                    "inline_keyboard": [[
+                                        { "text": self.text("button_confirm"), "callback_data": "receipt:confirm" },
+                                        { "text": self.text("button_cancel"), "callback_data": "receipt:cancel" },
+                                    ]],
+                                },
+                            }))
+                            .await;
+                        }
+
+                        self.text_with("receipt_confirm", summary)
+                    },
+                    _ => self.text("receipt_unreadable"),
+                }
+            },
+            (Some(user), Some(_)) if !user.is_ready() => self.text("setup_unfinished"),
+            (None, _) => self.text("setup_required"),
+            _ => self.text("receipt_disabled"),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    async fn cmd_transact(&self, payload: &str, outcome: rules::Action) -> Result<reqwest::Response, Error> {
+        let exist = self.db.users.get(self.get_user_id())?;
+
+        if let Some(user) = exist {
+            if user.is_ready() {
+                if settings::confirm_mode(&self.db, self.state.from_id)? == ConfirmMode::Repeats
+                    && dedup::is_repeated_text(&self.db, &self.state.user_id(), self.state.chat_id, payload)? {
+                    return self.confirm_repeat(payload).await;
+                }
+
+                self.transact(user, payload, outcome).await
+            } else if self.state.in_group {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("group_setup"),
+                }))
+                .await
+            } else {
+                match user.state.as_str() {
+                    "upload-url" => self.upload_url(payload).await,
+                    "upload-pat" => self.upload_pat(payload).await,
+                    _ => Err(Error::InvalidUpdate("Unknown user state".into())),
+                }
+            }
+        } else {
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("setup_required"),
+            }))
+            .await
+        }
+    }
+
+    /// Holds a text sent twice in a row instead of logging a duplicate.
+    async fn confirm_repeat(&self, payload: &str) -> Result<reqwest::Response, Error> {
+        pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::Repeat(payload.to_owned()))?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text("repeat_confirm"),
+            "reply_markup": {
+                "inline_keyboard": [[
+                    { "text": self.text("button_log_again"), "callback_data": "repeat:log" },
+                    { "text": self.text("button_skip"), "callback_data": "repeat:skip" },
+                ]],
+            },
+        }))
+        .await
+    }
+
+    async fn resolve_repeat(&self, message_id: i32, confirmed: bool) -> Result<reqwest::Response, Error> {
+        let repeat = pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::Repeat(_)))?;
+
+        let message = match repeat {
+            Some(PendingAction::Repeat(_)) if !confirmed => self.text("repeat_skipped"),
+            Some(_) => self.text("repeat_logging"),
+            None => self.text("repeat_handled"),
+        };
+
+        let response = self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await?;
+
+        match repeat {
+            Some(PendingAction::Repeat(text)) if confirmed => {
+                dedup::forget_text(&self.db, &self.state.user_id(), self.state.chat_id)?;
+                self.cmd_text(&text).await
+            },
+            _ => Ok(response),
+        }
+    }
+
+    /// Turns the message into a transaction using the configured NLP provider,
+    /// returns `None` if no transaction intent was detected.
+    async fn parse_transaction(&self, user: &UserClue, payload: &str, mut outcome: rules::Action) -> Result<Option<Transaction>, Error> {
+        if !super::RATE_LIMITER.check(&self.db, self.state.chat_id)? {
+            return Err(Error::RateLimited);
+        }
+
+        if let Some(project) = project::active(&self.db, &self.state.user_id())? {
+            outcome.tags.push(project);
+        }
+
+        if let (true, Some(tag)) = (self.state.in_group, &super::config().group_transaction_tag) {
+            outcome.tags.push(tag.to_owned());
+        }
+
+        let context = ParseContext {
+            default_currency: user.default_currency.to_owned(),
+            categories: self.db.categories.get(self.get_user_id())?,
+            rules: outcome,
+            date: self.booking_date().format("%Y-%m-%d").to_string(),
+            clean_description: !settings::raw_descriptions(&self.db, self.state.from_id)?,
+            default_source: accounts::default_source(&self.db, &self.state.user_id())?,
+            remembered_destination: None,
+        };
+
+        let provider: &dyn NlpProvider = match super::config().nlp_provider.name() {
+            "wit" => match self.consume_wit_quota().await? {
+                Quota::Available => &*super::config().nlp_provider,
+                Quota::JustExhausted | Quota::Exhausted => &LocalProvider,
+            },
+            _ => &*super::config().nlp_provider,
+        };
+
+        let parsed = match provider.parse(payload).await {
+            Err(e) if e.is_nlp_unavailable() => {
+                log::warn!("Falling back to the local parser, {} is unavailable: {}", provider.name(), e);
+                LocalProvider.parse(payload).await?
+            },
+            result => result?,
+        };
+
+        let mut context = context;
+        if let Some(parsed) = parsed.as_ref().filter(|parsed| parsed.destination_name.is_none()) {
+            let merchant = parsed.description.as_deref().unwrap_or(&parsed.text);
+            context.remembered_destination = merchants::recall(&self.db, &self.state.user_id(), merchant)?;
+        }
+
+        self.record(|fixture| {
+            fixture.parsed = parsed.clone();
+            fixture.context = Some(context.clone());
+        });
+
+        parsed.map(|parsed| build_transaction(parsed, &context)).transpose()
+    }
+
+    /// Counts a wit.ai call against the user's daily quota, telling them
+    /// when they just ran out.
+    async fn consume_wit_quota(&self) -> Result<Quota, Error> {
+        let quota = quota::consume(&self.db, &self.get_user_id(), super::config().wit_daily_quota)?;
+
+        if quota == Quota::JustExhausted {
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("quota_exhausted"),
+            }))
+            .await?;
+        }
+
+        Ok(quota)
+    }
+
+    async fn transact(&self, user: UserClue, payload: &str, outcome: rules::Action) -> Result<reqwest::Response, Error> {
+        let (repetition, payload) = match recurrence::detect(payload, self.booking_date()) {
+            Some((repetition, rest)) => (Some(repetition), rest),
+            None => (None, payload.to_owned()),
+        };
+
+        match self.parse_transaction(&user, &payload, outcome).await? {
+            Some(mut transact) => {
+                if self.state.forwarded {
+                    self.translate_description(&mut transact).await;
+                }
+
+                match repetition {
+                    Some(repetition) => self.create_recurrence(&user, transact, repetition).await,
+                    None => self.check_accounts(&user, transact).await,
+                }
+            },
+            None => {
+                self.post("sendMessage", &serde_json::json!({
+                    "chat_id": self.state.chat_id,
+                    "text": self.text("no_transaction"),
+                }))
+                .await
+            },
+        }
+    }
+
+    /// Sets the transaction up as a Firefly III recurring transaction
+    /// starting on its next occurrence, rather than booking it once.
+    async fn create_recurrence(&self, user: &UserClue, transact: Transaction, repetition: Repetition) -> Result<reqwest::Response, Error> {
+        let first_date = repetition.first_date(self.booking_date());
+        let description = transact.description.to_owned();
+        let payload = recurrence::payload(serde_json::to_value(&transact)?, repetition, first_date);
+
+        let message = match user.firefly().create_recurrence(&payload).await {
+            Ok(()) => self.text_with("recurrence_created", serde_json::json!({
+                "description": description,
+                "repetition": repetition.describe(),
+                "start": first_date.format("%Y-%m-%d").to_string(),
+            })),
+            Err(Error::FireflyRejected(reasons)) => {
+                log::warn!("Firefly III rejected the recurrence: {}", reasons);
+                self.text_with("recurrence_rejected", serde_json::json!({ "reasons": reasons }))
+            },
+            Err(e) => return Err(e),
+        };
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Translates the description into the user's language when a
+    /// translation API is configured, keeping the original in the notes.
+    /// Translation is best-effort, failures leave the description as is.
+    async fn translate_description(&self, transact: &mut Transaction) {
+        let (api_url, language) = match (&super::config().translate_api_url, &self.state.language_code) {
+            (Some(api_url), Some(language)) => (api_url, language),
+            _ => return,
+        };
+
+        // Telegram sends IETF tags such as `en-US`, translation APIs want the language alone.
+        let target = language.split('-').next().unwrap_or(language);
+
+        match translate::translate(api_url, super::config().translate_api_key.as_deref(), &transact.description, target).await {
+            Ok(Some(translated)) => {
+                transact.notes = Some(format!("Original description: {}", transact.description));
+                transact.description = translated;
+            },
+            Ok(None) => {},
+            Err(e) => log::warn!("Failed to translate the description: {}", e),
+        }
+    }
+
+    /// The names of the user's accounts of a type, cached in the database
+    /// for `ACCOUNT_CACHE_TTL_SECS`.
+    async fn account_names(&self, user: &UserClue, account_type: &str) -> Result<Vec<String>, Error> {
+        let user_id = self.state.user_id();
+
+        if let Some(names) = accounts::get_fresh(&self.db, &user_id, account_type, super::config().account_cache_ttl_secs)? {
+            return Ok(names);
+        }
+
+        // Closed accounts are left out so transactions don't end up booked against them.
+        let names = user.firefly().get_accounts(account_type)
+            .await?
+            .into_iter()
+            .filter(|a| a.attributes.is_active())
+            .map(|a| a.attributes.name)
+            .collect::<Vec<String>>();
+        accounts::store(&self.db, &user_id, account_type, names.to_owned())?;
+
+        Ok(names)
+    }
+
+    /// Matches the parsed account names against the user's Firefly III
+    /// accounts before creating the transaction. Close matches are corrected
+    /// silently. Asset accounts must exist, so when one can't be matched
+    /// with confidence the user picks from the closest ones; expense and
+    /// revenue accounts are created by Firefly III as needed and kept as is.
+    async fn check_accounts(&self, user: &UserClue, mut transact: Transaction) -> Result<reqwest::Response, Error> {
+        // "moved 200 from checking to savings" is often parsed as a
+        // withdrawal, which would book it against a new expense account
+        // named after the savings account.
+        if transact.transact_type == "withdrawal" || transact.transact_type == "deposit" {
+            match self.account_names(user, "asset").await {
+                Ok(assets) => {
+                    let source = match_account(&transact.source_name, &assets).map(|a| a.to_owned());
+                    let destination = match_account(&transact.destination_name, &assets).map(|a| a.to_owned());
+
+                    if let (Some(source), Some(destination)) = (source, destination) {
+                        if source != destination {
+                            transact.transact_type = "transfer".into();
+                            transact.source_name = source;
+                            transact.destination_name = destination;
+                        }
+                    }
+                },
+                Err(e) => log::warn!("Cannot check for a transfer between own accounts: {}", e),
+            }
+        }
+
+        let roles = match transact.transact_type.as_str() {
+            "withdrawal" => [("source", "asset"), ("destination", "expense")],
+            "deposit" => [("source", "revenue"), ("destination", "asset")],
+            "transfer" => [("source", "asset"), ("destination", "asset")],
+            _ => return self.finish_transaction(user, transact).await,
+        };
+
+        for (role, account_type) in roles.iter() {
+            let accounts = match self.account_names(user, account_type).await {
+                Ok(accounts) => accounts,
+                Err(e) => {
+                    log::warn!("Cannot check the {} account: {}", role, e);
+                    continue;
+                },
+            };
+
+            let name = match *role {
+                "source" => &mut transact.source_name,
+                _ => &mut transact.destination_name,
+            };
+
+            if let Some(account) = match_account(name, &accounts) {
+                *name = account.to_owned();
+                continue;
+            }
+
+            let ranked = fuzzy::rank(name, &accounts);
+            if *account_type != "asset" || ranked.is_empty() {
+                continue;
+            }
+
+            let suggestions = ranked
+                .into_iter()
+                .take(ACCOUNT_SUGGESTIONS_LIMIT)
+                .map(|(_, account)| account.to_owned())
+                .collect::<Vec<String>>();
+            let text = self.text_with("account_choice", serde_json::json!({ "name": name, "role": role }));
+
+            let mut keyboard = suggestions
+                .iter()
+                .enumerate()
+                .map(|(i, account)| vec![serde_json::json!({
+                    "text": account,
+                    "callback_data": format!("account:{}", i),
+                })])
+                .collect::<Vec<Vec<serde_json::Value>>>();
+            keyboard.push(vec![serde_json::json!({ "text": self.text("button_cancel"), "callback_data": "account:cancel" })]);
+
+            pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::AccountChoice(PendingAccountChoice {
+                payload: TransactPayload::single(transact),
+                role: role.to_string(),
+                suggestions,
+            }))?;
+
+            return self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": text,
+                "reply_markup": {
+                    "inline_keyboard": keyboard,
+                },
+            }))
+            .await;
+        }
+
+        self.check_currency(user, transact).await
+    }
+
+    /// Makes sure the amount is in the currency of the asset account before
+    /// creating the transaction, Firefly III would otherwise book it as if
+    /// it were in the account's currency. On a mismatch the user converts
+    /// it, keeping the original as the foreign amount, or picks an account
+    /// in the currency of the amount.
+    async fn check_currency(&self, user: &UserClue, transact: Transaction) -> Result<reqwest::Response, Error> {
+        let currency_code = match &transact.currency_code {
+            Some(code) if transact.foreign_amount.is_none() => code.to_owned(),
+            _ => return self.finish_transaction(user, transact).await,
+        };
+
+        let (role, name) = match transact.transact_type.as_str() {
+            "withdrawal" | "transfer" => ("source", &transact.source_name),
+            "deposit" => ("destination", &transact.destination_name),
+            _ => return self.finish_transaction(user, transact).await,
+        };
+
+        let assets = match user.firefly().get_accounts("asset").await {
+            Ok(assets) => assets,
+            Err(e) => {
+                log::warn!("Cannot check the currency of the {} account: {}", role, e);
+                return self.finish_transaction(user, transact).await;
+            },
+        };
+
+        let account_currency = assets
+            .iter()
+            .find(|a| a.attributes.name.eq_ignore_ascii_case(name))
+            .and_then(|a| a.attributes.currency_code.to_owned())
+            .filter(|code| !code.eq_ignore_ascii_case(&currency_code));
+        let account_currency = match account_currency {
+            Some(code) => code,
+            None => return self.finish_transaction(user, transact).await,
+        };
+
+        let suggestions = assets
+            .into_iter()
+            .filter(|a| a.attributes.is_active())
+            .filter(|a| a.attributes.currency_code.as_deref().is_some_and(|code| code.eq_ignore_ascii_case(&currency_code)))
+            .take(ACCOUNT_SUGGESTIONS_LIMIT)
+            .map(|a| a.attributes.name)
+            .collect::<Vec<String>>();
+
+        let text = self.text_with("currency_mismatch", serde_json::json!({
+            "amount": transact.amount,
+            "currency": currency_code,
+            "account": name,
+            "account_currency": account_currency,
+        }));
+
+        let mut keyboard = vec![vec![serde_json::json!({
+            "text": self.text_with("button_convert", serde_json::json!({ "currency": account_currency })),
+            "callback_data": "currency:convert",
+        })]];
+        keyboard.extend(suggestions.iter().enumerate().map(|(i, account)| vec![serde_json::json!({
+            "text": account,
+            "callback_data": format!("currency:{}", i),
+        })]));
+        keyboard.push(vec![serde_json::json!({ "text": self.text("button_cancel"), "callback_data": "currency:cancel" })]);
+
+        pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::CurrencyChoice(PendingCurrencyChoice {
+            payload: TransactPayload::single(transact),
+            role: role.to_string(),
+            account_currency,
+            suggestions,
+        }))?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": text,
+            "reply_markup": {
+                "inline_keyboard": keyboard,
+            },
+        }))
+        .await
+    }
+
+    async fn resolve_currency_choice(&self, message_id: i32, choice: &str) -> Result<reqwest::Response, Error> {
+        let user_id = self.state.user_id();
+        let pending = pending::take(&self.db, &user_id, self.state.chat_id, |a| matches!(a, PendingAction::CurrencyChoice(_)))?;
+
+        let (pending, mut transact) = match pending {
+            Some(PendingAction::CurrencyChoice(pending)) => match pending.payload.transactions.first().cloned() {
+                Some(transact) if choice != "cancel" => (pending, transact),
+                _ => return self.edit_progress(i64::from(message_id), &self.text("transaction_discarded")).await,
+            },
+            _ => return self.edit_progress(i64::from(message_id), &self.text("transaction_handled")).await,
+        };
+
+        let message = if choice == "convert" {
+            let from = transact.currency_code.to_owned().unwrap_or_default();
+            let amount = transact.amount.parse::<f64>().unwrap_or_default();
+
+            let converted = match exchange::fetch_rates(&pending.account_currency).await {
+                Ok(rates) => rates.to_base(amount, &from),
+                Err(e) => {
+                    log::warn!("Failed to fetch exchange rates: {}", e);
+                    None
+                },
+            };
+            let converted = match converted {
+                Some(converted) => converted,
+                None => {
+                    // Kept so the user can pick an account instead.
+                    let text = self.text_with("currency_no_rate", serde_json::json!({
+                        "currency": from,
+                        "account_currency": pending.account_currency,
+                    }));
+                    pending::set(&self.db, &user_id, self.state.chat_id, PendingAction::CurrencyChoice(pending))?;
+
+                    return self.post("sendMessage", &serde_json::json!({
+                        "chat_id": self.state.chat_id,
+                        "text": text,
+                    }))
+                    .await;
+                },
+            };
+
+            transact.foreign_amount = Some(transact.amount);
+            transact.foreign_currency_code = Some(from);
+            transact.amount = format!("{:.2}", converted);
+            transact.currency_code = Some(pending.account_currency.to_owned());
+
+            self.text_with("currency_converted", serde_json::json!({
+                "amount": transact.foreign_amount,
+                "currency": transact.foreign_currency_code,
+                "converted": transact.amount,
+                "account_currency": pending.account_currency,
+            }))
+        } else {
+            let account = match choice.parse::<usize>().ok().and_then(|i| pending.suggestions.get(i).cloned()) {
+                Some(account) => account,
+                None => return self.edit_progress(i64::from(message_id), &self.text("transaction_discarded")).await,
+            };
+
+            let message = self.text_with("account_chosen", serde_json::json!({ "account": account, "role": pending.role }));
+            match pending.role.as_str() {
+                "source" => transact.source_name = account,
+                _ => transact.destination_name = account,
+            }
+            message
+        };
+
+        self.edit_progress(i64::from(message_id), &message).await?;
+
+        let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+        self.finish_transaction(&user, transact).await
+    }
+
+    async fn resolve_account_choice(&self, message_id: i32, choice: &str) -> Result<reqwest::Response, Error> {
+        let pending = pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::AccountChoice(_)))?;
+
+        let (message, chosen) = match pending {
+            Some(PendingAction::AccountChoice(pending)) => {
+                let account = choice.parse::<usize>().ok().and_then(|i| pending.suggestions.get(i).cloned());
+
+                match (pending.payload.transactions.into_iter().next(), account) {
+                    (Some(transact), Some(account)) => {
+                        (self.text_with("account_chosen", serde_json::json!({ "account": account, "role": pending.role })), Some((transact, account, pending.role)))
+                    },
+                    _ => (self.text("transaction_discarded"), None),
+                }
+            },
+            _ => (self.text("transaction_handled"), None),
+        };
+
+        let tg_resp = self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await?;
+
+        let (mut transact, account, role) = match chosen {
+            Some(chosen) => chosen,
+            None => return Ok(tg_resp),
+        };
+
+        match role.as_str() {
+            "source" => transact.source_name = account,
+            _ => transact.destination_name = account,
+        }
+
+        let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+        self.check_accounts(&user, transact).await
+    }
+
+    /// Creates the transaction and offers to assign it to a budget.
+    async fn finish_transaction(&self, user: &UserClue, transact: Transaction) -> Result<reqwest::Response, Error> {
+        let budget_name = transact.budget_name.to_owned();
+        let uncategorized = transact.category_name.is_none();
+        let details = serde_json::json!({
+            "amount": transact.amount,
+            "currency": transact.currency_code,
+            "description": transact.description,
+            "source": transact.source_name,
+            "destination": transact.destination_name,
+            "category": transact.category_name,
+            "date": transact.date,
+        });
+        // Sent before the day cutoff, the user is told in case it was meant for today.
+        let booked_note = if transact.date != self.today().format("%Y-%m-%d").to_string() {
+            format!("\n\n{}", self.text_with("booked_previous_day", serde_json::json!({
+                "date": transact.date,
+                "cutoff": super::config().day_cutoff.describe_cutoff(),
+            })))
+        } else {
+            String::new()
+        };
+        let merchant = match transact.transact_type.as_str() {
+            "withdrawal" => Some((transact.description.to_owned(), transact.destination_name.to_owned())),
+            _ => None,
+        };
+        let unbudgeted_expense = match (transact.transact_type.as_str(), &transact.category_name, &transact.budget_name) {
+            ("withdrawal", Some(category), None) => transact.amount
+                .parse::<f64>()
+                .ok()
+                .map(|amount| (category.to_owned(), amount, transact.currency_code.to_owned())),
+            _ => None,
+        };
+
+        let payload = TransactPayload::single(transact);
+        let description = payload.description();
+
+        let created = match self.create_or_enqueue(user, payload).await? {
+            Some(created) => created,
+            None => return self.send_queued_notice().await,
+        };
+
+        log::info!("Transaction created");
+
+        // A photo sent with the message is attached to the transaction it logged.
+        let attached = match self.state.photo_id.as_deref().filter(|_| super::config().receipt_attachments) {
+            Some(photo_id) => format!("\n\n{}", self.attach_receipt(user, &created.data.id, photo_id).await),
+            None => String::new(),
+        };
+
+        if let Some((description, destination)) = merchant {
+            merchants::remember(&self.db, &self.state.user_id(), &[&description, &destination], &destination, super::config().merchant_memory_limit)?;
+        }
+
+        let budgets = if budget_name.is_none() {
+            user.firefly().get_active_budgets().await.unwrap_or_default()
+        } else {
+            vec![]
+        };
+
+        let sent = if budgets.is_empty() {
+            self.post_tracked("sendMessage", self.outgoing(serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": format!("{}{}{}", self.text_with("transaction_created", details), booked_note, attached),
+            })))
+            .await
+        } else {
+            let keyboard = budgets
+                .chunks(2)
+                .map(|row| row
+                    .iter()
+                    .map(|b| serde_json::json!({
+                        "text": b.attributes.name,
+                        "callback_data": format!("budget:{}:{}:{}", self.state.from_id, created.data.id, b.id),
+                    }))
+                    .collect::<Vec<serde_json::Value>>())
+                .collect::<Vec<Vec<serde_json::Value>>>();
+
+            self.post_tracked("sendMessage", self.outgoing(serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": format!("{}{}{}", self.text_with("transaction_created_pick_budget", details), booked_note, attached),
+                "reply_markup": {
+                    "inline_keyboard": keyboard,
+                },
+            })))
+            .await
+        };
+
+        // The transaction exists even if the confirmation didn't go out, it
+        // can still be undone.
+        let message_id = sent.as_ref().ok().and_then(|(_, message_id)| *message_id);
+        self.remember_created(&created, description, message_id)?;
+        let (tg_resp, _) = sent?;
+
+        let tg_resp = if uncategorized {
+            self.offer_categories(&created.data.id).await?.unwrap_or(tg_resp)
+        } else {
+            tg_resp
+        };
+
+        let (category, amount, currency_code) = match unbudgeted_expense {
+            // A budget named after the category means the user already budgets for it.
+            Some((category, ..)) if budgets.iter().any(|b| b.attributes.name.eq_ignore_ascii_case(&category)) => return Ok(tg_resp),
+            Some(expense) => expense,
+            None => return Ok(tg_resp),
+        };
+
+        match budget::record_expense(&self.db, &self.state.user_id(), &category, amount, currency_code.as_deref())? {
+            Some(suggestion) => self.suggest_budget(suggestion).await,
+            None => Ok(tg_resp),
+        }
+    }
+
+    async fn suggest_budget(&self, suggestion: BudgetSuggestion) -> Result<reqwest::Response, Error> {
+        let text = suggestion.render();
+        pending::set(&self.db, &self.state.user_id(), self.state.chat_id, PendingAction::BudgetSuggestion(suggestion))?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": text,
+            "reply_markup": {
+                "inline_keyboard": [[
+                    { "text": self.text("button_create_budget"), "callback_data": "newbudget:create" },
+                    { "text": self.text("button_no_thanks"), "callback_data": "newbudget:dismiss" },
+                ]],
+            },
+        }))
+        .await
+    }
+
+    /// Creates the suggested budget with a limit for the current month.
+    async fn resolve_budget_suggestion(&self, message_id: i32, confirmed: bool) -> Result<reqwest::Response, Error> {
+        let suggestion = pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::BudgetSuggestion(_)))?;
+
+        let message = match suggestion {
+            Some(PendingAction::BudgetSuggestion(suggestion)) if confirmed => {
+                let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                let (start, end) = report::month_range("", self.today()).ok_or_else(|| Error::Parse("month_unresolved", serde_json::json!({})))?;
+
+                let budget_id = user.firefly().create_budget(&suggestion.category).await?;
+                let mut limit = serde_json::json!({
+                    "start": start.format("%Y-%m-%d").to_string(),
+                    "end": end.format("%Y-%m-%d").to_string(),
+                    "amount": format!("{:.2}", suggestion.monthly_amount),
+                });
+
+                if let Some(currency_code) = &suggestion.currency_code {
+                    limit["currency_code"] = serde_json::Value::String(currency_code.to_owned());
+                }
+
+                user.firefly().create_budget_limit(&budget_id, &limit).await?;
+
+                self.text_with("budget_suggestion_created", serde_json::json!({
+                    "category": suggestion.category,
+                    "amount": format!("{:.0}", suggestion.monthly_amount),
+                }))
+            },
+            Some(_) => self.text("budget_suggestion_dismissed"),
+            _ => self.text("budget_suggestion_handled"),
+        };
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Creates the transaction, queueing it for a later retry when Firefly III
+    /// is unreachable. Returns `None` if the transaction was queued.
+    async fn create_or_enqueue(&self, user: &UserClue, mut payload: TransactPayload) -> Result<Option<TransactionSingle>, Error> {
+        self.record(|fixture| fixture.firefly_payload = serde_json::to_value(&payload).ok());
+
+        let external_id = payload.external_id()
+            .map(str::to_owned)
+            .unwrap_or_else(|| self.state.external_id());
+        payload.set_external_id(&external_id);
+
+        // A webhook retry or a double tap on a keyboard carries the same
+        // message, so it finds the transaction created the first time. A
+        // failed lookup is left to the create call below to deal with.
+        let firefly = user.firefly_for_posting(&self.db, &self.state.user_id())?;
+        if settings::cold_start(&self.db, &self.state.user_id())? {
+            self.post("sendMessage", &serde_json::json!({
+                "chat_id": self.state.chat_id,
+                "text": self.text("firefly_warming_up"),
+            }))
+            .await?;
+
+            // A failed warm-up is left to the create call below, which
+            // queues the transaction if the instance is still asleep.
+            if let Err(e) = firefly.warm_up().await {
+                log::warn!("Failed to warm up Firefly III: {}", e);
+            }
+        }
+
+        if let Ok(Some(id)) = firefly.find_by_external_id(&external_id).await {
+            log::info!("Transaction {} already exists, not creating it again", external_id);
+            return Ok(Some(TransactionSingle { data: TransactionRead { id } }));
+        }
+
+        let result = firefly.create_transaction(&payload).await;
+
+        if outbox::is_transient(&result) {
+            let error = outbox::describe_failure(&result);
+            log::warn!("Queueing transaction for retry: {}", error);
+
+            outbox::enqueue(&self.db, &self.state.user_id(), self.state.chat_id, payload, error)?;
+            return Ok(None);
+        }
+
+        let created = firefly::error_for_status(result.map_err(Error::Firefly)?)
+            .await?
+            .json::<TransactionSingle>()
+            .await
+            .map_err(Error::Firefly)?;
+
+        stats::record(&self.db, stats::TRANSACTIONS_CREATED);
+
+        Ok(Some(created))
+    }
+
+    /// Remembers the created transaction for `/undo` and, when a message
+    /// confirmed it, for corrections sent as a reply to that message.
+    fn remember_created(&self, created: &TransactionSingle, description: String, message_id: Option<i64>) -> Result<(), Error> {
+        let user_id = self.state.user_id();
+        let mut batch = WriteBatch::default();
+
+        undo::remember(&self.db, &mut batch, &user_id, &created.data.id, description)?;
+        if let Some(message_id) = message_id {
+            // Kept only to be submitted with a correction, see `submit_correction`.
+            let text = self.state.text.as_deref().filter(|_| super::config().wit_training_enabled);
+            correction::remember(&self.db, &mut batch, &user_id, self.state.chat_id, message_id, &created.data.id, text)?;
+        }
+
+        batch.apply(&self.db)?;
+
+        Ok(())
+    }
+
+    async fn send_queued_notice(&self) -> Result<reqwest::Response, Error> {
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text("transaction_queued"),
+        }))
+        .await
+    }
+
+    async fn assign_budget(&self, message_id: i32, transaction_id: &str, budget_id: &str) -> Result<reqwest::Response, Error> {
+        let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+
+        user.firefly().update_transaction(transaction_id, &serde_json::json!({
+            "apply_rules": false,
+            "transactions": [{ "budget_id": budget_id }],
+        }))
+        .await?;
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": self.text("transaction_created_with_budget"),
+        }))
+        .await
+    }
+
+    async fn resolve_receipt(&self, message_id: i32, confirmed: bool, photo_id: Option<String>) -> Result<reqwest::Response, Error> {
+        let receipt = pending::take(&self.db, &self.state.user_id(), self.state.chat_id, |a| matches!(a, PendingAction::Receipt(_)))?;
+
+        let message = match receipt {
+            Some(PendingAction::Receipt(receipt)) if confirmed => {
+                let user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+                let merchant = receipt.merchant.ok_or_else(|| Error::Parse("receipt_no_merchant", serde_json::json!({})))?;
+
+                let mut transact = Transaction {
+                    transact_type: "withdrawal".into(),
+                    amount: receipt.total.ok_or_else(|| Error::Parse("receipt_no_total", serde_json::json!({})))?.to_string(),
+                    description: merchant.to_owned(),
+                    source_name: receipt.source_name.ok_or_else(|| Error::Parse("receipt_no_source", serde_json::json!({})))?,
+                    destination_name: merchant,
+                    currency_code: receipt.currency_code,
+                    category_name: None,
+                    budget_name: None,
+                    tags: vec![],
+                    notes: None,
+                    foreign_amount: None,
+                    foreign_currency_code: None,
+                    external_id: None,
+                    date: self.booking_date().format("%Y-%m-%d").to_string(),
+                };
+                self.translate_description(&mut transact).await;
+
+                let payload = TransactPayload::single(transact);
+                let description = payload.description();
+
+                match self.create_or_enqueue(&user, payload).await? {
+                    Some(created) => {
+                        self.remember_created(&created, description, Some(i64::from(message_id)))?;
+
+                        match photo_id.filter(|_| super::config().receipt_attachments) {
+                            Some(photo_id) => format!("{}\n\n{}", self.text("transaction_created"), self.attach_receipt(&user, &created.data.id, &photo_id).await),
+                            None => self.text("transaction_created"),
+                        }
+                    },
+                    None => self.text("transaction_queued"),
+                }
+            },
+            Some(_) => self.text("receipt_discarded"),
+            _ => self.text("receipt_handled"),
+        };
+
+        self.post("editMessageText", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "message_id": message_id,
+            "text": message,
+        }))
+        .await
+    }
+
+    /// Attaches the photo of a receipt to its transaction, returns a line
+    /// telling how it went. The transaction is kept either way.
+    async fn attach_receipt(&self, user: &UserClue, transaction_id: &str, photo_id: &str) -> String {
+        let uploaded = async {
+            let image = super::telegram_download_file(photo_id).await?;
+            let name = format!("receipt-{}", transaction_id);
+            // Decoding and compressing the photo takes a while, so it's kept
+            // off the async workers.
+            let attachment = tokio::task::spawn_blocking(move || {
+                attachment::prepare(&name, image, &super::config().attachment_limits)
+            })
+            .await??;
+            let size = attachment.bytes.len();
+
+            user.firefly().upload_attachment(transaction_id, &attachment.filename, "Receipt", attachment.bytes).await?;
+            Ok::<_, Error>((size, attachment.original_size))
+        }
+        .await;
+
+        match uploaded {
+            Ok((size, original_size)) => self.text_with("receipt_attached", serde_json::json!({
+                "size": attachment::describe_size(size),
+                "original_size": Some(original_size).filter(|original| size < *original).map(attachment::describe_size),
+            })),
+            Err(e) => {
+                log::warn!("Failed to attach a receipt: {}", e);
+                self.text_with("receipt_not_attached", serde_json::json!({ "reason": e.user_message(self.state.language_code.as_deref()).unwrap_or_else(|| e.to_string()) }))
+            },
+        }
+    }
+
+    async fn upload_url(&self, payload: &str) -> Result<reqwest::Response, Error> {
+        let firefly_url = payload.trim();
+
+        let mut user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+        user.firefly_url = firefly_url.to_owned();
+        user.state = "upload-pat".into();
+        self.db.users.insert(self.get_user_id(), user)?;
+
+        let context = serde_json::json!({ "url": firefly_url });
+        self.post("sendMessage", &self.formatted_message("setup_pat", context))
             .await
     }
+
+    async fn upload_pat(&self, payload: &str) -> Result<reqwest::Response, Error> {
+        let firefly_pat = payload.trim();
+
+        let mut user = self.db.users.get(self.get_user_id())?.ok_or(Error::UserNotFound)?;
+        user.firefly_pat = secrets::seal(user.id, firefly_pat)?;
+        user.state = "ready".into();
+        self.db.users.insert(self.get_user_id(), user)?;
+
+        self.post("sendMessage", &serde_json::json!({
+            "chat_id": self.state.chat_id,
+            "text": self.text("setup_complete"),
+            "reply_markup": {
+                "inline_keyboard": [[
+                    { "text": self.text("button_pick_categories"), "callback_data": "shortlist:page:0" },
+                ]],
+            },
+        }))
+        .await
+    }
+}
+
+/// The number of users listed by `/admin users`, keeping the reply under Telegram's message size limit.
+const ADMIN_USERS_LIMIT: usize = 50;
+
+/// wit.ai's `/speech` endpoint only accepts up to 20 seconds of audio.
+const MAX_VOICE_DURATION_SECS: i32 = 20;
+
+/// The number of asset accounts offered when an account name doesn't match.
+const ACCOUNT_SUGGESTIONS_LIMIT: usize = 4;
+
+/// How many transactions `/find` lists per page.
+const FIND_PAGE_SIZE: u32 = 10;
+
+/// The number of transactions fetched at a time by `/export csv`.
+const EXPORT_SEARCH_PAGE_SIZE: u32 = 100;
+
+/// How many remembered merchants `/forget` lists.
+const FORGET_LIST_LIMIT: usize = 30;
+
+/// How similar an account name must be to be corrected without asking.
+const ACCOUNT_MATCH_SIMILARITY: f64 = 0.8;
+
+/// How much better than the runner-up a match must be to be corrected without asking.
+const ACCOUNT_MATCH_MARGIN: f64 = 0.15;
+
+/// The most lines `/batch` takes at once, each goes through the rate limiter
+/// so this matches its default burst.
+const BATCH_MAX_ITEMS: usize = 10;
+
+/// The callbacks whose data carries the Telegram id of the user the buttons
+/// were sent to, right after the kind.
+const OWNED_CALLBACKS: &[&str] = &["budget", "category"];
+
+/// What a transaction is built from besides the NLP provider's output.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ParseContext {
+    pub default_currency: Option<String>,
+    pub categories: Option<CategoryMap>,
+    pub rules: rules::Action,
+    pub date: String,
+    /// Whether the description is tidied up with `description::clean`, off
+    /// in fixtures recorded before it was.
+    #[serde(default)]
+    pub clean_description: bool,
+    /// The asset account of messages that don't name where the money came from.
+    #[serde(default)]
+    pub default_source: Option<String>,
+    /// The account the merchant was booked against before, for messages
+    /// that don't name where the money went.
+    #[serde(default)]
+    pub remembered_destination: Option<String>,
+}
+
+/// Builds the transaction out of what the NLP provider parsed, filling in
+/// the user's default currency and categories.
+pub fn build_transaction(parsed: ParsedTransaction, context: &ParseContext) -> Result<Transaction, Error> {
+    let category_name = match parsed.category_name {
+        Some(category) => Some(category),
+        None if context.rules.category.is_some() => context.rules.category.to_owned(),
+        None => context.categories
+            .as_ref()
+            .and_then(|categories| categories.find(&parsed.text)),
+    };
+    let text = &parsed.text;
+    let currency_code = parsed.unit
+        .and_then(|unit| currency::currency_code_from_unit(&unit))
+        .or_else(|| currency::detect_in_text(text))
+        .or_else(|| context.default_currency.to_owned());
+    // Deposits come from a revenue account, the default is an asset account.
+    let default_source = match parsed.transact_type.as_deref() {
+        Some("deposit") => None,
+        _ => context.default_source.to_owned(),
+    };
+    // Merchants are remembered for expenses only.
+    let remembered_destination = match parsed.transact_type.as_deref() {
+        Some("deposit") | Some("transfer") => None,
+        _ => context.remembered_destination.to_owned(),
+    };
+    let description = parsed.description.unwrap_or(parsed.text);
+    let description = if context.clean_description {
+        description::clean(&description, parsed.amount, currency_code.as_deref(), parsed.source_name.as_deref(), parsed.destination_name.as_deref())
+    } else {
+        description
+    };
+    let amount = parsed.amount
+        .ok_or_else(|| Error::Parse("transaction_no_amount", serde_json::json!({})))?
+        .to_string();
+    let source_name = parsed.source_name
+        .or(default_source)
+        .ok_or_else(|| Error::Parse("transaction_no_source", serde_json::json!({})))?;
+    let destination_name = parsed.destination_name
+        .or(remembered_destination)
+        .ok_or_else(|| Error::Parse("transaction_no_destination", serde_json::json!({})))?;
+    let transact_type = parsed.transact_type
+        .ok_or_else(|| Error::Parse("transaction_no_type", serde_json::json!({})))?;
+
+    let budget_name = parsed.budget_name;
+
+    Ok(Transaction {
+        transact_type,
+        amount,
+        description,
+        source_name,
+        destination_name,
+        currency_code,
+        category_name,
+        budget_name,
+        tags: context.rules.tags.to_owned(),
+        notes: None,
+        foreign_amount: None,
+        foreign_currency_code: None,
+        external_id: None,
+        date: context.date.to_owned(),
+    })
+}
+
+fn log_unknown_fields(kind: &str, extra: &HashMap<String, serde_json::Value>) {
+    if !extra.is_empty() {
+        let keys = extra.keys().map(|k| k.as_str()).collect::<Vec<&str>>();
+        log::debug!("Ignoring unknown {} fields: {}", kind, keys.join(", "));
+    }
+}
+
+/// Whether a photo caption reads as a transaction, e.g. `Lunch 12.50`,
+/// rather than naming the account a receipt was paid from.
+fn is_transaction_caption(caption: &str) -> bool {
+    caption
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_digit()).replace(',', "."))
+        .any(|word| !word.is_empty() && word.parse::<f64>().is_ok())
+}
+
+/// Whether `name` is the bot's username. Without `TG_BOT_USERNAME` any
+/// name is taken as the bot's, in groups with privacy mode on Telegram only
+/// delivers the messages meant for it anyway.
+/// The account the name refers to, matched exactly or closely enough to be
+/// corrected without asking.
+fn match_account<'a>(name: &str, accounts: &'a [String]) -> Option<&'a String> {
+    if let Some(account) = accounts.iter().find(|a| a.eq_ignore_ascii_case(name.trim())) {
+        return Some(account);
+    }
+
+    match fuzzy::rank(name, accounts).as_slice() {
+        [(best, account)] if *best >= ACCOUNT_MATCH_SIMILARITY => Some(account),
+        [(best, account), (second, _), ..] if *best >= ACCOUNT_MATCH_SIMILARITY && best - second >= ACCOUNT_MATCH_MARGIN => Some(account),
+        _ => None,
+    }
+}
+
+fn is_bot_username(name: &str) -> bool {
+    super::config().tg_bot_username.as_deref().is_none_or(|bot| bot.eq_ignore_ascii_case(name))
+}
+
+/// The text of a message meant for the bot, without the bot's username in
+/// `/report@bot` or a leading `@bot` mention. Returns `None` for messages
+/// addressed to another bot or to nobody in particular.
+fn addressed_to_bot(text: &str) -> Option<String> {
+    let text = text.trim();
+    let mut parts = text.splitn(2, char::is_whitespace);
+    let first = parts.next().unwrap_or_default();
+    let rest = parts.next().unwrap_or_default().trim();
+
+    if let Some(mention) = first.strip_prefix('@') {
+        return is_bot_username(mention).then(|| rest.to_owned());
+    }
+
+    if !first.starts_with('/') {
+        return None;
+    }
+
+    match first.split_once('@') {
+        Some((command, bot)) if is_bot_username(bot) => Some(format!("{} {}", command, rest).trim_end().to_owned()),
+        Some(_) => None,
+        None => Some(text.to_owned()),
+    }
+}
+
+/// What handling an update that isn't for the bot results in, nothing is
+/// sent to Telegram.
+fn ignored() -> reqwest::Response {
+    reqwest::Response::from(hyper::http::Response::new(Vec::<u8>::new()))
+}
+
+/// The external id of transactions created from a message, a hash of where
+/// the message was sent.
+fn external_id(chat_id: i64, message_id: i32) -> String {
+    let digest = Sha256::digest(format!("{}:{}", chat_id, message_id).as_bytes());
+    let hex = digest.iter().take(16).map(|b| format!("{:02x}", b)).collect::<String>();
+
+    format!("tg-{}", hex)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TransactPayload {
+    transactions: Vec<Transaction>,
+}
+
+impl TransactPayload {
+    pub fn single(transaction: Transaction) -> Self {
+        Self {
+            transactions: vec![transaction],
+        }
+    }
+
+    pub fn external_id(&self) -> Option<&str> {
+        self.transactions.first().and_then(|t| t.external_id.as_deref())
+    }
+
+    pub fn set_external_id(&mut self, external_id: &str) {
+        for transaction in &mut self.transactions {
+            transaction.external_id = Some(external_id.to_owned());
+        }
+    }
+
+    pub fn description(&self) -> String {
+        self.transactions
+            .iter()
+            .map(|t| format!("{} ({})", t.description, t.amount))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    transact_type: String,
+    description: String,
+    date: String,
+    amount: String,
+    source_name: String,
+    destination_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budget_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    /// The amount in the currency it was spent in when `amount` was
+    /// converted into the currency of the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    foreign_amount: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    foreign_currency_code: Option<String>,
+    /// Identifies the message the transaction was created from, so it's
+    /// never created twice.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
+}
+
+impl Transaction {
+    /// A copy of a transaction the bot created before, booked on `date`.
+    fn repeat_of(split: TransactionSplit, date: String, amount: Option<String>) -> Option<Self> {
+        Some(Self {
+            transact_type: split.transact_type,
+            description: split.description?,
+            date,
+            amount: amount.unwrap_or(split.amount),
+            source_name: split.source_name?,
+            destination_name: split.destination_name?,
+            currency_code: split.currency_code,
+            category_name: split.category_name,
+            budget_name: split.budget_name,
+            tags: split.tags.unwrap_or_default(),
+            notes: None,
+            foreign_amount: None,
+            foreign_currency_code: None,
+            external_id: None,
+        })
+    }
+}
+
+/// The lines of a `/batch` left to create, with their position in the
+/// original message.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FailedBatch {
+    /// The external id of the original message, suffixed with the position
+    /// of each line.
+    external_id: String,
+    items: Vec<(usize, String)>,
+}
+
+/// A transaction waiting for the user to pick which asset account they meant.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PendingAccountChoice {
+    #[serde(with = "outbox::stored_payload")]
+    payload: TransactPayload,
+    /// Either `source` or `destination`.
+    role: String,
+    suggestions: Vec<String>,
+}
+
+impl PendingAccountChoice {
+    pub fn description(&self) -> String {
+        self.payload.description()
+    }
+}
+
+/// A transaction in another currency than its asset account, waiting for
+/// the user to convert it or pick an account in its currency.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PendingCurrencyChoice {
+    #[serde(with = "outbox::stored_payload")]
+    payload: TransactPayload,
+    /// Either `source` or `destination`.
+    role: String,
+    account_currency: String,
+    /// The asset accounts in the currency of the transaction.
+    suggestions: Vec<String>,
+}
+
+impl PendingCurrencyChoice {
+    pub fn description(&self) -> String {
+        self.payload.description()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct UserClue {
+    id: i64,
+    state: String,
+    firefly_url: String,
+    firefly_pat: String,
+    default_currency: Option<String>,
+    timezone: Option<String>,
+    terms_version: Option<String>,
+    terms_accepted_at: Option<i64>,
+}
+
+impl UserClue {
+    pub fn new(id: i64) -> Self {
+        Self {
+            id,
+            state: "upload-url".into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state == "ready"
+    }
+
+    /// The Telegram id of the user who set the account up.
+    pub fn telegram_id(&self) -> i64 {
+        self.id
+    }
+
+    /// The time zone set with `/timezone`, `None` books transactions by the
+    /// operator's `LOCAL_UTC_OFFSET` and runs digests on UTC.
+    pub fn timezone(&self) -> Option<Tz> {
+        self.timezone.as_deref().and_then(timezone::parse)
+    }
+
+    pub fn set_timezone(&mut self, timezone: Option<Tz>) {
+        self.timezone = timezone.map(|tz| tz.name().to_owned());
+    }
+
+    /// The version of the operator's terms notice the user accepted.
+    pub fn terms_version(&self) -> Option<&str> {
+        self.terms_version.as_deref()
+    }
+
+    /// Records that the user accepted the given version of the terms notice just now.
+    pub fn accept_terms(&mut self, version: String) {
+        self.terms_version = Some(version);
+        self.terms_accepted_at = Some(Utc::now().timestamp());
+    }
+
+    /// The decrypted personal access token. It is left empty when it cannot
+    /// be decrypted so Firefly III rejects the call and the user is told to
+    /// set up their account again.
+    fn access_token(&self) -> String {
+        secrets::open(self.id, &self.firefly_pat).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            String::new()
+        })
+    }
+
+    /// The user's settings for `/support` reports, without the access token.
+    pub fn support_details(&self) -> Vec<(&'static str, String)> {
+        let token = if self.firefly_pat.is_empty() {
+            "not set"
+        } else if self.has_sealed_token() {
+            "set, encrypted"
+        } else {
+            "set, unencrypted"
+        };
+
+        vec![
+            ("Setup state", self.state.to_owned()),
+            ("Firefly III URL", if self.firefly_url.is_empty() { "not set".to_owned() } else { self.firefly_url.to_owned() }),
+            ("Access token", token.to_owned()),
+            ("Default currency", self.default_currency.to_owned().unwrap_or_else(|| "not set".into())),
+            ("Time zone", self.timezone.to_owned().unwrap_or_else(|| "not set".into())),
+        ]
+    }
+
+    /// A ready account for another Telegram user logging to the same
+    /// Firefly III instance, the access token is encrypted for them.
+    pub fn linked_to(&self, id: i64) -> Result<Self, Error> {
+        let firefly_pat = secrets::open(self.id, &self.firefly_pat)?;
+
+        Ok(Self {
+            id,
+            state: "ready".into(),
+            firefly_url: self.firefly_url.to_owned(),
+            firefly_pat: secrets::seal(id, &firefly_pat)?,
+            default_currency: self.default_currency.to_owned(),
+            timezone: None,
+            terms_version: None,
+            terms_accepted_at: None,
+        })
+    }
+
+    pub fn has_sealed_token(&self) -> bool {
+        secrets::is_sealed(&self.firefly_pat)
+    }
+
+    /// Re-encrypts the personal access token with the current master key.
+    pub fn rekey(&mut self) -> Result<(), Error> {
+        let firefly_pat = secrets::open(self.id, &self.firefly_pat)?;
+        self.firefly_pat = secrets::seal(self.id, &firefly_pat)?;
+
+        Ok(())
+    }
+
+    /// A client of the user's Firefly III instance.
+    pub fn firefly(&self) -> FireflyClient {
+        FireflyClient::new(&self.firefly_url, self.access_token(), format!("{}|", self.id))
+    }
+
+    /// A client for posting the transactions of the account stored under
+    /// `user_id`, giving the instance time to wake up if the user turned on
+    /// `/settings coldstart`.
+    pub fn firefly_for_posting(&self, db: &Database, user_id: &str) -> Result<FireflyClient, Error> {
+        if settings::cold_start(db, user_id)? {
+            Ok(self.firefly().cold_start())
+        } else {
+            Ok(self.firefly())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_messages_with_unknown_fields() {
+        let update = serde_json::from_value::<Update>(serde_json::json!({
+            "update_id": 10,
+            "message": {
+                "message_id": 7,
+                "date": 1700000000,
+                "text": "coffee 3.50",
+                "message_thread_id": 3,
+                "link_preview_options": { "is_disabled": true },
+                "chat": {
+                    "id": -100123,
+                    "type": "supergroup",
+                    "title": "Household",
+                    "is_forum": true,
+                },
+                "from": {
+                    "id": 5000000000i64,
+                    "first_name": "Sam",
+                    "is_premium": true,
+                    "added_to_attachment_menu": false,
+                },
+            },
+        }))
+        .unwrap();
+
+        let message = update.message.unwrap();
+        assert_eq!(message.text.as_deref(), Some("coffee 3.50"));
+        assert!(message.extra.contains_key("message_thread_id"));
+        assert!(message.extra.contains_key("link_preview_options"));
+        assert!(message.chat.is_group());
+        assert!(message.chat.extra.contains_key("is_forum"));
+
+        let from = message.from.unwrap();
+        assert_eq!(from.id, 5_000_000_000);
+        assert!(!from.is_bot);
+        assert_eq!(from.extra.get("is_premium"), Some(&serde_json::json!(true)));
+    }
+
+    #[test]
+    fn reads_messages_missing_optional_fields() {
+        let update = serde_json::from_value::<Update>(serde_json::json!({
+            "update_id": 11,
+            "message": {
+                "message_id": 8,
+                "date": 1700000000,
+                "chat": { "id": 42, "type": "private" },
+            },
+        }))
+        .unwrap();
+
+        let message = update.message.unwrap();
+        assert!(message.from.is_none());
+        assert!(message.text.is_none());
+        assert!(message.extra.is_empty());
+    }
+
+    #[test]
+    fn keeps_update_kinds_it_does_not_handle() {
+        let update = serde_json::from_value::<Update>(serde_json::json!({
+            "update_id": 12,
+            "message_reaction": {
+                "chat": { "id": 42, "type": "private" },
+                "message_id": 8,
+                "date": 1700000000,
+                "old_reaction": [],
+                "new_reaction": [{ "type": "emoji", "emoji": "👍" }],
+            },
+        }))
+        .unwrap();
+
+        assert!(update.message.is_none());
+        assert!(update.callback_query.is_none());
+        assert!(update.extra.contains_key("message_reaction"));
+        assert_eq!(update.chat_id(), None);
+    }
 }
@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use minijinja::{Environment, Value};
+use serde::Serialize;
+
+use crate::format;
+
+use super::Error;
+
+/// The message catalogs shipped with the bot, the first is the built-in
+/// wording every other language falls back on. Text is written plain, the
+/// `bold` and `code` filters mark what's formatted. To add a translation,
+/// put a `locales/<language>.json` catalog next to `en.json` and list it here.
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+];
+
+lazy_static! {
+    /// The catalogs as shipped, for text that doesn't go to a user.
+    static ref BUILTIN: Templates = Templates::load(None, None).expect("The built-in message catalog is valid.");
+}
+
+/// Template names to their source.
+type Catalog = BTreeMap<String, String>;
+
+fn parse_catalog(source: &str, origin: &str) -> Result<Catalog, Error> {
+    serde_json::from_str(source).map_err(|e| Error::Config(format!("Invalid message catalog {}: {}", origin, e)))
+}
+
+/// Turns the `{amount}` placeholders of a templates file into minijinja's
+/// `{{ amount }}`, anything already written for minijinja is left alone.
+fn expand_placeholders(source: &str) -> String {
+    let mut expanded = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // Already written for minijinja, e.g. `{{ amount }}` or `{% if category %}`.
+        let closer = if rest.starts_with("{{") {
+            Some("}}")
+        } else if rest.starts_with("{%") {
+            Some("%}")
+        } else {
+            None
+        };
+
+        if let Some(closer) = closer {
+            let end = rest.find(closer).map(|i| i + closer.len()).unwrap_or(rest.len());
+            expanded.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        match rest.find('}').map(|end| &rest[1..end]) {
+            Some(name) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                expanded.push_str(&format!("{{{{ {} }}}}", name));
+                rest = &rest[name.len() + 2..];
+            },
+            _ => {
+                expanded.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Adds the templates of a catalog as the translation to `language`.
+fn add_translation(env: &mut Environment<'static>, builtin: &Catalog, language: &str, catalog: Catalog, origin: &str) -> Result<(), Error> {
+    for (name, source) in catalog {
+        if !builtin.contains_key(&name) {
+            return Err(Error::Config(format!("Unknown template {} in {}", name, origin)));
+        }
+
+        env.add_template_owned(format!("{}.{}", name, language), source)
+            .map_err(|e| Error::Config(format!("Invalid template {} in {}: {}", name, origin, e)))?;
+    }
+
+    Ok(())
+}
+
+/// Key of the built-in version of a template, kept around to fall back on
+/// when an override fails to render.
+fn builtin_key(name: &str) -> String {
+    format!("builtin/{}", name)
+}
+
+fn new_environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_filter("bold", |text: String| Value::from_safe_string(format::bold(&text)));
+    env.add_filter("code", |text: String| Value::from_safe_string(format::code(&text)));
+    // Values are written as is, only their formatting markers are dropped
+    // so user input can't format a reply.
+    env.set_formatter(|out, _, value| {
+        let text = value.to_string();
+        if value.is_safe() {
+            out.write_str(&text)?;
+        } else {
+            out.write_str(&format::strip(&text))?;
+        }
+        Ok(())
+    });
+    env
+}
+
+/// The templates currently in use, replaced as a whole on reload.
+struct Loaded {
+    env: Environment<'static>,
+    overrides: usize,
+    languages: BTreeSet<String>,
+}
+
+impl Loaded {
+    /// Loads the built-in templates and the shipped translations, then the
+    /// overrides in `dir` and `file` if any.
+    fn build(dir: Option<&str>, file: Option<&str>) -> Result<Self, Error> {
+        let mut env = new_environment();
+
+        let (baseline, source) = LOCALES[0];
+        let builtin = parse_catalog(source, baseline)?;
+        for (name, source) in &builtin {
+            env.add_template_owned(builtin_key(name), source.to_owned())
+                .map_err(|e| Error::Config(format!("Invalid built-in template {}: {}", name, e)))?;
+        }
+
+        let mut languages = BTreeSet::new();
+        languages.insert(baseline.to_owned());
+
+        for (language, source) in &LOCALES[1..] {
+            add_translation(&mut env, &builtin, language, parse_catalog(source, language)?, language)?;
+            languages.insert(language.to_string());
+        }
+
+        let mut overrides = 0;
+
+        if let Some(dir) = dir {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+                if let Some(language) = file_name.strip_suffix(".json") {
+                    let origin = path.display().to_string();
+                    let catalog = parse_catalog(&fs::read_to_string(&path)?, &origin)?;
+                    overrides += catalog.len();
+                    add_translation(&mut env, &builtin, &language.to_lowercase(), catalog, &origin)?;
+                    languages.insert(language.to_lowercase());
+                    continue;
+                }
+
+                let key = match file_name.strip_suffix(".txt") {
+                    Some(key) => key.to_owned(),
+                    None => continue,
+                };
+
+                let mut parts = key.split('.');
+                let name = parts.next().unwrap_or_default();
+                if !builtin.contains_key(name) {
+                    return Err(Error::Config(format!("Unknown template {} in {}", name, dir)));
+                }
+
+                let source = fs::read_to_string(&path)?;
+                env.add_template_owned(key.clone(), source)
+                    .map_err(|e| Error::Config(format!("Invalid template {}: {}", path.display(), e)))?;
+                overrides += 1;
+
+                if let Some(language) = parts.next() {
+                    languages.insert(language.to_owned());
+                }
+            }
+        }
+
+        if let Some(file) = file {
+            let table = toml::from_str::<toml::value::Table>(&fs::read_to_string(file)?)
+                .map_err(|e| Error::Config(format!("Invalid templates file {}: {}", file, e)))?;
+
+            for (key, value) in table {
+                match value {
+                    toml::Value::String(source) => {
+                        if !builtin.contains_key(&key) {
+                            return Err(Error::Config(format!("Unknown template {} in {}", key, file)));
+                        }
+
+                        env.add_template_owned(key.clone(), expand_placeholders(&source))
+                            .map_err(|e| Error::Config(format!("Invalid template {} in {}: {}", key, file, e)))?;
+                        overrides += 1;
+                    },
+                    // A `[de]` table holds the German wording.
+                    toml::Value::Table(entries) => {
+                        let catalog = entries
+                            .into_iter()
+                            .map(|(name, source)| match source {
+                                toml::Value::String(source) => Ok((name, expand_placeholders(&source))),
+                                _ => Err(Error::Config(format!("Template {}.{} in {} isn't a string", name, key, file))),
+                            })
+                            .collect::<Result<Catalog, Error>>()?;
+                        overrides += catalog.len();
+                        add_translation(&mut env, &builtin, &key.to_lowercase(), catalog, file)?;
+                        languages.insert(key.to_lowercase());
+                    },
+                    _ => return Err(Error::Config(format!("Template {} in {} isn't a string", key, file))),
+                }
+            }
+        }
+
+        Ok(Self { env, overrides, languages })
+    }
+}
+
+/// The minijinja templates replies are rendered from.
+pub struct Templates {
+    dir: Option<String>,
+    file: Option<String>,
+    loaded: RwLock<Loaded>,
+}
+
+impl Templates {
+    /// Loads the templates, overrides in `dir` are named `<name>.txt`, or
+    /// `<name>.<language>.txt` (e.g. `setup_required.de.txt`) to only apply
+    /// to users whose Telegram is set to that language. A `<language>.json`
+    /// catalog translates several templates at once. The TOML `file` maps
+    /// template names to their wording with `{amount}` style placeholders,
+    /// and takes precedence over `dir`.
+    pub fn load(dir: Option<String>, file: Option<String>) -> Result<Self, Error> {
+        let loaded = Loaded::build(dir.as_deref(), file.as_deref())?;
+        Ok(Self { dir, file, loaded: RwLock::new(loaded) })
+    }
+
+    /// Reads the overrides again, returns how many there are. The current
+    /// templates are kept if the new ones fail to load.
+    pub fn reload(&self) -> Result<usize, Error> {
+        let loaded = Loaded::build(self.dir.as_deref(), self.file.as_deref())?;
+        let overrides = loaded.overrides;
+        *self.loaded.write().unwrap() = loaded;
+        Ok(overrides)
+    }
+
+    /// How many templates were overridden by the operator.
+    pub fn overrides(&self) -> usize {
+        self.loaded.read().unwrap().overrides
+    }
+
+    /// The languages replies have been translated to, at least in part.
+    pub fn languages(&self) -> BTreeSet<String> {
+        self.loaded.read().unwrap().languages.clone()
+    }
+
+    /// Renders the template in the user's language, falling back to the
+    /// operator's override and then to the built-in wording. The text still
+    /// carries the formatting markers, see `format::ParseMode::render`.
+    pub fn render<S: Serialize>(&self, name: &str, language_code: Option<&str>, context: S) -> String {
+        let loaded = self.loaded.read().unwrap();
+
+        let language = language_code
+            .and_then(|code| code.split('-').next())
+            .map(|language| format!("{}.{}", name, language.to_lowercase()));
+
+        let candidates = language
+            .into_iter()
+            .chain(Some(name.to_owned()))
+            .chain(Some(builtin_key(name)));
+
+        for key in candidates {
+            let template = match loaded.env.get_template(&key) {
+                Ok(template) => template,
+                Err(_) => continue,
+            };
+
+            match template.render(&context) {
+                Ok(text) => return text,
+                Err(e) => log::error!("Failed to render the template {}: {}", key, e),
+            }
+        }
+
+        name.to_owned()
+    }
+}
+
+/// The built-in English wording of a template as plain text, for logs and
+/// operators.
+pub fn english<S: Serialize>(name: &str, context: S) -> String {
+    format::strip(&BUILTIN.render(name, None, context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin() -> Templates {
+        Templates::load(None, None).unwrap()
+    }
+
+    #[test]
+    fn lists_the_catalog_entries() {
+        let templates = builtin();
+        let mappings = serde_json::json!([
+            { "keyword": "coffee", "category": "Dining" },
+            { "keyword": "rent", "category": "Housing" },
+        ]);
+
+        assert_eq!(
+            templates.render("categories_list", None, serde_json::json!({ "mappings": mappings })),
+            "Your category mappings:\n\ncoffee -> Dining\nrent -> Housing\n\nType /categories unmap <keyword> to remove one.",
+        );
+        assert_eq!(
+            templates.render("budgets_list", None, serde_json::json!({ "budgets": ["Food", "Rent"] })),
+            "Your budgets:\n\n- Food\n- Rent",
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_is_missing() {
+        let templates = builtin();
+
+        assert_eq!(
+            templates.render("transaction_updated", None, serde_json::json!({ "amount": "25", "category": "Dining" })),
+            "Transaction updated:\n- amount 25\n- category Dining",
+        );
+        assert_eq!(
+            templates.render("receipt_attached", None, serde_json::json!({ "size": "120 KB", "original_size": null })),
+            "Receipt attached (120 KB).",
+        );
+        assert_eq!(
+            templates.render("project_started", None, serde_json::json!({ "name": "trip" })),
+            "New transactions will be tagged \"trip\" until you type /project stop.",
+        );
+    }
+
+    #[test]
+    fn renders_the_digest() {
+        let context = serde_json::json!({
+            "frequency": "weekly",
+            "start": "May 1",
+            "end": "May 7",
+            "spent": "42.00 EUR",
+            "categories": [{ "name": null, "amount": "42.00 EUR" }],
+            "budgets": [{ "name": "Food", "spent": "60.00 EUR", "amount": "50.00 EUR", "over": true }],
+        });
+
+        assert_eq!(
+            builtin().render("digest", None, context),
+            "Weekly digest for May 1 to May 7\n\nSpent: 42.00 EUR\n\nTop spending categories:\n- Uncategorized: 42.00 EUR\n\nBudgets this month:\n- Food: 60.00 EUR of 50.00 EUR (over budget)",
+        );
+    }
+
+    #[test]
+    fn describes_errors_in_english() {
+        let error = Error::Parse("voice_too_long", serde_json::json!({ "max": 60 }));
+
+        assert_eq!(error.to_string(), "Please keep voice messages under 60 seconds.");
+        assert_eq!(english("rule_reply", serde_json::json!({ "reply": "*Use* the shared card" })), "*Use* the shared card");
+    }
+}
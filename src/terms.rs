@@ -0,0 +1,34 @@
+use sha2::{Digest, Sha256};
+
+use crate::telegram::UserClue;
+
+/// The version of the operator's terms notice, a digest of its text so an
+/// edited notice is accepted again. `None` when the operator set no notice.
+pub fn current_version() -> Option<String> {
+    super::config().terms_notice.as_deref().map(version)
+}
+
+fn version(notice: &str) -> String {
+    let digest = Sha256::digest(notice.as_bytes());
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether the user has to accept the terms notice, i.e. the operator set
+/// one and the user hasn't accepted this version of it yet.
+pub fn is_required(user: Option<&UserClue>) -> bool {
+    match current_version() {
+        Some(version) => user.and_then(UserClue::terms_version) != Some(version.as_str()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_the_notice_by_its_text() {
+        assert_eq!(version("Be nice."), version("Be nice."));
+        assert_ne!(version("Be nice."), version("Be nicer."));
+    }
+}
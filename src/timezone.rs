@@ -0,0 +1,6 @@
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+/// Reads an IANA time zone name such as `Europe/Berlin`, in any case.
+pub fn parse(name: &str) -> Option<Tz> {
+    TZ_VARIANTS.iter().find(|tz| tz.name().eq_ignore_ascii_case(name.trim())).copied()
+}
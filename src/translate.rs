@@ -0,0 +1,51 @@
+use serde::Deserialize;
+
+use super::Error;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslateResponse {
+    translated_text: String,
+    detected_language: Option<DetectedLanguage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DetectedLanguage {
+    language: String,
+}
+
+/// Translates the text into the target language with a LibreTranslate
+/// compatible API (`POST /translate`). Returns `None` when the text is
+/// already in the target language or comes back unchanged.
+pub async fn translate(api_url: &str, api_key: Option<&str>, text: &str, target: &str) -> Result<Option<String>, Error> {
+    let mut payload = serde_json::json!({
+        "q": text,
+        "source": "auto",
+        "target": target,
+        "format": "text",
+    });
+
+    if let Some(key) = api_key {
+        payload["api_key"] = serde_json::Value::String(key.to_owned());
+    }
+
+    let response = super::HTTP_CLIENTS.translate
+        .post(format!("{}/translate", api_url.trim_end_matches('/')))
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Upstream)?
+        .json::<TranslateResponse>()
+        .await
+        .map_err(Error::Upstream)?;
+
+    let same_language = response.detected_language.is_some_and(|d| d.language.eq_ignore_ascii_case(target));
+    let translated = response.translated_text.trim();
+
+    if same_language || translated.is_empty() || translated.eq_ignore_ascii_case(text.trim()) {
+        return Ok(None);
+    }
+
+    Ok(Some(translated.to_owned()))
+}
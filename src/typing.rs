@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Telegram clears a chat action after 5 seconds, so it is re-sent a bit earlier.
+const REFRESH_INTERVAL_SECS: u64 = 4;
+
+/// Requests answered quicker than this show no chat action, it would only
+/// flicker.
+const CHAT_ACTION_DELAY_MS: u64 = 1000;
+
+/// How long a request runs before an interim message tells the user it is
+/// still being worked on.
+const INTERIM_AFTER_SECS: u64 = 5;
+
+/// The status shown in a chat while a request is processed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatAction {
+    Typing,
+    /// Rendering a chart to be sent as a photo.
+    UploadPhoto,
+    /// Generating an export to be sent as a document.
+    UploadDocument,
+}
+
+impl ChatAction {
+    /// Picks the action matching what the command is about to send.
+    pub fn for_command(command: &str) -> Self {
+        match command {
+            "/chart" => Self::UploadPhoto,
+            "/export" => Self::UploadDocument,
+            _ => Self::Typing,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Typing => "typing",
+            Self::UploadPhoto => "upload_photo",
+            Self::UploadDocument => "upload_document",
+        }
+    }
+}
+
+/// Keeps a chat action such as "typing…" visible in a chat until dropped.
+/// Requests answered within `CHAT_ACTION_DELAY_MS` show nothing at all,
+/// ones still running after `INTERIM_AFTER_SECS` get an interim message
+/// that the reply is edited into.
+pub struct TypingIndicator {
+    handle: JoinHandle<()>,
+    interim: InterimReply,
+    chat_id: i64,
+}
+
+impl TypingIndicator {
+    /// Starts the indicator, `interim_text` is the interim message sent to
+    /// slow requests, `None` to never send one.
+    pub fn start(chat_id: i64, action: ChatAction, interim_text: Option<String>) -> Self {
+        let interim = InterimReply::default();
+        let state = interim.clone();
+
+        let handle = tokio::spawn(async move {
+            let started = Instant::now();
+            let mut interim_text = interim_text;
+
+            sleep(Duration::from_millis(CHAT_ACTION_DELAY_MS)).await;
+
+            loop {
+                let tg_resp = super::telegram_send_chat_action(chat_id, action).await;
+
+                if let Err(e) = tg_resp {
+                    log::warn!("Failed to send chat action: {}", e);
+                }
+
+                if started.elapsed() >= Duration::from_secs(INTERIM_AFTER_SECS) {
+                    if let Some(text) = interim_text.take() {
+                        state.send(chat_id, text).await;
+                    }
+                }
+
+                sleep(Duration::from_secs(REFRESH_INTERVAL_SECS)).await;
+            }
+        });
+
+        Self { handle, interim, chat_id }
+    }
+
+    /// The interim message of the request, for the replies to take over.
+    pub fn interim(&self) -> InterimReply {
+        self.interim.clone()
+    }
+}
+
+impl Drop for TypingIndicator {
+    fn drop(&mut self) {
+        self.handle.abort();
+
+        // Nothing replaced the interim message, e.g. the reply was a photo.
+        let interim = self.interim.clone();
+        let chat_id = self.chat_id;
+        tokio::spawn(async move {
+            if let Some(message_id) = interim.take().await {
+                let tg_resp = super::telegram_post("deleteMessage", &serde_json::json!({
+                    "chat_id": chat_id,
+                    "message_id": message_id,
+                }))
+                .await;
+
+                if let Err(e) = tg_resp {
+                    log::warn!("Failed to delete the interim message: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[derive(Debug)]
+enum Interim {
+    Waiting,
+    Sent(i64),
+    /// The request was answered, no interim message is sent anymore.
+    Done,
+}
+
+/// The interim message of a slow request, shared by the indicator sending
+/// it and the reply taking its place.
+#[derive(Debug, Clone)]
+pub struct InterimReply(Arc<Mutex<Interim>>);
+
+impl Default for InterimReply {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Interim::Waiting)))
+    }
+}
+
+impl InterimReply {
+    /// Sends the interim message unless the request was answered already.
+    /// The lock is held meanwhile so a reply waits for the message id.
+    async fn send(&self, chat_id: i64, text: String) {
+        let mut interim = self.0.lock().await;
+        if !matches!(*interim, Interim::Waiting) {
+            return;
+        }
+
+        let tg_resp = super::telegram_post("sendMessage", &serde_json::json!({
+            "chat_id": chat_id,
+            "text": text,
+        }))
+        .await;
+
+        let sent = match tg_resp {
+            Ok(resp) => resp.json::<serde_json::Value>().await.ok(),
+            Err(e) => {
+                log::warn!("Failed to send the interim message: {}", e);
+                None
+            },
+        };
+
+        if let Some(message_id) = sent.and_then(|sent| sent["result"]["message_id"].as_i64()) {
+            *interim = Interim::Sent(message_id);
+        }
+    }
+
+    /// Marks the request answered, returning the id of the interim message
+    /// to be replaced if one was sent.
+    pub async fn take(&self) -> Option<i64> {
+        let mut interim = self.0.lock().await;
+
+        match std::mem::replace(&mut *interim, Interim::Done) {
+            Interim::Sent(message_id) => Some(message_id),
+            Interim::Waiting | Interim::Done => None,
+        }
+    }
+}
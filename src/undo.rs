@@ -0,0 +1,47 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::WriteBatch;
+
+use super::{Database, Error};
+
+/// The latest transaction the bot created for a user, what `/undo` deletes.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LastCreated {
+    pub transaction_id: String,
+    /// The description and amount, as shown in the confirmation.
+    pub description: String,
+    pub created_at: i64,
+}
+
+pub fn remember(db: &Database, batch: &mut WriteBatch, user_id: &str, transaction_id: &str, description: String) -> Result<(), Error> {
+    let last = LastCreated {
+        transaction_id: transaction_id.to_owned(),
+        description,
+        created_at: Utc::now().timestamp(),
+    };
+
+    batch.insert(&db.last_created, user_id, &last)
+}
+
+/// The transaction `/again` repeats, left in place for `/undo`.
+pub fn last(db: &Database, user_id: &str) -> Result<Option<LastCreated>, Error> {
+    Ok(db.last_created.get(user_id.as_bytes())?)
+}
+
+/// Takes the transaction to undo, so a second `/undo` running at the same
+/// time finds nothing and cannot delete it twice.
+pub fn take(db: &Database, user_id: &str) -> Result<Option<LastCreated>, Error> {
+    Ok(db.last_created.remove(user_id.as_bytes())?)
+}
+
+/// Puts the transaction back when deleting it failed, unless a newer one
+/// was created in the meantime.
+pub fn restore(db: &Database, user_id: &str, last: LastCreated) -> Result<(), Error> {
+    db.last_created.fetch_and_update(user_id.as_bytes(), |current| match current {
+        Some(current) => Some(current),
+        None => Some(last.to_owned()),
+    })?;
+
+    Ok(())
+}
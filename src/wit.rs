@@ -1,10 +1,16 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
 pub struct WitMessageResponse {
     pub text: String,
+
+    #[serde(default)]
     pub intents: Vec<Intent>,
+
+    #[serde(default)]
     pub entities: Entities,
+
+    #[serde(default)]
     pub traits: Traits,
 }
 
@@ -15,12 +21,15 @@ pub struct Intent {
 
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
 pub struct Entities {
+    #[serde(default)]
     #[serde(rename = "account:destination")]
     pub destination: Vec<AccountEntity>,
 
+    #[serde(default)]
     #[serde(rename = "account:origin")]
     pub origin: Vec<AccountEntity>,
 
+    #[serde(default)]
     #[serde(rename = "wit$amount_of_money:amount_of_money")]
     pub amount_of_money: Vec<WitAmountOfMoney>,
 
@@ -39,6 +48,14 @@ pub struct Entities {
     #[serde(default)]
     #[serde(rename = "deed:deed")]
     pub deed: Option<Vec<Deed>>,
+
+    #[serde(default)]
+    #[serde(rename = "category:category")]
+    pub category: Option<Vec<CategoryEntity>>,
+
+    #[serde(default)]
+    #[serde(rename = "budget:budget")]
+    pub budget: Option<Vec<BudgetEntity>>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
@@ -50,6 +67,7 @@ pub struct AccountEntity {
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
 pub struct WitAmountOfMoney {
     pub role: String,
+    #[serde(default)]
     pub unit: String,
     pub value: f64,
 }
@@ -65,8 +83,21 @@ pub struct Deed {
     pub value: String,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct CategoryEntity {
+    pub role: String,
+    pub value: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct BudgetEntity {
+    pub role: String,
+    pub value: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
 pub struct Traits {
+    #[serde(default)]
     pub flow: Vec<Flow>,
 }
 
@@ -74,3 +105,175 @@ pub struct Traits {
 pub struct Flow {
     pub value: String,
 }
+
+/// A training sample submitted to wit.ai's `/utterances` endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Utterance {
+    pub text: String,
+    pub intent: String,
+    pub entities: Vec<UtteranceEntity>,
+    pub traits: Vec<UtteranceTrait>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UtteranceEntity {
+    pub entity: String,
+    pub start: usize,
+    pub end: usize,
+    pub body: String,
+    pub entities: Vec<UtteranceEntity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UtteranceTrait {
+    #[serde(rename = "trait")]
+    pub name: String,
+    pub value: String,
+}
+
+impl Utterance {
+    pub fn new(text: &str, intent: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            intent: intent.to_owned(),
+            entities: vec![],
+            traits: vec![],
+        }
+    }
+
+    /// Marks the first occurrence of `body` in the text as the given entity,
+    /// e.g. `account:origin`. Values that do not appear verbatim are skipped
+    /// since wit.ai only accepts spans of the original text.
+    pub fn with_entity(mut self, entity: &str, body: &str) -> Self {
+        let lower = self.text.to_lowercase();
+
+        let span = lower
+            .find(&body.to_lowercase())
+            .filter(|_| !body.is_empty())
+            .and_then(|start| {
+                let end = start + body.len();
+                self.text.get(start..end).map(|b| (start, end, b.to_owned()))
+            });
+
+        if let Some((start, end, body)) = span {
+            self.entities.push(UtteranceEntity {
+                entity: entity.to_owned(),
+                start,
+                end,
+                body,
+                entities: vec![],
+            });
+        }
+
+        self
+    }
+
+    pub fn with_trait(mut self, name: &str, value: &str) -> Self {
+        self.traits.push(UtteranceTrait {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        });
+
+        self
+    }
+}
+
+/// An entity of the wit.ai app as its entity management API lists it.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Entity {
+    pub name: String,
+
+    #[serde(default)]
+    pub keywords: Vec<Keyword>,
+}
+
+/// A value of a keywords entity, matched by any of its synonyms.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Keyword {
+    pub keyword: String,
+
+    #[serde(default)]
+    pub synonyms: Vec<String>,
+}
+
+/// A synonym to add to an entity, e.g. `chequing` for the `checking`
+/// keyword of `account`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Synonym {
+    pub entity: String,
+    pub synonym: String,
+    pub keyword: String,
+}
+
+impl Synonym {
+    /// Parses `account:origin "chequing" -> "checking"`. The roles of an
+    /// entity share its keywords, so the role after the colon is dropped.
+    pub fn parse(args: &str) -> Option<Self> {
+        let (entity, rest) = args.trim().split_once(char::is_whitespace)?;
+        let (synonym, keyword) = rest.split_once("->").or_else(|| rest.split_once('→'))?;
+        let unquote = |value: &str| value
+            .trim()
+            .trim_matches(|c| matches!(c, '"' | '\'' | '“' | '”'))
+            .trim()
+            .to_owned();
+
+        let synonym = Self {
+            entity: entity.split(':').next().unwrap_or_default().to_owned(),
+            synonym: unquote(synonym),
+            keyword: unquote(keyword),
+        };
+
+        if synonym.entity.is_empty() || synonym.synonym.is_empty() || synonym.keyword.is_empty() {
+            return None;
+        }
+
+        Some(synonym)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_responses_with_unknown_keys() {
+        let response = serde_json::from_value::<WitMessageResponse>(serde_json::json!({
+            "text": "paid 12 from checking",
+            "speech": { "confidence": 0.9 },
+            "intents": [{ "id": "1", "name": "transact", "confidence": 0.98 }],
+            "entities": {
+                "account:origin": [{
+                    "id": "2",
+                    "name": "account",
+                    "role": "origin",
+                    "body": "checking",
+                    "value": "Checking",
+                    "confidence": 0.95,
+                    "entities": {},
+                }],
+                "wit$amount_of_money:amount_of_money": [{
+                    "role": "amount_of_money",
+                    "value": 12,
+                    "type": "value",
+                }],
+                "wit$datetime:datetime": [{ "role": "datetime", "value": "2024-01-01T00:00:00.000-08:00" }],
+            },
+            "traits": { "wit$sentiment": [{ "value": "neutral" }] },
+        }))
+        .unwrap();
+
+        assert_eq!(response.intents[0].name, "transact");
+        assert_eq!(response.entities.origin[0].value, "Checking");
+        assert_eq!(response.entities.amount_of_money[0].value, 12.0);
+        assert!(response.entities.amount_of_money[0].unit.is_empty());
+    }
+
+    #[test]
+    fn reads_responses_missing_fields() {
+        let response = serde_json::from_value::<WitMessageResponse>(serde_json::json!({ "text": "hello" })).unwrap();
+
+        assert!(response.intents.is_empty());
+        assert!(response.entities.origin.is_empty());
+        assert!(response.entities.withdraw.is_none());
+    }
+}